@@ -0,0 +1,59 @@
+use luisa::runtime::CaptureFile;
+use luisa_compute as luisa;
+use std::env::current_exe;
+use std::path::PathBuf;
+
+// Replays a capture written with `LUISA_CAPTURE=<file>` or
+// `Context::create_device_with_capture`.
+fn main() {
+    luisa::init_logger();
+    let args: Vec<String> = std::env::args().collect();
+    let usage = format!(
+        "Usage: {} <capture> [<backend>] [--dump <dir>]. <backend>: cpu, cuda, dx, metal, remote, interpreter",
+        args[0]
+    );
+    let mut positional = vec![];
+    let mut dump = None;
+    let mut iter = args.iter().skip(1);
+    while let Some(arg) = iter.next() {
+        if arg == "--dump" {
+            dump = Some(PathBuf::from(iter.next().expect(&usage)));
+        } else {
+            positional.push(arg.as_str());
+        }
+    }
+    assert!(!positional.is_empty() && positional.len() <= 2, "{}", usage);
+
+    let capture = CaptureFile::load(positional[0]).unwrap_or_else(|e| panic!("{}", e));
+    let backend = positional.get(1).copied().unwrap_or(capture.device_name());
+    println!(
+        "replaying {} steps captured on `{}` on `{}`",
+        capture.len(),
+        capture.device_name(),
+        backend
+    );
+    let ctx = luisa::Context::new(current_exe().unwrap());
+    let device = ctx.create_device(backend);
+    if let Some(dir) = &dump {
+        std::fs::create_dir_all(dir).unwrap();
+    }
+    let mut replayer = capture.replay(&device);
+    while !replayer.is_finished() {
+        let index = replayer.position();
+        println!("[{}] {}", index, capture.describe(index));
+        if let Err(e) = replayer.step() {
+            eprintln!("replay stopped at step {}: {}", index, e);
+            std::process::exit(1);
+        }
+        if let Some(dir) = &dump {
+            for buffer in replayer.buffers() {
+                let data = replayer.read_buffer(buffer).unwrap();
+                std::fs::write(
+                    dir.join(format!("{:06}-buffer-{}.bin", index, buffer)),
+                    data,
+                )
+                .unwrap();
+            }
+        }
+    }
+}
@@ -0,0 +1,592 @@
+//! Device-wide parallel primitives built on top of the kernel API.
+//!
+//! All primitives are expressed as a sequence of [`Command`]s so they can be
+//! submitted together with user kernels in the same [`Scope`]:
+//! ```no_run
+//! use luisa_compute::prelude::*;
+//! use luisa_compute::algorithms::Algorithms;
+//! #[tracked]
+//! fn add(a: Expr<f32>, b: Expr<f32>) -> Expr<f32> {
+//!     a + b
+//! }
+//! let ctx = Context::new(std::env::current_exe().unwrap());
+//! let device = ctx.create_device("cpu");
+//! let algo = Algorithms::new(&device);
+//! let x = device.create_buffer_from_fn(1024, |i| i as f32);
+//! let sum = device.create_buffer::<f32>(1);
+//! device.default_stream().with_scope(|s| {
+//!     s.submit(algo.reduce(&x.view(..), &sum.view(..), add));
+//! });
+//! ```
+//! Kernels are compiled lazily and cached per element type and operator type.
+//! Operators are functions or closures, e.g. `|a, b| a.max_(b)`. Functions and
+//! closures that capture nothing have a type of their own and are compiled
+//! once; closures that capture state and function pointers are compiled again
+//! on every call, since values of the same type may compute different things.
+//!
+//! Every primitive splits its input into chunks of [`CHUNK_SIZE`] elements
+//! that are processed sequentially by a single thread, so no shared memory or
+//! block synchronization is required and all backends (including cpu) are supported.
+//! Temporary buffers are kept alive by the returned commands.
+//!
+//! This trades speed for portability: only one thread runs per chunk, and
+//! neighbouring threads access memory `CHUNK_SIZE` elements apart. On GPUs,
+//! block-level primitives that cooperate through shared memory make far better
+//! use of the device, so these are best kept off hot paths there.
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+
+use crate::internal_prelude::*;
+use crate::runtime::Kernel;
+
+/// Number of elements processed by a single thread.
+pub const CHUNK_SIZE: u32 = 256;
+const RADIX_BITS: u32 = 4;
+const RADIX: usize = 1 << RADIX_BITS;
+
+/// An associative binary operator, e.g. `|a, b| a + b`.
+///
+/// Implemented by functions and closures, see the [module documentation](self).
+pub trait BinaryOp<T: Value>: Fn(Expr<T>, Expr<T>) -> Expr<T> + Copy + 'static {}
+impl<T: Value, F: Fn(Expr<T>, Expr<T>) -> Expr<T> + Copy + 'static> BinaryOp<T> for F {}
+
+/// A predicate used by [`Algorithms::compact`], see [`BinaryOp`].
+pub trait Predicate<T: Value>: Fn(Expr<T>) -> Expr<bool> + Copy + 'static {}
+impl<T: Value, F: Fn(Expr<T>) -> Expr<bool> + Copy + 'static> Predicate<T> for F {}
+
+/// Keys sorted by [`Algorithms::sort_keys`] and [`Algorithms::sort_pairs`].
+///
+/// Implemented for `u32`, `i32`, `u64` and `f32`. Floats are sorted by their
+/// bits, so `-0.0` comes before `0.0` and NaNs with the sign bit set come
+/// first, other NaNs last.
+pub trait SortKey: Value {
+    /// Number of bits of the key, a multiple of 8.
+    const BITS: u32;
+    /// The digit of `key` at bit `shift` in an unsigned integer ordered like
+    /// the keys.
+    fn digit(key: Expr<Self>, shift: Expr<u32>) -> Expr<u32>;
+}
+
+#[inline]
+fn digit_u32(bits: Expr<u32>, shift: Expr<u32>) -> Expr<u32> {
+    (bits >> shift) & (RADIX as u32 - 1)
+}
+
+impl SortKey for u32 {
+    const BITS: u32 = 32;
+    fn digit(key: Expr<u32>, shift: Expr<u32>) -> Expr<u32> {
+        digit_u32(key, shift)
+    }
+}
+impl SortKey for i32 {
+    const BITS: u32 = 32;
+    fn digit(key: Expr<i32>, shift: Expr<u32>) -> Expr<u32> {
+        // negative keys first
+        digit_u32(key.bitcast::<u32>() ^ 0x8000_0000u32, shift)
+    }
+}
+impl SortKey for f32 {
+    const BITS: u32 = 32;
+    fn digit(key: Expr<f32>, shift: Expr<u32>) -> Expr<u32> {
+        // flips all bits of negative keys and only the sign bit of positive ones
+        let bits = key.bitcast::<u32>();
+        let mask = (0u32.expr() - (bits >> 31u32)) | 0x8000_0000u32;
+        digit_u32(bits ^ mask, shift)
+    }
+}
+impl SortKey for u64 {
+    const BITS: u32 = 64;
+    fn digit(key: Expr<u64>, shift: Expr<u32>) -> Expr<u32> {
+        ((key >> shift.as_u64()) & (RADIX as u64 - 1)).as_u32()
+    }
+}
+
+/// Element type, kernel name and operator type.
+type KernelKey = (TypeId, &'static str, TypeId);
+
+pub struct Algorithms {
+    device: Device,
+    kernels: Mutex<HashMap<KernelKey, Arc<dyn Any + Send + Sync>>>,
+}
+
+#[tracked]
+fn add_u32(a: Expr<u32>, b: Expr<u32>) -> Expr<u32> {
+    a + b
+}
+
+#[inline]
+fn num_chunks(n: usize) -> u32 {
+    let n: u32 = n
+        .try_into()
+        .unwrap_or_else(|_| panic!("Too many elements: {}", n));
+    (n + CHUNK_SIZE - 1) / CHUNK_SIZE
+}
+
+fn hold_temporaries(commands: &mut Vec<Command<'static, 'static>>, rt: ResourceTracker) {
+    commands
+        .last_mut()
+        .expect("no command to attach temporaries to")
+        .resource_tracker
+        .merge(rt);
+}
+
+type ScanChunksKernel<T> = Kernel<fn(Buffer<T>, Buffer<T>, Buffer<T>, u32)>;
+type AddPrefixKernel<T> = Kernel<fn(Buffer<T>, Buffer<T>, u32)>;
+type ShiftKernel<T> = Kernel<fn(Buffer<T>, Buffer<T>, T)>;
+type ReduceChunksKernel<T> = Kernel<fn(Buffer<T>, Buffer<T>, u32)>;
+type SegmentedReduceKernel<T> = Kernel<fn(Buffer<T>, Buffer<u32>, Buffer<T>, T)>;
+type CompactFlagKernel<T> = Kernel<fn(Buffer<T>, Buffer<u32>)>;
+type CompactScatterKernel<T> =
+    Kernel<fn(Buffer<T>, Buffer<u32>, Buffer<u32>, Buffer<T>, Buffer<u32>)>;
+type RadixHistogramKernel<K> = Kernel<fn(Buffer<K>, Buffer<u32>, u32, u32)>;
+type RadixScatterKernel<K, V> =
+    Kernel<fn(Buffer<K>, Buffer<K>, Buffer<V>, Buffer<V>, Buffer<u32>, u32, u32, bool)>;
+
+#[tracked]
+fn scan_chunks_kernel<T: Value>(device: &Device, op: impl BinaryOp<T>) -> ScanChunksKernel<T> {
+    device.create_kernel::<fn(Buffer<T>, Buffer<T>, Buffer<T>, u32)>(&|input, output, sums, n| {
+        let c = dispatch_id().x;
+        let start = c * CHUNK_SIZE;
+        let end = (start + CHUNK_SIZE).min_(n);
+        let acc = input.read(start).var();
+        output.write(start, acc);
+        for i in start + 1..end {
+            *acc = op(**acc, input.read(i));
+            output.write(i, acc);
+        }
+        sums.write(c, acc);
+    })
+}
+
+#[tracked]
+fn add_prefix_kernel<T: Value>(device: &Device, op: impl BinaryOp<T>) -> AddPrefixKernel<T> {
+    device.create_kernel::<fn(Buffer<T>, Buffer<T>, u32)>(&|output, prefix, n| {
+        // chunk 0 has no prefix
+        let c = dispatch_id().x + 1;
+        let p = prefix.read(c - 1);
+        let start = c * CHUNK_SIZE;
+        let end = (start + CHUNK_SIZE).min_(n);
+        for i in start..end {
+            output.write(i, op(p, output.read(i)));
+        }
+    })
+}
+
+#[tracked]
+fn shift_kernel<T: Value>(device: &Device, op: impl BinaryOp<T>) -> ShiftKernel<T> {
+    device.create_kernel::<fn(Buffer<T>, Buffer<T>, T)>(&|inclusive, output, init| {
+        let i = dispatch_id().x;
+        if i == 0 {
+            output.write(i, init);
+        } else {
+            output.write(i, op(init, inclusive.read(i - 1)));
+        }
+    })
+}
+
+#[tracked]
+fn reduce_chunks_kernel<T: Value>(device: &Device, op: impl BinaryOp<T>) -> ReduceChunksKernel<T> {
+    device.create_kernel::<fn(Buffer<T>, Buffer<T>, u32)>(&|input, output, n| {
+        let c = dispatch_id().x;
+        let start = c * CHUNK_SIZE;
+        let end = (start + CHUNK_SIZE).min_(n);
+        let acc = input.read(start).var();
+        for i in start + 1..end {
+            *acc = op(**acc, input.read(i));
+        }
+        output.write(c, acc);
+    })
+}
+
+#[tracked]
+fn segmented_reduce_kernel<T: Value>(
+    device: &Device,
+    op: impl BinaryOp<T>,
+) -> SegmentedReduceKernel<T> {
+    device.create_kernel::<fn(Buffer<T>, Buffer<u32>, Buffer<T>, T)>(
+        &|input, offsets, output, init| {
+            let s = dispatch_id().x;
+            let acc = init.var();
+            for i in offsets.read(s)..offsets.read(s + 1) {
+                *acc = op(**acc, input.read(i));
+            }
+            output.write(s, acc);
+        },
+    )
+}
+
+#[tracked]
+fn compact_flag_kernel<T: Value>(device: &Device, pred: impl Predicate<T>) -> CompactFlagKernel<T> {
+    device.create_kernel::<fn(Buffer<T>, Buffer<u32>)>(&|input, flags| {
+        let i = dispatch_id().x;
+        flags.write(i, select(pred(input.read(i)), 1u32.expr(), 0u32.expr()));
+    })
+}
+
+#[tracked]
+fn compact_scatter_kernel<T: Value>(device: &Device) -> CompactScatterKernel<T> {
+    device.create_kernel::<fn(Buffer<T>, Buffer<u32>, Buffer<u32>, Buffer<T>, Buffer<u32>)>(
+        &|input, flags, offsets, output, count| {
+            let i = dispatch_id().x;
+            let f = flags.read(i);
+            let o = offsets.read(i);
+            if f != 0 {
+                output.write(o, input.read(i));
+            }
+            if i == dispatch_size().x - 1 {
+                count.write(0, o + f);
+            }
+        },
+    )
+}
+
+#[tracked]
+fn radix_histogram_kernel<K: SortKey>(device: &Device) -> RadixHistogramKernel<K> {
+    device.create_kernel::<fn(Buffer<K>, Buffer<u32>, u32, u32)>(&|keys, hist, shift, n| {
+        let c = dispatch_id().x;
+        let chunks = dispatch_size().x;
+        let start = c * CHUNK_SIZE;
+        let end = (start + CHUNK_SIZE).min_(n);
+        let local = Var::<[u32; RADIX]>::zeroed();
+        for i in start..end {
+            let d = K::digit(keys.read(i), shift);
+            local.write(d, local.read(d) + 1);
+        }
+        // digit-major layout so that a single scan yields the global offsets
+        for d in 0..RADIX as u32 {
+            hist.write(d * chunks + c, local.read(d));
+        }
+    })
+}
+
+#[tracked]
+fn radix_scatter_kernel<K: SortKey, V: Value>(device: &Device) -> RadixScatterKernel<K, V> {
+    device.create_kernel::<fn(
+        Buffer<K>,
+        Buffer<K>,
+        Buffer<V>,
+        Buffer<V>,
+        Buffer<u32>,
+        u32,
+        u32,
+        bool,
+    )>(
+        &|src_keys, dst_keys, src_values, dst_values, offsets, shift, n, has_values| {
+            let c = dispatch_id().x;
+            let chunks = dispatch_size().x;
+            let start = c * CHUNK_SIZE;
+            let end = (start + CHUNK_SIZE).min_(n);
+            let local = Var::<[u32; RADIX]>::zeroed();
+            for d in 0..RADIX as u32 {
+                local.write(d, offsets.read(d * chunks + c));
+            }
+            for i in start..end {
+                let k = src_keys.read(i);
+                let d = K::digit(k, shift);
+                let p = local.read(d);
+                dst_keys.write(p, k);
+                if has_values {
+                    dst_values.write(p, src_values.read(i));
+                }
+                local.write(d, p + 1);
+            }
+        },
+    )
+}
+
+impl Algorithms {
+    pub fn new(device: &Device) -> Self {
+        Self {
+            device: device.internal(),
+            kernels: Mutex::new(HashMap::new()),
+        }
+    }
+    pub fn device(&self) -> &Device {
+        &self.device
+    }
+    fn kernel<S: KernelSignature + 'static>(
+        &self,
+        key: KernelKey,
+        create: impl FnOnce(&Device) -> Kernel<S>,
+    ) -> Arc<Kernel<S>> {
+        let mut kernels = self.kernels.lock();
+        let kernel = kernels
+            .entry(key)
+            .or_insert_with(|| Arc::new(create(&self.device)))
+            .clone();
+        kernel
+            .downcast::<Kernel<S>>()
+            .unwrap_or_else(|_| panic!("Kernel cache entry `{}` has a different signature", key.1))
+    }
+    /// The kernel `name` for elements of type `T` and operator `op`, only
+    /// cached if `op` captures nothing, see the [module documentation](self).
+    fn op_kernel<T: 'static, Op: 'static, S: KernelSignature + 'static>(
+        &self,
+        name: &'static str,
+        _op: &Op,
+        create: impl FnOnce(&Device) -> Kernel<S>,
+    ) -> Arc<Kernel<S>> {
+        if std::mem::size_of::<Op>() == 0 {
+            self.kernel((TypeId::of::<T>(), name, TypeId::of::<Op>()), create)
+        } else {
+            Arc::new(create(&self.device))
+        }
+    }
+
+    /// Computes `output[i] = input[0] op input[1] op ... op input[i]`.
+    /// `input` and `output` may alias.
+    pub fn inclusive_scan<T: Value>(
+        &self,
+        input: &BufferView<T>,
+        output: &BufferView<T>,
+        op: impl BinaryOp<T>,
+    ) -> Vec<Command<'static, 'static>> {
+        assert_eq!(input.len(), output.len());
+        let mut commands = vec![];
+        if input.len() == 0 {
+            return commands;
+        }
+        let mut rt = ResourceTracker::new();
+        self.inclusive_scan_impl(input, output, op, &mut commands, &mut rt);
+        hold_temporaries(&mut commands, rt);
+        commands
+    }
+    fn inclusive_scan_impl<T: Value>(
+        &self,
+        input: &BufferView<T>,
+        output: &BufferView<T>,
+        op: impl BinaryOp<T>,
+        commands: &mut Vec<Command<'static, 'static>>,
+        rt: &mut ResourceTracker,
+    ) {
+        let n = input.len() as u32;
+        let chunks = num_chunks(input.len());
+        let scan_chunks =
+            self.op_kernel::<T, _, _>("scan_chunks", &op, |device| scan_chunks_kernel(device, op));
+        let sums = self.device.create_buffer::<T>(chunks as usize);
+        commands.push(scan_chunks.dispatch_async([chunks, 1, 1], input, output, &sums, &n));
+        if chunks > 1 {
+            let add_prefix = self
+                .op_kernel::<T, _, _>("add_prefix", &op, |device| add_prefix_kernel(device, op));
+            let prefix = self.device.create_buffer::<T>(chunks as usize);
+            self.inclusive_scan_impl(&sums.view(..), &prefix.view(..), op, commands, rt);
+            commands.push(add_prefix.dispatch_async([chunks - 1, 1, 1], output, &prefix, &n));
+            rt.add(Arc::new(prefix));
+        }
+        rt.add(Arc::new(sums));
+    }
+
+    /// Computes `output[0] = init` and `output[i] = init op input[0] op ... op input[i - 1]`.
+    /// `input` and `output` may alias.
+    pub fn exclusive_scan<T: Value>(
+        &self,
+        input: &BufferView<T>,
+        output: &BufferView<T>,
+        init: T,
+        op: impl BinaryOp<T>,
+    ) -> Vec<Command<'static, 'static>> {
+        assert_eq!(input.len(), output.len());
+        let mut commands = vec![];
+        if input.len() == 0 {
+            return commands;
+        }
+        let n = input.len() as u32;
+        let mut rt = ResourceTracker::new();
+        let inclusive = self.device.create_buffer::<T>(input.len());
+        self.inclusive_scan_impl(input, &inclusive.view(..), op, &mut commands, &mut rt);
+        let shift = self.op_kernel::<T, _, _>("shift", &op, |device| shift_kernel(device, op));
+        commands.push(shift.dispatch_async([n, 1, 1], &inclusive, output, &init));
+        rt.add(Arc::new(inclusive));
+        hold_temporaries(&mut commands, rt);
+        commands
+    }
+
+    /// Reduces `input` into `output[0]`. `input` must not be empty.
+    pub fn reduce<T: Value>(
+        &self,
+        input: &BufferView<T>,
+        output: &BufferView<T>,
+        op: impl BinaryOp<T>,
+    ) -> Vec<Command<'static, 'static>> {
+        assert!(input.len() > 0, "Cannot reduce an empty buffer");
+        assert_eq!(output.len(), 1);
+        let reduce_chunks = self.op_kernel::<T, _, _>("reduce_chunks", &op, |device| {
+            reduce_chunks_kernel(device, op)
+        });
+        let mut commands = vec![];
+        let mut rt = ResourceTracker::new();
+        let mut current = input.view(..);
+        loop {
+            let n = current.len() as u32;
+            let chunks = num_chunks(current.len());
+            if chunks == 1 {
+                commands.push(reduce_chunks.dispatch_async([1, 1, 1], &current, output, &n));
+                break;
+            }
+            let partial = Arc::new(self.device.create_buffer::<T>(chunks as usize));
+            commands.push(reduce_chunks.dispatch_async([chunks, 1, 1], &current, &*partial, &n));
+            current = partial.view(..);
+            rt.add(partial);
+        }
+        hold_temporaries(&mut commands, rt);
+        commands
+    }
+
+    /// Reduces each segment `input[offsets[s]..offsets[s + 1]]` into `output[s]`.
+    /// Empty segments are set to `init`.
+    pub fn segmented_reduce<T: Value>(
+        &self,
+        input: &BufferView<T>,
+        offsets: &BufferView<u32>,
+        output: &BufferView<T>,
+        init: T,
+        op: impl BinaryOp<T>,
+    ) -> Vec<Command<'static, 'static>> {
+        assert_eq!(
+            offsets.len(),
+            output.len() + 1,
+            "offsets must have one more element than output"
+        );
+        if output.len() == 0 {
+            return vec![];
+        }
+        let segmented_reduce = self.op_kernel::<T, _, _>("segmented_reduce", &op, |device| {
+            segmented_reduce_kernel(device, op)
+        });
+        vec![segmented_reduce.dispatch_async(
+            [output.len() as u32, 1, 1],
+            input,
+            offsets,
+            output,
+            &init,
+        )]
+    }
+
+    /// Copies the elements of `input` that satisfy `pred` to the front of `output`,
+    /// preserving their order. The number of selected elements is written to `count[0]`.
+    pub fn compact<T: Value>(
+        &self,
+        input: &BufferView<T>,
+        output: &BufferView<T>,
+        count: &BufferView<u32>,
+        pred: impl Predicate<T>,
+    ) -> Vec<Command<'static, 'static>> {
+        assert!(output.len() >= input.len());
+        assert_eq!(count.len(), 1);
+        if input.len() == 0 {
+            let zero = Arc::new(self.device.create_buffer_from_slice(&[0u32]));
+            let mut command = zero.copy_to_buffer_async(count);
+            command.resource_tracker.add(zero);
+            return vec![command];
+        }
+        let n = input.len() as u32;
+        let flag = self.op_kernel::<T, _, _>("compact_flag", &pred, |device| {
+            compact_flag_kernel(device, pred)
+        });
+        let scatter = self.kernel(
+            (TypeId::of::<T>(), "compact_scatter", TypeId::of::<()>()),
+            |device| compact_scatter_kernel(device),
+        );
+        let flags = self.device.create_buffer::<u32>(input.len());
+        let offsets = self.device.create_buffer::<u32>(input.len());
+        let mut commands = vec![flag.dispatch_async([n, 1, 1], input, &flags)];
+        commands.extend(self.exclusive_scan(&flags.view(..), &offsets.view(..), 0, add_u32));
+        commands.push(scatter.dispatch_async([n, 1, 1], input, &flags, &offsets, output, count));
+        let mut rt = ResourceTracker::new();
+        rt.add(Arc::new(flags));
+        rt.add(Arc::new(offsets));
+        hold_temporaries(&mut commands, rt);
+        commands
+    }
+
+    /// Sorts `keys` in ascending order. The sort is stable.
+    pub fn sort_keys<K: SortKey>(&self, keys: &BufferView<K>) -> Vec<Command<'static, 'static>> {
+        self.radix_sort::<K, u32>(keys, None)
+    }
+
+    /// Sorts `keys` in ascending order and applies the same permutation to `values`.
+    /// The sort is stable.
+    pub fn sort_pairs<K: SortKey, V: Value>(
+        &self,
+        keys: &BufferView<K>,
+        values: &BufferView<V>,
+    ) -> Vec<Command<'static, 'static>> {
+        assert_eq!(keys.len(), values.len());
+        self.radix_sort(keys, Some(values))
+    }
+
+    fn radix_sort<K: SortKey, V: Value>(
+        &self,
+        keys: &BufferView<K>,
+        values: Option<&BufferView<V>>,
+    ) -> Vec<Command<'static, 'static>> {
+        let mut commands = vec![];
+        if keys.len() <= 1 {
+            return commands;
+        }
+        let n = keys.len() as u32;
+        let chunks = num_chunks(keys.len());
+        let histogram = self.kernel(
+            (TypeId::of::<K>(), "radix_histogram", TypeId::of::<()>()),
+            |device| radix_histogram_kernel(device),
+        );
+        let scatter = self.kernel(
+            (TypeId::of::<(K, V)>(), "radix_scatter", TypeId::of::<()>()),
+            |device| radix_scatter_kernel(device),
+        );
+        let hist = self.device.create_buffer::<u32>(RADIX * chunks as usize);
+        let hist_offsets = self.device.create_buffer::<u32>(RADIX * chunks as usize);
+        let tmp_keys = self.device.create_buffer::<K>(keys.len());
+        // the value buffers are never touched when `values` is None,
+        // but the kernel signature still needs something bound
+        let tmp_values = self
+            .device
+            .create_buffer::<V>(values.map(|v| v.len()).unwrap_or(1));
+        let has_values = values.is_some();
+        let values = values
+            .map(|v| v.view(..))
+            .unwrap_or_else(|| tmp_values.view(..));
+        let key_bufs = [keys.view(..), tmp_keys.view(..)];
+        let value_bufs = [values, tmp_values.view(..)];
+        // an even number of passes, so the result ends up back in `keys`
+        for pass in 0..(K::BITS / RADIX_BITS) {
+            let shift = pass * RADIX_BITS;
+            let src = (pass % 2) as usize;
+            let dst = 1 - src;
+            commands.push(histogram.dispatch_async(
+                [chunks, 1, 1],
+                &key_bufs[src],
+                &hist,
+                &shift,
+                &n,
+            ));
+            commands.extend(self.exclusive_scan(
+                &hist.view(..),
+                &hist_offsets.view(..),
+                0,
+                add_u32,
+            ));
+            commands.push(scatter.dispatch_async(
+                [chunks, 1, 1],
+                &key_bufs[src],
+                &key_bufs[dst],
+                &value_bufs[src],
+                &value_bufs[dst],
+                &hist_offsets,
+                &shift,
+                &n,
+                &has_values,
+            ));
+        }
+        let mut rt = ResourceTracker::new();
+        rt.add(Arc::new(hist));
+        rt.add(Arc::new(hist_offsets));
+        rt.add(Arc::new(tmp_keys));
+        rt.add(Arc::new(tmp_values));
+        hold_temporaries(&mut commands, rt);
+        commands
+    }
+}
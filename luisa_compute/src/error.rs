@@ -0,0 +1,70 @@
+use std::fmt::{Display, Formatter};
+
+use luisa_compute_api_types as api;
+
+/// Errors returned by the fallible `try_*` APIs of [`Context`](crate::Context)
+/// and [`Device`](crate::runtime::Device).
+///
+/// The corresponding non-`try_` methods panic with the formatted error instead.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// The backend failed to allocate a resource.
+    OutOfMemory {
+        resource: &'static str,
+        requested_bytes: Option<usize>,
+    },
+    /// The arguments are rejected before reaching the backend.
+    InvalidArgument(String),
+    /// The backend or the requested feature is not available.
+    Unsupported(String),
+    /// The backend failed to compile a kernel.
+    CompileFailure { name: String },
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::OutOfMemory {
+                resource,
+                requested_bytes: Some(bytes),
+            } => write!(f, "out of memory while creating {} ({} bytes)", resource, bytes),
+            Error::OutOfMemory {
+                resource,
+                requested_bytes: None,
+            } => write!(f, "out of memory while creating {}", resource),
+            Error::InvalidArgument(msg) => write!(f, "invalid argument: {}", msg),
+            Error::Unsupported(msg) => write!(f, "unsupported: {}", msg),
+            Error::CompileFailure { name } if name.is_empty() => {
+                write!(f, "failed to compile kernel")
+            }
+            Error::CompileFailure { name } => write!(f, "failed to compile kernel `{}`", name),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Backends report creation failures with the handle of
+/// `ResourceCreationInfo::make_invalid()`, i.e. [`api::INVALID_RESOURCE_HANDLE`].
+#[inline]
+pub(crate) fn is_valid_handle(handle: u64) -> bool {
+    handle != api::INVALID_RESOURCE_HANDLE
+}
+
+/// Buffers and textures always wrap device memory, so a null native handle
+/// is a failure as well even if the backend forgot to invalidate the handle.
+#[inline]
+pub(crate) fn is_valid_memory(info: &api::CreatedResourceInfo) -> bool {
+    is_valid_handle(info.handle) && !info.native_handle.is_null()
+}
+
+macro_rules! ensure_arg {
+    ($cond:expr, $($arg:tt)*) => {
+        if !$cond {
+            return Err($crate::Error::InvalidArgument(format!($($arg)*)));
+        }
+    };
+}
+pub(crate) use ensure_arg;
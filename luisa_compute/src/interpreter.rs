@@ -0,0 +1,611 @@
+//! A device that interprets the IR of kernels on the host.
+//!
+//! The interpreter is slow but runs no code of the native backends. It serves
+//! as a reference implementation for differential testing of the compiled
+//! backends, see [`DeviceType::Interpreter`](crate::DeviceType::Interpreter).
+//! The [`Context`](crate::Context) it is created from still loads the native
+//! runtime libraries.
+//!
+//! Commands are executed synchronously when they are dispatched, streams and
+//! events therefore only keep track of handles. Ray tracing, autodiff, external
+//! functions and swapchains are not supported and panic when used.
+use std::alloc::Layout;
+use std::collections::HashMap;
+use std::ffi::c_void;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use parking_lot::Mutex;
+
+use crate::internal_prelude::*;
+use crate::resource::Sampler;
+use api::{
+    CreatedBufferInfo, CreatedResourceInfo, CreatedShaderInfo, CreatedSwapchainInfo,
+    INVALID_RESOURCE_HANDLE,
+};
+use ir::{Binding, Capture, KernelModule};
+use luisa_compute_api_types as api;
+use luisa_compute_ir::context::type_hash;
+
+use self::eval::{DispatchContext, Shader, Val};
+use self::texture::HostTexture;
+
+mod eval;
+mod texture;
+mod value;
+
+/// Host memory backing a buffer or a texture level.
+pub(crate) struct Allocation {
+    pub(crate) ptr: *mut u8,
+    pub(crate) size: usize,
+    owned: bool,
+}
+
+unsafe impl Send for Allocation {}
+unsafe impl Sync for Allocation {}
+
+impl Allocation {
+    const ALIGN: usize = 16;
+    /// Allocates zeroed memory, returns `None` instead of aborting when the
+    /// allocator fails so that resource creation can report
+    /// [`Error::OutOfMemory`](crate::Error::OutOfMemory).
+    pub(crate) fn try_new(size: usize) -> Option<Self> {
+        let ptr = if size == 0 {
+            std::ptr::NonNull::<u128>::dangling().as_ptr() as *mut u8
+        } else {
+            let layout = Layout::from_size_align(size, Self::ALIGN).ok()?;
+            let ptr = unsafe { std::alloc::alloc_zeroed(layout) };
+            if ptr.is_null() {
+                return None;
+            }
+            ptr
+        };
+        Some(Self {
+            ptr,
+            size,
+            owned: true,
+        })
+    }
+    /// Wraps memory provided by the user, see [`Device::import_external_buffer`].
+    pub(crate) fn external(ptr: *mut u8, size: usize) -> Self {
+        Self {
+            ptr,
+            size,
+            owned: false,
+        }
+    }
+}
+
+const INVALID_RESOURCE: CreatedResourceInfo = CreatedResourceInfo {
+    handle: INVALID_RESOURCE_HANDLE,
+    native_handle: std::ptr::null_mut(),
+};
+
+fn invalid_buffer(element_stride: usize) -> CreatedBufferInfo {
+    CreatedBufferInfo {
+        resource: INVALID_RESOURCE,
+        element_stride,
+        total_size_bytes: 0,
+    }
+}
+
+impl Drop for Allocation {
+    fn drop(&mut self) {
+        if self.owned && self.size != 0 {
+            let layout = Layout::from_size_align(self.size, Self::ALIGN).unwrap();
+            unsafe { std::alloc::dealloc(self.ptr, layout) };
+        }
+    }
+}
+
+/// A range of a buffer bound to a kernel, offsets are in bytes.
+#[derive(Clone)]
+pub(crate) struct BufferView {
+    pub(crate) alloc: Arc<Allocation>,
+    pub(crate) offset: usize,
+    pub(crate) size: usize,
+}
+
+impl BufferView {
+    pub(crate) fn new(alloc: Arc<Allocation>, offset: usize, size: Option<usize>) -> Self {
+        assert!(
+            offset <= alloc.size,
+            "buffer offset {} out of range, buffer has {} bytes",
+            offset,
+            alloc.size
+        );
+        let size = size.unwrap_or(alloc.size - offset);
+        assert!(
+            offset + size <= alloc.size,
+            "buffer range {}..{} out of range, buffer has {} bytes",
+            offset,
+            offset + size,
+            alloc.size
+        );
+        Self {
+            alloc,
+            offset,
+            size,
+        }
+    }
+    /// Address of `len` bytes at `offset`, panics on out of bounds accesses.
+    pub(crate) fn bytes(&self, offset: usize, len: usize) -> *mut u8 {
+        assert!(
+            offset
+                .checked_add(len)
+                .map_or(false, |end| end <= self.size),
+            "buffer access out of bounds: {} bytes at offset {}, buffer view has {} bytes",
+            len,
+            offset,
+            self.size
+        );
+        unsafe { self.alloc.ptr.add(self.offset + offset) }
+    }
+    pub(crate) fn element(&self, index: usize, stride: usize) -> *mut u8 {
+        let len = if stride == 0 { 1 } else { self.size / stride };
+        assert!(
+            index < len,
+            "buffer index {} out of bounds, buffer view has {} elements",
+            index,
+            len
+        );
+        self.bytes(index * stride, stride)
+    }
+}
+
+#[derive(Clone, Default)]
+pub(crate) struct BindlessSlot {
+    /// Buffer and the byte offset of the slot into it.
+    pub(crate) buffer: Option<(Arc<Allocation>, usize)>,
+    /// `type_hash` of the elements of the buffer, 0 without one
+    pub(crate) buffer_type: u64,
+    pub(crate) tex2d: Option<(Arc<HostTexture>, Sampler)>,
+    pub(crate) tex3d: Option<(Arc<HostTexture>, Sampler)>,
+}
+
+struct HostBuffer {
+    memory: Arc<Allocation>,
+    element_stride: usize,
+    type_hash: u64,
+}
+
+#[derive(Default)]
+struct Resources {
+    buffers: HashMap<u64, HostBuffer>,
+    textures: HashMap<u64, Arc<HostTexture>>,
+    // slots are copied on write so that running dispatches keep their view
+    bindless_arrays: HashMap<u64, Arc<Vec<BindlessSlot>>>,
+    shaders: HashMap<u64, Arc<Shader>>,
+    // last signaled value of each event
+    events: HashMap<u64, u64>,
+}
+
+impl Resources {
+    fn buffer(&self, handle: u64) -> &HostBuffer {
+        self.buffers
+            .get(&handle)
+            .unwrap_or_else(|| panic!("invalid buffer handle {}", handle))
+    }
+    fn texture(&self, handle: u64) -> Arc<HostTexture> {
+        self.textures
+            .get(&handle)
+            .cloned()
+            .unwrap_or_else(|| panic!("invalid texture handle {}", handle))
+    }
+    fn bindless_array(&self, handle: u64) -> Arc<Vec<BindlessSlot>> {
+        self.bindless_arrays
+            .get(&handle)
+            .cloned()
+            .unwrap_or_else(|| panic!("invalid bindless array handle {}", handle))
+    }
+    fn buffer_view(&self, handle: u64, offset: usize, size: usize) -> Val {
+        Val::Buffer(BufferView::new(
+            self.buffer(handle).memory.clone(),
+            offset,
+            Some(size),
+        ))
+    }
+    fn bind(&self, capture: &Capture) -> Val {
+        match &capture.binding {
+            Binding::Buffer(b) => self.buffer_view(b.handle, b.offset as usize, b.size),
+            Binding::Texture(t) => Val::Texture(self.texture(t.handle), t.level),
+            Binding::BindlessArray(a) => Val::Bindless(self.bindless_array(a.handle)),
+            Binding::Accel(_) => Val::Accel,
+            #[allow(unreachable_patterns)]
+            _ => panic!("unsupported capture binding"),
+        }
+    }
+}
+
+/// The [`Backend`] of interpreter devices.
+pub(crate) struct Interpreter {
+    next_handle: AtomicU64,
+    resources: Mutex<Resources>,
+}
+
+impl Interpreter {
+    pub(crate) fn new() -> Self {
+        Self {
+            next_handle: AtomicU64::new(1),
+            resources: Mutex::new(Resources::default()),
+        }
+    }
+    fn handle(&self) -> u64 {
+        self.next_handle.fetch_add(1, Ordering::Relaxed)
+    }
+    fn resource(&self, native_handle: *mut c_void) -> CreatedResourceInfo {
+        CreatedResourceInfo {
+            handle: self.handle(),
+            native_handle,
+        }
+    }
+
+    fn execute(&self, command: &api::Command) {
+        match command {
+            api::Command::BufferUpload(c) => {
+                let view = self.buffer_range(c.buffer.0, c.offset, c.size);
+                unsafe { std::ptr::copy_nonoverlapping(c.data, view, c.size) };
+            }
+            api::Command::BufferDownload(c) => {
+                let view = self.buffer_range(c.buffer.0, c.offset, c.size);
+                unsafe { std::ptr::copy_nonoverlapping(view, c.data, c.size) };
+            }
+            api::Command::BufferCopy(c) => {
+                let src = self.buffer_range(c.src.0, c.src_offset, c.size);
+                let dst = self.buffer_range(c.dst.0, c.dst_offset, c.size);
+                unsafe { std::ptr::copy(src, dst, c.size) };
+            }
+            api::Command::BufferToTextureCopy(c) => {
+                let (texture, stride) = {
+                    let resources = self.resources.lock();
+                    (
+                        resources.texture(c.texture.0),
+                        resources.buffer(c.buffer.0).element_stride,
+                    )
+                };
+                let (level, size) = self.texture_level(&texture, c.texture_level, c.texture_size);
+                let src = self.buffer_range(c.buffer.0, c.buffer_offset * stride, size);
+                unsafe { std::ptr::copy_nonoverlapping(src, level, size) };
+            }
+            api::Command::TextureToBufferCopy(c) => {
+                let (texture, stride) = {
+                    let resources = self.resources.lock();
+                    (
+                        resources.texture(c.texture.0),
+                        resources.buffer(c.buffer.0).element_stride,
+                    )
+                };
+                let (level, size) = self.texture_level(&texture, c.texture_level, c.texture_size);
+                let dst = self.buffer_range(c.buffer.0, c.buffer_offset * stride, size);
+                unsafe { std::ptr::copy_nonoverlapping(level, dst, size) };
+            }
+            api::Command::TextureUpload(c) => {
+                let texture = self.resources.lock().texture(c.texture.0);
+                let (level, size) = self.texture_level(&texture, c.level, c.size);
+                unsafe { std::ptr::copy_nonoverlapping(c.data, level, size) };
+            }
+            api::Command::TextureDownload(c) => {
+                let texture = self.resources.lock().texture(c.texture.0);
+                let (level, size) = self.texture_level(&texture, c.level, c.size);
+                unsafe { std::ptr::copy_nonoverlapping(level, c.data, size) };
+            }
+            api::Command::TextureCopy(c) => {
+                let (src, dst) = {
+                    let resources = self.resources.lock();
+                    (resources.texture(c.src.0), resources.texture(c.dst.0))
+                };
+                let (src, size) = self.texture_level(&src, c.src_level, c.size);
+                let (dst, dst_size) = self.texture_level(&dst, c.dst_level, c.size);
+                assert_eq!(
+                    size, dst_size,
+                    "texture copy between different pixel formats"
+                );
+                unsafe { std::ptr::copy(src, dst, size) };
+            }
+            api::Command::BindlessArrayUpdate(c) => self.update_bindless_array(c),
+            api::Command::ShaderDispatch(c) => self.dispatch_shader(c),
+            api::Command::MeshBuild(_)
+            | api::Command::ProceduralPrimitiveBuild(_)
+            | api::Command::AccelBuild(_) => {
+                panic!("ray tracing is not supported by the interpreter")
+            }
+            #[allow(unreachable_patterns)]
+            _ => panic!("command is not supported by the interpreter"),
+        }
+    }
+
+    fn buffer_range(&self, handle: u64, offset: usize, size: usize) -> *mut u8 {
+        let memory = self.resources.lock().buffer(handle).memory.clone();
+        BufferView::new(memory, offset, Some(size)).bytes(0, size)
+    }
+
+    /// Pointer to a texture level and the size of `size` texels of it in bytes.
+    fn texture_level(&self, texture: &HostTexture, level: u32, size: [u32; 3]) -> (*mut u8, usize) {
+        let memory = texture
+            .levels
+            .get(level as usize)
+            .unwrap_or_else(|| panic!("mipmap level {} out of range", level));
+        let bytes = size.iter().map(|x| *x as usize).product::<usize>() * texture.pixel_size();
+        assert!(
+            bytes <= memory.size,
+            "texture region of {:?} exceeds mipmap level {} of size {:?}",
+            size,
+            level,
+            texture.level_size(level)
+        );
+        (memory.ptr, bytes)
+    }
+
+    fn update_bindless_array(&self, c: &api::BindlessArrayUpdateCommand) {
+        let modifications =
+            unsafe { std::slice::from_raw_parts(c.modifications, c.modifications_count) };
+        let mut resources = self.resources.lock();
+        let mut slots = resources.bindless_array(c.handle.0).as_ref().clone();
+        for m in modifications {
+            let slot = slots
+                .get_mut(m.slot)
+                .unwrap_or_else(|| panic!("bindless slot {} out of range", m.slot));
+            match m.buffer.op {
+                api::BindlessArrayUpdateOperation::Emplace => {
+                    let buffer = resources.buffer(m.buffer.handle);
+                    // the frontend passes offsets of buffer views in elements
+                    let offset = m.buffer.offset * buffer.element_stride;
+                    slot.buffer = Some((buffer.memory.clone(), offset));
+                    slot.buffer_type = buffer.type_hash;
+                }
+                api::BindlessArrayUpdateOperation::Remove => {
+                    slot.buffer = None;
+                    slot.buffer_type = 0;
+                }
+                _ => {}
+            }
+            match m.tex2d.op {
+                api::BindlessArrayUpdateOperation::Emplace => {
+                    slot.tex2d = Some((resources.texture(m.tex2d.handle), m.tex2d.sampler));
+                }
+                api::BindlessArrayUpdateOperation::Remove => slot.tex2d = None,
+                _ => {}
+            }
+            match m.tex3d.op {
+                api::BindlessArrayUpdateOperation::Emplace => {
+                    slot.tex3d = Some((resources.texture(m.tex3d.handle), m.tex3d.sampler));
+                }
+                api::BindlessArrayUpdateOperation::Remove => slot.tex3d = None,
+                _ => {}
+            }
+        }
+        resources
+            .bindless_arrays
+            .insert(c.handle.0, Arc::new(slots));
+    }
+
+    fn dispatch_shader(&self, c: &api::ShaderDispatchCommand) {
+        let args = unsafe { std::slice::from_raw_parts(c.args, c.args_count) };
+        let ctx = {
+            let resources = self.resources.lock();
+            let shader = resources
+                .shaders
+                .get(&c.shader.0)
+                .cloned()
+                .unwrap_or_else(|| panic!("invalid shader handle {}", c.shader.0));
+            let module = &shader.module;
+            assert_eq!(
+                args.len(),
+                module.args.len(),
+                "kernel expects {} arguments",
+                module.args.len()
+            );
+            let mut globals = HashMap::new();
+            for (node, arg) in module.args.as_ref().iter().zip(args) {
+                let val = match arg {
+                    api::Argument::Buffer(b) => resources.buffer_view(b.buffer.0, b.offset, b.size),
+                    api::Argument::Texture(t) => {
+                        Val::Texture(resources.texture(t.texture.0), t.level)
+                    }
+                    api::Argument::Uniform(u) => {
+                        Val::Data(unsafe { std::slice::from_raw_parts(u.data, u.size).to_vec() })
+                    }
+                    api::Argument::BindlessArray(a) => Val::Bindless(resources.bindless_array(a.0)),
+                    api::Argument::Accel(_) => Val::Accel,
+                };
+                globals.insert(*node, val);
+            }
+            let captures = module.captures.as_ref().iter().chain(
+                shader
+                    .callables
+                    .iter()
+                    .flat_map(|c| c.0.captures.as_ref().iter()),
+            );
+            for capture in captures {
+                globals.insert(capture.node, resources.bind(capture));
+            }
+            DispatchContext {
+                shader,
+                globals,
+                dispatch_size: c.dispatch_size,
+            }
+        };
+        ctx.run();
+    }
+}
+
+impl Backend for Interpreter {
+    fn native_handle(&self) -> *mut c_void {
+        self as *const Self as *mut c_void
+    }
+    fn compute_warp_size(&self) -> u32 {
+        1
+    }
+    fn create_buffer(
+        &self,
+        ty: &CArc<Type>,
+        count: usize,
+        ext_mem: *mut c_void,
+    ) -> CreatedBufferInfo {
+        let element_stride = ty.size();
+        let Some(total_size_bytes) = element_stride.checked_mul(count) else {
+            return invalid_buffer(element_stride);
+        };
+        let memory = if ext_mem.is_null() {
+            match Allocation::try_new(total_size_bytes) {
+                Some(memory) => memory,
+                None => return invalid_buffer(element_stride),
+            }
+        } else {
+            Allocation::external(ext_mem as *mut u8, total_size_bytes)
+        };
+        let resource = self.resource(memory.ptr as *mut c_void);
+        self.resources.lock().buffers.insert(
+            resource.handle,
+            HostBuffer {
+                memory: Arc::new(memory),
+                element_stride,
+                type_hash: type_hash(ty),
+            },
+        );
+        CreatedBufferInfo {
+            resource,
+            element_stride,
+            total_size_bytes,
+        }
+    }
+    fn destroy_buffer(&self, buffer: api::Buffer) {
+        self.resources.lock().buffers.remove(&buffer.0);
+    }
+    fn create_texture(
+        &self,
+        format: api::PixelFormat,
+        dimension: u32,
+        width: u32,
+        height: u32,
+        depth: u32,
+        mipmap_levels: u32,
+        _allow_simultaneous_access: bool,
+        _allow_raster_target: bool,
+    ) -> CreatedResourceInfo {
+        let depth = if dimension == 2 { 1 } else { depth };
+        let Some(texture) =
+            HostTexture::try_new(format, [width, height, depth], mipmap_levels.max(1))
+        else {
+            return INVALID_RESOURCE;
+        };
+        let resource = self.resource(texture.levels[0].ptr as *mut c_void);
+        self.resources
+            .lock()
+            .textures
+            .insert(resource.handle, Arc::new(texture));
+        resource
+    }
+    fn destroy_texture(&self, texture: api::Texture) {
+        self.resources.lock().textures.remove(&texture.0);
+    }
+    fn create_bindless_array(&self, size: usize) -> CreatedResourceInfo {
+        let resource = self.resource(std::ptr::null_mut());
+        self.resources.lock().bindless_arrays.insert(
+            resource.handle,
+            Arc::new(vec![BindlessSlot::default(); size]),
+        );
+        resource
+    }
+    fn destroy_bindless_array(&self, array: api::BindlessArray) {
+        self.resources.lock().bindless_arrays.remove(&array.0);
+    }
+    fn create_stream(&self, _tag: api::StreamTag) -> CreatedResourceInfo {
+        self.resource(std::ptr::null_mut())
+    }
+    fn destroy_stream(&self, _stream: api::Stream) {}
+    fn synchronize_stream(&self, _stream: api::Stream) {}
+    fn dispatch(
+        &self,
+        _stream: api::Stream,
+        command_list: &[api::Command],
+        callback: (extern "C" fn(*mut u8), *mut u8),
+    ) {
+        for command in command_list {
+            self.execute(command);
+        }
+        (callback.0)(callback.1);
+    }
+    fn create_swapchain(
+        &self,
+        _option: &api::SwapchainOption,
+        _stream: api::Stream,
+    ) -> CreatedSwapchainInfo {
+        panic!("swapchains are not supported by the interpreter")
+    }
+    fn destroy_swapchain(&self, _swapchain: api::Swapchain) {}
+    fn present_display_in_stream(
+        &self,
+        _stream: api::Stream,
+        _swapchain: api::Swapchain,
+        _image: api::Texture,
+    ) {
+        panic!("swapchains are not supported by the interpreter")
+    }
+    fn create_shader(
+        &self,
+        kernel: &CArc<KernelModule>,
+        _option: &api::ShaderOption,
+    ) -> CreatedShaderInfo {
+        let shader = Shader::new(kernel.clone());
+        let block_size = kernel.block_size;
+        let resource = self.resource(std::ptr::null_mut());
+        self.resources
+            .lock()
+            .shaders
+            .insert(resource.handle, Arc::new(shader));
+        CreatedShaderInfo {
+            resource,
+            block_size,
+        }
+    }
+    fn shader_cache_dir(&self, _shader: api::Shader) -> Option<PathBuf> {
+        None
+    }
+    fn destroy_shader(&self, shader: api::Shader) {
+        self.resources.lock().shaders.remove(&shader.0);
+    }
+    fn create_event(&self) -> CreatedResourceInfo {
+        let resource = self.resource(std::ptr::null_mut());
+        self.resources.lock().events.insert(resource.handle, 0);
+        resource
+    }
+    fn destroy_event(&self, event: api::Event) {
+        self.resources.lock().events.remove(&event.0);
+    }
+    fn signal_event(&self, event: api::Event, _stream: api::Stream, value: u64) {
+        let mut resources = self.resources.lock();
+        let signaled = resources.events.entry(event.0).or_default();
+        *signaled = (*signaled).max(value);
+    }
+    fn wait_event(&self, _event: api::Event, _stream: api::Stream, _value: u64) {}
+    fn synchronize_event(&self, _event: api::Event, _value: u64) {}
+    fn is_event_completed(&self, event: api::Event, value: u64) -> bool {
+        self.resources
+            .lock()
+            .events
+            .get(&event.0)
+            .map_or(false, |signaled| *signaled >= value)
+    }
+    fn create_mesh(&self, _option: api::AccelOption) -> CreatedResourceInfo {
+        INVALID_RESOURCE
+    }
+    fn create_procedural_primitive(&self, _option: api::AccelOption) -> CreatedResourceInfo {
+        INVALID_RESOURCE
+    }
+    fn destroy_mesh(&self, _mesh: api::Mesh) {}
+    fn destroy_procedural_primitive(&self, _primitive: api::ProceduralPrimitive) {}
+    fn create_accel(&self, _option: api::AccelOption) -> CreatedResourceInfo {
+        INVALID_RESOURCE
+    }
+    fn destroy_accel(&self, _accel: api::Accel) {}
+    fn query(&self, property: &str) -> Option<String> {
+        match property {
+            "device_name" => Some("interpreter".to_string()),
+            _ => None,
+        }
+    }
+    fn denoiser_ext(&self) -> api::DenoiserExt {
+        unreachable!("interpreter devices have no denoiser, see DeviceExtensions::denoiser_ext")
+    }
+}
@@ -0,0 +1,1417 @@
+//! Execution of a kernel dispatch.
+//!
+//! Every thread walks the IR of the kernel on its own. Blocks run in parallel
+//! and the threads of a block run one after another, except for kernels that
+//! synchronize blocks: their blocks run one at a time on a pool that has one
+//! host thread per thread of a block so that barriers work. The pool is created
+//! on the first dispatch and kept by the shader. When a thread of such a block
+//! panics, the others are released from the barrier and the panic is raised on
+//! the dispatching thread.
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::panic::{catch_unwind, resume_unwind, AssertUnwindSafe};
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::OnceLock;
+
+use parking_lot::{Condvar, Mutex};
+use rayon::prelude::*;
+
+use super::texture::HostTexture;
+use super::value::{self, primitive_size, Scalar};
+use super::{Allocation, BindlessSlot, BufferView};
+use crate::internal_prelude::*;
+use ir::{BasicBlock, CallableModuleRef, KernelModule, Primitive};
+
+#[derive(Clone)]
+pub(super) enum Val {
+    Void,
+    Data(Vec<u8>),
+    /// Address of a local variable, a shared array or an element of either.
+    Ptr(*mut u8),
+    Buffer(BufferView),
+    Texture(Arc<HostTexture>, u32),
+    Bindless(Arc<Vec<BindlessSlot>>),
+    Accel,
+}
+
+// pointers only refer to memory owned by the dispatch or by the thread using them
+unsafe impl Send for Val {}
+unsafe impl Sync for Val {}
+
+pub(super) struct Shader {
+    pub(super) module: CArc<KernelModule>,
+    /// Callables reachable from the kernel, their captures are bound at dispatch.
+    pub(super) callables: Vec<CallableModuleRef>,
+    block_sync: bool,
+    block_pool: OnceLock<rayon::ThreadPool>,
+}
+
+unsafe impl Send for Shader {}
+unsafe impl Sync for Shader {}
+
+fn visit_block(block: &Pooled<BasicBlock>, f: &mut impl FnMut(NodeRef)) {
+    for node in block.iter() {
+        f(node);
+        match node.get().instruction.as_ref() {
+            Instruction::Loop { body, .. }
+            | Instruction::AdDetach(body)
+            | Instruction::AdScope { body, .. } => visit_block(body, f),
+            Instruction::GenericLoop {
+                prepare,
+                body,
+                update,
+                ..
+            } => {
+                visit_block(prepare, f);
+                visit_block(body, f);
+                visit_block(update, f);
+            }
+            Instruction::If {
+                true_branch,
+                false_branch,
+                ..
+            } => {
+                visit_block(true_branch, f);
+                visit_block(false_branch, f);
+            }
+            Instruction::Switch { default, cases, .. } => {
+                visit_block(default, f);
+                for case in cases.as_ref() {
+                    visit_block(&case.block, f);
+                }
+            }
+            Instruction::RayQuery {
+                on_triangle_hit,
+                on_procedural_hit,
+                ..
+            } => {
+                visit_block(on_triangle_hit, f);
+                visit_block(on_procedural_hit, f);
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Shader {
+    pub(super) fn new(module: CArc<KernelModule>) -> Self {
+        let mut callables: Vec<CallableModuleRef> = vec![];
+        let mut block_sync = false;
+        let mut pending = vec![module.module.entry.clone()];
+        while let Some(block) = pending.pop() {
+            visit_block(&block, &mut |node| {
+                if let Instruction::Call(func, _) = node.get().instruction.as_ref() {
+                    match func {
+                        Func::SynchronizeBlock => block_sync = true,
+                        Func::Callable(c) => {
+                            if !callables
+                                .iter()
+                                .any(|x| CArc::as_ptr(&x.0) == CArc::as_ptr(&c.0))
+                            {
+                                callables.push(c.clone());
+                                pending.push(c.0.module.entry.clone());
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+            });
+        }
+        Self {
+            module,
+            callables,
+            block_sync,
+            block_pool: OnceLock::new(),
+        }
+    }
+}
+
+pub(super) struct DispatchContext {
+    pub(super) shader: Arc<Shader>,
+    /// Kernel arguments and captured resources.
+    pub(super) globals: HashMap<NodeRef, Val>,
+    pub(super) dispatch_size: [u32; 3],
+}
+
+impl DispatchContext {
+    pub(super) fn run(&self) {
+        let block_size = self.shader.module.block_size;
+        let grid: [u32; 3] =
+            std::array::from_fn(|i| (self.dispatch_size[i] + block_size[i] - 1) / block_size[i]);
+        let blocks = grid.iter().map(|x| *x as usize).product::<usize>();
+        let block_id = |b: usize| {
+            let (gx, gy) = (grid[0] as usize, grid[1] as usize);
+            [
+                (b % gx) as u32,
+                ((b / gx) % gy) as u32,
+                (b / (gx * gy)) as u32,
+            ]
+        };
+        if self.shader.block_sync {
+            (0..blocks).for_each(|b| self.run_block(block_id(b)));
+        } else {
+            (0..blocks)
+                .into_par_iter()
+                .for_each(|b| self.run_block(block_id(b)));
+        }
+    }
+
+    fn run_block(&self, block_id: [u32; 3]) {
+        let block_size = self.shader.module.block_size;
+        let shared_memory = self
+            .shader
+            .module
+            .shared
+            .as_ref()
+            .iter()
+            .map(|node| {
+                let memory = Allocation::try_new(node.type_().size())
+                    .expect("failed to allocate shared memory");
+                (*node, memory)
+            })
+            .collect::<Vec<_>>();
+        let shared = shared_memory
+            .iter()
+            .map(|(node, memory)| (*node, Val::Ptr(memory.ptr)))
+            .collect::<HashMap<_, _>>();
+        let mut threads = vec![];
+        for z in 0..block_size[2] {
+            for y in 0..block_size[1] {
+                for x in 0..block_size[0] {
+                    let thread_id = [x, y, z];
+                    let in_range = (0..3).all(|i| {
+                        block_id[i] * block_size[i] + thread_id[i] < self.dispatch_size[i]
+                    });
+                    if in_range {
+                        threads.push(thread_id);
+                    }
+                }
+            }
+        }
+        if !self.shader.block_sync {
+            for thread_id in threads {
+                Thread::new(self, &shared, None, thread_id, block_id).run();
+            }
+            return;
+        }
+        let pool = self.shader.block_pool.get_or_init(|| {
+            rayon::ThreadPoolBuilder::new()
+                .num_threads(block_size.iter().product::<u32>() as usize)
+                .thread_name(|i| format!("interpreter-thread-{}", i))
+                .build()
+                .expect("failed to create the threads of a block")
+        });
+        let barrier = BlockBarrier::new(threads.len());
+        // every thread of the pool runs at most one kernel thread of the block
+        let panics = pool.broadcast(|ctx| {
+            let thread_id = *threads.get(ctx.index())?;
+            let run = || Thread::new(self, &shared, Some(&barrier), thread_id, block_id).run();
+            let payload = catch_unwind(AssertUnwindSafe(run)).err()?;
+            barrier.poison();
+            (!payload.is::<BlockAborted>()).then_some(payload)
+        });
+        if let Some(payload) = panics.into_iter().flatten().next() {
+            resume_unwind(payload);
+        }
+    }
+}
+
+/// Unwinds the threads waiting at a [`BlockBarrier`] after another thread of
+/// the block panicked.
+struct BlockAborted;
+
+#[derive(Default)]
+struct BarrierState {
+    waiting: usize,
+    generation: u64,
+    poisoned: bool,
+}
+
+/// A barrier for the threads of a block, which releases all of them once one
+/// panics instead of leaving them waiting for it.
+struct BlockBarrier {
+    threads: usize,
+    state: Mutex<BarrierState>,
+    released: Condvar,
+}
+
+impl BlockBarrier {
+    fn new(threads: usize) -> Self {
+        Self {
+            threads,
+            state: Mutex::new(BarrierState::default()),
+            released: Condvar::new(),
+        }
+    }
+    fn wait(&self) {
+        let mut state = self.state.lock();
+        let generation = state.generation;
+        if !state.poisoned {
+            state.waiting += 1;
+            if state.waiting == self.threads {
+                state.waiting = 0;
+                state.generation += 1;
+                self.released.notify_all();
+                return;
+            }
+            while state.generation == generation && !state.poisoned {
+                self.released.wait(&mut state);
+            }
+        }
+        if state.poisoned {
+            drop(state);
+            resume_unwind(Box::new(BlockAborted));
+        }
+    }
+    fn poison(&self) {
+        self.state.lock().poisoned = true;
+        self.released.notify_all();
+    }
+}
+
+enum Flow {
+    Normal,
+    Break,
+    Continue,
+    Return(Val),
+}
+
+#[derive(Default)]
+struct Frame {
+    values: HashMap<NodeRef, Val>,
+    // backing storage of locals, the boxes never move
+    locals: Vec<Box<[u128]>>,
+}
+
+impl Frame {
+    fn alloc(&mut self, bytes: &[u8]) -> *mut u8 {
+        let mut storage = vec![0u128; (bytes.len() + 15) / 16].into_boxed_slice();
+        let ptr = storage.as_mut_ptr() as *mut u8;
+        unsafe { std::ptr::copy_nonoverlapping(bytes.as_ptr(), ptr, bytes.len()) };
+        self.locals.push(storage);
+        ptr
+    }
+}
+
+struct Thread<'a> {
+    ctx: &'a DispatchContext,
+    shared: &'a HashMap<NodeRef, Val>,
+    barrier: Option<&'a BlockBarrier>,
+    thread_id: [u32; 3],
+    block_id: [u32; 3],
+    // blocks that ran to completion, used to resolve phi nodes
+    stamp: Cell<u64>,
+    completed: RefCell<HashMap<*const BasicBlock, u64>>,
+}
+
+fn uint3(ty: &CArc<Type>, v: [u32; 3]) -> Val {
+    Val::Data(value::write_lanes(
+        ty,
+        &v.map(|x| Scalar::Int(x as i128, Primitive::Uint32)),
+    ))
+}
+
+fn message(msg: &[u8]) -> String {
+    String::from_utf8_lossy(msg.strip_suffix(&[0]).unwrap_or(msg)).into_owned()
+}
+
+impl<'a> Thread<'a> {
+    fn new(
+        ctx: &'a DispatchContext,
+        shared: &'a HashMap<NodeRef, Val>,
+        barrier: Option<&'a BlockBarrier>,
+        thread_id: [u32; 3],
+        block_id: [u32; 3],
+    ) -> Self {
+        Self {
+            ctx,
+            shared,
+            barrier,
+            thread_id,
+            block_id,
+            stamp: Cell::new(0),
+            completed: RefCell::new(HashMap::new()),
+        }
+    }
+
+    fn run(&self) {
+        let mut frame = Frame::default();
+        self.eval_block(&mut frame, &self.ctx.shader.module.module.entry);
+    }
+
+    fn dispatch_id(&self) -> [u32; 3] {
+        let block_size = self.ctx.shader.module.block_size;
+        std::array::from_fn(|i| self.block_id[i] * block_size[i] + self.thread_id[i])
+    }
+
+    fn val<'f>(&'f self, frame: &'f Frame, node: NodeRef) -> &'f Val {
+        frame
+            .values
+            .get(&node)
+            .or_else(|| self.shared.get(&node))
+            .or_else(|| self.ctx.globals.get(&node))
+            .unwrap_or_else(|| {
+                panic!(
+                    "node {:?} is used before it is defined",
+                    node.get().instruction
+                )
+            })
+    }
+    /// Contents of a value or of the variable a pointer refers to.
+    fn data(&self, frame: &Frame, node: NodeRef) -> Vec<u8> {
+        match self.val(frame, node) {
+            Val::Data(data) => data.clone(),
+            Val::Ptr(ptr) => unsafe {
+                std::slice::from_raw_parts(*ptr, node.type_().size()).to_vec()
+            },
+            _ => panic!("expected a value, found a resource"),
+        }
+    }
+    fn lanes(&self, frame: &Frame, node: NodeRef) -> Vec<Scalar> {
+        value::read_lanes(node.type_(), &self.data(frame, node))
+    }
+    fn scalar(&self, frame: &Frame, node: NodeRef) -> Scalar {
+        self.lanes(frame, node)[0]
+    }
+    fn index(&self, frame: &Frame, node: NodeRef) -> usize {
+        self.scalar(frame, node).as_usize()
+    }
+    fn ptr(&self, frame: &Frame, node: NodeRef) -> *mut u8 {
+        match self.val(frame, node) {
+            Val::Ptr(ptr) => *ptr,
+            _ => panic!("expected a variable"),
+        }
+    }
+    fn buffer(&self, frame: &Frame, node: NodeRef) -> &BufferView {
+        match self.val(frame, node) {
+            Val::Buffer(buffer) => buffer,
+            _ => panic!("expected a buffer"),
+        }
+    }
+    fn texture(&self, frame: &Frame, node: NodeRef) -> (&Arc<HostTexture>, u32) {
+        match self.val(frame, node) {
+            Val::Texture(texture, level) => (texture, *level),
+            _ => panic!("expected a texture"),
+        }
+    }
+    fn bindless_slot(&self, frame: &Frame, array: NodeRef, slot: NodeRef) -> BindlessSlot {
+        let slot = self.index(frame, slot);
+        match self.val(frame, array) {
+            Val::Bindless(slots) => slots
+                .get(slot)
+                .cloned()
+                .unwrap_or_else(|| panic!("bindless slot {} out of range", slot)),
+            _ => panic!("expected a bindless array"),
+        }
+    }
+    /// Offset and type after applying `indices` to a value of type `ty`.
+    fn walk(&self, frame: &Frame, mut ty: CArc<Type>, indices: &[NodeRef]) -> (CArc<Type>, usize) {
+        let mut offset = 0;
+        for i in indices {
+            let (element, o) = value::element(&ty, self.index(frame, *i));
+            ty = element;
+            offset += o;
+        }
+        (ty, offset)
+    }
+
+    fn eval_block(&self, frame: &mut Frame, block: &Pooled<BasicBlock>) -> Flow {
+        for node in block.iter() {
+            match self.eval_node(frame, node) {
+                Flow::Normal => {}
+                flow => return flow,
+            }
+        }
+        let stamp = self.stamp.get() + 1;
+        self.stamp.set(stamp);
+        self.completed
+            .borrow_mut()
+            .insert(&**block as *const BasicBlock, stamp);
+        Flow::Normal
+    }
+
+    fn eval_node(&self, frame: &mut Frame, node: NodeRef) -> Flow {
+        let value = match node.get().instruction.as_ref() {
+            Instruction::Buffer
+            | Instruction::Bindless
+            | Instruction::Texture2D
+            | Instruction::Texture3D
+            | Instruction::Accel
+            | Instruction::Shared
+            | Instruction::Uniform
+            | Instruction::Argument { .. }
+            | Instruction::Invalid
+            | Instruction::Comment(_) => return Flow::Normal,
+            Instruction::Local { init } => {
+                let init = self.data(frame, *init);
+                // locals in loops reuse their storage
+                if let Some(Val::Ptr(ptr)) = frame.values.get(&node) {
+                    unsafe { std::ptr::copy_nonoverlapping(init.as_ptr(), *ptr, init.len()) };
+                    return Flow::Normal;
+                }
+                Val::Ptr(frame.alloc(&init))
+            }
+            Instruction::Const(c) => Val::Data(constant(c)),
+            Instruction::Update { var, value } => {
+                let data = self.data(frame, *value);
+                let ptr = self.ptr(frame, *var);
+                unsafe { std::ptr::copy_nonoverlapping(data.as_ptr(), ptr, data.len()) };
+                return Flow::Normal;
+            }
+            Instruction::Call(func, args) => self.call(frame, node, func, args.as_ref()),
+            Instruction::Phi(incomings) => {
+                let incoming = {
+                    let completed = self.completed.borrow();
+                    incomings
+                        .as_ref()
+                        .iter()
+                        .max_by_key(|i| {
+                            completed
+                                .get(&(&*i.block as *const BasicBlock))
+                                .copied()
+                                .unwrap_or(0)
+                        })
+                        .expect("phi node without incomings")
+                        .value
+                };
+                Val::Data(self.data(frame, incoming))
+            }
+            Instruction::Return(v) => {
+                return Flow::Return(if v.valid() {
+                    Val::Data(self.data(frame, *v))
+                } else {
+                    Val::Void
+                })
+            }
+            Instruction::Loop { body, cond } => {
+                loop {
+                    match self.eval_block(frame, body) {
+                        Flow::Break => break,
+                        Flow::Return(v) => return Flow::Return(v),
+                        Flow::Normal | Flow::Continue => {}
+                    }
+                    if !self.scalar(frame, *cond).as_bool() {
+                        break;
+                    }
+                }
+                return Flow::Normal;
+            }
+            Instruction::GenericLoop {
+                prepare,
+                cond,
+                body,
+                update,
+            } => {
+                loop {
+                    if let Flow::Return(v) = self.eval_block(frame, prepare) {
+                        return Flow::Return(v);
+                    }
+                    if !self.scalar(frame, *cond).as_bool() {
+                        break;
+                    }
+                    match self.eval_block(frame, body) {
+                        Flow::Break => break,
+                        Flow::Return(v) => return Flow::Return(v),
+                        Flow::Normal | Flow::Continue => {}
+                    }
+                    if let Flow::Return(v) = self.eval_block(frame, update) {
+                        return Flow::Return(v);
+                    }
+                }
+                return Flow::Normal;
+            }
+            Instruction::Break => return Flow::Break,
+            Instruction::Continue => return Flow::Continue,
+            Instruction::If {
+                cond,
+                true_branch,
+                false_branch,
+            } => {
+                let branch = if self.scalar(frame, *cond).as_bool() {
+                    true_branch
+                } else {
+                    false_branch
+                };
+                return self.eval_block(frame, branch);
+            }
+            Instruction::Switch {
+                value,
+                default,
+                cases,
+            } => {
+                let v = self.scalar(frame, *value).as_i128();
+                let block = cases
+                    .as_ref()
+                    .iter()
+                    .find(|c| c.value as i128 == v)
+                    .map_or(default, |c| &c.block);
+                // like in C, `break` leaves the switch
+                return match self.eval_block(frame, block) {
+                    Flow::Break => Flow::Normal,
+                    flow => flow,
+                };
+            }
+            Instruction::AdDetach(body) => return self.eval_block(frame, body),
+            Instruction::AdScope { .. } => {
+                panic!("autodiff is not supported by the interpreter")
+            }
+            Instruction::RayQuery { .. } => {
+                panic!("ray queries are not supported by the interpreter")
+            }
+            Instruction::Print { fmt, args } => {
+                let fmt = message(fmt.as_ref());
+                let mut out = String::new();
+                let mut parts = fmt.split("{}");
+                out.push_str(parts.next().unwrap_or(""));
+                for (part, arg) in parts.zip(args.as_ref()) {
+                    out.push_str(&value::format_value(arg.type_(), &self.data(frame, *arg)));
+                    out.push_str(part);
+                }
+                println!("{}", out);
+                return Flow::Normal;
+            }
+            inst => panic!("{:?} is not supported by the interpreter", inst),
+        };
+        frame.values.insert(node, value);
+        Flow::Normal
+    }
+
+    fn elementwise(&self, frame: &Frame, ty: &CArc<Type>, func: &Func, args: &[NodeRef]) -> Val {
+        let args = args
+            .iter()
+            .map(|a| self.lanes(frame, *a))
+            .collect::<Vec<_>>();
+        let n = args.iter().map(|a| a.len()).max().unwrap();
+        let lane = |a: &Vec<Scalar>, i: usize| if a.len() == 1 { a[0] } else { a[i] };
+        let lanes = (0..n)
+            .map(|i| match args.len() {
+                1 => value::unary(func, args[0][i]),
+                2 => value::binary(func, lane(&args[0], i), lane(&args[1], i)),
+                _ => value::ternary(
+                    func,
+                    lane(&args[0], i),
+                    lane(&args[1], i),
+                    lane(&args[2], i),
+                ),
+            })
+            .collect::<Vec<_>>();
+        Val::Data(value::write_lanes(ty, &lanes))
+    }
+
+    fn floats(&self, frame: &Frame, node: NodeRef) -> Vec<f64> {
+        self.lanes(frame, node).iter().map(|s| s.as_f64()).collect()
+    }
+
+    fn float_result(ty: &CArc<Type>, v: &[f64]) -> Val {
+        let (p, _) = value::lanes(ty);
+        let lanes = v.iter().map(|x| Scalar::float(*x, p)).collect::<Vec<_>>();
+        Val::Data(value::write_lanes(ty, &lanes))
+    }
+
+    fn int_result(ty: &CArc<Type>, v: usize) -> Val {
+        Val::Data(value::write_lanes(
+            ty,
+            &[Scalar::Int(v as i128, Primitive::Uint64)],
+        ))
+    }
+
+    fn read(ptr: *const u8, len: usize) -> Val {
+        Val::Data(unsafe { std::slice::from_raw_parts(ptr, len).to_vec() })
+    }
+
+    fn write(ptr: *mut u8, data: &[u8]) {
+        unsafe { std::ptr::copy_nonoverlapping(data.as_ptr(), ptr, data.len()) };
+    }
+
+    fn call(&self, frame: &mut Frame, node: NodeRef, func: &Func, args: &[NodeRef]) -> Val {
+        let ty = node.type_();
+        match func {
+            Func::ZeroInitializer => Val::Data(vec![0; ty.size()]),
+            Func::Assume | Func::Detach => {
+                if args.is_empty() {
+                    Val::Void
+                } else {
+                    Val::Data(self.data(frame, args[0]))
+                }
+            }
+            Func::Assert(msg) => {
+                if !self.scalar(frame, args[0]).as_bool() {
+                    panic!("{}", message(msg.as_ref()));
+                }
+                Val::Void
+            }
+            Func::Unreachable(msg) => panic!("{}", message(msg.as_ref())),
+            Func::ThreadId => uint3(ty, self.thread_id),
+            Func::BlockId => uint3(ty, self.block_id),
+            Func::DispatchId => uint3(ty, self.dispatch_id()),
+            Func::DispatchSize => uint3(ty, self.ctx.dispatch_size),
+            Func::SynchronizeBlock => {
+                if let Some(barrier) = self.barrier {
+                    barrier.wait();
+                }
+                Val::Void
+            }
+            Func::Load => Val::Data(self.data(frame, args[0])),
+            Func::Cast => {
+                let lanes = self.lanes(frame, args[0]);
+                Val::Data(value::write_lanes(ty, &lanes))
+            }
+            Func::Bitcast => {
+                let mut data = self.data(frame, args[0]);
+                data.resize(ty.size(), 0);
+                Val::Data(data)
+            }
+
+            Func::Neg
+            | Func::Not
+            | Func::BitNot
+            | Func::Abs
+            | Func::Clz
+            | Func::Ctz
+            | Func::PopCount
+            | Func::Reverse
+            | Func::IsInf
+            | Func::IsNan
+            | Func::Acos
+            | Func::Acosh
+            | Func::Asin
+            | Func::Asinh
+            | Func::Atan
+            | Func::Atanh
+            | Func::Cos
+            | Func::Cosh
+            | Func::Sin
+            | Func::Sinh
+            | Func::Tan
+            | Func::Tanh
+            | Func::Exp
+            | Func::Exp2
+            | Func::Exp10
+            | Func::Log
+            | Func::Log2
+            | Func::Log10
+            | Func::Sqrt
+            | Func::Rsqrt
+            | Func::Ceil
+            | Func::Floor
+            | Func::Fract
+            | Func::Trunc
+            | Func::Round
+            | Func::Saturate
+            | Func::Add
+            | Func::Sub
+            | Func::Div
+            | Func::Rem
+            | Func::BitAnd
+            | Func::BitOr
+            | Func::BitXor
+            | Func::Shl
+            | Func::Shr
+            | Func::RotLeft
+            | Func::RotRight
+            | Func::Eq
+            | Func::Ne
+            | Func::Lt
+            | Func::Le
+            | Func::Gt
+            | Func::Ge
+            | Func::MatCompMul
+            | Func::Min
+            | Func::Max
+            | Func::Atan2
+            | Func::Powf
+            | Func::Powi
+            | Func::Copysign
+            | Func::Step
+            | Func::Clamp
+            | Func::Lerp
+            | Func::Fma
+            | Func::SmoothStep => self.elementwise(frame, ty, func, args),
+            Func::Select => match ty.as_ref() {
+                Type::Primitive(_) | Type::Vector(_) | Type::Matrix(_) => {
+                    self.elementwise(frame, ty, func, args)
+                }
+                _ => {
+                    let pick = if self.scalar(frame, args[0]).as_bool() {
+                        args[1]
+                    } else {
+                        args[2]
+                    };
+                    Val::Data(self.data(frame, pick))
+                }
+            },
+            Func::Mul => match (args[0].type_().as_ref(), args[1].type_().as_ref()) {
+                (Type::Matrix(m), Type::Matrix(_)) => {
+                    let n = m.dimension as usize;
+                    let (a, b) = (self.floats(frame, args[0]), self.floats(frame, args[1]));
+                    let c = (0..n * n)
+                        .map(|i| {
+                            let (col, row) = (i / n, i % n);
+                            (0..n).map(|k| a[k * n + row] * b[col * n + k]).sum()
+                        })
+                        .collect::<Vec<f64>>();
+                    Self::float_result(ty, &c)
+                }
+                (Type::Matrix(m), Type::Vector(_)) => {
+                    let n = m.dimension as usize;
+                    let (a, x) = (self.floats(frame, args[0]), self.floats(frame, args[1]));
+                    let y = (0..n)
+                        .map(|row| (0..n).map(|k| a[k * n + row] * x[k]).sum())
+                        .collect::<Vec<f64>>();
+                    Self::float_result(ty, &y)
+                }
+                _ => self.elementwise(frame, ty, func, args),
+            },
+
+            Func::All | Func::Any => {
+                let lanes = self.lanes(frame, args[0]);
+                let r = if *func == Func::All {
+                    lanes.iter().all(|s| s.as_bool())
+                } else {
+                    lanes.iter().any(|s| s.as_bool())
+                };
+                Val::Data(vec![r as u8])
+            }
+            Func::ReduceSum | Func::ReduceProd | Func::ReduceMin | Func::ReduceMax => {
+                let op = match func {
+                    Func::ReduceSum => Func::Add,
+                    Func::ReduceProd => Func::Mul,
+                    Func::ReduceMin => Func::Min,
+                    _ => Func::Max,
+                };
+                let lanes = self.lanes(frame, args[0]);
+                let r = lanes[1..]
+                    .iter()
+                    .fold(lanes[0], |acc, x| value::binary(&op, acc, *x));
+                Val::Data(value::write_lanes(ty, &[r]))
+            }
+            Func::Dot => {
+                let (a, b) = (self.floats(frame, args[0]), self.floats(frame, args[1]));
+                Self::float_result(ty, &[dot(&a, &b)])
+            }
+            Func::Length => {
+                let a = self.floats(frame, args[0]);
+                Self::float_result(ty, &[dot(&a, &a).sqrt()])
+            }
+            Func::LengthSquared => {
+                let a = self.floats(frame, args[0]);
+                Self::float_result(ty, &[dot(&a, &a)])
+            }
+            Func::Normalize => {
+                let a = self.floats(frame, args[0]);
+                let len = dot(&a, &a).sqrt();
+                Self::float_result(ty, &a.iter().map(|x| x / len).collect::<Vec<_>>())
+            }
+            Func::Distance => {
+                let (a, b) = (self.floats(frame, args[0]), self.floats(frame, args[1]));
+                let d = a.iter().zip(&b).map(|(x, y)| x - y).collect::<Vec<_>>();
+                Self::float_result(ty, &[dot(&d, &d).sqrt()])
+            }
+            Func::Cross => {
+                let (a, b) = (self.floats(frame, args[0]), self.floats(frame, args[1]));
+                Self::float_result(
+                    ty,
+                    &[
+                        a[1] * b[2] - a[2] * b[1],
+                        a[2] * b[0] - a[0] * b[2],
+                        a[0] * b[1] - a[1] * b[0],
+                    ],
+                )
+            }
+            // faceforward(n, i, n_ref)
+            Func::Faceforward => {
+                let n = self.floats(frame, args[0]);
+                let (i, n_ref) = (self.floats(frame, args[1]), self.floats(frame, args[2]));
+                let sign = if dot(&n_ref, &i) < 0.0 { 1.0 } else { -1.0 };
+                Self::float_result(ty, &n.iter().map(|x| x * sign).collect::<Vec<_>>())
+            }
+            // reflect(i, n)
+            Func::Reflect => {
+                let (i, n) = (self.floats(frame, args[0]), self.floats(frame, args[1]));
+                let d = 2.0 * dot(&n, &i);
+                Self::float_result(
+                    ty,
+                    &i.iter().zip(&n).map(|(i, n)| i - d * n).collect::<Vec<_>>(),
+                )
+            }
+            Func::OuterProduct => {
+                let (a, b) = (self.floats(frame, args[0]), self.floats(frame, args[1]));
+                let n = a.len();
+                let m = (0..n * n).map(|i| a[i % n] * b[i / n]).collect::<Vec<_>>();
+                Self::float_result(ty, &m)
+            }
+            Func::Transpose => {
+                let a = self.lanes(frame, args[0]);
+                let n = (a.len() as f64).sqrt() as usize;
+                let t = (0..n * n)
+                    .map(|i| a[(i % n) * n + i / n])
+                    .collect::<Vec<_>>();
+                Val::Data(value::write_lanes(ty, &t))
+            }
+            Func::Determinant => {
+                let a = self.floats(frame, args[0]);
+                Self::float_result(ty, &[determinant(&a)])
+            }
+            Func::Inverse => {
+                let a = self.floats(frame, args[0]);
+                Self::float_result(ty, &inverse(&a))
+            }
+
+            Func::Vec | Func::Mat => {
+                let s = self.scalar(frame, args[0]);
+                Val::Data(value::write_lanes(ty, &[s]))
+            }
+            Func::Vec2 | Func::Vec3 | Func::Vec4 => {
+                let lanes = args
+                    .iter()
+                    .map(|a| self.scalar(frame, *a))
+                    .collect::<Vec<_>>();
+                Val::Data(value::write_lanes(ty, &lanes))
+            }
+            Func::Mat2 | Func::Mat3 | Func::Mat4 | Func::Struct | Func::Array => {
+                let mut data = vec![0u8; ty.size()];
+                for (i, a) in args.iter().enumerate() {
+                    let (_, offset) = value::element(ty, i);
+                    let part = self.data(frame, *a);
+                    data[offset..offset + part.len()].copy_from_slice(&part);
+                }
+                Val::Data(data)
+            }
+            Func::Permute => {
+                let lanes = self.lanes(frame, args[0]);
+                let permuted = args[1..]
+                    .iter()
+                    .map(|i| lanes[self.index(frame, *i)])
+                    .collect::<Vec<_>>();
+                Val::Data(value::write_lanes(ty, &permuted))
+            }
+            Func::ExtractElement => {
+                let data = self.data(frame, args[0]);
+                let (element, offset) = self.walk(frame, args[0].type_().clone(), &args[1..]);
+                Val::Data(data[offset..offset + element.size()].to_vec())
+            }
+            Func::InsertElement => {
+                let mut data = self.data(frame, args[0]);
+                let part = self.data(frame, args[1]);
+                let (_, offset) = self.walk(frame, args[0].type_().clone(), &args[2..]);
+                data[offset..offset + part.len()].copy_from_slice(&part);
+                Val::Data(data)
+            }
+            Func::GetElementPtr => {
+                let ptr = self.ptr(frame, args[0]);
+                let (_, offset) = self.walk(frame, args[0].type_().clone(), &args[1..]);
+                Val::Ptr(unsafe { ptr.add(offset) })
+            }
+
+            Func::BufferRead => {
+                let i = self.index(frame, args[1]);
+                let size = ty.size();
+                Self::read(self.buffer(frame, args[0]).element(i, size), size)
+            }
+            Func::BufferWrite => {
+                let i = self.index(frame, args[1]);
+                let data = self.data(frame, args[2]);
+                Self::write(self.buffer(frame, args[0]).element(i, data.len()), &data);
+                Val::Void
+            }
+            Func::BufferSize => {
+                let buffer = self.buffer(frame, args[0]);
+                Self::int_result(ty, buffer.size / args[0].type_().size().max(1))
+            }
+            Func::ByteBufferRead => {
+                let offset = self.index(frame, args[1]);
+                let size = ty.size();
+                Self::read(self.buffer(frame, args[0]).bytes(offset, size), size)
+            }
+            Func::ByteBufferWrite => {
+                let offset = self.index(frame, args[1]);
+                let data = self.data(frame, args[2]);
+                Self::write(self.buffer(frame, args[0]).bytes(offset, data.len()), &data);
+                Val::Void
+            }
+            Func::ByteBufferSize => Self::int_result(ty, self.buffer(frame, args[0]).size),
+            Func::BufferAddress => {
+                let buffer = self.buffer(frame, args[0]);
+                Self::int_result(ty, buffer.bytes(0, 0) as usize)
+            }
+            // pack_to(value, buffer, index), values are stored at `index` u32s into the buffer
+            Func::Pack => {
+                let data = self.data(frame, args[0]);
+                let offset = self.index(frame, args[2]) * 4;
+                Self::write(self.buffer(frame, args[1]).bytes(offset, data.len()), &data);
+                Val::Void
+            }
+            Func::Unpack => {
+                let offset = self.index(frame, args[1]) * 4;
+                let size = ty.size();
+                Self::read(self.buffer(frame, args[0]).bytes(offset, size), size)
+            }
+
+            Func::Texture2dRead | Func::Texture3dRead => {
+                let (texture, level) = self.texture(frame, args[0]);
+                let texel = texture.read(level, self.coord(frame, args[1]));
+                Val::Data(value::write_lanes(ty, &texel))
+            }
+            Func::Texture2dWrite | Func::Texture3dWrite => {
+                let coord = self.coord(frame, args[1]);
+                let texel = self.lanes(frame, args[2]);
+                let (texture, level) = self.texture(frame, args[0]);
+                texture.write(level, coord, &texel);
+                Val::Void
+            }
+            Func::Texture2dSize | Func::Texture3dSize => {
+                let (texture, level) = self.texture(frame, args[0]);
+                uint3(ty, texture.level_size(level))
+            }
+
+            Func::BindlessBufferRead | Func::BindlessByteBufferRead => {
+                let (memory, base) = self.bindless_buffer(frame, args[0], args[1]);
+                let size = ty.size();
+                let i = self.index(frame, args[2]);
+                let offset = if *func == Func::BindlessBufferRead {
+                    i * size
+                } else {
+                    i
+                };
+                let view = BufferView::new(memory, base, None);
+                Self::read(view.bytes(offset, size), size)
+            }
+            Func::BindlessBufferWrite => {
+                let (memory, base) = self.bindless_buffer(frame, args[0], args[1]);
+                let data = self.data(frame, args[3]);
+                let i = self.index(frame, args[2]);
+                let view = BufferView::new(memory, base, None);
+                Self::write(view.element(i, data.len()), &data);
+                Val::Void
+            }
+            Func::BindlessBufferSize => {
+                let (memory, base) = self.bindless_buffer(frame, args[0], args[1]);
+                let stride = self.index(frame, args[2]).max(1);
+                Self::int_result(ty, (memory.size - base) / stride)
+            }
+            Func::BindlessBufferAddress => {
+                let (memory, base) = self.bindless_buffer(frame, args[0], args[1]);
+                Self::int_result(ty, memory.ptr as usize + base)
+            }
+            Func::BindlessBufferType => {
+                let slot = self.bindless_slot(frame, args[0], args[1]);
+                Self::int_result(ty, slot.buffer_type as usize)
+            }
+            Func::BindlessTexture2dRead
+            | Func::BindlessTexture3dRead
+            | Func::BindlessTexture2dReadLevel
+            | Func::BindlessTexture3dReadLevel => {
+                let (texture, _) = self.bindless_texture(frame, func, args);
+                let level = args.get(3).map_or(0, |l| self.index(frame, *l) as u32);
+                let texel = texture.read(level, self.coord(frame, args[2]));
+                Val::Data(value::write_lanes(ty, &texel))
+            }
+            Func::BindlessTexture2dSize
+            | Func::BindlessTexture3dSize
+            | Func::BindlessTexture2dSizeLevel
+            | Func::BindlessTexture3dSizeLevel => {
+                let (texture, _) = self.bindless_texture(frame, func, args);
+                let level = args.get(2).map_or(0, |l| self.index(frame, *l) as u32);
+                uint3(ty, texture.level_size(level))
+            }
+            Func::BindlessTexture2dSample
+            | Func::BindlessTexture3dSample
+            | Func::BindlessTexture2dSampleLevel
+            | Func::BindlessTexture3dSampleLevel => {
+                let (texture, sampler) = self.bindless_texture(frame, func, args);
+                let dimension = match func {
+                    Func::BindlessTexture2dSample | Func::BindlessTexture2dSampleLevel => 2,
+                    _ => 3,
+                };
+                let uv = self.floats(frame, args[2]);
+                let uvw = std::array::from_fn(|i| uv.get(i).copied().unwrap_or(0.0));
+                let level = match args.len() {
+                    4 => self.scalar(frame, args[3]).as_f64(),
+                    // sample_grad(uv, ddx, ddy)
+                    5 => {
+                        let size = texture.level_size(0);
+                        let footprint = |d: Vec<f64>| {
+                            d.iter()
+                                .enumerate()
+                                .map(|(i, x)| (x * size[i] as f64).powi(2))
+                                .sum::<f64>()
+                                .sqrt()
+                        };
+                        let ddx = footprint(self.floats(frame, args[3]));
+                        let ddy = footprint(self.floats(frame, args[4]));
+                        ddx.max(ddy).max(1e-12).log2()
+                    }
+                    _ => 0.0,
+                };
+                let texel = texture.sample(sampler, dimension, uvw, level);
+                Self::float_result(ty, &texel)
+            }
+
+            Func::AtomicExchange
+            | Func::AtomicCompareExchange
+            | Func::AtomicFetchAdd
+            | Func::AtomicFetchSub
+            | Func::AtomicFetchAnd
+            | Func::AtomicFetchOr
+            | Func::AtomicFetchXor
+            | Func::AtomicFetchMin
+            | Func::AtomicFetchMax => {
+                let operands = if *func == Func::AtomicCompareExchange {
+                    2
+                } else {
+                    1
+                };
+                let (indices, operands) = args[1..].split_at(args.len() - 1 - operands);
+                let operands = operands
+                    .iter()
+                    .map(|o| self.scalar(frame, *o))
+                    .collect::<Vec<_>>();
+                let (ptr, p) = self.atomic_target(frame, args[0], indices);
+                let old = atomic_update(ptr, p, |old| {
+                    let v = operands[0].cast(p);
+                    Some(match func {
+                        Func::AtomicExchange => v,
+                        Func::AtomicCompareExchange => {
+                            if old.bits() != v.bits() {
+                                return None;
+                            }
+                            operands[1].cast(p)
+                        }
+                        Func::AtomicFetchAdd => value::binary(&Func::Add, old, v),
+                        Func::AtomicFetchSub => value::binary(&Func::Sub, old, v),
+                        Func::AtomicFetchAnd => value::binary(&Func::BitAnd, old, v),
+                        Func::AtomicFetchOr => value::binary(&Func::BitOr, old, v),
+                        Func::AtomicFetchXor => value::binary(&Func::BitXor, old, v),
+                        Func::AtomicFetchMin => value::binary(&Func::Min, old, v),
+                        _ => value::binary(&Func::Max, old, v),
+                    })
+                });
+                Val::Data(value::write_lanes(ty, &[old]))
+            }
+
+            // warps have a single lane
+            Func::WarpIsFirstActiveLane
+            | Func::WarpActiveAllEqual
+            | Func::WarpActiveAll
+            | Func::WarpActiveAny
+                if args.is_empty() || matches!(func, Func::WarpActiveAllEqual) =>
+            {
+                let n = value::lanes(ty).1.len();
+                Val::Data(value::write_lanes(ty, &vec![Scalar::Bool(true); n]))
+            }
+            Func::WarpActiveAll
+            | Func::WarpActiveAny
+            | Func::WarpActiveSum
+            | Func::WarpActiveProduct
+            | Func::WarpActiveMin
+            | Func::WarpActiveMax
+            | Func::WarpActiveBitAnd
+            | Func::WarpActiveBitOr
+            | Func::WarpActiveBitXor
+            | Func::WarpReadFirstLane
+            | Func::WarpReadLaneAt => Val::Data(self.data(frame, args[0])),
+            Func::WarpActiveCountBits => {
+                let active = self.scalar(frame, args[0]).as_bool();
+                Self::int_result(ty, active as usize)
+            }
+            Func::WarpActiveBitMask => {
+                let active = self.scalar(frame, args[0]).as_bool();
+                let n = value::lanes(ty).1.len();
+                let mut mask = vec![Scalar::Int(0, Primitive::Uint32); n];
+                mask[0] = Scalar::Int(active as i128, Primitive::Uint32);
+                Val::Data(value::write_lanes(ty, &mask))
+            }
+            Func::WarpPrefixCountBits | Func::WarpPrefixSum => Val::Data(vec![0; ty.size()]),
+            Func::WarpPrefixProduct => {
+                let (p, _) = value::lanes(ty);
+                Val::Data(value::write_lanes(ty, &[Scalar::one(p)]))
+            }
+
+            Func::CpuCustomOp(op) => {
+                let data = self.data(frame, args[0]);
+                // the argument is handed out as `&mut T`, so it has to be aligned
+                let mut storage = vec![0u128; (data.len() + 15) / 16];
+                let ptr = storage.as_mut_ptr() as *mut u8;
+                Self::write(ptr, &data);
+                (op.func)(op.data, ptr);
+                Self::read(ptr, data.len())
+            }
+            Func::Callable(callable) => self.call_callable(frame, callable, args),
+
+            Func::External(_) => panic!("external functions are not supported by the interpreter"),
+            Func::PropagateGrad
+            | Func::OutputGrad
+            | Func::RequiresGradient
+            | Func::Backward
+            | Func::Gradient
+            | Func::GradientMarker
+            | Func::AccGrad => panic!("autodiff is not supported by the interpreter"),
+            Func::RayTracingInstanceTransform
+            | Func::RayTracingTraceClosest
+            | Func::RayTracingTraceAny
+            | Func::RayTracingQueryAll
+            | Func::RayTracingQueryAny
+            | Func::RayQueryWorldSpaceRay
+            | Func::RayQueryProceduralCandidateHit
+            | Func::RayQueryTriangleCandidateHit
+            | Func::RayQueryCommittedHit
+            | Func::RayQueryCommitTriangle
+            | Func::RayQueryCommitProcedural
+            | Func::RayQueryTerminate => {
+                panic!("ray tracing is not supported by the interpreter")
+            }
+            func => panic!("{:?} is not supported by the interpreter", func),
+        }
+    }
+
+    fn coord(&self, frame: &Frame, node: NodeRef) -> [u32; 3] {
+        let lanes = self.lanes(frame, node);
+        std::array::from_fn(|i| lanes.get(i).map_or(0, |s| s.as_i128() as u32))
+    }
+
+    fn bindless_buffer(
+        &self,
+        frame: &Frame,
+        array: NodeRef,
+        slot: NodeRef,
+    ) -> (Arc<Allocation>, usize) {
+        let index = self.index(frame, slot);
+        self.bindless_slot(frame, array, slot)
+            .buffer
+            .unwrap_or_else(|| panic!("bindless slot {} has no buffer", index))
+    }
+
+    fn bindless_texture(
+        &self,
+        frame: &Frame,
+        func: &Func,
+        args: &[NodeRef],
+    ) -> (Arc<HostTexture>, crate::resource::Sampler) {
+        let index = self.index(frame, args[1]);
+        let slot = self.bindless_slot(frame, args[0], args[1]);
+        let is_3d = matches!(
+            func,
+            Func::BindlessTexture3dRead
+                | Func::BindlessTexture3dReadLevel
+                | Func::BindlessTexture3dSize
+                | Func::BindlessTexture3dSizeLevel
+                | Func::BindlessTexture3dSample
+                | Func::BindlessTexture3dSampleLevel
+        );
+        let texture = if is_3d { slot.tex3d } else { slot.tex2d };
+        texture.unwrap_or_else(|| {
+            panic!(
+                "bindless slot {} has no {} texture",
+                index,
+                if is_3d { "3d" } else { "2d" }
+            )
+        })
+    }
+
+    /// Address and type of the scalar an atomic operation applies to.
+    fn atomic_target(
+        &self,
+        frame: &Frame,
+        root: NodeRef,
+        indices: &[NodeRef],
+    ) -> (*mut u8, Primitive) {
+        let (ptr, ty, indices) = match self.val(frame, root) {
+            Val::Buffer(buffer) => {
+                let ty = root.type_().clone();
+                let i = self.index(frame, indices[0]);
+                (buffer.element(i, ty.size()), ty, &indices[1..])
+            }
+            Val::Ptr(ptr) => (*ptr, root.type_().clone(), indices),
+            _ => panic!("atomic operations require a buffer or shared memory"),
+        };
+        let (ty, offset) = self.walk(frame, ty, indices);
+        match ty.as_ref() {
+            Type::Primitive(p) => (unsafe { ptr.add(offset) }, *p),
+            _ => panic!(
+                "atomic operations are only defined for scalars, found {:?}",
+                ty
+            ),
+        }
+    }
+
+    fn call_callable(
+        &self,
+        frame: &mut Frame,
+        callable: &CallableModuleRef,
+        args: &[NodeRef],
+    ) -> Val {
+        let callable = callable.0.as_ref();
+        let mut callee = Frame::default();
+        for (param, arg) in callable.args.as_ref().iter().zip(args) {
+            let val = match param.get().instruction.as_ref() {
+                Instruction::Argument { by_value: false } => match self.val(frame, *arg) {
+                    Val::Ptr(ptr) => Val::Ptr(*ptr),
+                    // a temporary passed by reference
+                    _ => Val::Ptr(callee.alloc(&self.data(frame, *arg))),
+                },
+                // arguments passed by value may be assigned to by the callable
+                Instruction::Argument { by_value: true } => {
+                    Val::Ptr(callee.alloc(&self.data(frame, *arg)))
+                }
+                _ => self.val(frame, *arg).clone(),
+            };
+            callee.values.insert(*param, val);
+        }
+        match self.eval_block(&mut callee, &callable.module.entry) {
+            Flow::Return(v) => v,
+            _ => Val::Void,
+        }
+    }
+}
+
+fn dot(a: &[f64], b: &[f64]) -> f64 {
+    a.iter().zip(b).map(|(x, y)| x * y).sum()
+}
+
+/// Column major `n` x `n` matrix to rows.
+fn rows(m: &[f64]) -> Vec<Vec<f64>> {
+    let n = (m.len() as f64).sqrt() as usize;
+    (0..n)
+        .map(|r| (0..n).map(|c| m[c * n + r]).collect())
+        .collect()
+}
+
+fn determinant(m: &[f64]) -> f64 {
+    let mut a = rows(m);
+    let n = a.len();
+    let mut det = 1.0;
+    for col in 0..n {
+        let pivot = (col..n)
+            .max_by(|x, y| a[*x][col].abs().total_cmp(&a[*y][col].abs()))
+            .unwrap();
+        if a[pivot][col] == 0.0 {
+            return 0.0;
+        }
+        if pivot != col {
+            a.swap(pivot, col);
+            det = -det;
+        }
+        det *= a[col][col];
+        for r in col + 1..n {
+            let f = a[r][col] / a[col][col];
+            for c in col..n {
+                a[r][c] -= f * a[col][c];
+            }
+        }
+    }
+    det
+}
+
+fn inverse(m: &[f64]) -> Vec<f64> {
+    let mut a = rows(m);
+    let n = a.len();
+    let mut inv = (0..n)
+        .map(|r| (0..n).map(|c| (r == c) as u8 as f64).collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+    for col in 0..n {
+        let pivot = (col..n)
+            .max_by(|x, y| a[*x][col].abs().total_cmp(&a[*y][col].abs()))
+            .unwrap();
+        a.swap(pivot, col);
+        inv.swap(pivot, col);
+        let p = a[col][col];
+        for c in 0..n {
+            a[col][c] /= p;
+            inv[col][c] /= p;
+        }
+        for r in 0..n {
+            if r != col {
+                let f = a[r][col];
+                for c in 0..n {
+                    a[r][c] -= f * a[col][c];
+                    inv[r][c] -= f * inv[col][c];
+                }
+            }
+        }
+    }
+    (0..n * n).map(|i| inv[i % n][i / n]).collect()
+}
+
+fn constant(c: &Const) -> Vec<u8> {
+    match c {
+        Const::Zero(t) => vec![0; t.size()],
+        Const::One(t) => match t.as_ref() {
+            Type::Matrix(m) => {
+                let n = m.dimension as usize;
+                let (p, _) = value::lanes(t);
+                let lanes = (0..n * n)
+                    .map(|i| {
+                        if i % n == i / n {
+                            Scalar::one(p)
+                        } else {
+                            Scalar::zero(p)
+                        }
+                    })
+                    .collect::<Vec<_>>();
+                value::write_lanes(t, &lanes)
+            }
+            _ => {
+                let (p, _) = value::lanes(t);
+                value::write_lanes(t, &[Scalar::one(p)])
+            }
+        },
+        Const::Bool(v) => vec![*v as u8],
+        Const::Int32(v) => v.to_le_bytes().to_vec(),
+        Const::Uint32(v) => v.to_le_bytes().to_vec(),
+        Const::Int64(v) => v.to_le_bytes().to_vec(),
+        Const::Uint64(v) => v.to_le_bytes().to_vec(),
+        Const::Float32(v) => v.to_le_bytes().to_vec(),
+        Const::Float64(v) => v.to_le_bytes().to_vec(),
+        Const::Generic(data, _) => data.as_ref().to_vec(),
+        c => panic!("constant {:?} is not supported by the interpreter", c),
+    }
+}
+
+/// Applies `f` to the scalar at `ptr` atomically and returns the previous value,
+/// `f` returning `None` leaves the value unchanged.
+fn atomic_update(ptr: *mut u8, p: Primitive, f: impl Fn(Scalar) -> Option<Scalar>) -> Scalar {
+    match primitive_size(p) {
+        4 => {
+            let atomic = unsafe { &*(ptr as *const AtomicU32) };
+            let mut old = atomic.load(Ordering::SeqCst);
+            loop {
+                let s = Scalar::from_bits(old as u64, p);
+                let Some(new) = f(s) else {
+                    return s;
+                };
+                match atomic.compare_exchange_weak(
+                    old,
+                    new.bits() as u32,
+                    Ordering::SeqCst,
+                    Ordering::SeqCst,
+                ) {
+                    Ok(_) => return s,
+                    Err(x) => old = x,
+                }
+            }
+        }
+        8 => {
+            let atomic = unsafe { &*(ptr as *const AtomicU64) };
+            let mut old = atomic.load(Ordering::SeqCst);
+            loop {
+                let s = Scalar::from_bits(old, p);
+                let Some(new) = f(s) else {
+                    return s;
+                };
+                match atomic.compare_exchange_weak(
+                    old,
+                    new.bits(),
+                    Ordering::SeqCst,
+                    Ordering::SeqCst,
+                ) {
+                    Ok(_) => return s,
+                    Err(x) => old = x,
+                }
+            }
+        }
+        size => panic!(
+            "atomic operations on {}-byte values are not supported",
+            size
+        ),
+    }
+}
@@ -0,0 +1,273 @@
+//! Texel encoding and sampling.
+//!
+//! Mip levels are stored tightly packed in the [`PixelStorage`] of the texture,
+//! the same layout used by texture uploads and downloads.
+use super::value::{primitive_size, Scalar};
+use super::Allocation;
+use crate::resource::{PixelFormat, PixelStorage, Sampler, SamplerAddress, SamplerFilter};
+use ir::Primitive;
+
+use crate::internal_prelude::*;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum TexelKind {
+    Unorm,
+    Sint,
+    Uint,
+    Float,
+}
+
+fn storage(format: PixelFormat) -> PixelStorage {
+    use PixelFormat::*;
+    match format {
+        R8Unorm | R8Sint | R8Uint => PixelStorage::Byte1,
+        Rg8Unorm | Rg8Sint | Rg8Uint => PixelStorage::Byte2,
+        Rgba8Unorm | Rgba8Sint | Rgba8Uint => PixelStorage::Byte4,
+        R16Unorm | R16Sint | R16Uint => PixelStorage::Short1,
+        Rg16Unorm | Rg16Sint | Rg16Uint => PixelStorage::Short2,
+        Rgba16Unorm | Rgba16Sint | Rgba16Uint => PixelStorage::Short4,
+        R32Sint | R32Uint => PixelStorage::Int1,
+        Rg32Sint | Rg32Uint => PixelStorage::Int2,
+        Rgba32Sint | Rgba32Uint => PixelStorage::Int4,
+        R16f => PixelStorage::Half1,
+        Rg16f => PixelStorage::Half2,
+        Rgba16f => PixelStorage::Half4,
+        R32f => PixelStorage::Float1,
+        Rg32f => PixelStorage::Float2,
+        Rgba32f => PixelStorage::Float4,
+        _ => panic!(
+            "pixel format {:?} is not supported by the interpreter",
+            format
+        ),
+    }
+}
+
+fn texel_kind(format: PixelFormat) -> TexelKind {
+    use PixelFormat::*;
+    match format {
+        R8Unorm | Rg8Unorm | Rgba8Unorm | R16Unorm | Rg16Unorm | Rgba16Unorm => TexelKind::Unorm,
+        R8Sint | Rg8Sint | Rgba8Sint | R16Sint | Rg16Sint | Rgba16Sint | R32Sint | Rg32Sint
+        | Rgba32Sint => TexelKind::Sint,
+        R8Uint | Rg8Uint | Rgba8Uint | R16Uint | Rg16Uint | Rgba16Uint | R32Uint | Rg32Uint
+        | Rgba32Uint => TexelKind::Uint,
+        R16f | Rg16f | Rgba16f | R32f | Rg32f | Rgba32f => TexelKind::Float,
+        _ => panic!(
+            "pixel format {:?} is not supported by the interpreter",
+            format
+        ),
+    }
+}
+
+/// Channel type and channel count of a storage.
+fn channels(storage: PixelStorage, kind: TexelKind) -> (Primitive, usize) {
+    let signed = kind == TexelKind::Sint;
+    let int = |s: Primitive, u: Primitive| if signed { s } else { u };
+    match storage {
+        PixelStorage::Byte1 => (int(Primitive::Int8, Primitive::Uint8), 1),
+        PixelStorage::Byte2 => (int(Primitive::Int8, Primitive::Uint8), 2),
+        PixelStorage::Byte4 => (int(Primitive::Int8, Primitive::Uint8), 4),
+        PixelStorage::Short1 => (int(Primitive::Int16, Primitive::Uint16), 1),
+        PixelStorage::Short2 => (int(Primitive::Int16, Primitive::Uint16), 2),
+        PixelStorage::Short4 => (int(Primitive::Int16, Primitive::Uint16), 4),
+        PixelStorage::Int1 => (int(Primitive::Int32, Primitive::Uint32), 1),
+        PixelStorage::Int2 => (int(Primitive::Int32, Primitive::Uint32), 2),
+        PixelStorage::Int4 => (int(Primitive::Int32, Primitive::Uint32), 4),
+        PixelStorage::Half1 => (Primitive::Float16, 1),
+        PixelStorage::Half2 => (Primitive::Float16, 2),
+        PixelStorage::Half4 => (Primitive::Float16, 4),
+        PixelStorage::Float1 => (Primitive::Float32, 1),
+        PixelStorage::Float2 => (Primitive::Float32, 2),
+        PixelStorage::Float4 => (Primitive::Float32, 4),
+        _ => panic!(
+            "pixel storage {:?} is not supported by the interpreter",
+            storage
+        ),
+    }
+}
+
+fn unorm_max(p: Primitive) -> f64 {
+    ((1u64 << (primitive_size(p) * 8)) - 1) as f64
+}
+
+pub(super) struct HostTexture {
+    pub(super) size: [u32; 3],
+    pub(super) levels: Vec<Allocation>,
+    kind: TexelKind,
+    channel: Primitive,
+    channel_count: usize,
+}
+
+impl HostTexture {
+    pub(super) fn try_new(format: PixelFormat, size: [u32; 3], levels: u32) -> Option<Self> {
+        let kind = texel_kind(format);
+        let (channel, channel_count) = channels(storage(format), kind);
+        let mut texture = Self {
+            size,
+            levels: vec![],
+            kind,
+            channel,
+            channel_count,
+        };
+        texture.levels = (0..levels)
+            .map(|l| {
+                let [w, h, d] = texture.level_size(l);
+                let texels = w as usize * h as usize * d as usize;
+                Allocation::try_new(texels.checked_mul(texture.pixel_size())?)
+            })
+            .collect::<Option<_>>()?;
+        Some(texture)
+    }
+    pub(super) fn pixel_size(&self) -> usize {
+        primitive_size(self.channel) * self.channel_count
+    }
+    pub(super) fn level_size(&self, level: u32) -> [u32; 3] {
+        self.size.map(|s| (s >> level).max(1))
+    }
+    fn texel_ptr(&self, level: u32, coord: [u32; 3]) -> Option<*mut u8> {
+        let size = self.level_size(level);
+        if (0..3).any(|i| coord[i] >= size[i]) || level as usize >= self.levels.len() {
+            return None;
+        }
+        let index = (coord[2] as usize * size[1] as usize + coord[1] as usize) * size[0] as usize
+            + coord[0] as usize;
+        Some(unsafe {
+            self.levels[level as usize]
+                .ptr
+                .add(index * self.pixel_size())
+        })
+    }
+    /// Out of range reads return zero, like most GPUs.
+    pub(super) fn read(&self, level: u32, coord: [u32; 3]) -> [Scalar; 4] {
+        let mut texel = [Scalar::zero(self.channel); 4];
+        let Some(ptr) = self.texel_ptr(level, coord) else {
+            return texel;
+        };
+        let bytes = unsafe { std::slice::from_raw_parts(ptr, self.pixel_size()) };
+        let size = primitive_size(self.channel);
+        for c in 0..self.channel_count {
+            let v = Scalar::load(self.channel, &bytes[c * size..]);
+            texel[c] = match self.kind {
+                TexelKind::Unorm => {
+                    Scalar::Float(v.as_f64() / unorm_max(self.channel), Primitive::Float32)
+                }
+                _ => v,
+            };
+        }
+        texel
+    }
+    /// Out of range writes are dropped.
+    pub(super) fn write(&self, level: u32, coord: [u32; 3], value: &[Scalar]) {
+        let Some(ptr) = self.texel_ptr(level, coord) else {
+            return;
+        };
+        let bytes = unsafe { std::slice::from_raw_parts_mut(ptr, self.pixel_size()) };
+        let size = primitive_size(self.channel);
+        for c in 0..self.channel_count {
+            let v = value.get(c).copied().unwrap_or(Scalar::zero(self.channel));
+            let v = match self.kind {
+                TexelKind::Unorm => {
+                    let max = unorm_max(self.channel);
+                    Scalar::int(
+                        (v.as_f64().clamp(0.0, 1.0) * max).round() as i128,
+                        self.channel,
+                    )
+                }
+                _ => v.cast(self.channel),
+            };
+            v.store(&mut bytes[c * size..]);
+        }
+    }
+    fn read_f64(&self, level: u32, coord: [u32; 3]) -> [f64; 4] {
+        self.read(level, coord).map(|s| s.as_f64())
+    }
+    /// Filtered lookup at normalized coordinates, `dimension` is 2 or 3.
+    pub(super) fn sample(
+        &self,
+        sampler: Sampler,
+        dimension: usize,
+        uvw: [f64; 3],
+        level: f64,
+    ) -> [f64; 4] {
+        let max_level = (self.levels.len() - 1) as f64;
+        let level = level.clamp(0.0, max_level);
+        match sampler.filter {
+            SamplerFilter::Point => {
+                self.sample_level(sampler, dimension, uvw, level.round() as u32, false)
+            }
+            SamplerFilter::LinearPoint => {
+                self.sample_level(sampler, dimension, uvw, level.round() as u32, true)
+            }
+            _ => {
+                let l0 = level.floor();
+                let a = self.sample_level(sampler, dimension, uvw, l0 as u32, true);
+                if l0 == level {
+                    return a;
+                }
+                let b = self.sample_level(sampler, dimension, uvw, l0 as u32 + 1, true);
+                let t = level - l0;
+                std::array::from_fn(|i| a[i] + (b[i] - a[i]) * t)
+            }
+        }
+    }
+    fn sample_level(
+        &self,
+        sampler: Sampler,
+        dimension: usize,
+        uvw: [f64; 3],
+        level: u32,
+        linear: bool,
+    ) -> [f64; 4] {
+        let size = self.level_size(level);
+        let address = |i: i64, n: u32| -> Option<u32> {
+            let n = n as i64;
+            match sampler.address {
+                SamplerAddress::Edge => Some(i.clamp(0, n - 1) as u32),
+                SamplerAddress::Repeat => Some(i.rem_euclid(n) as u32),
+                SamplerAddress::Mirror => {
+                    let m = i.rem_euclid(2 * n);
+                    Some(if m >= n { 2 * n - 1 - m } else { m } as u32)
+                }
+                _ => (0..n).contains(&i).then_some(i as u32),
+            }
+        };
+        let fetch = |c: [i64; 3]| -> [f64; 4] {
+            let mut coord = [0u32; 3];
+            for i in 0..dimension {
+                match address(c[i], size[i]) {
+                    Some(x) => coord[i] = x,
+                    None => return [0.0; 4],
+                }
+            }
+            self.read_f64(level, coord)
+        };
+        if !linear {
+            let c = std::array::from_fn(|i| (uvw[i] * size[i] as f64).floor() as i64);
+            return fetch(c);
+        }
+        let mut base = [0i64; 3];
+        let mut frac = [0.0; 3];
+        for i in 0..dimension {
+            let x = uvw[i] * size[i] as f64 - 0.5;
+            base[i] = x.floor() as i64;
+            frac[i] = x - x.floor();
+        }
+        let mut result = [0.0; 4];
+        for corner in 0..(1 << dimension) {
+            let mut c = base;
+            let mut weight = 1.0;
+            for i in 0..dimension {
+                if corner & (1 << i) != 0 {
+                    c[i] += 1;
+                    weight *= frac[i];
+                } else {
+                    weight *= 1.0 - frac[i];
+                }
+            }
+            let texel = fetch(c);
+            for k in 0..4 {
+                result[k] += texel[k] * weight;
+            }
+        }
+        result
+    }
+}
@@ -0,0 +1,538 @@
+//! Memory layout of IR types and scalar arithmetic.
+//!
+//! Values are kept as raw bytes in the layout the frontend uses for `Value`
+//! types, so they can be copied to and from buffers without conversion.
+//! Arithmetic decomposes a value into [`Scalar`] lanes: integers are widened to
+//! `i128` and wrapped back to their width after every operation, floats are
+//! computed in `f64` and rounded back, which is exact for the basic operations.
+use std::fmt::Write as _;
+
+use half::f16;
+
+use crate::internal_prelude::*;
+use ir::{MatrixType, Primitive, StructType, VectorElementType, VectorType};
+
+pub(super) fn primitive_size(p: Primitive) -> usize {
+    match p {
+        Primitive::Bool | Primitive::Int8 | Primitive::Uint8 => 1,
+        Primitive::Int16 | Primitive::Uint16 | Primitive::Float16 => 2,
+        Primitive::Int32 | Primitive::Uint32 | Primitive::Float32 => 4,
+        Primitive::Int64 | Primitive::Uint64 | Primitive::Float64 => 8,
+    }
+}
+
+fn is_float(p: Primitive) -> bool {
+    matches!(
+        p,
+        Primitive::Float16 | Primitive::Float32 | Primitive::Float64
+    )
+}
+
+pub(super) fn vector_scalar(e: &VectorElementType) -> Primitive {
+    match e {
+        VectorElementType::Scalar(p) => *p,
+        VectorElementType::Vector(_) => panic!("vectors of vectors are not supported"),
+    }
+}
+
+/// Elements of 3-vectors are padded to 4.
+fn vector_stride(p: Primitive, length: u32) -> usize {
+    primitive_size(p) * if length == 3 { 4 } else { length as usize }
+}
+
+pub(super) fn column_type(m: &MatrixType) -> CArc<Type> {
+    register_type(Type::Vector(VectorType {
+        element: m.element.clone(),
+        length: m.dimension,
+    }))
+}
+
+pub(super) fn field_offsets(s: &StructType) -> Vec<usize> {
+    let mut offset = 0;
+    s.fields
+        .as_ref()
+        .iter()
+        .map(|f| {
+            let align = f.alignment().max(1);
+            offset = (offset + align - 1) / align * align;
+            let o = offset;
+            offset += f.size();
+            o
+        })
+        .collect()
+}
+
+/// Type and byte offset of the `i`-th element of an aggregate.
+pub(super) fn element(ty: &CArc<Type>, i: usize) -> (CArc<Type>, usize) {
+    match ty.as_ref() {
+        Type::Vector(v) => {
+            assert!(i < v.length as usize, "vector index {} out of range", i);
+            let p = vector_scalar(&v.element);
+            (register_type(Type::Primitive(p)), i * primitive_size(p))
+        }
+        Type::Matrix(m) => {
+            assert!(i < m.dimension as usize, "matrix column {} out of range", i);
+            let p = vector_scalar(&m.element);
+            (column_type(m), i * vector_stride(p, m.dimension))
+        }
+        Type::Struct(s) => (s.fields.as_ref()[i].clone(), field_offsets(s)[i]),
+        Type::Array(a) => {
+            assert!(
+                i < a.length,
+                "array index {} out of range, length is {}",
+                i,
+                a.length
+            );
+            (a.element.clone(), i * a.element.size())
+        }
+        _ => panic!("cannot index into {:?}", ty),
+    }
+}
+
+/// Primitive type and byte offsets of the scalars of a scalar, vector or matrix
+/// type, matrices are in column major order.
+pub(super) fn lanes(ty: &Type) -> (Primitive, Vec<usize>) {
+    match ty {
+        Type::Primitive(p) => (*p, vec![0]),
+        Type::Vector(v) => {
+            let p = vector_scalar(&v.element);
+            (
+                p,
+                (0..v.length as usize)
+                    .map(|i| i * primitive_size(p))
+                    .collect(),
+            )
+        }
+        Type::Matrix(m) => {
+            let p = vector_scalar(&m.element);
+            let n = m.dimension as usize;
+            let stride = vector_stride(p, m.dimension);
+            (
+                p,
+                (0..n * n)
+                    .map(|i| (i / n) * stride + (i % n) * primitive_size(p))
+                    .collect(),
+            )
+        }
+        _ => panic!("{:?} is not a scalar, vector or matrix type", ty),
+    }
+}
+
+pub(super) fn read_lanes(ty: &Type, bytes: &[u8]) -> Vec<Scalar> {
+    let (p, offsets) = lanes(ty);
+    offsets
+        .into_iter()
+        .map(|o| Scalar::load(p, &bytes[o..]))
+        .collect()
+}
+
+/// Stores `values` converted to the lane type of `ty`. A single value is
+/// broadcast to all lanes.
+pub(super) fn write_lanes(ty: &Type, values: &[Scalar]) -> Vec<u8> {
+    let (p, offsets) = lanes(ty);
+    let mut bytes = vec![0u8; ty.size()];
+    for (i, o) in offsets.iter().enumerate() {
+        let v = if values.len() == 1 {
+            values[0]
+        } else {
+            values[i]
+        };
+        v.cast(p).store(&mut bytes[*o..]);
+    }
+    bytes
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(super) enum Scalar {
+    Bool(bool),
+    Int(i128, Primitive),
+    Float(f64, Primitive),
+}
+
+impl Scalar {
+    pub(super) fn int(v: i128, p: Primitive) -> Self {
+        let v = match p {
+            Primitive::Int8 => v as i8 as i128,
+            Primitive::Uint8 => v as u8 as i128,
+            Primitive::Int16 => v as i16 as i128,
+            Primitive::Uint16 => v as u16 as i128,
+            Primitive::Int32 => v as i32 as i128,
+            Primitive::Uint32 => v as u32 as i128,
+            Primitive::Int64 => v as i64 as i128,
+            Primitive::Uint64 => v as u64 as i128,
+            _ => unreachable!(),
+        };
+        Scalar::Int(v, p)
+    }
+    pub(super) fn float(v: f64, p: Primitive) -> Self {
+        let v = match p {
+            Primitive::Float16 => f16::from_f64(v).to_f64(),
+            Primitive::Float32 => v as f32 as f64,
+            _ => v,
+        };
+        Scalar::Float(v, p)
+    }
+    pub(super) fn load(p: Primitive, b: &[u8]) -> Self {
+        macro_rules! read {
+            ($t:ty) => {
+                <$t>::from_le_bytes(b[..std::mem::size_of::<$t>()].try_into().unwrap())
+            };
+        }
+        match p {
+            Primitive::Bool => Scalar::Bool(b[0] != 0),
+            Primitive::Int8 => Scalar::Int(read!(i8) as i128, p),
+            Primitive::Uint8 => Scalar::Int(read!(u8) as i128, p),
+            Primitive::Int16 => Scalar::Int(read!(i16) as i128, p),
+            Primitive::Uint16 => Scalar::Int(read!(u16) as i128, p),
+            Primitive::Int32 => Scalar::Int(read!(i32) as i128, p),
+            Primitive::Uint32 => Scalar::Int(read!(u32) as i128, p),
+            Primitive::Int64 => Scalar::Int(read!(i64) as i128, p),
+            Primitive::Uint64 => Scalar::Int(read!(u64) as i128, p),
+            Primitive::Float16 => Scalar::Float(f16::from_bits(read!(u16)).to_f64(), p),
+            Primitive::Float32 => Scalar::Float(read!(f32) as f64, p),
+            Primitive::Float64 => Scalar::Float(read!(f64), p),
+        }
+    }
+    pub(super) fn store(self, b: &mut [u8]) {
+        macro_rules! write {
+            ($v:expr) => {{
+                let bytes = $v.to_le_bytes();
+                b[..bytes.len()].copy_from_slice(&bytes);
+            }};
+        }
+        match self {
+            Scalar::Bool(v) => b[0] = v as u8,
+            Scalar::Int(v, p) => match primitive_size(p) {
+                1 => write!(v as u8),
+                2 => write!(v as u16),
+                4 => write!(v as u32),
+                _ => write!(v as u64),
+            },
+            Scalar::Float(v, Primitive::Float16) => write!(f16::from_f64(v).to_bits()),
+            Scalar::Float(v, Primitive::Float32) => write!(v as f32),
+            Scalar::Float(v, _) => write!(v),
+        }
+    }
+    pub(super) fn primitive(self) -> Primitive {
+        match self {
+            Scalar::Bool(_) => Primitive::Bool,
+            Scalar::Int(_, p) | Scalar::Float(_, p) => p,
+        }
+    }
+    pub(super) fn cast(self, p: Primitive) -> Self {
+        if self.primitive() == p {
+            return self;
+        }
+        match p {
+            Primitive::Bool => Scalar::Bool(match self {
+                Scalar::Bool(v) => v,
+                Scalar::Int(v, _) => v != 0,
+                Scalar::Float(v, _) => v != 0.0,
+            }),
+            p if is_float(p) => Scalar::float(self.as_f64(), p),
+            p => Scalar::int(self.as_i128(), p),
+        }
+    }
+    pub(super) fn as_bool(self) -> bool {
+        match self.cast(Primitive::Bool) {
+            Scalar::Bool(v) => v,
+            _ => unreachable!(),
+        }
+    }
+    /// Floats are truncated and saturated like Rust's `as`.
+    pub(super) fn as_i128(self) -> i128 {
+        match self {
+            Scalar::Bool(v) => v as i128,
+            Scalar::Int(v, _) => v,
+            Scalar::Float(v, _) => v as i128,
+        }
+    }
+    pub(super) fn as_f64(self) -> f64 {
+        match self {
+            Scalar::Bool(v) => v as u8 as f64,
+            Scalar::Int(v, _) => v as f64,
+            Scalar::Float(v, _) => v,
+        }
+    }
+    pub(super) fn as_usize(self) -> usize {
+        let v = self.as_i128();
+        assert!(v >= 0, "negative index {}", v);
+        v as usize
+    }
+    /// Raw bits zero extended to 64 bits, used by atomics.
+    pub(super) fn bits(self) -> u64 {
+        let mut b = [0u8; 8];
+        self.store(&mut b);
+        u64::from_le_bytes(b)
+    }
+    pub(super) fn from_bits(bits: u64, p: Primitive) -> Self {
+        Scalar::load(p, &bits.to_le_bytes())
+    }
+    pub(super) fn zero(p: Primitive) -> Self {
+        Scalar::Int(0, Primitive::Int32).cast(p)
+    }
+    pub(super) fn one(p: Primitive) -> Self {
+        Scalar::Int(1, Primitive::Int32).cast(p)
+    }
+}
+
+fn bit_width(p: Primitive) -> u32 {
+    primitive_size(p) as u32 * 8
+}
+
+pub(super) fn unary(func: &Func, a: Scalar) -> Scalar {
+    use Scalar::*;
+    match (func, a) {
+        (Func::Neg, Int(v, p)) => Scalar::int(v.wrapping_neg(), p),
+        (Func::Neg, Float(v, p)) => Float(-v, p),
+        (Func::Not, Bool(v)) => Bool(!v),
+        (Func::Not | Func::BitNot, Int(v, p)) => Scalar::int(!v, p),
+        (Func::Abs, Int(v, p)) => Scalar::int(v.abs(), p),
+        (Func::Abs, Float(v, p)) => Float(v.abs(), p),
+        (Func::Clz, Int(v, p)) => {
+            let w = bit_width(p);
+            let bits = (v as u128) & ((1u128 << w) - 1);
+            Scalar::int(
+                (bits.leading_zeros() - (128 - w)) as i128,
+                Primitive::Uint32,
+            )
+        }
+        (Func::Ctz, Int(v, p)) => {
+            let w = bit_width(p);
+            let bits = (v as u128) & ((1u128 << w) - 1);
+            Scalar::int(bits.trailing_zeros().min(w) as i128, Primitive::Uint32)
+        }
+        (Func::PopCount, Int(v, p)) => {
+            let bits = (v as u128) & ((1u128 << bit_width(p)) - 1);
+            Scalar::int(bits.count_ones() as i128, Primitive::Uint32)
+        }
+        (Func::Reverse, Int(v, p)) => {
+            let w = bit_width(p);
+            Scalar::int(((v as u128).reverse_bits() >> (128 - w)) as i128, p)
+        }
+        (Func::IsInf, Float(v, _)) => Bool(v.is_infinite()),
+        (Func::IsNan, Float(v, _)) => Bool(v.is_nan()),
+        (func, Float(v, p)) => {
+            let r = match func {
+                Func::Acos => v.acos(),
+                Func::Acosh => v.acosh(),
+                Func::Asin => v.asin(),
+                Func::Asinh => v.asinh(),
+                Func::Atan => v.atan(),
+                Func::Atanh => v.atanh(),
+                Func::Cos => v.cos(),
+                Func::Cosh => v.cosh(),
+                Func::Sin => v.sin(),
+                Func::Sinh => v.sinh(),
+                Func::Tan => v.tan(),
+                Func::Tanh => v.tanh(),
+                Func::Exp => v.exp(),
+                Func::Exp2 => v.exp2(),
+                Func::Exp10 => 10f64.powf(v),
+                Func::Log => v.ln(),
+                Func::Log2 => v.log2(),
+                Func::Log10 => v.log10(),
+                Func::Sqrt => v.sqrt(),
+                Func::Rsqrt => 1.0 / v.sqrt(),
+                Func::Ceil => v.ceil(),
+                Func::Floor => v.floor(),
+                Func::Fract => v - v.floor(),
+                Func::Trunc => v.trunc(),
+                Func::Round => v.round(),
+                Func::Saturate => v.clamp(0.0, 1.0),
+                func => panic!("{:?} is not defined for {:?}", func, p),
+            };
+            Scalar::float(r, p)
+        }
+        (func, a) => panic!("{:?} is not defined for {:?}", func, a.primitive()),
+    }
+}
+
+pub(super) fn binary(func: &Func, a: Scalar, b: Scalar) -> Scalar {
+    use Scalar::*;
+    let cmp = |o: std::cmp::Ordering| match func {
+        Func::Eq => Some(o.is_eq()),
+        Func::Ne => Some(o.is_ne()),
+        Func::Lt => Some(o.is_lt()),
+        Func::Le => Some(o.is_le()),
+        Func::Gt => Some(o.is_gt()),
+        Func::Ge => Some(o.is_ge()),
+        _ => None,
+    };
+    match (a, b) {
+        (Bool(a), Bool(b)) => Bool(match func {
+            Func::BitAnd => a & b,
+            Func::BitOr => a | b,
+            Func::BitXor => a ^ b,
+            Func::Eq => a == b,
+            Func::Ne => a != b,
+            Func::Min => a & b,
+            Func::Max => a | b,
+            func => panic!("{:?} is not defined for bool", func),
+        }),
+        (Int(a, p), Int(b, _)) => {
+            if let Some(c) = cmp(a.cmp(&b)) {
+                return Bool(c);
+            }
+            let w = bit_width(p);
+            let rotate = |a: i128, s: i128, left: bool| {
+                let mask = (1u128 << w) - 1;
+                let x = (a as u128) & mask;
+                let s = (s.rem_euclid(w as i128)) as u32;
+                if s == 0 {
+                    return x as i128;
+                }
+                let r = if left {
+                    (x << s) | (x >> (w - s))
+                } else {
+                    (x >> s) | (x << (w - s))
+                };
+                (r & mask) as i128
+            };
+            let r = match func {
+                Func::Add => a.wrapping_add(b),
+                Func::Sub => a.wrapping_sub(b),
+                Func::Mul | Func::MatCompMul => a.wrapping_mul(b),
+                // `select` evaluates both branches, so a division guarded by it must not trap
+                Func::Div => a.checked_div(b).unwrap_or(0),
+                Func::Rem => a.checked_rem(b).unwrap_or(0),
+                Func::BitAnd => a & b,
+                Func::BitOr => a | b,
+                Func::BitXor => a ^ b,
+                Func::Shl => a.wrapping_shl((b as u32) & (w - 1)),
+                Func::Shr => a >> ((b as u32) & (w - 1)),
+                Func::RotLeft => rotate(a, b, true),
+                Func::RotRight => rotate(a, b, false),
+                Func::Min => a.min(b),
+                Func::Max => a.max(b),
+                func => panic!("{:?} is not defined for {:?}", func, p),
+            };
+            Scalar::int(r, p)
+        }
+        (Float(a, p), Float(b, _)) => {
+            if let Some(o) = a.partial_cmp(&b) {
+                if let Some(c) = cmp(o) {
+                    return Bool(c);
+                }
+            } else if cmp(std::cmp::Ordering::Equal).is_some() {
+                // comparisons with NaN are false, except `!=`
+                return Bool(matches!(func, Func::Ne));
+            }
+            let r = match func {
+                Func::Add => a + b,
+                Func::Sub => a - b,
+                Func::Mul | Func::MatCompMul => a * b,
+                Func::Div => a / b,
+                Func::Rem => a % b,
+                Func::Min => a.min(b),
+                Func::Max => a.max(b),
+                Func::Atan2 => a.atan2(b),
+                Func::Powf => a.powf(b),
+                Func::Copysign => a.copysign(b),
+                // step(edge, x)
+                Func::Step => {
+                    if b < a {
+                        0.0
+                    } else {
+                        1.0
+                    }
+                }
+                func => panic!("{:?} is not defined for {:?}", func, p),
+            };
+            Scalar::float(r, p)
+        }
+        (Float(a, p), Int(b, _)) if *func == Func::Powi => Scalar::float(a.powi(b as i32), p),
+        (a, b) => panic!(
+            "{:?} is not defined for {:?} and {:?}",
+            func,
+            a.primitive(),
+            b.primitive()
+        ),
+    }
+}
+
+pub(super) fn ternary(func: &Func, a: Scalar, b: Scalar, c: Scalar) -> Scalar {
+    match func {
+        Func::Select => {
+            if a.as_bool() {
+                b
+            } else {
+                c
+            }
+        }
+        Func::Clamp => binary(&Func::Min, binary(&Func::Max, a, b), c),
+        Func::Lerp => {
+            let p = a.primitive();
+            let (a, b, t) = (a.as_f64(), b.as_f64(), c.as_f64());
+            Scalar::float(a + t * (b - a), p)
+        }
+        Func::Fma => {
+            let p = a.primitive();
+            Scalar::float(a.as_f64().mul_add(b.as_f64(), c.as_f64()), p)
+        }
+        // smooth_step(edge0, edge1, x)
+        Func::SmoothStep => {
+            let p = c.primitive();
+            let (e0, e1, x) = (a.as_f64(), b.as_f64(), c.as_f64());
+            let t = ((x - e0) / (e1 - e0)).clamp(0.0, 1.0);
+            Scalar::float(t * t * (3.0 - 2.0 * t), p)
+        }
+        func => panic!("{:?} is not a ternary function", func),
+    }
+}
+
+/// Formats a value for `device_log!`.
+pub(super) fn format_value(ty: &CArc<Type>, bytes: &[u8]) -> String {
+    let scalar = |s: Scalar| match s {
+        Scalar::Bool(v) => v.to_string(),
+        Scalar::Int(v, _) => v.to_string(),
+        Scalar::Float(v, _) => v.to_string(),
+    };
+    match ty.as_ref() {
+        Type::Primitive(_) => scalar(read_lanes(ty, bytes)[0]),
+        Type::Vector(_) => {
+            let lanes = read_lanes(ty, bytes);
+            format!(
+                "({})",
+                lanes.into_iter().map(scalar).collect::<Vec<_>>().join(", ")
+            )
+        }
+        Type::Matrix(m) => {
+            let mut s = String::from("[");
+            for i in 0..m.dimension as usize {
+                let (col, offset) = element(ty, i);
+                if i > 0 {
+                    s.push_str(", ");
+                }
+                s.push_str(&format_value(&col, &bytes[offset..]));
+            }
+            s.push(']');
+            s
+        }
+        Type::Struct(st) => {
+            let mut s = String::from("{");
+            for (i, offset) in field_offsets(st).into_iter().enumerate() {
+                if i > 0 {
+                    s.push_str(", ");
+                }
+                let field = &st.fields.as_ref()[i];
+                write!(s, "{}", format_value(field, &bytes[offset..])).unwrap();
+            }
+            s.push('}');
+            s
+        }
+        Type::Array(a) => {
+            let mut s = String::from("[");
+            for i in 0..a.length {
+                if i > 0 {
+                    s.push_str(", ");
+                }
+                let offset = i * a.element.size();
+                s.push_str(&format_value(&a.element, &bytes[offset..]));
+            }
+            s.push(']');
+            s
+        }
+        _ => format!("<{:?}>", ty),
+    }
+}
@@ -300,6 +300,9 @@ pub(crate) struct FnRecorder {
     pub(crate) shared: Vec<NodeRef>,
     pub(crate) device: Option<WeakDevice>,
     pub(crate) block_size: Option<[u32; 3]>,
+    /// the hidden sizes and offset arguments of a kernel dispatched indirectly
+    /// and the size read from them, see [`crate::resource::set_indirect_dispatch`]
+    pub(crate) indirect_dispatch: Option<(NodeRef, NodeRef, NodeRef)>,
     pub(crate) building_kernel: bool,
     pub(crate) pools: CArc<ModulePools>,
     pub(crate) arena: Rc<Bump>,
@@ -419,6 +422,7 @@ impl FnRecorder {
             shared: vec![],
             device: None,
             block_size: None,
+            indirect_dispatch: None,
             pools: pools.clone(),
             arena: parent
                 .as_ref()
@@ -965,7 +969,7 @@ pub fn outline<F: Fn()>(f: F) {
         let r = r.borrow();
         let r = r.as_ref().unwrap();
         let r = r.borrow();
-        r.device.clone().map(|x| x.upgrade().unwrap())
+        r.device.clone().map(|x| x.upgrade_internal().unwrap())
     });
     Callable::<fn()>::new_maybe_device(device, f).call();
 }
@@ -2,6 +2,7 @@ use std::cell::RefCell;
 
 use crate::internal_prelude::*;
 
+use super::types::array::VLArrayVar;
 use super::with_recorder;
 
 struct AdContext {
@@ -200,6 +201,33 @@ pub fn output_gradients<V: Value>(v: Expr<V>) -> Vec<Expr<V>> {
     grads
 }
 
+/// Whether `block` contains a dynamic loop, including in the callables it calls.
+fn has_dynamic_loop(block: &Pooled<BasicBlock>) -> bool {
+    block
+        .iter()
+        .any(|node| match node.get().instruction.as_ref() {
+            Instruction::Loop { .. } | Instruction::GenericLoop { .. } => true,
+            Instruction::If {
+                true_branch,
+                false_branch,
+                ..
+            } => has_dynamic_loop(true_branch) || has_dynamic_loop(false_branch),
+            Instruction::Switch { default, cases, .. } => {
+                has_dynamic_loop(default)
+                    || cases.as_ref().iter().any(|c| has_dynamic_loop(&c.block))
+            }
+            Instruction::Call(Func::Callable(c), _) => has_dynamic_loop(&c.0.module.entry),
+            _ => false,
+        })
+}
+
+/// Start a *Reverse mode* AD section.
+///
+/// The section may call callables and use `if`, `switch` and [`for_unrolled`]
+/// but no loop with a dynamic trip count. Differentiate such loops with
+/// [`loop_vjp`] instead, which composes with the sections around it.
+///
+/// [`for_unrolled`]: crate::lang::control_flow::for_unrolled
 pub fn autodiff(body: impl Fn()) {
     AD_CONTEXT.with(|c| {
         let mut c = c.borrow_mut();
@@ -219,7 +247,370 @@ pub fn autodiff(body: impl Fn()) {
         c.reset();
     });
     let body = __pop_scope();
+    assert!(
+        !has_dynamic_loop(&body),
+        "autodiff() cannot differentiate loops with a dynamic trip count, use loop_vjp() or for_unrolled()"
+    );
     __current_scope(|b| {
         b.ad_scope(body);
     });
 }
+
+/// Where [`loop_vjp`] keeps the loop state recorded during the forward pass.
+pub enum CheckpointStorage<T: Value> {
+    /// A local array of `ceil(max_iters / interval)` states, allocated by [`loop_vjp`].
+    Local { max_iters: u32 },
+    /// `buffer[offset..]`, e.g. a per-thread region of a global buffer.
+    Buffer {
+        buffer: BufferVar<T>,
+        offset: Expr<u64>,
+    },
+}
+
+/// Checkpointing strategy for [`loop_vjp`].
+pub struct Checkpoint<T: Value> {
+    pub storage: CheckpointStorage<T>,
+    /// Only every `interval`-th state is stored, the states in between are
+    /// recomputed from the closest checkpoint during the reverse pass.
+    pub interval: u32,
+}
+
+impl<T: Value> Checkpoint<T> {
+    /// Checkpoints every iteration into a local array, the trip count must not exceed `max_iters`.
+    pub fn local(max_iters: u32) -> Self {
+        Self {
+            storage: CheckpointStorage::Local { max_iters },
+            interval: 1,
+        }
+    }
+    /// Checkpoints every iteration into `buffer[offset..offset + n]`.
+    pub fn buffer(buffer: &BufferVar<T>, offset: Expr<u64>) -> Self {
+        Self {
+            storage: CheckpointStorage::Buffer {
+                buffer: buffer.clone(),
+                offset,
+            },
+            interval: 1,
+        }
+    }
+    /// Trades memory for recomputation: stores `ceil(n / interval)` states instead of `n`.
+    pub fn interval(self, interval: u32) -> Self {
+        assert!(interval > 0, "checkpoint interval must be greater than 0");
+        Self { interval, ..self }
+    }
+}
+
+enum CheckpointTape<T: Value> {
+    Local(VLArrayVar<T>),
+    Buffer {
+        buffer: BufferVar<T>,
+        offset: Expr<u64>,
+    },
+}
+
+impl<T: Value> CheckpointTape<T> {
+    #[tracked]
+    fn read(&self, i: Expr<u32>) -> Expr<T> {
+        match self {
+            CheckpointTape::Local(a) => a.read(i),
+            CheckpointTape::Buffer { buffer, offset } => buffer.read(*offset + i.as_u64()),
+        }
+    }
+    #[tracked]
+    fn write(&self, i: Expr<u32>, v: Expr<T>) {
+        match self {
+            CheckpointTape::Local(a) => a.write(i, v),
+            CheckpointTape::Buffer { buffer, offset } => buffer.write(*offset + i.as_u64(), v),
+        }
+    }
+}
+
+/// Reverse mode AD through a loop with a dynamic trip count.
+///
+/// Runs `state = body(i, state)` for `i in 0..n` starting from `init`, calls
+/// `output_grad` with the final state to obtain the gradient of the loss w.r.t. it,
+/// then walks the loop backwards and returns `(final state, gradient w.r.t. init)`.
+///
+/// The input state of every iteration is restored from `checkpoint` and the
+/// iteration is differentiated in its own [`autodiff`] section, so `body` may
+/// use anything that is supported there, including callables and control flow.
+/// Parameters that need gradients should be carried in the state and passed
+/// through unchanged by `body`; their gradients then accumulate over all iterations.
+///
+/// Must be called outside of an [`autodiff`] section, as sections cannot
+/// contain dynamic loops. Code around the loop is differentiated in sections
+/// of its own: `output_grad` may open one for the code after the loop, and the
+/// code computing `init` is differentiated by recomputing it in a section that
+/// calls [`backward_with_grad`] with the returned gradient:
+/// ```no_run
+/// # use luisa_compute::prelude::*;
+/// # use luisa_compute::lang::autodiff::*;
+/// # use luisa_compute::lang::types::vector::alias::*;
+/// # fn f(a: Expr<Float2>, n: Expr<u32>, step: impl Fn(Expr<u32>, Expr<Float2>) -> Expr<Float2>) {
+/// let prologue = |a: Expr<Float2>| a * a;
+/// let (_, d_init) = loop_vjp(n, prologue(a), Checkpoint::local(16), step, |s| {
+///     let d_s = Float2::var_zeroed();
+///     autodiff(|| {
+///         requires_grad(s);
+///         backward(s.x * s.x);
+///         d_s.store(gradient(s));
+///     });
+///     d_s.load()
+/// });
+/// let d_a = Float2::var_zeroed();
+/// autodiff(|| {
+///     requires_grad(a);
+///     backward_with_grad(prologue(a), d_init);
+///     d_a.store(gradient(a));
+/// });
+/// # }
+/// ```
+#[tracked]
+pub fn loop_vjp<T: Value>(
+    n: Expr<u32>,
+    init: Expr<T>,
+    checkpoint: Checkpoint<T>,
+    body: impl Fn(Expr<u32>, Expr<T>) -> Expr<T>,
+    output_grad: impl FnOnce(Expr<T>) -> Expr<T>,
+) -> (Expr<T>, Expr<T>) {
+    AD_CONTEXT.with(|c| {
+        let c = c.borrow();
+        assert!(
+            !c.started,
+            "loop_vjp() cannot be called inside an autodiff section"
+        );
+    });
+    let interval = checkpoint.interval;
+    let tape = match checkpoint.storage {
+        CheckpointStorage::Local { max_iters } => {
+            if need_runtime_check() {
+                lc_assert!(n <= max_iters);
+            }
+            CheckpointTape::Local(VLArrayVar::<T>::zero(
+                ((max_iters + interval - 1) / interval).max(1) as usize,
+            ))
+        }
+        CheckpointStorage::Buffer { buffer, offset } => CheckpointTape::Buffer { buffer, offset },
+    };
+    let state = init.var();
+    for i in 0..n {
+        if i % interval == 0 {
+            tape.write(i / interval, **state);
+        }
+        *state = body(i, **state);
+    }
+    let output = **state;
+    let adj = output_grad(output).var();
+    for k in 0..n {
+        let i = n - 1 - k;
+        // recompute the input state of iteration `i` from the closest checkpoint
+        let c = i / interval;
+        let s = tape.read(c).var();
+        for j in c * interval..i {
+            *s = body(j, **s);
+        }
+        let s = **s;
+        autodiff(|| {
+            requires_grad(s);
+            let next = body(i, s);
+            backward_with_grad(next, **adj);
+            *adj = gradient(s);
+        });
+    }
+    (output, **adj)
+}
+
+/// Settings for [`check_gradients`].
+#[derive(Clone, Copy, Debug)]
+pub struct GradientCheckOptions {
+    /// Step size of the central differences.
+    pub eps: f32,
+    /// A component passes if either its absolute or its relative error is within tolerance.
+    pub abs_tol: f32,
+    pub rel_tol: f32,
+}
+
+impl Default for GradientCheckOptions {
+    fn default() -> Self {
+        Self {
+            eps: 1e-3,
+            abs_tol: 1e-3,
+            rel_tol: 1e-2,
+        }
+    }
+}
+
+/// Gradient of a single scalar component of one input at one sample point.
+#[derive(Clone, Copy, Debug)]
+pub struct GradientError {
+    pub sample: usize,
+    pub input: usize,
+    pub component: usize,
+    pub autodiff: f32,
+    pub finite_difference: f32,
+    pub abs_error: f32,
+    pub rel_error: f32,
+}
+
+#[derive(Clone, Debug)]
+pub struct GradientCheckReport {
+    pub options: GradientCheckOptions,
+    /// One entry per sample, input and component, in that order.
+    pub errors: Vec<GradientError>,
+}
+
+impl GradientCheckReport {
+    pub fn max_abs_error(&self) -> f32 {
+        self.errors.iter().map(|e| e.abs_error).fold(0.0, f32::max)
+    }
+    pub fn max_rel_error(&self) -> f32 {
+        self.errors.iter().map(|e| e.rel_error).fold(0.0, f32::max)
+    }
+    /// Components whose absolute and relative errors both exceed the tolerance.
+    /// NaN gradients always fail.
+    pub fn failures(&self) -> impl Iterator<Item = &GradientError> {
+        let options = self.options;
+        self.errors
+            .iter()
+            .filter(move |e| !(e.abs_error <= options.abs_tol || e.rel_error <= options.rel_tol))
+    }
+    pub fn is_ok(&self) -> bool {
+        self.failures().next().is_none()
+    }
+    /// Panics with the worst offending components if the check failed.
+    pub fn assert_ok(&self) {
+        let mut failures = self.failures().copied().collect::<Vec<_>>();
+        if failures.is_empty() {
+            return;
+        }
+        let n_failures = failures.len();
+        failures.sort_by(|a, b| b.abs_error.total_cmp(&a.abs_error));
+        let worst = failures
+            .iter()
+            .take(8)
+            .map(|e| format!("  {:?}", e))
+            .collect::<Vec<_>>()
+            .join("\n");
+        panic!(
+            "gradient check failed for {} out of {} components, worst:\n{}",
+            n_failures,
+            self.errors.len(),
+            worst
+        );
+    }
+}
+
+fn component<T: Linear<Scalar = f32>>(v: Expr<T>, i: usize) -> Expr<f32> {
+    if T::N == 1 {
+        Expr::<f32>::from_node(v.node())
+    } else {
+        Expr::<f32>::from_node(__extract::<f32>(v.node(), i))
+    }
+}
+
+fn with_component<T: Linear<Scalar = f32>>(v: Expr<T>, i: usize, x: Expr<f32>) -> Expr<T> {
+    if T::N == 1 {
+        Expr::<T>::from_node(x.node())
+    } else {
+        Expr::<T>::from_node(__insert::<T>(v.node().get(), i, x.node().get()).into())
+    }
+}
+
+/// Compares the gradients computed by [`backward`] against central differences.
+///
+/// `f` maps the inputs to a scalar and is traced twice: once inside an [`autodiff`]
+/// section and once per input component for the finite differences. Every entry of
+/// `samples` is one evaluation point holding a value for each input; all of them are
+/// evaluated in a single dispatch on `device`.
+///
+/// ```no_run
+/// # use luisa_compute::prelude::*;
+/// # use luisa_compute::lang::autodiff::*;
+/// # use luisa_compute::lang::types::vector::alias::*;
+/// # fn f(device: &Device) {
+/// let samples = vec![vec![Float3::new(0.1, 0.2, 0.3), Float3::new(1.0, 2.0, 3.0)]];
+/// check_gradients(device, &samples, &GradientCheckOptions::default(), |x| {
+///     x[0].dot(x[1]).sin()
+/// })
+/// .assert_ok();
+/// # }
+/// ```
+pub fn check_gradients<T: Linear<Scalar = f32>>(
+    device: &Device,
+    samples: &[Vec<T>],
+    options: &GradientCheckOptions,
+    f: impl Fn(&[Expr<T>]) -> Expr<f32>,
+) -> GradientCheckReport {
+    assert!(!samples.is_empty(), "no samples to check");
+    let n_inputs = samples[0].len();
+    assert!(n_inputs > 0, "no inputs to check");
+    assert!(
+        samples.iter().all(|s| s.len() == n_inputs),
+        "all samples must have the same number of inputs"
+    );
+    let n_components = n_inputs * T::N;
+    let inputs = device.create_buffer_from_fn(samples.len() * n_inputs, |i| {
+        samples[i / n_inputs][i % n_inputs]
+    });
+    let grad_ad = device.create_buffer::<f32>(samples.len() * n_components);
+    let grad_fd = device.create_buffer::<f32>(samples.len() * n_components);
+    let eps = options.eps;
+    let kernel = device.create_kernel::<fn()>(&|| {
+        let tid = dispatch_id().x;
+        let inputs_var = inputs.var();
+        let grad_ad = grad_ad.var();
+        let grad_fd = grad_fd.var();
+        let x = (0..n_inputs)
+            .map(|i| inputs_var.read(track!(tid * n_inputs as u32 + i as u32)))
+            .collect::<Vec<_>>();
+        let out = |i: usize, c: usize| {
+            let k = (i * T::N + c) as u32;
+            track!(tid * n_components as u32 + k)
+        };
+        autodiff(|| {
+            for x in &x {
+                requires_grad(*x);
+            }
+            backward(f(&x));
+            for i in 0..n_inputs {
+                let g = gradient(x[i]);
+                for c in 0..T::N {
+                    grad_ad.write(out(i, c), component(g, c));
+                }
+            }
+        });
+        for i in 0..n_inputs {
+            for c in 0..T::N {
+                let xc = component(x[i], c);
+                let mut x_add = x.clone();
+                x_add[i] = with_component(x[i], c, track!(xc + eps));
+                let mut x_sub = x.clone();
+                x_sub[i] = with_component(x[i], c, track!(xc - eps));
+                let (f_add, f_sub) = (f(&x_add), f(&x_sub));
+                grad_fd.write(out(i, c), track!((f_add - f_sub) / (2.0 * eps)));
+            }
+        }
+    });
+    kernel.dispatch([samples.len() as u32, 1, 1]);
+    let grad_ad = grad_ad.copy_to_vec();
+    let grad_fd = grad_fd.copy_to_vec();
+    let errors = (0..samples.len() * n_components)
+        .map(|k| {
+            let (ad, fd) = (grad_ad[k], grad_fd[k]);
+            let abs_error = (ad - fd).abs();
+            GradientError {
+                sample: k / n_components,
+                input: k % n_components / T::N,
+                component: k % T::N,
+                autodiff: ad,
+                finite_difference: fd,
+                abs_error,
+                rel_error: abs_error / ad.abs().max(fd.abs()).max(f32::MIN_POSITIVE),
+            }
+        })
+        .collect();
+    GradientCheckReport {
+        options: *options,
+        errors,
+    }
+}
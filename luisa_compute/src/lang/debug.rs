@@ -39,7 +39,7 @@ macro_rules! lc_assert {
     };
 }
 pub fn __cpu_dbg<V: Value + Debug>(arg: Expr<V>, file: &'static str, line: u32) {
-    if !is_cpu_backend() {
+    if !is_host_backend() {
         return;
     }
     let f = CpuFn::new(move |x: &mut V| {
@@ -65,6 +65,25 @@ pub fn is_cpu_backend() -> bool {
     })
 }
 
+/// Whether kernels run on the host, i.e. on the cpu or the interpreter device,
+/// so that [`CpuFn`]s can be called.
+pub fn is_host_backend() -> bool {
+    with_recorder(|r| {
+        if r.device.is_none() {
+            return false;
+        }
+        r.device
+            .as_ref()
+            .unwrap()
+            .upgrade()
+            .unwrap()
+            .inner
+            .query("device_name")
+            .map(|s| s == "cpu" || s == "interpreter")
+            .unwrap_or(false)
+    })
+}
+
 pub fn __env_need_backtrace() -> bool {
     match std::env::var("LUISA_BACKTRACE") {
         Ok(s) => s == "1" || s == "ON",
@@ -32,17 +32,18 @@ impl<T: Value> CpuFn<T> {
     }
     pub fn call(&self, arg: impl AsExpr<Value = T>) -> Expr<T> {
         with_recorder(|r| {
-            assert_eq!(
-                r.device
-                    .as_ref()
-                    .unwrap()
-                    .upgrade()
-                    .unwrap()
-                    .inner
-                    .query("device_name")
-                    .unwrap(),
-                "cpu",
-                "CpuFn can only be used in cpu backend"
+            let device_name = r
+                .device
+                .as_ref()
+                .unwrap()
+                .upgrade()
+                .unwrap()
+                .inner
+                .query("device_name")
+                .unwrap();
+            assert!(
+                device_name == "cpu" || device_name == "interpreter",
+                "CpuFn can only be used in cpu or interpreter backend"
             );
             let addr = CArc::as_ptr(&self.op) as u64;
             if let Some((_, op)) = r.cpu_custom_ops.get(&addr) {
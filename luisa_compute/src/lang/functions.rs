@@ -18,7 +18,13 @@ pub fn dispatch_id() -> Expr<Uint3> {
     )
 }
 
+/// The size of the dispatch. In kernels dispatched indirectly, this is the size
+/// read from the [`crate::resource::IndirectDispatchBuffer`], except inside
+/// callables, where it is the size of the launched grid.
 pub fn dispatch_size() -> Expr<Uint3> {
+    if let Some((_, _, size)) = with_recorder(|r| r.indirect_dispatch) {
+        return Expr::<Uint3>::from_node(size.into());
+    }
     Expr::<Uint3>::from_node(
         __current_scope(|b| b.call(Func::DispatchSize, &[], Uint3::type_())).into(),
     )
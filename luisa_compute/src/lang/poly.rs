@@ -144,7 +144,7 @@ impl<K: Hash + Eq + Clone + 'static + Debug, T: ?Sized + 'static> PolymorphicBui
             self.key_to_tag.insert(pair, tag);
             let key = key.clone();
             self.arrays.push(PolyVec {
-                device: self.device.clone(),
+                device: self.device.internal(),
                 push: Box::new(|array: &mut dyn Any, value: *const u8| -> u32 {
                     let value: &U = unsafe { &*(value as *const U) };
                     let array = array.downcast_mut::<Vec<U>>().unwrap();
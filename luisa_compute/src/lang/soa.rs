@@ -1,15 +1,22 @@
 use crate::internal_prelude::*;
 use crate::prelude::*;
-use crate::runtime::submit_default_stream;
+use crate::runtime::{submit_default_stream, submit_default_stream_and_sync};
 use parking_lot::Mutex;
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
 use std::ops::RangeBounds;
 use std::sync::Arc;
 
 use crate::runtime::Kernel;
 
 use super::index::IntoIndex;
-use super::types::SoaValue;
+use super::types::{SoaBufferProxy, SoaFieldsProxy, SoaValue};
 /** A buffer with SOA layout.
+
+Each field of `T` is stored in its own array, so kernels reading a single
+field, e.g. `soa.position.read(i)` on [`SoaBuffer::var`], only load that field.
+Nested `#[derive(Soa)]` structs, vectors, matrices and arrays are split down to
+their primitives. A field can be viewed on the host with [`SoaBuffer::field`].
  */
 pub struct SoaBuffer<T: SoaValue> {
     pub(crate) device: Device,
@@ -17,6 +24,8 @@ pub struct SoaBuffer<T: SoaValue> {
     pub(crate) metadata_buf: Arc<Buffer<SoaMetadata>>,
     pub(crate) metadata: SoaMetadata,
     pub(crate) copy_kernel: Mutex<Option<SoaBufferCopyKernel<T>>>,
+    /// [`SoaFieldCopyKernel`]s by field type and global offset
+    pub(crate) field_copy_kernels: Mutex<HashMap<(TypeId, usize), Box<dyn Any + Send + Sync>>>,
     pub(crate) _marker: std::marker::PhantomData<T>,
 }
 pub(crate) struct SoaBufferCopyKernel<T: SoaValue> {
@@ -41,6 +50,30 @@ impl<T: SoaValue> SoaBufferCopyKernel<T> {
         Self { copy_to, copy_from }
     }
 }
+pub(crate) struct SoaFieldCopyKernel<F: SoaValue> {
+    copy_to: Kernel<fn(ByteBuffer, Buffer<SoaMetadata>, Buffer<F>)>,
+    copy_from: Kernel<fn(ByteBuffer, Buffer<SoaMetadata>, Buffer<F>)>,
+}
+impl<F: SoaValue> SoaFieldCopyKernel<F> {
+    #[tracked]
+    fn new(device: &Device, global_offset: usize) -> Self {
+        let copy_to = device.create_kernel::<fn(ByteBuffer, Buffer<SoaMetadata>, Buffer<F>)>(
+            &|storage, meta, buf| {
+                let field = F::SoaBuffer::from_soa_storage(storage, meta.read(0), global_offset);
+                let i = dispatch_id().x.as_u64();
+                buf.write(i, field.read(i));
+            },
+        );
+        let copy_from = device.create_kernel::<fn(ByteBuffer, Buffer<SoaMetadata>, Buffer<F>)>(
+            &|storage, meta, buf| {
+                let field = F::SoaBuffer::from_soa_storage(storage, meta.read(0), global_offset);
+                let i = dispatch_id().x.as_u64();
+                field.write(i, buf.read(i));
+            },
+        );
+        Self { copy_to, copy_from }
+    }
+}
 impl<T: SoaValue> SoaBuffer<T> {
     pub fn var(&self) -> SoaBufferVar<T> {
         self.view(..).var()
@@ -95,6 +128,23 @@ impl<T: SoaValue> SoaBuffer<T> {
     pub fn copy_to_buffer(&self, buffer: &Buffer<T>) {
         self.view(..).copy_to_buffer(buffer)
     }
+    pub fn copy_from(&self, data: &[T]) {
+        self.view(..).copy_from(data)
+    }
+    pub fn copy_to(&self, data: &mut [T]) {
+        self.view(..).copy_to(data)
+    }
+    pub fn copy_to_vec(&self) -> Vec<T> {
+        self.view(..).copy_to_vec()
+    }
+    /// A field of every element, selected from the fields of `T`, e.g.
+    /// `soa.field(|x| x.position)` or `soa.field(|x| x.weights[1].clone())`.
+    pub fn field<P: SoaFieldsProxy>(
+        &self,
+        select: impl FnOnce(T::SoaFields) -> P,
+    ) -> SoaFieldView<'_, T, P::Value> {
+        self.view(..).field(select)
+    }
 }
 impl<'a, T: SoaValue> SoaBufferView<'a, T> {
     fn init_copy_kernel(&self) {
@@ -146,6 +196,251 @@ impl<'a, T: SoaValue> SoaBufferView<'a, T> {
     pub fn copy_to_buffer(&self, buffer: &Buffer<T>) {
         submit_default_stream(&self.buffer.device, [self.copy_to_buffer_async(buffer)]);
     }
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.metadata.view_count as usize
+    }
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    /// Copies `data` into the view through a temporary AOS buffer.
+    pub fn copy_from(&self, data: &[T]) {
+        assert_eq!(
+            data.len(),
+            self.len(),
+            "length mismatch copying from host to soa buffer view"
+        );
+        if data.is_empty() {
+            return;
+        }
+        let staging = self.buffer.device.create_buffer_from_slice(data);
+        submit_default_stream_and_sync(
+            &self.buffer.device,
+            [self.copy_from_buffer_async(&staging)],
+        );
+    }
+    /// Copies the view into `data` through a temporary AOS buffer.
+    pub fn copy_to(&self, data: &mut [T]) {
+        assert_eq!(
+            data.len(),
+            self.len(),
+            "length mismatch copying from soa buffer view to host"
+        );
+        if data.is_empty() {
+            return;
+        }
+        let staging = self.buffer.device.create_buffer::<T>(data.len());
+        submit_default_stream_and_sync(&self.buffer.device, [self.copy_to_buffer_async(&staging)]);
+        staging.copy_to(data);
+    }
+    pub fn copy_to_vec(&self) -> Vec<T> {
+        let mut data = Vec::with_capacity(self.len());
+        unsafe {
+            let slice = std::slice::from_raw_parts_mut(data.as_mut_ptr(), self.len());
+            self.copy_to(slice);
+            data.set_len(self.len());
+        }
+        data
+    }
+    /// A field of every element of the view, see [`SoaBuffer::field`].
+    pub fn field<P: SoaFieldsProxy>(
+        &self,
+        select: impl FnOnce(T::SoaFields) -> P,
+    ) -> SoaFieldView<'a, T, P::Value> {
+        SoaFieldView {
+            view: self.clone(),
+            global_offset: 0,
+            _marker: PhantomData,
+        }
+        .field(select)
+    }
+}
+
+/// A field of the elements of a [`SoaBufferView`], see [`SoaBuffer::field`].
+pub struct SoaFieldView<'a, T: SoaValue, F: SoaValue> {
+    pub(crate) view: SoaBufferView<'a, T>,
+    /// index of the first 32bits buffer of the field
+    pub(crate) global_offset: usize,
+    pub(crate) _marker: PhantomData<F>,
+}
+impl<'a, T: SoaValue, F: SoaValue> Clone for SoaFieldView<'a, T, F> {
+    fn clone(&self) -> Self {
+        Self {
+            view: self.view.clone(),
+            global_offset: self.global_offset,
+            _marker: PhantomData,
+        }
+    }
+}
+impl<'a, T: SoaValue, F: SoaValue> SoaFieldView<'a, T, F> {
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.view.len()
+    }
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.view.is_empty()
+    }
+    /// A field of this field, e.g. `soa.field(|x| x.position).field(|p| p.x)`.
+    pub fn field<P: SoaFieldsProxy>(
+        &self,
+        select: impl FnOnce(F::SoaFields) -> P,
+    ) -> SoaFieldView<'a, T, P::Value> {
+        let field = select(F::SoaFields::from_global_offset(self.global_offset));
+        let offset = field.global_offset();
+        assert!(
+            offset >= self.global_offset
+                && offset + <P::Value as SoaValue>::SoaBuffer::num_buffers()
+                    <= self.global_offset + F::SoaBuffer::num_buffers(),
+            "`{}` field at global offset {} is not a field of `{}`",
+            std::any::type_name::<P::Value>(),
+            offset,
+            std::any::type_name::<F>()
+        );
+        SoaFieldView {
+            view: self.view.clone(),
+            global_offset: offset,
+            _marker: PhantomData,
+        }
+    }
+    /// Reads and writes only this field in kernels, see [`SoaBufferView::var`].
+    pub fn var(&self) -> SoaBufferVar<F> {
+        SoaBufferVar {
+            proxy: F::SoaBuffer::from_soa_storage(
+                self.view.buffer.storage.var(),
+                self.view.metadata_buf.read(0),
+                self.global_offset,
+            ),
+        }
+    }
+    /// Whether the field is stored as a plain array, i.e. is a primitive of at
+    /// least 32bits, and can be viewed with [`SoaFieldView::buffer_view`].
+    #[inline]
+    pub fn is_dense(&self) -> bool {
+        F::SoaFields::DENSE
+    }
+    /// The field as a plain buffer view.
+    ///
+    /// Panics if the field is not [dense](SoaFieldView::is_dense), or is a 64bits
+    /// primitive misaligned by the fields stored before it.
+    pub fn buffer_view(&self) -> BufferView<F> {
+        assert!(
+            self.is_dense(),
+            "`{}` field of soa buffer is not stored as a plain array",
+            std::any::type_name::<F>()
+        );
+        let size = std::mem::size_of::<F>();
+        let start = self.start_bytes();
+        self.view
+            .buffer
+            .storage
+            .view(start..start + self.len() * size)
+            .reinterpret::<F>()
+    }
+    /// Byte offset of the first element of a dense field in the storage.
+    fn start_bytes(&self) -> usize {
+        let metadata = &self.view.metadata;
+        self.global_offset * metadata.count as usize * 4
+            + metadata.view_start as usize * std::mem::size_of::<F>()
+    }
+    /// Whether the field can be copied through [`SoaFieldView::buffer_view`],
+    /// otherwise copies go through a kernel.
+    fn is_plain(&self) -> bool {
+        let start = self.start_bytes();
+        self.is_dense()
+            && start % std::mem::size_of::<F>() == 0
+            && start % F::type_().alignment() == 0
+    }
+    fn with_copy_kernel<R>(&self, f: impl FnOnce(&SoaFieldCopyKernel<F>) -> R) -> R {
+        let buffer = self.view.buffer;
+        let mut kernels = buffer.field_copy_kernels.lock();
+        let kernel = kernels
+            .entry((TypeId::of::<F>(), self.global_offset))
+            .or_insert_with(|| {
+                Box::new(SoaFieldCopyKernel::<F>::new(
+                    &buffer.device,
+                    self.global_offset,
+                ))
+            });
+        f(kernel.downcast_ref().unwrap())
+    }
+    pub fn copy_from_buffer_async(&self, buffer: &Buffer<F>) -> Command<'static, 'static> {
+        assert_eq!(self.len(), buffer.len());
+        self.with_copy_kernel(|kernel| {
+            kernel.copy_from.dispatch_async(
+                [self.len() as u32, 1, 1],
+                &*self.view.buffer.storage,
+                &*self.view.metadata_buf,
+                buffer,
+            )
+        })
+    }
+    pub fn copy_from_buffer(&self, buffer: &Buffer<F>) {
+        submit_default_stream(
+            &self.view.buffer.device,
+            [self.copy_from_buffer_async(buffer)],
+        );
+    }
+    pub fn copy_to_buffer_async(&self, buffer: &Buffer<F>) -> Command<'static, 'static> {
+        assert_eq!(self.len(), buffer.len());
+        self.with_copy_kernel(|kernel| {
+            kernel.copy_to.dispatch_async(
+                [self.len() as u32, 1, 1],
+                &*self.view.buffer.storage,
+                &*self.view.metadata_buf,
+                buffer,
+            )
+        })
+    }
+    pub fn copy_to_buffer(&self, buffer: &Buffer<F>) {
+        submit_default_stream(
+            &self.view.buffer.device,
+            [self.copy_to_buffer_async(buffer)],
+        );
+    }
+    pub fn copy_from(&self, data: &[F]) {
+        assert_eq!(
+            data.len(),
+            self.len(),
+            "length mismatch copying from host to soa buffer field"
+        );
+        if data.is_empty() {
+            return;
+        }
+        if self.is_plain() {
+            return self.buffer_view().copy_from(data);
+        }
+        let device = &self.view.buffer.device;
+        let staging = device.create_buffer_from_slice(data);
+        submit_default_stream_and_sync(device, [self.copy_from_buffer_async(&staging)]);
+    }
+    pub fn copy_to(&self, data: &mut [F]) {
+        assert_eq!(
+            data.len(),
+            self.len(),
+            "length mismatch copying from soa buffer field to host"
+        );
+        if data.is_empty() {
+            return;
+        }
+        if self.is_plain() {
+            return self.buffer_view().copy_to(data);
+        }
+        let device = &self.view.buffer.device;
+        let staging = device.create_buffer::<F>(data.len());
+        submit_default_stream_and_sync(device, [self.copy_to_buffer_async(&staging)]);
+        staging.copy_to(data);
+    }
+    pub fn copy_to_vec(&self) -> Vec<F> {
+        let mut data = Vec::with_capacity(self.len());
+        unsafe {
+            let slice = std::slice::from_raw_parts_mut(data.as_mut_ptr(), self.len());
+            self.copy_to(slice);
+            data.set_len(self.len());
+        }
+        data
+    }
 }
 #[derive(Clone, Copy, Value, PartialEq, Eq, Hash, Debug)]
 #[repr(C)]
@@ -156,6 +451,7 @@ pub struct SoaMetadata {
     pub view_start: u64,
     pub view_count: u64,
 }
+#[derive(Clone)]
 pub struct SoaBufferView<'a, T: SoaValue> {
     pub(crate) metadata_buf: Arc<Buffer<SoaMetadata>>,
     pub(crate) metadata: SoaMetadata,
@@ -55,6 +55,7 @@ pub trait Value: Copy + TypeOf + 'static {
 
 pub trait SoaValue: Value {
     type SoaBuffer: SoaBufferProxy<Value = Self>;
+    type SoaFields: SoaFieldsProxy<Value = Self>;
 }
 
 /// A trait for implementing remote impls on top of an [`Expr`] using [`Deref`].
@@ -100,6 +101,22 @@ pub trait SoaBufferProxy: IndexRead<Element = Self::Value> + IndexWrite + 'stati
     ) -> Self;
 }
 
+/// The fields of a [`SoaValue`] on the host, mirroring its [`SoaBufferProxy`].
+///
+/// Used to select a field with [`SoaBuffer::field`](crate::lang::soa::SoaBuffer::field),
+/// e.g. `soa.field(|x| x.position)`.
+pub trait SoaFieldsProxy: Clone + 'static {
+    type Value: SoaValue<SoaFields = Self>;
+
+    /// Whether the values are stored as a plain array of `Self::Value`, so
+    /// that the field can be viewed as a [`BufferView`](crate::resource::BufferView).
+    const DENSE: bool = false;
+
+    fn from_global_offset(global_offset: usize) -> Self;
+    /// Index of the first 32bits buffer of the field.
+    fn global_offset(&self) -> usize;
+}
+
 pub(crate) struct ExprProxyData<T: Value> {
     pub(crate) data: UnsafeCell<Option<T::Expr>>,
 }
@@ -25,8 +25,43 @@ pub struct ArraySoa<T: SoaValue, const N: usize> {
     pub(crate) elems: Vec<T::SoaBuffer>,
     _marker: PhantomData<[T; N]>,
 }
+impl<T: SoaValue, const N: usize> Index<usize> for ArraySoa<T, N> {
+    type Output = T::SoaBuffer;
+    fn index(&self, i: usize) -> &Self::Output {
+        &self.elems[i]
+    }
+}
+#[derive(Clone)]
+pub struct ArraySoaFields<T: SoaValue, const N: usize> {
+    global_offset: usize,
+    elems: Vec<T::SoaFields>,
+}
+impl<T: SoaValue, const N: usize> Index<usize> for ArraySoaFields<T, N> {
+    type Output = T::SoaFields;
+    fn index(&self, i: usize) -> &Self::Output {
+        &self.elems[i]
+    }
+}
 impl<T: SoaValue, const N: usize> SoaValue for [T; N] {
     type SoaBuffer = ArraySoa<T, N>;
+    type SoaFields = ArraySoaFields<T, N>;
+}
+impl<T: SoaValue, const N: usize> SoaFieldsProxy for ArraySoaFields<T, N> {
+    type Value = [T; N];
+    fn from_global_offset(global_offset: usize) -> Self {
+        let elems = (0..N)
+            .map(|i| {
+                T::SoaFields::from_global_offset(global_offset + i * T::SoaBuffer::num_buffers())
+            })
+            .collect::<Vec<_>>();
+        Self {
+            global_offset,
+            elems,
+        }
+    }
+    fn global_offset(&self) -> usize {
+        self.global_offset
+    }
 }
 impl<T: SoaValue, const N: usize> SoaBufferProxy for ArraySoa<T, N> {
     type Value = [T; N];
@@ -228,6 +228,28 @@ fn check_soa_impl() {
     let _u32: SoaBuffer<u32> = unimplemented!();
     let _u64: SoaBuffer<u64> = unimplemented!();
 }
+#[derive(Clone, Copy, Debug)]
+pub struct PrimitiveSoaFields<T> {
+    global_offset: usize,
+    _marker: std::marker::PhantomData<fn() -> T>,
+}
+impl<T: Primitive> SoaFieldsProxy for PrimitiveSoaFields<T>
+where
+    T: SoaValue<SoaFields = Self>,
+{
+    type Value = T;
+    // smaller primitives are widened to 32bits
+    const DENSE: bool = std::mem::size_of::<T>() >= 4;
+    fn from_global_offset(global_offset: usize) -> Self {
+        Self {
+            global_offset,
+            _marker: std::marker::PhantomData,
+        }
+    }
+    fn global_offset(&self) -> usize {
+        self.global_offset
+    }
+}
 impl<T: Primitive> SoaBufferProxy for PrimitiveSoaProxy<T>
 where
     Self: IndexRead<Element = T> + IndexWrite,
@@ -266,6 +288,7 @@ where
     PrimitiveSoaProxy<T>: IndexWrite + IndexRead<Element = T>,
 {
     type SoaBuffer = PrimitiveSoaProxy<T>;
+    type SoaFields = PrimitiveSoaFields<T>;
 }
 
 impl_simple_expr_proxy!([T: Primitive] PrimitiveExpr[T] for T);
@@ -92,7 +92,7 @@ impl<X: FromNode + Copy> FromNode for DoubledProxyData<X> {
 }
 
 macro_rules! vector_proxies {
-    ($N:literal [ $($real_c:ident),* ] [ $($c:ident),* ]: $ExprName:ident, $VarName:ident, $AtomicName:ident, $SoaName:ident) => {
+    ($N:literal [ $($real_c:ident),* ] [ $($c:ident),* ]: $ExprName:ident, $VarName:ident, $AtomicName:ident, $SoaName:ident, $SoaFieldsName:ident) => {
         #[repr(C)]
         #[derive(Copy, Clone)]
         pub struct $ExprName<T: VectorAlign<$N>> {
@@ -117,8 +117,34 @@ macro_rules! vector_proxies {
         pub struct $SoaName<T: VectorAlign<$N> + SoaValue> {
             $(pub $c: T::SoaBuffer),*
         }
+        #[derive(Clone)]
+        pub struct $SoaFieldsName<T: VectorAlign<$N> + SoaValue> {
+            global_offset: usize,
+            $(pub $c: T::SoaFields),*
+        }
         impl<T: VectorAlign<$N> + SoaValue> SoaValue for Vector<T, $N> {
             type SoaBuffer = $SoaName<T>;
+            type SoaFields = $SoaFieldsName<T>;
+        }
+        impl<T: VectorAlign<$N> + SoaValue> SoaFieldsProxy for $SoaFieldsName<T> {
+            type Value = Vector<T, $N>;
+            #[allow(unused_assignments)]
+            fn from_global_offset(global_offset: usize) -> Self {
+                let s = <T::SoaBuffer as SoaBufferProxy>::num_buffers();
+                let mut i = 0;
+                $(
+                    let $c = T::SoaFields::from_global_offset(global_offset + i * s);
+                    i += 1;
+                    if i >= $N { i = 0; }
+                )*
+                Self{
+                    global_offset,
+                    $($c),*
+                }
+            }
+            fn global_offset(&self) -> usize {
+                self.global_offset
+            }
         }
         impl<T: VectorAlign<$N> + SoaValue> SoaBufferProxy for $SoaName<T> {
             type Value = Vector<T, $N>;
@@ -252,9 +278,9 @@ macro_rules! vector_proxies {
     }
 }
 
-vector_proxies!(2 [x, y] [x, y]: VectorExprProxy2, VectorVarProxy2, VectorAtomicRefProxy2, VectorSoaProxy2);
-vector_proxies!(3 [x, y, z] [x, y, z, r, g, b]: VectorExprProxy3, VectorVarProxy3, VectorAtomicRefProxy3, VectorSoaProxy3);
-vector_proxies!(4 [x, y, z, w] [x, y, z, w, r, g, b, a]: VectorExprProxy4, VectorVarProxy4, VectorAtomicRefProxy4, VectorSoaProxy4);
+vector_proxies!(2 [x, y] [x, y]: VectorExprProxy2, VectorVarProxy2, VectorAtomicRefProxy2, VectorSoaProxy2, VectorSoaFields2);
+vector_proxies!(3 [x, y, z] [x, y, z, r, g, b]: VectorExprProxy3, VectorVarProxy3, VectorAtomicRefProxy3, VectorSoaProxy3, VectorSoaFields3);
+vector_proxies!(4 [x, y, z, w] [x, y, z, w, r, g, b, a]: VectorExprProxy4, VectorVarProxy4, VectorAtomicRefProxy4, VectorSoaProxy4, VectorSoaFields4);
 
 impl<T: VectorAlign<N>, const N: usize> TypeOf for Vector<T, N> {
     fn type_() -> CArc<Type> {
@@ -466,7 +492,7 @@ where
 }
 
 macro_rules! matrix_proxies {
-    ($N:literal [ $($real_c:ident),* ] [ $($c:ident),* ]: $ExprName:ident, $VarName:ident, $AtomicName:ident, $SoaName:ident) => {
+    ($N:literal [ $($real_c:ident),* ] [ $($c:ident),* ]: $ExprName:ident, $VarName:ident, $AtomicName:ident, $SoaName:ident, $SoaFieldsName:ident) => {
         #[repr(C)]
         #[derive(Copy, Clone)]
         pub struct $ExprName {
@@ -492,8 +518,35 @@ macro_rules! matrix_proxies {
             $(pub $c: <Vector<f32, $N> as SoaValue>::SoaBuffer),*
         }
 
+        #[derive(Clone)]
+        pub struct $SoaFieldsName {
+            global_offset: usize,
+            $(pub $c: <Vector<f32, $N> as SoaValue>::SoaFields),*
+        }
+
         impl SoaValue for SquareMatrix<$N> {
             type SoaBuffer = $SoaName;
+            type SoaFields = $SoaFieldsName;
+        }
+        impl SoaFieldsProxy for $SoaFieldsName {
+            type Value = SquareMatrix<$N>;
+            #[allow(unused_assignments)]
+            fn from_global_offset(global_offset: usize) -> Self {
+                let s = <<Vector<f32,$N> as SoaValue>::SoaBuffer as SoaBufferProxy>::num_buffers();
+                let mut i = 0;
+                $(
+                    let $c = <Vector<f32,$N> as SoaValue>::SoaFields::from_global_offset(global_offset + i * s);
+                    i += 1;
+                    if i >= $N { i = 0; }
+                )*
+                Self{
+                    global_offset,
+                    $($c),*
+                }
+            }
+            fn global_offset(&self) -> usize {
+                self.global_offset
+            }
         }
         impl SoaBufferProxy for $SoaName {
             type Value = SquareMatrix<$N>;
@@ -628,9 +681,9 @@ macro_rules! matrix_proxies {
     }
 }
 
-matrix_proxies!(2 [x, y] [x, y]: SquareMatrixExpr2, SquareMatrixVar2, SquareMatrixAtomicRef2, SquareMatrixSoaProxy2);
-matrix_proxies!(3 [x, y, z] [x, y, z]: SquareMatrixExpr3, SquareMatrixVar3, SquareMatrixAtomicRef3, SquareMatrixSoaProxy3);
-matrix_proxies!(4 [x, y, z, w] [x, y, z, w]: SquareMatrixExpr4, SquareMatrixVar4, SquareMatrixAtomicRef4, SquareMatrixSoaProxy4);
+matrix_proxies!(2 [x, y] [x, y]: SquareMatrixExpr2, SquareMatrixVar2, SquareMatrixAtomicRef2, SquareMatrixSoaProxy2, SquareMatrixSoaFields2);
+matrix_proxies!(3 [x, y, z] [x, y, z]: SquareMatrixExpr3, SquareMatrixVar3, SquareMatrixAtomicRef3, SquareMatrixSoaProxy3, SquareMatrixSoaFields3);
+matrix_proxies!(4 [x, y, z, w] [x, y, z, w]: SquareMatrixExpr4, SquareMatrixVar4, SquareMatrixAtomicRef4, SquareMatrixSoaProxy4, SquareMatrixSoaFields4);
 
 impl Value for SquareMatrix<2> {
     type Expr = SquareMatrixExpr2;
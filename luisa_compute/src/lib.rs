@@ -4,9 +4,13 @@ extern crate self as luisa_compute;
 
 use std::any::Any;
 use std::backtrace::Backtrace;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 
+pub mod algorithms;
+pub mod error;
+mod interpreter;
 pub mod lang;
 #[deprecated(note = "use device_log! instead for builtin kernel printing")]
 pub mod printer;
@@ -14,6 +18,7 @@ pub mod resource;
 pub mod rtx;
 pub mod runtime;
 
+pub use crate::error::Error;
 pub use crate::lang::ops::{lerp, max, min};
 
 pub mod prelude {
@@ -41,8 +46,8 @@ pub mod prelude {
     pub use crate::resource::{IoTexel, StorageTexel, *};
     pub use crate::runtime::api::StreamTag;
     pub use crate::runtime::{
-        Callable, Command, Device, DynCallable, Kernel, KernelBuildOptions, KernelDef, Scope,
-        Stream, Swapchain,
+        Callable, Command, CommandGraph, Device, DynCallable, Kernel, KernelBuildOptions,
+        KernelDef, Scope, Stream, Swapchain,
     };
     pub use crate::{
         cpu_dbg, device_log, if_, lc_assert, lc_comment_lineno, lc_unreachable, loop_, while_,
@@ -64,7 +69,7 @@ mod internal_prelude {
     pub(crate) use crate::lang::ops::Linear;
     pub(crate) use crate::lang::types::vector::alias::*;
     pub(crate) use crate::lang::types::vector::*;
-    pub(crate) use crate::lang::types::SoaBufferProxy;
+    pub(crate) use crate::lang::types::{SoaBufferProxy, SoaFieldsProxy};
     #[allow(unused_imports)]
     pub(crate) use crate::lang::{
         check_index_lt_usize, ir, CallFuncTrait, FnRecorder, SafeNodeRef, __compose, __extract,
@@ -97,6 +102,7 @@ use std::sync::Weak;
 
 pub struct Context {
     inner: Arc<backend::Context>,
+    lib_path: PathBuf,
 }
 
 pub fn init_logger() {
@@ -121,6 +127,19 @@ pub enum DeviceType {
     Dx,
     Metal,
     Remote,
+    /// Interprets the IR of kernels on the host, see [`Context::create_device`].
+    Interpreter,
+}
+
+impl DeviceType {
+    pub const ALL: [DeviceType; 6] = [
+        DeviceType::Cpu,
+        DeviceType::Cuda,
+        DeviceType::Dx,
+        DeviceType::Metal,
+        DeviceType::Remote,
+        DeviceType::Interpreter,
+    ];
 }
 
 pub trait IntoDeviceName {
@@ -135,6 +154,7 @@ impl IntoDeviceName for DeviceType {
             DeviceType::Dx => "dx".to_string(),
             DeviceType::Metal => "metal".to_string(),
             DeviceType::Remote => "remote".to_string(),
+            DeviceType::Interpreter => "interpreter".to_string(),
         }
     }
 }
@@ -177,14 +197,17 @@ impl Context {
             let mut cache = CTX_CACHE.lock();
             if let Some(ctx) = cache.get(lib_path.to_str().unwrap()) {
                 if let Some(ctx) = ctx.upgrade() {
-                    return Self { inner: ctx.clone() };
+                    return Self {
+                        inner: ctx.clone(),
+                        lib_path,
+                    };
                 }
             }
             let ctx = Arc::new(backend::Context::new(lib_path.clone()));
             cache.insert(lib_path.to_str().unwrap().to_string(), Arc::downgrade(&ctx));
             ctx
         };
-        Self { inner }
+        Self { inner, lib_path }
     }
     #[inline]
     pub fn create_cpu_device(&self) -> Device {
@@ -193,7 +216,13 @@ impl Context {
 
     /// create a device with the given name
     ///
-    /// name can be "cpu", "cuda", "dx", "metal", "remote"
+    /// name can be "cpu", "cuda", "dx", "metal", "remote", "interpreter"
+    ///
+    /// The "interpreter" device executes kernels by interpreting their IR on the
+    /// host and is meant for testing: it is much slower than "cpu" and does not
+    /// support ray tracing, autodiff, external functions or swapchains. It runs
+    /// no native backend code, but the [`Context`] still needs the runtime
+    /// libraries.
     ///
     /// Alternatively, you can use [`DeviceType`] to specify the device
     pub fn create_device<D: IntoDeviceName>(&self, device: D) -> Device {
@@ -204,7 +233,114 @@ impl Context {
         device: D,
         config: serde_json::Value,
     ) -> Device {
-        let backend = self.inner.create_device(&device.into_device_name(), config);
+        self.try_create_device_with_config(device, config)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+    /// Fallible version of [`Context::create_device`].
+    ///
+    /// Returns [`Error::Unsupported`] if the backend is unknown, its library is
+    /// missing or the backend reports an error, whose message is included.
+    ///
+    /// Errors reported by the backend are caught as panics of the Rust side of
+    /// the bridge, so they are still printed by the panic hook and are lost
+    /// with `panic = "abort"`. Failures that abort inside the native backend,
+    /// e.g. no supported adapter on some platforms, cannot be caught at all.
+    pub fn try_create_device<D: IntoDeviceName>(&self, device: D) -> error::Result<Device> {
+        self.try_create_device_with_config(device, serde_json::json!({}))
+    }
+    pub fn try_create_device_with_config<D: IntoDeviceName>(
+        &self,
+        device: D,
+        config: serde_json::Value,
+    ) -> error::Result<Device> {
+        let name = device.into_device_name();
+        let mut backend = self.create_backend(&name, config)?;
+        if let Some(path) = std::env::var_os("LUISA_CAPTURE") {
+            // every device of the process gets its own file
+            static CAPTURES: AtomicUsize = AtomicUsize::new(0);
+            let mut path = PathBuf::from(path);
+            let index = CAPTURES.fetch_add(1, Ordering::Relaxed);
+            if index > 0 {
+                let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+                file_name.push(format!(".{}", index));
+                path.set_file_name(file_name);
+            }
+            backend = Box::new(runtime::CaptureBackend::new(backend, &name, &path)?);
+        }
+        Ok(self.wrap_backend(backend))
+    }
+    /// Creates a device that records every API call to the file at `path`, see
+    /// [`CaptureFile`](runtime::CaptureFile) for replaying it.
+    ///
+    /// Setting the `LUISA_CAPTURE` environment variable to a path captures every
+    /// device created by the process without changing the application, later
+    /// devices append `.1`, `.2`, ... to the file name.
+    pub fn create_device_with_capture<D: IntoDeviceName>(
+        &self,
+        device: D,
+        path: impl AsRef<Path>,
+    ) -> Device {
+        self.try_create_device_with_capture(device, path)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+    /// Fallible version of [`Context::create_device_with_capture`].
+    pub fn try_create_device_with_capture<D: IntoDeviceName>(
+        &self,
+        device: D,
+        path: impl AsRef<Path>,
+    ) -> error::Result<Device> {
+        let name = device.into_device_name();
+        let backend = self.create_backend(&name, serde_json::json!({}))?;
+        let backend = runtime::CaptureBackend::new(backend, &name, path.as_ref())?;
+        Ok(self.wrap_backend(Box::new(backend)))
+    }
+    fn create_backend(
+        &self,
+        name: &str,
+        config: serde_json::Value,
+    ) -> error::Result<Box<dyn Backend>> {
+        if !DeviceType::ALL
+            .iter()
+            .any(|ty| ty.into_device_name() == name)
+        {
+            return Err(Error::Unsupported(format!("unknown backend `{}`", name)));
+        }
+        if name == "interpreter" {
+            return Ok(Box::new(interpreter::Interpreter::new()));
+        }
+        // native backends abort instead of panicking when their library is missing
+        if matches!(name, "cuda" | "dx" | "metal") {
+            let lib = format!(
+                "{}lc-backend-{}{}",
+                std::env::consts::DLL_PREFIX,
+                name,
+                std::env::consts::DLL_SUFFIX
+            );
+            if !self.lib_path.join(&lib).is_file() {
+                return Err(Error::Unsupported(format!(
+                    "`{}` not found in {}",
+                    lib,
+                    self.lib_path.display()
+                )));
+            }
+        }
+        // the Rust side of the bridge panics on errors, aborts in C++ cannot be caught
+        let backend = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            self.inner.create_device(name, config)
+        }))
+        .map_err(|payload| {
+            let status = payload
+                .downcast_ref::<&str>()
+                .copied()
+                .or_else(|| payload.downcast_ref::<String>().map(String::as_str));
+            Error::Unsupported(match status {
+                Some(status) => format!("failed to create `{}` device: {}", name, status),
+                None => format!("failed to create `{}` device", name),
+            })
+        })?;
+        Ok(Box::new(backend))
+    }
+    fn wrap_backend(&self, backend: Box<dyn Backend>) -> Device {
         let default_stream = backend.create_stream(api::StreamTag::Graphics);
         Device {
             inner: Arc::new_cyclic(|weak| DeviceHandle {
@@ -213,9 +349,14 @@ impl Context {
                     handle: api::Stream(default_stream.handle),
                     native_handle: default_stream.native_handle,
                     device: weak.clone(),
+                    name: resource::ResourceName::default(),
                 })),
+                memory: runtime::MemoryTracker::new(),
+                held: 1.into(),
+                kernels: Default::default(),
                 ctx: self.inner.clone(),
             }),
+            held: true,
         }
     }
 }
@@ -261,6 +402,27 @@ impl ResourceTracker {
             weak_refs: vec![],
         }
     }
+    pub(crate) fn find<T: Any>(&self) -> Option<&T> {
+        self.strong_refs.iter().find_map(|r| (**r).downcast_ref::<T>())
+    }
+    pub(crate) fn find_arc<T: Any>(&self) -> Option<Arc<T>> {
+        let r = self.strong_refs.iter().find(|r| (**r).is::<T>())?;
+        // the `Arc<T>` coerced to `Arc<dyn Any>` by `add`
+        Some(unsafe { Arc::from_raw(Arc::into_raw(r.clone()) as *const T) })
+    }
+    /// Like [`ResourceTracker::upgrade`], but returns `None` if any resource was dropped.
+    pub fn try_upgrade(&self) -> Option<Self> {
+        let mut strong_refs = self
+            .weak_refs
+            .iter()
+            .map(|r| r.upgrade())
+            .collect::<Option<Vec<_>>>()?;
+        strong_refs.extend(self.strong_refs.iter().cloned());
+        Some(Self {
+            strong_refs,
+            weak_refs: vec![],
+        })
+    }
     pub fn new() -> Self {
         Self {
             strong_refs: vec![],
@@ -6,7 +6,7 @@ use std::process::abort;
 use std::sync::{Arc, Weak};
 
 use parking_lot::lock_api::RawMutex as RawMutexTrait;
-use parking_lot::RawMutex;
+use parking_lot::{Mutex, RawMutex};
 
 use crate::internal_prelude::*;
 
@@ -17,6 +17,30 @@ use crate::runtime::*;
 use api::{BufferDownloadCommand, BufferUploadCommand, INVALID_RESOURCE_HANDLE};
 use std::ffi::c_void;
 
+mod bindless;
+mod bindless_ref;
+mod device_vec;
+#[cfg(feature = "image")]
+mod image_io;
+mod indirect;
+mod layered;
+mod mipmap;
+mod sampling;
+mod strided;
+mod texel;
+
+pub use bindless::*;
+pub use bindless_ref::*;
+pub use device_vec::*;
+#[cfg(feature = "image")]
+pub use image_io::*;
+pub use indirect::*;
+pub use layered::*;
+pub use mipmap::*;
+pub use sampling::*;
+pub use strided::*;
+pub use texel::*;
+
 pub type ByteBuffer = Buffer<u8>;
 pub type ByteBufferView = BufferView<u8>;
 pub type ByteBufferVar = BufferVar<u8>;
@@ -274,11 +298,289 @@ impl BufferVar<u8> {
         let self_node = self.node.get();
         __current_scope(|b| b.call(Func::ByteBufferWrite, &[self_node, i, value], Type::void()));
     }
+    /// A `T` record at `index_bytes`, see [`ByteRecordVar`].
+    pub fn record<T: Value>(&self, index_bytes: impl IntoIndex) -> ByteRecordVar<T> {
+        ByteRecordVar {
+            buffer: self.clone(),
+            offset: index_bytes.to_u64(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+/// Alignment of a `T` record in a byte buffer.
+///
+/// This is the alignment of the type on the device but at least 4, as dx12
+/// cannot access values in byte buffers at smaller alignments.
+pub fn byte_record_alignment<T: Value>() -> usize {
+    T::type_().alignment().max(4)
+}
+/// Distance between consecutive `T`s in a packed array.
+pub fn byte_record_stride<T: Value>() -> usize {
+    let align = byte_record_alignment::<T>();
+    (std::mem::size_of::<T>() + align - 1) / align * align
+}
+
+/// Packs heterogeneous records into bytes for a [`ByteBuffer`].
+///
+/// Every record is placed at the next offset satisfying
+/// [`byte_record_alignment`], arrays use [`byte_record_stride`] between elements.
+/// The returned [`ByteRecord`]s locate the records on the host with
+/// [`BufferView::read_as`] and in kernels with [`ByteRecord::var`].
+/// ```no_run
+/// # use luisa_compute::prelude::*;
+/// # fn f(device: &Device) {
+/// let mut layout = ByteBufferLayout::new();
+/// let count = layout.push(3u32);
+/// let points = layout.push_slice(&[Float3::new(0.0, 1.0, 2.0); 3]);
+/// let buffer = device.create_buffer_from_slice(layout.as_bytes());
+/// assert_eq!(buffer.read_as::<Float3>(points.at(2).offset()).z, 2.0);
+/// # }
+/// ```
+#[derive(Clone, Default)]
+pub struct ByteBufferLayout {
+    data: Vec<u8>,
+}
+impl ByteBufferLayout {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Size of the packed records in bytes.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+    #[inline]
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
+    #[inline]
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.data
+    }
+    /// Appends `count` zeroed `T`s.
+    pub fn reserve<T: Value>(&mut self, count: usize) -> ByteRecord<T> {
+        let align = byte_record_alignment::<T>();
+        let offset = (self.data.len() + align - 1) / align * align;
+        self.data.resize(offset + count * byte_record_stride::<T>(), 0);
+        ByteRecord {
+            offset,
+            len: count,
+            _marker: PhantomData,
+        }
+    }
+    pub fn push<T: Value>(&mut self, value: T) -> ByteRecord<T> {
+        self.push_slice(&[value])
+    }
+    /// Appends `values` as a packed array.
+    pub fn push_slice<T: Value>(&mut self, values: &[T]) -> ByteRecord<T> {
+        let record = self.reserve::<T>(values.len());
+        for (i, value) in values.iter().enumerate() {
+            self.write(record.at(i), *value);
+        }
+        record
+    }
+    /// Overwrites a record pushed before.
+    pub fn write<T: Value>(&mut self, record: ByteRecord<T>, value: T) {
+        let size = std::mem::size_of::<T>();
+        self.data[record.offset..record.offset + size].copy_from_slice(unsafe {
+            std::slice::from_raw_parts(&value as *const T as *const u8, size)
+        });
+    }
+}
+
+/// A `T`, or an array of `len` `T`s, at a byte offset, see [`ByteBufferLayout`].
+pub struct ByteRecord<T: Value> {
+    offset: usize,
+    len: usize,
+    _marker: PhantomData<fn() -> T>,
+}
+impl<T: Value> Clone for ByteRecord<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+impl<T: Value> Copy for ByteRecord<T> {}
+impl<T: Value> fmt::Debug for ByteRecord<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "ByteRecord<{}>(offset: {}, len: {})",
+            std::any::type_name::<T>(),
+            self.offset,
+            self.len
+        )
+    }
+}
+impl<T: Value> ByteRecord<T> {
+    /// Offset of the first element in bytes.
+    #[inline]
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+    /// Number of elements.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+    /// The `i`-th element of the array.
+    pub fn at(&self, i: usize) -> ByteRecord<T> {
+        assert!(
+            i < self.len,
+            "index {} out of bounds of `{}` record with length {}",
+            i,
+            std::any::type_name::<T>(),
+            self.len
+        );
+        ByteRecord {
+            offset: self.offset + i * byte_record_stride::<T>(),
+            len: 1,
+            _marker: PhantomData,
+        }
+    }
+    /// The record in a kernel.
+    pub fn var(&self, buffer: &ByteBufferVar) -> ByteRecordVar<T> {
+        buffer.record(self.offset as u64)
+    }
+}
+
+/// A `T` at a byte offset of a [`ByteBufferVar`].
+///
+/// Records can be chained with [`ByteRecordVar::next`] and
+/// [`ByteRecordVar::after`] to walk variable-length data packed by
+/// [`ByteBufferLayout`]. Reads and writes check alignment and bounds when
+/// runtime checks are enabled. Alignment is checked relative to the start of
+/// the buffer for captured views, but only relative to the start of the view
+/// for views passed as kernel arguments, whose offset is not known when the
+/// kernel is built.
+#[derive(Clone)]
+pub struct ByteRecordVar<T: Value> {
+    buffer: ByteBufferVar,
+    offset: Expr<u64>,
+    _marker: PhantomData<fn() -> T>,
+}
+impl<T: Value> ByteRecordVar<T> {
+    /// Offset in bytes.
+    #[inline]
+    pub fn offset(&self) -> Expr<u64> {
+        self.offset
+    }
+    /// The `i`-th `T` of an array starting at this record.
+    #[tracked]
+    pub fn at(&self, i: impl IntoIndex) -> ByteRecordVar<T> {
+        let stride = byte_record_stride::<T>() as u64;
+        self.buffer.record(self.offset + i.to_u64() * stride)
+    }
+    /// The `U` record packed right after this one.
+    pub fn next<U: Value>(&self) -> ByteRecordVar<U> {
+        self.after(1u64)
+    }
+    /// The `U` record packed after an array of `count` `T`s starting at this record.
+    #[tracked]
+    pub fn after<U: Value>(&self, count: impl IntoIndex) -> ByteRecordVar<U> {
+        let align = byte_record_alignment::<U>() as u64;
+        let end = self.offset + count.to_u64() * byte_record_stride::<T>() as u64;
+        self.buffer.record((end + (align - 1)) / align * align)
+    }
+    #[tracked]
+    fn check(&self) {
+        if need_runtime_check() {
+            let buffer = self.buffer.describe();
+            let ty = std::any::type_name::<T>();
+            let view_offset = self.buffer.offset_bytes.unwrap_or(0);
+            lc_assert!(
+                (view_offset + self.offset) % byte_record_alignment::<T>() as u64 == 0,
+                &format!("unaligned `{}` record in {}", ty, buffer)
+            );
+            lc_assert!(
+                self.offset + std::mem::size_of::<T>() as u64 <= self.buffer.len_bytes_expr(),
+                &format!("`{}` record out of bounds of {}", ty, buffer)
+            );
+        }
+    }
+    pub fn read(&self) -> Expr<T> {
+        self.check();
+        self.buffer.read_as::<T>(self.offset)
+    }
+    pub fn write(&self, value: impl AsExpr<Value = T>) {
+        self.check();
+        self.buffer.write_as::<T>(self.offset, value)
+    }
+}
+
+impl BufferView<u8> {
+    fn check_record<T: Value>(&self, offset_bytes: usize) {
+        let ty = std::any::type_name::<T>();
+        assert!(
+            offset_bytes + std::mem::size_of::<T>() <= self.len,
+            "`{}` at byte offset {} out of bounds of {} with length {}",
+            ty,
+            offset_bytes,
+            self.describe(),
+            self.len
+        );
+        let align = byte_record_alignment::<T>();
+        assert!(
+            (self.offset + offset_bytes) % align == 0,
+            "`{}` at byte offset {} of {} is not {}-byte aligned",
+            ty,
+            self.offset + offset_bytes,
+            self.describe(),
+            align
+        );
+    }
+    /// Reads a `T` at `offset_bytes` from the start of the view.
+    ///
+    /// The offset has to be aligned as by [`byte_record_alignment`] relative to
+    /// the start of the buffer, like reads in kernels.
+    pub fn read_as<T: Value>(&self, offset_bytes: usize) -> T {
+        self.check_record::<T>(offset_bytes);
+        let mut bytes = vec![0u8; std::mem::size_of::<T>()];
+        self.view(offset_bytes..offset_bytes + bytes.len()).copy_to(&mut bytes);
+        unsafe { std::ptr::read_unaligned(bytes.as_ptr() as *const T) }
+    }
+    /// Writes a `T` at `offset_bytes` from the start of the view, see [`BufferView::read_as`].
+    pub fn write_as<T: Value>(&self, offset_bytes: usize, value: T) {
+        self.check_record::<T>(offset_bytes);
+        let size = std::mem::size_of::<T>();
+        self.view(offset_bytes..offset_bytes + size).copy_from(unsafe {
+            std::slice::from_raw_parts(&value as *const T as *const u8, size)
+        });
+    }
 }
 pub struct Buffer<T: Value> {
     pub(crate) handle: Arc<BufferHandle>,
     pub(crate) full_view: BufferView<T>,
 }
+impl<T: Value> Buffer<T> {
+    /// Sets a debug name used in panic messages, failed bounds checks
+    /// and [`Device::leak_report`].
+    ///
+    /// Names are kept on the host: the backend interface has no call to label
+    /// native objects, so they do not show up in native debuggers or profilers.
+    /// Failed checks in a kernel report the name the resource had when the
+    /// kernel was built, and buffers passed as arguments by their index.
+    pub fn set_name(&self, name: &str) {
+        self.handle.name.set(name);
+        self.handle
+            .device
+            .inner
+            .memory
+            .set_name(self.handle.allocation, name);
+    }
+    pub fn with_name(self, name: &str) -> Self {
+        self.set_name(name);
+        self
+    }
+}
 impl<T: Value> BufferView<T> {
     pub fn copy_async<'a>(&self, s: &'a Scope<'a>) -> Buffer<T> {
         let copy = self.device.create_buffer(self.len);
@@ -317,10 +619,37 @@ impl<T: Value + fmt::Debug> fmt::Debug for Buffer<T> {
     }
 }
 
+/// Debug name of a resource, see [`Buffer::set_name`].
+#[derive(Default)]
+pub(crate) struct ResourceName(parking_lot::RwLock<Option<String>>);
+
+impl ResourceName {
+    pub(crate) fn get(&self) -> Option<String> {
+        self.0.read().clone()
+    }
+    pub(crate) fn set(&self, name: &str) {
+        *self.0.write() = Some(name.to_string());
+    }
+    /// ``kind `name` `` if named, `kind #handle` otherwise.
+    pub(crate) fn describe(&self, kind: &str, handle: u64) -> String {
+        match &*self.0.read() {
+            Some(name) => format!("{} `{}`", kind, name),
+            None => format!("{} #{}", kind, handle),
+        }
+    }
+}
+
 pub(crate) struct BufferHandle {
     pub(crate) device: Device,
     pub(crate) handle: api::Buffer,
     pub(crate) native_handle: *mut c_void,
+    pub(crate) allocation: AllocationId,
+    pub(crate) name: ResourceName,
+}
+impl BufferHandle {
+    pub(crate) fn describe(&self) -> String {
+        self.name.describe("buffer", self.handle.0)
+    }
 }
 unsafe impl Send for BufferHandle {}
 unsafe impl Sync for BufferHandle {}
@@ -328,6 +657,7 @@ unsafe impl Sync for BufferHandle {}
 impl Drop for BufferHandle {
     fn drop(&mut self) {
         self.device.inner.destroy_buffer(self.handle);
+        self.device.inner.memory.release(self.allocation);
     }
 }
 #[derive(Clone)]
@@ -382,22 +712,52 @@ impl_resource_deref_to_var!(Tex3d, Tex3dVar [T: IoTexel]);
 impl_resource_deref_to_var!(BindlessArray, BindlessArrayVar);
 
 impl<T: Value> BufferView<T> {
-    /// reinterpret the buffer as a different type
-    /// must satisfy `std::mem::size_of::<T>() * self.len() % std::mem::size_of::<U>() == 0`
-    pub unsafe fn transmute<U: Value>(&self) -> BufferView<U> {
-        assert_eq!(
-            std::mem::size_of::<T>() * self.len() % std::mem::size_of::<U>(),
-            0
+    /// Views the same bytes as `U`s.
+    ///
+    /// The size of the view has to be a multiple of `size_of::<U>()` and its
+    /// start, relative to the start of the buffer, a multiple of both the size
+    /// and the device alignment of `U`, as views are bound at `U` granularity.
+    /// ```no_run
+    /// # use luisa_compute::prelude::*;
+    /// # fn f(device: &Device) {
+    /// let colors = device.create_buffer::<Float4>(16);
+    /// let channels = colors.view(..).reinterpret::<f32>();
+    /// assert_eq!(channels.len(), 64);
+    /// # }
+    /// ```
+    pub fn reinterpret<U: Value>(&self) -> BufferView<U> {
+        let (size, align) = (std::mem::size_of::<U>(), U::type_().alignment());
+        let offset_bytes = self.offset * std::mem::size_of::<T>();
+        assert!(
+            self.size_bytes() % size == 0,
+            "cannot reinterpret {} bytes of {} as `{}` of size {}",
+            self.size_bytes(),
+            self.describe(),
+            std::any::type_name::<U>(),
+            size
+        );
+        assert!(
+            offset_bytes % size == 0 && offset_bytes % align == 0,
+            "cannot reinterpret {} at byte offset {} as `{}` of size {} and alignment {}",
+            self.describe(),
+            offset_bytes,
+            std::any::type_name::<U>(),
+            size,
+            align
         );
         BufferView {
-            device: self.device.clone(),
+            device: self.device.internal(),
             handle: self.handle.clone(),
-            offset: self.offset,
-            len: self.len * std::mem::size_of::<T>() / std::mem::size_of::<U>(),
+            offset: offset_bytes / size,
+            len: self.size_bytes() / size,
             total_size_bytes: self.total_size_bytes,
             _marker: PhantomData,
         }
     }
+    /// See [`BufferView::reinterpret`].
+    pub unsafe fn transmute<U: Value>(&self) -> BufferView<U> {
+        self.reinterpret()
+    }
     #[inline]
     pub fn var(&self) -> BufferVar<T> {
         BufferVar::new(self)
@@ -407,6 +767,16 @@ impl<T: Value> BufferView<T> {
             panic!("BufferView was created from a Buffer that has already been dropped.")
         })
     }
+    pub(crate) fn describe(&self) -> String {
+        match Weak::upgrade(&self.handle) {
+            Some(handle) => handle.describe(),
+            None => "dropped buffer".to_string(),
+        }
+    }
+    /// The name set by [`Buffer::set_name`].
+    pub fn name(&self) -> Option<String> {
+        self._handle().name.get()
+    }
     #[inline]
     pub fn handle(&self) -> api::Buffer {
         self._handle().handle
@@ -424,19 +794,25 @@ impl<T: Value> BufferView<T> {
         self.len * std::mem::size_of::<T>()
     }
     pub fn copy_to_async<'a>(&self, data: &'a mut [T]) -> Command<'a, 'a> {
-        assert_eq!(data.len(), self.len);
+        assert_eq!(
+            data.len(),
+            self.len,
+            "length mismatch copying from {} to host",
+            self.describe()
+        );
         let mut rt = ResourceTracker::new();
         rt.add(self._handle());
         Command {
-            inner: api::Command::BufferDownload(BufferDownloadCommand {
+            inner: Some(api::Command::BufferDownload(BufferDownloadCommand {
                 buffer: self.handle(),
                 offset: self.offset * std::mem::size_of::<T>(),
                 size: data.len() * std::mem::size_of::<T>(),
                 data: data.as_mut_ptr() as *mut u8,
-            }),
+            })),
             marker: PhantomData,
             resource_tracker: rt,
             callback: None,
+            rearm: None,
         }
     }
     pub fn copy_to_vec(&self) -> Vec<T> {
@@ -453,19 +829,25 @@ impl<T: Value> BufferView<T> {
     }
 
     pub fn copy_from_async<'a>(&self, data: &'a [T]) -> Command<'a, 'static> {
-        assert_eq!(data.len(), self.len);
+        assert_eq!(
+            data.len(),
+            self.len,
+            "length mismatch copying from host to {}",
+            self.describe()
+        );
         let mut rt = ResourceTracker::new();
         rt.add(self._handle());
         Command {
-            inner: api::Command::BufferUpload(BufferUploadCommand {
+            inner: Some(api::Command::BufferUpload(BufferUploadCommand {
                 buffer: self.handle(),
                 offset: self.offset * std::mem::size_of::<T>(),
                 size: data.len() * std::mem::size_of::<T>(),
                 data: data.as_ptr() as *const u8,
-            }),
+            })),
             marker: PhantomData,
             resource_tracker: rt,
             callback: None,
+            rearm: None,
         }
     }
     pub fn copy_from(&self, data: &[T]) {
@@ -478,21 +860,28 @@ impl<T: Value> BufferView<T> {
         self.fill_fn(|_| value);
     }
     pub fn copy_to_buffer_async(&self, dst: &BufferView<T>) -> Command<'static, 'static> {
-        assert_eq!(self.len, dst.len);
+        assert_eq!(
+            self.len,
+            dst.len,
+            "length mismatch copying from {} to {}",
+            self.describe(),
+            dst.describe()
+        );
         let mut rt = ResourceTracker::new();
         rt.add(self._handle());
         rt.add(dst._handle());
         Command {
-            inner: api::Command::BufferCopy(api::BufferCopyCommand {
+            inner: Some(api::Command::BufferCopy(api::BufferCopyCommand {
                 src: self.handle(),
                 src_offset: self.offset * std::mem::size_of::<T>(),
                 dst: dst.handle(),
                 dst_offset: dst.offset * std::mem::size_of::<T>(),
                 size: self.len * std::mem::size_of::<T>(),
-            }),
+            })),
             marker: PhantomData,
             resource_tracker: rt,
             callback: None,
+            rearm: None,
         }
     }
     pub fn copy_to_buffer(&self, dst: &BufferView<T>) {
@@ -511,12 +900,18 @@ impl<T: Value> BufferView<T> {
             std::ops::Bound::Excluded(&x) => x,
             std::ops::Bound::Unbounded => self.len,
         };
-        assert!(lower <= upper);
-        assert!(upper <= self.len);
+        assert!(
+            lower <= upper && upper <= self.len,
+            "range {}..{} out of bounds of {} with length {}",
+            lower,
+            upper,
+            self.describe(),
+            self.len
+        );
         BufferView {
-            device: self.device.clone(),
+            device: self.device.internal(),
             handle: self.handle.clone(),
-            offset: lower,
+            offset: self.offset + lower,
             len: upper - lower,
             total_size_bytes: self.total_size_bytes,
             _marker: PhantomData,
@@ -528,10 +923,18 @@ pub(crate) struct BindlessArrayHandle {
     pub(crate) device: Device,
     pub(crate) handle: api::BindlessArray,
     pub(crate) native_handle: *mut c_void,
+    pub(crate) allocation: AllocationId,
+    pub(crate) name: ResourceName,
+}
+impl BindlessArrayHandle {
+    pub(crate) fn describe(&self) -> String {
+        self.name.describe("bindless array", self.handle.0)
+    }
 }
 impl Drop for BindlessArrayHandle {
     fn drop(&mut self) {
         self.device.inner.destroy_bindless_array(self.handle);
+        self.device.inner.memory.release(self.allocation);
     }
 }
 #[derive(Clone)]
@@ -565,7 +968,8 @@ impl BindlessArray {
         self.lock();
         assert!(
             self.modifications.borrow().is_empty(),
-            "Did not call update() after last modification"
+            "Did not call update() after last modification of {}",
+            self.handle.describe()
         );
         let var = BindlessArrayVar::new(self);
         self.unlock();
@@ -575,6 +979,21 @@ impl BindlessArray {
     pub fn handle(&self) -> api::BindlessArray {
         self.handle.handle
     }
+    /// See [`Buffer::set_name`].
+    pub fn set_name(&self, name: &str) {
+        self.handle.name.set(name);
+        self.device
+            .inner
+            .memory
+            .set_name(self.handle.allocation, name);
+    }
+    pub fn with_name(self, name: &str) -> Self {
+        self.set_name(name);
+        self
+    }
+    pub fn name(&self) -> Option<String> {
+        self.handle.name.get()
+    }
     #[inline]
     pub fn native_handle(&self) -> *mut std::ffi::c_void {
         self.handle.native_handle
@@ -589,6 +1008,14 @@ impl BindlessArray {
         self.emplace_buffer_view_async(index, &buffer.view(..))
     }
     pub fn emplace_buffer_view_async<T: Value>(&self, index: usize, bufferview: &BufferView<T>) {
+        self.emplace_buffer_handle_async(index, bufferview._handle(), bufferview.offset)
+    }
+    pub(crate) fn emplace_buffer_handle_async(
+        &self,
+        index: usize,
+        handle: Arc<BufferHandle>,
+        offset: usize,
+    ) {
         self.lock();
         self.modifications
             .borrow_mut()
@@ -601,11 +1028,11 @@ impl BindlessArray {
             })
             .buffer = api::BindlessArrayUpdateBuffer {
             op: api::BindlessArrayUpdateOperation::Emplace,
-            handle: bufferview.handle(),
-            offset: bufferview.offset,
+            handle: handle.handle,
+            offset,
         };
         let mut slots = self.slots.borrow_mut();
-        slots[index].buffer = Some(bufferview._handle());
+        slots[index].buffer = Some(handle);
         self.unlock();
     }
     pub fn emplace_tex2d_async<T: IoTexel>(
@@ -613,6 +1040,14 @@ impl BindlessArray {
         index: usize,
         texture: &Tex2d<T>,
         sampler: Sampler,
+    ) {
+        self.emplace_tex2d_handle_async(index, &texture.handle, sampler)
+    }
+    pub(crate) fn emplace_tex2d_handle_async(
+        &self,
+        index: usize,
+        handle: &Arc<TextureHandle>,
+        sampler: Sampler,
     ) {
         self.lock();
         self.modifications
@@ -626,11 +1061,11 @@ impl BindlessArray {
             })
             .tex2d = api::BindlessArrayUpdateTexture {
             op: api::BindlessArrayUpdateOperation::Emplace,
-            handle: texture.handle(),
+            handle: handle.handle,
             sampler,
         };
         let mut slots = self.slots.borrow_mut();
-        slots[index].tex2d = Some(texture.handle.clone());
+        slots[index].tex2d = Some(handle.clone());
         self.unlock();
     }
     pub fn emplace_tex3d_async<T: IoTexel>(
@@ -638,6 +1073,14 @@ impl BindlessArray {
         index: usize,
         texture: &Tex3d<T>,
         sampler: Sampler,
+    ) {
+        self.emplace_tex3d_handle_async(index, &texture.handle, sampler)
+    }
+    pub(crate) fn emplace_tex3d_handle_async(
+        &self,
+        index: usize,
+        handle: &Arc<TextureHandle>,
+        sampler: Sampler,
     ) {
         self.lock();
         self.modifications
@@ -651,11 +1094,11 @@ impl BindlessArray {
             })
             .tex3d = api::BindlessArrayUpdateTexture {
             op: api::BindlessArrayUpdateOperation::Emplace,
-            handle: texture.handle(),
+            handle: handle.handle,
             sampler,
         };
         let mut slots = self.slots.borrow_mut();
-        slots[index].tex3d = Some(texture.handle.clone());
+        slots[index].tex3d = Some(handle.clone());
         self.unlock();
     }
     pub fn remove_buffer_async(&self, index: usize) {
@@ -775,17 +1218,28 @@ impl BindlessArray {
         let modifications = Arc::new(modifications.drain().map(|(_k, v)| v).collect::<Vec<_>>());
         rt.add(modifications.clone());
         let lock = self.lock.clone();
+        let rearm_lock = self.lock.clone();
         Command {
-            inner: api::Command::BindlessArrayUpdate(api::BindlessArrayUpdateCommand {
-                handle: self.handle.handle,
-                modifications: modifications.as_ptr(),
-                modifications_count: modifications.len(),
-            }),
+            inner: Some(api::Command::BindlessArrayUpdate(
+                api::BindlessArrayUpdateCommand {
+                    handle: self.handle.handle,
+                    modifications: modifications.as_ptr(),
+                    modifications_count: modifications.len(),
+                },
+            )),
             marker: PhantomData,
             resource_tracker: rt,
             callback: Some(Box::new(move || unsafe {
                 lock.unlock();
             })),
+            // replays apply the same modifications again
+            rearm: Some(Arc::new(move || {
+                rearm_lock.lock();
+                let lock = rearm_lock.clone();
+                Box::new(move || unsafe {
+                    lock.unlock();
+                })
+            })),
         }
     }
     pub fn tex2d(&self, tex2d_index: impl AsExpr<Value = u32>) -> BindlessTex2dVar {
@@ -817,6 +1271,18 @@ pub(crate) struct TextureHandle {
     pub(crate) height: u32,
     pub(crate) depth: u32,
     pub(crate) levels: u32,
+    pub(crate) allocation: AllocationId,
+    pub(crate) name: ResourceName,
+    pub(crate) mipmap_tables: Mutex<MipmapTables>,
+}
+impl TextureHandle {
+    pub(crate) fn describe(&self) -> String {
+        self.name.describe("texture", self.handle.0)
+    }
+    pub(crate) fn set_name(&self, name: &str) {
+        self.name.set(name);
+        self.device.inner.memory.set_name(self.allocation, name);
+    }
 }
 unsafe impl Send for TextureHandle {}
 unsafe impl Sync for TextureHandle {}
@@ -829,7 +1295,7 @@ impl GetPixelFormat for f32 {
             PixelStorage::Byte1 => PixelFormat::R8Unorm,
             PixelStorage::Byte2 => PixelFormat::Rg8Unorm,
             PixelStorage::Byte4 => PixelFormat::Rgba8Unorm,
-            PixelStorage::Half1 => PixelFormat::Rg16f,
+            PixelStorage::Half1 => PixelFormat::R16f,
             PixelStorage::Half2 => PixelFormat::Rg16f,
             PixelStorage::Half4 => PixelFormat::Rgba16f,
             PixelStorage::Short1 => PixelFormat::R16Unorm,
@@ -838,6 +1304,8 @@ impl GetPixelFormat for f32 {
             PixelStorage::Float1 => PixelFormat::R32f,
             PixelStorage::Float2 => PixelFormat::Rg32f,
             PixelStorage::Float4 => PixelFormat::Rgba32f,
+            PixelStorage::R10G10B10A2 => PixelFormat::R10G10B10A2UNorm,
+            PixelStorage::R11G11B10 => PixelFormat::R11G11B10F,
             _ => panic!("Invalid pixel storage for f32"),
         }
     }
@@ -870,6 +1338,7 @@ impl GetPixelFormat for u32 {
             PixelStorage::Int1 => PixelFormat::R32Uint,
             PixelStorage::Int2 => PixelFormat::Rg32Uint,
             PixelStorage::Int4 => PixelFormat::Rgba32Uint,
+            PixelStorage::R10G10B10A2 => PixelFormat::R10G10B10A2UInt,
             _ => panic!("Invalid pixel storage for u32"),
         }
     }
@@ -878,15 +1347,19 @@ impl GetPixelFormat for u32 {
 // This is the type that is read from/written to a texture
 pub trait IoTexel: Value {
     type RwType: Value;
+    /// Number of channels of `RwType` holding the texel, starting from `x`.
+    /// Used by `#[derive(IoTexel)]` to pack several texels into one.
+    const CHANNELS: usize = 4;
     fn pixel_format(storage: PixelStorage) -> PixelFormat;
     fn convert_from_read(texel: Expr<Self::RwType>) -> Expr<Self>;
     fn convert_to_write(value: Expr<Self>) -> Expr<Self::RwType>;
 }
 
 macro_rules! impl_io_texel {
-    ($t:ty,$el:ty, $rw:ty, $cvt_from:expr, $cvt_to:expr) => {
+    ($t:ty,$el:ty, $rw:ty, $channels:literal, $cvt_from:expr, $cvt_to:expr) => {
         impl IoTexel for $t {
             type RwType = $rw;
+            const CHANNELS: usize = $channels;
             fn pixel_format(storage: PixelStorage) -> PixelFormat {
                 <$el as GetPixelFormat>::pixel_format(storage)
             }
@@ -903,6 +1376,7 @@ impl_io_texel!(
     f16,
     f32,
     Float4,
+    1,
     |a: Expr<Float4>| a.x.cast_f16(),
     |x: Expr<f16>| { Float4::splat_expr(x.cast_f32()) }
 );
@@ -910,6 +1384,7 @@ impl_io_texel!(
     Half2,
     f32,
     Float4,
+    2,
     |a: Expr<Float4>| a.xy().cast_f16(),
     |x: Expr<Half2>| {
         let x = x.cast_f32();
@@ -920,6 +1395,7 @@ impl_io_texel!(
     Half3,
     f32,
     Float4,
+    3,
     |a: Expr<Float4>| a.xyz().cast_f16(),
     |x: Expr<Half3>| { x.cast_f32().extend(0.0) }
 );
@@ -927,6 +1403,7 @@ impl_io_texel!(
     Half4,
     f32,
     Float4,
+    4,
     |a: Expr<Float4>| a.cast_f16(),
     |x: Expr<Half4>| { x.cast_f32() }
 );
@@ -934,57 +1411,99 @@ impl_io_texel!(
     bool,
     f32,
     Float4,
+    1,
     |x: Expr<Float4>| x.x.ne(0.0f32.expr()),
     |x: Expr<bool>| { Float4::splat_expr(x.select(1.0f32.expr(), 0.0f32.expr())) }
 );
-impl_io_texel!(f32, f32, Float4, |x: Expr<Float4>| x.x, |x| {
+impl_io_texel!(f32, f32, Float4, 1, |x: Expr<Float4>| x.x, |x| {
     Float4::splat_expr(x)
 });
-impl_io_texel!(Float2, f32, Float4, |x: Expr<Float4>| x.xy(), |x: Expr<
+impl_io_texel!(Float2, f32, Float4, 2, |x: Expr<Float4>| x.xy(), |x: Expr<
     Float2,
 >| {
     Float4::expr(x.x, x.y, 0.0, 0.0)
 });
-impl_io_texel!(Float3, f32, Float4, |x: Expr<Float4>| x.xyz(), |x: Expr<
+impl_io_texel!(Float3, f32, Float4, 3, |x: Expr<Float4>| x.xyz(), |x: Expr<
     Float3,
 >| x
     .extend(0.0));
-impl_io_texel!(Float4, f32, Float4, |x: Expr<Float4>| x, |x: Expr<
+impl_io_texel!(Float4, f32, Float4, 4, |x: Expr<Float4>| x, |x: Expr<
     Float4,
 >| x);
 
-// impl_io_texel!(u16,);
-// impl_io_texel!(i16,);
-// impl_io_texel!(Ushort2,);
-// impl_io_texel!(Short2,);
-// impl_io_texel!(Ushort4,);
-// impl_io_texel!(Short4,);
+// narrow integers are read as 32-bit integers and truncated on write
+impl_io_texel!(u8, u32, Uint4, 1, |x: Expr<Uint4>| x.x.cast_u8(), |x: Expr<u8>| {
+    Uint4::splat_expr(x.cast_u32())
+});
+impl_io_texel!(i8, i32, Int4, 1, |x: Expr<Int4>| x.x.cast_i8(), |x: Expr<i8>| {
+    Int4::splat_expr(x.cast_i32())
+});
+impl_io_texel!(u16, u32, Uint4, 1, |x: Expr<Uint4>| x.x.cast_u16(), |x: Expr<u16>| {
+    Uint4::splat_expr(x.cast_u32())
+});
+impl_io_texel!(i16, i32, Int4, 1, |x: Expr<Int4>| x.x.cast_i16(), |x: Expr<i16>| {
+    Int4::splat_expr(x.cast_i32())
+});
+impl_io_texel!(Ubyte2, u32, Uint4, 2, |x: Expr<Uint4>| x.xy().cast_u8(), |x: Expr<
+    Ubyte2,
+>| {
+    let x = x.cast_u32();
+    Uint4::expr(x.x, x.y, 0u32, 0u32)
+});
+impl_io_texel!(Byte2, i32, Int4, 2, |x: Expr<Int4>| x.xy().cast_i8(), |x: Expr<
+    Byte2,
+>| {
+    let x = x.cast_i32();
+    Int4::expr(x.x, x.y, 0i32, 0i32)
+});
+impl_io_texel!(Ushort2, u32, Uint4, 2, |x: Expr<Uint4>| x.xy().cast_u16(), |x: Expr<
+    Ushort2,
+>| {
+    let x = x.cast_u32();
+    Uint4::expr(x.x, x.y, 0u32, 0u32)
+});
+impl_io_texel!(Short2, i32, Int4, 2, |x: Expr<Int4>| x.xy().cast_i16(), |x: Expr<
+    Short2,
+>| {
+    let x = x.cast_i32();
+    Int4::expr(x.x, x.y, 0i32, 0i32)
+});
+impl_io_texel!(Ubyte4, u32, Uint4, 4, |x: Expr<Uint4>| x.cast_u8(), |x: Expr<Ubyte4>| x
+    .cast_u32());
+impl_io_texel!(Byte4, i32, Int4, 4, |x: Expr<Int4>| x.cast_i8(), |x: Expr<Byte4>| x
+    .cast_i32());
+impl_io_texel!(Ushort4, u32, Uint4, 4, |x: Expr<Uint4>| x.cast_u16(), |x: Expr<
+    Ushort4,
+>| x.cast_u32());
+impl_io_texel!(Short4, i32, Int4, 4, |x: Expr<Int4>| x.cast_i16(), |x: Expr<Short4>| x
+    .cast_i32());
 impl_io_texel!(
     u32,
     u32,
     Uint4,
+    1,
     |x: Expr<Uint4>| x.x,
     |x| Uint4::splat_expr(x)
 );
-impl_io_texel!(i32, i32, Int4, |x: Expr<Int4>| x.x, |x| Int4::splat_expr(x));
-impl_io_texel!(Uint2, u32, Uint4, |x: Expr<Uint4>| x.xy(), |x: Expr<
+impl_io_texel!(i32, i32, Int4, 1, |x: Expr<Int4>| x.x, |x| Int4::splat_expr(x));
+impl_io_texel!(Uint2, u32, Uint4, 2, |x: Expr<Uint4>| x.xy(), |x: Expr<
     Uint2,
 >| {
     Uint4::expr(x.x, x.y, 0u32, 0u32)
 });
-impl_io_texel!(Int2, i32, Int4, |x: Expr<Int4>| x.xy(), |x: Expr<Int2>| {
+impl_io_texel!(Int2, i32, Int4, 2, |x: Expr<Int4>| x.xy(), |x: Expr<Int2>| {
     Int4::expr(x.x, x.y, 0i32, 0i32)
 });
-impl_io_texel!(Uint3, u32, Uint4, |x: Expr<Uint4>| x.xyz(), |x: Expr<
+impl_io_texel!(Uint3, u32, Uint4, 3, |x: Expr<Uint4>| x.xyz(), |x: Expr<
     Uint3,
 >| {
     Uint4::expr(x.x, x.y, x.z, 0u32)
 });
-impl_io_texel!(Int3, i32, Int4, |x: Expr<Int4>| x.xyz(), |x: Expr<Int3>| {
+impl_io_texel!(Int3, i32, Int4, 3, |x: Expr<Int4>| x.xyz(), |x: Expr<Int3>| {
     Int4::expr(x.x, x.y, x.z, 0i32)
 });
-impl_io_texel!(Uint4, u32, Uint4, |x: Expr<Uint4>| x, |x| x);
-impl_io_texel!(Int4, i32, Int4, |x: Expr<Int4>| x, |x| x);
+impl_io_texel!(Uint4, u32, Uint4, 4, |x: Expr<Uint4>| x, |x| x);
+impl_io_texel!(Int4, i32, Int4, 4, |x: Expr<Int4>| x, |x| x);
 
 // Types that is stored in a texture
 pub trait StorageTexel<T: IoTexel> {
@@ -1004,53 +1523,107 @@ macro_rules! impl_storage_texel {
     };
 }
 
-impl_storage_texel!(u8, Byte1, f32, Float2, Float4, Int2, Int4, Uint2, Uint4,);
-impl_storage_texel!(Ubyte2, Byte2, f32, Float2, Float4, Int2, Int4, Uint2, Uint4,);
-impl_storage_texel!(Ubyte4, Byte4, f32, Float2, Float4, Int2, Int4, Uint2, Uint4,);
-impl_storage_texel!([u8; 2], Byte2, f32, Float2, Float4, Int2, Int4, Uint2, Uint4,);
-impl_storage_texel!([u8; 4], Byte4, f32, Float2, Float4, Int2, Int4, Uint2, Uint4,);
+// host types of each storage and the texel types they can be copied to or from
+impl_storage_texel!(u8, Byte1,
+    f32, Float2, Float4, i32, u32, Int2, Int4, Uint2, Uint4, u8, i8, Ubyte2, Byte2, Ubyte4, Byte4,
+);
+impl_storage_texel!(Ubyte2, Byte2,
+    f32, Float2, Float4, i32, u32, Int2, Int4, Uint2, Uint4, u8, i8, Ubyte2, Byte2, Ubyte4, Byte4,
+);
+impl_storage_texel!(Ubyte4, Byte4,
+    f32, Float2, Float4, i32, u32, Int2, Int4, Uint2, Uint4, u8, i8, Ubyte2, Byte2, Ubyte4, Byte4,
+);
+impl_storage_texel!([u8; 2], Byte2,
+    f32, Float2, Float4, i32, u32, Int2, Int4, Uint2, Uint4, u8, i8, Ubyte2, Byte2, Ubyte4, Byte4,
+);
+impl_storage_texel!([u8; 4], Byte4,
+    f32, Float2, Float4, i32, u32, Int2, Int4, Uint2, Uint4, u8, i8, Ubyte2, Byte2, Ubyte4, Byte4,
+);
 
-impl_storage_texel!(i8, Byte1, f32, Float2, Float4, Int2, Int4, Uint2, Uint4,);
-impl_storage_texel!(Byte2, Byte2, f32, Float2, Float4, Int2, Int4, Uint2, Uint4,);
-impl_storage_texel!(Byte4, Byte4, f32, Float2, Float4, Int2, Int4, Uint2, Uint4,);
-impl_storage_texel!([i8; 2], Byte2, f32, Float2, Float4, Int2, Int4, Uint2, Uint4,);
-impl_storage_texel!([i8; 4], Byte4, f32, Float2, Float4, Int2, Int4, Uint2, Uint4,);
+impl_storage_texel!(i8, Byte1,
+    f32, Float2, Float4, i32, u32, Int2, Int4, Uint2, Uint4, u8, i8, Ubyte2, Byte2, Ubyte4, Byte4,
+    Snorm8, Snorm8x2, Snorm8x4,
+);
+impl_storage_texel!(Byte2, Byte2,
+    f32, Float2, Float4, i32, u32, Int2, Int4, Uint2, Uint4, u8, i8, Ubyte2, Byte2, Ubyte4, Byte4,
+    Snorm8, Snorm8x2, Snorm8x4,
+);
+impl_storage_texel!(Byte4, Byte4,
+    f32, Float2, Float4, i32, u32, Int2, Int4, Uint2, Uint4, u8, i8, Ubyte2, Byte2, Ubyte4, Byte4,
+    Snorm8, Snorm8x2, Snorm8x4,
+);
+impl_storage_texel!([i8; 2], Byte2,
+    f32, Float2, Float4, i32, u32, Int2, Int4, Uint2, Uint4, u8, i8, Ubyte2, Byte2, Ubyte4, Byte4,
+    Snorm8, Snorm8x2, Snorm8x4,
+);
+impl_storage_texel!([i8; 4], Byte4,
+    f32, Float2, Float4, i32, u32, Int2, Int4, Uint2, Uint4, u8, i8, Ubyte2, Byte2, Ubyte4, Byte4,
+    Snorm8, Snorm8x2, Snorm8x4,
+);
 
-impl_storage_texel!(u16, Short1, f32, Float2, Float4, Int2, Int4, Uint2, Uint4,);
-impl_storage_texel!(Ushort2, Short2, f32, Float2, Float4, Int2, Int4, Uint2, Uint4,);
-impl_storage_texel!(Ushort4, Short4, f32, Float2, Float4, Int2, Int4, Uint2, Uint4,);
-impl_storage_texel!([u16; 2], Byte2, f32, Float2, Float4, Int2, Int4, Uint2, Uint4,);
-impl_storage_texel!([u16; 4], Byte4, f32, Float2, Float4, Int2, Int4, Uint2, Uint4,);
+impl_storage_texel!(u16, Short1,
+    f32, Float2, Float4, i32, u32, Int2, Int4, Uint2, Uint4, u16, i16, Ushort2, Short2, Ushort4, Short4,
+);
+impl_storage_texel!(Ushort2, Short2,
+    f32, Float2, Float4, i32, u32, Int2, Int4, Uint2, Uint4, u16, i16, Ushort2, Short2, Ushort4, Short4,
+);
+impl_storage_texel!(Ushort4, Short4,
+    f32, Float2, Float4, i32, u32, Int2, Int4, Uint2, Uint4, u16, i16, Ushort2, Short2, Ushort4, Short4,
+);
+impl_storage_texel!([u16; 2], Short2,
+    f32, Float2, Float4, i32, u32, Int2, Int4, Uint2, Uint4, u16, i16, Ushort2, Short2, Ushort4, Short4,
+);
+impl_storage_texel!([u16; 4], Short4,
+    f32, Float2, Float4, i32, u32, Int2, Int4, Uint2, Uint4, u16, i16, Ushort2, Short2, Ushort4, Short4,
+);
 
-impl_storage_texel!(i16, Short1, f32, Float2, Float4, Int2, Int4, Uint2, Uint4,);
-impl_storage_texel!(Short2, Short2, f32, Float2, Float4, Int2, Int4, Uint2, Uint4,);
-impl_storage_texel!(Short4, Short4, f32, Float2, Float4, Int2, Int4, Uint2, Uint4,);
-impl_storage_texel!([i16; 2], Byte2, f32, Float2, Float4, Int2, Int4, Uint2, Uint4,);
-impl_storage_texel!([i16; 4], Byte4, f32, Float2, Float4, Int2, Int4, Uint2, Uint4,);
+impl_storage_texel!(i16, Short1,
+    f32, Float2, Float4, i32, u32, Int2, Int4, Uint2, Uint4, u16, i16, Ushort2, Short2, Ushort4, Short4,
+    Snorm16, Snorm16x2, Snorm16x4,
+);
+impl_storage_texel!(Short2, Short2,
+    f32, Float2, Float4, i32, u32, Int2, Int4, Uint2, Uint4, u16, i16, Ushort2, Short2, Ushort4, Short4,
+    Snorm16, Snorm16x2, Snorm16x4,
+);
+impl_storage_texel!(Short4, Short4,
+    f32, Float2, Float4, i32, u32, Int2, Int4, Uint2, Uint4, u16, i16, Ushort2, Short2, Ushort4, Short4,
+    Snorm16, Snorm16x2, Snorm16x4,
+);
+impl_storage_texel!([i16; 2], Short2,
+    f32, Float2, Float4, i32, u32, Int2, Int4, Uint2, Uint4, u16, i16, Ushort2, Short2, Ushort4, Short4,
+    Snorm16, Snorm16x2, Snorm16x4,
+);
+impl_storage_texel!([i16; 4], Short4,
+    f32, Float2, Float4, i32, u32, Int2, Int4, Uint2, Uint4, u16, i16, Ushort2, Short2, Ushort4, Short4,
+    Snorm16, Snorm16x2, Snorm16x4,
+);
 
-impl_storage_texel!(u32, Int1, i32, u32, f32, Float2, Float4, Int2, Int4, Uint2, Uint4,);
-impl_storage_texel!(Uint2, Int2, i32, u32, f32, Float2, Float4, Int2, Int4, Uint2, Uint4,);
-impl_storage_texel!(Uint4, Int4, i32, u32, f32, Float2, Float4, Int2, Int4, Uint2, Uint4,);
-impl_storage_texel!([u32; 2], Byte2, i32, u32, f32, Float2, Float4, Int2, Int4, Uint2, Uint4,);
-impl_storage_texel!([u32; 4], Byte4, i32, u32, f32, Float2, Float4, Int2, Int4, Uint2, Uint4,);
+impl_storage_texel!(u32, Int1, i32, u32, Int2, Int4, Uint2, Uint4,);
+impl_storage_texel!(Uint2, Int2, i32, u32, Int2, Int4, Uint2, Uint4,);
+impl_storage_texel!(Uint4, Int4, i32, u32, Int2, Int4, Uint2, Uint4,);
+impl_storage_texel!([u32; 2], Int2, i32, u32, Int2, Int4, Uint2, Uint4,);
+impl_storage_texel!([u32; 4], Int4, i32, u32, Int2, Int4, Uint2, Uint4,);
 
-impl_storage_texel!(i32, Int1, i32, u32, f32, Float2, Float4, Int2, Int4, Uint2, Uint4,);
-impl_storage_texel!(Int2, Int2, i32, u32, f32, Float2, Float4, Int2, Int4, Uint2, Uint4,);
-impl_storage_texel!(Int4, Int4, i32, u32, f32, Float2, Float4, Int2, Int4, Uint2, Uint4,);
-impl_storage_texel!([i32; 2], Byte2, i32, u32, f32, Float2, Float4, Int2, Int4, Uint2, Uint4,);
-impl_storage_texel!([i32; 4], Byte4, i32, u32, f32, Float2, Float4, Int2, Int4, Uint2, Uint4,);
+impl_storage_texel!(i32, Int1, i32, u32, Int2, Int4, Uint2, Uint4,);
+impl_storage_texel!(Int2, Int2, i32, u32, Int2, Int4, Uint2, Uint4,);
+impl_storage_texel!(Int4, Int4, i32, u32, Int2, Int4, Uint2, Uint4,);
+impl_storage_texel!([i32; 2], Int2, i32, u32, Int2, Int4, Uint2, Uint4,);
+impl_storage_texel!([i32; 4], Int4, i32, u32, Int2, Int4, Uint2, Uint4,);
 
 impl_storage_texel!(f32, Float1, f32, Float2, Float4,);
 impl_storage_texel!(Float2, Float2, f32, Float2, Float4,);
 impl_storage_texel!(Float4, Float4, f32, Float2, Float4,);
-impl_storage_texel!([f32; 2], Byte2, f32, Float2, Float4, Int2, Int4, Uint2, Uint4,);
-impl_storage_texel!([f32; 4], Byte4, f32, Float2, Float4, Int2, Int4, Uint2, Uint4,);
+impl_storage_texel!([f32; 2], Float2, f32, Float2, Float4,);
+impl_storage_texel!([f32; 4], Float4, f32, Float2, Float4,);
+
+impl_storage_texel!(f16, Half1, f32, Float2, Float4, f16, Half2, Half4,);
+impl_storage_texel!(Half2, Half2, f32, Float2, Float4, f16, Half2, Half4,);
+impl_storage_texel!(Half4, Half4, f32, Float2, Float4, f16, Half2, Half4,);
+impl_storage_texel!([f16; 2], Half2, f32, Float2, Float4, f16, Half2, Half4,);
+impl_storage_texel!([f16; 4], Half4, f32, Float2, Float4, f16, Half2, Half4,);
 
-impl_storage_texel!(f16, Half1, f32, Float2, Float4,);
-impl_storage_texel!(Half2, Half2, f32, Float2, Float4,);
-impl_storage_texel!(Half4, Half4, f32, Float2, Float4,);
-impl_storage_texel!([f16; 2], Byte2, f32, Float2, Float4, Int2, Int4, Uint2, Uint4,);
-impl_storage_texel!([f16; 4], Byte4, f32, Float2, Float4, Int2, Int4, Uint2, Uint4,);
+impl_storage_texel!(PackedRgb10A2, R10G10B10A2, f32, Float2, Float4, u32, Uint2, Uint4,);
+impl_storage_texel!(PackedRg11B10f, R11G11B10, f32, Float2, Float4,);
 
 // `T` is the read out type of the texture, which is not necessarily the same as
 // the storage type In fact, the texture can be stored in any format as long as
@@ -1180,6 +1753,17 @@ impl<T: IoTexel> Tex2d<T> {
     pub fn handle(&self) -> api::Texture {
         self.handle.handle
     }
+    /// See [`Buffer::set_name`].
+    pub fn set_name(&self, name: &str) {
+        self.handle.set_name(name);
+    }
+    pub fn with_name(self, name: &str) -> Self {
+        self.set_name(name);
+        self
+    }
+    pub fn name(&self) -> Option<String> {
+        self.handle.name.get()
+    }
     pub fn var(&self) -> Tex2dVar<T> {
         Tex2dVar::new(self.view(0))
     }
@@ -1188,6 +1772,17 @@ impl<T: IoTexel> Tex3d<T> {
     pub fn handle(&self) -> api::Texture {
         self.handle.handle
     }
+    /// See [`Buffer::set_name`].
+    pub fn set_name(&self, name: &str) {
+        self.handle.set_name(name);
+    }
+    pub fn with_name(self, name: &str) -> Self {
+        self.set_name(name);
+        self
+    }
+    pub fn name(&self) -> Option<String> {
+        self.handle.name.get()
+    }
     pub fn var(&self) -> Tex3dVar<T> {
         Tex3dVar::new(self.view(0))
     }
@@ -1198,30 +1793,51 @@ macro_rules! impl_tex_view {
             pub(crate) fn _handle(&self) -> Arc<TextureHandle> {
                 self.handle.upgrade().unwrap()
             }
+            pub(crate) fn describe(&self) -> String {
+                match self.handle.upgrade() {
+                    Some(handle) => format!("{} (level {})", handle.describe(), self.level),
+                    None => "dropped texture".to_string(),
+                }
+            }
             pub fn copy_to_async<'a, U: StorageTexel<T>>(
                 &self,
                 data: &'a mut [U],
             ) -> Command<'a, 'a> {
-                assert_eq!(data.len(), self.texel_count() as usize);
-                assert_eq!(self.storage, U::pixel_storage());
+                assert_eq!(
+                    data.len(),
+                    self.texel_count() as usize,
+                    "texel count mismatch copying between {} and host",
+                    self.describe()
+                );
+                assert_eq!(
+                    self.storage,
+                    U::pixel_storage(),
+                    "storage mismatch copying between {} and host",
+                    self.describe()
+                );
                 let mut rt = ResourceTracker::new();
                 rt.add(self._handle());
                 Command {
-                    inner: api::Command::TextureDownload(api::TextureDownloadCommand {
+                    inner: Some(api::Command::TextureDownload(api::TextureDownloadCommand {
                         texture: self.handle(),
                         storage: self.storage,
                         level: self.level,
                         size: self.size(),
                         data: data.as_mut_ptr() as *mut u8,
-                    }),
+                    })),
                     resource_tracker: rt,
                     marker: PhantomData,
                     callback: None,
+                    rearm: None,
                 }
             }
             pub fn copy_to<U: StorageTexel<T>>(&self, data: &mut [U]) {
-                assert_eq!(data.len(), self.texel_count() as usize);
-
+                assert_eq!(
+                    data.len(),
+                    self.texel_count() as usize,
+                    "texel count mismatch copying from {} to host",
+                    self.describe()
+                );
                 submit_default_stream_and_sync(&self.device, [self.copy_to_async(data)]);
             }
             pub fn copy_to_vec<U: StorageTexel<T>>(&self) -> Vec<U> {
@@ -1236,21 +1852,32 @@ macro_rules! impl_tex_view {
                 &self,
                 data: &[U],
             ) -> Command<'static, 'static> {
-                assert_eq!(data.len(), self.texel_count() as usize);
-                assert_eq!(self.storage, U::pixel_storage());
+                assert_eq!(
+                    data.len(),
+                    self.texel_count() as usize,
+                    "texel count mismatch copying between {} and host",
+                    self.describe()
+                );
+                assert_eq!(
+                    self.storage,
+                    U::pixel_storage(),
+                    "storage mismatch copying between {} and host",
+                    self.describe()
+                );
                 let mut rt = ResourceTracker::new();
                 rt.add(self._handle());
                 Command {
-                    inner: api::Command::TextureUpload(api::TextureUploadCommand {
+                    inner: Some(api::Command::TextureUpload(api::TextureUploadCommand {
                         texture: self.handle(),
                         storage: self.storage,
                         level: self.level,
                         size: self.size(),
                         data: data.as_ptr() as *const u8,
-                    }),
+                    })),
                     resource_tracker: rt,
                     marker: PhantomData,
                     callback: None,
+                    rearm: None,
                 }
             }
             pub fn copy_from<U: StorageTexel<T>>(&self, data: &[U]) {
@@ -1263,20 +1890,35 @@ macro_rules! impl_tex_view {
                 let mut rt = ResourceTracker::new();
                 rt.add(self._handle());
                 rt.add(buffer_view._handle());
-                assert_eq!(buffer_view.len, self.texel_count() as usize);
-                assert_eq!(self.storage, U::pixel_storage());
+                assert_eq!(
+                    buffer_view.len,
+                    self.texel_count() as usize,
+                    "texel count mismatch copying between {} and {}",
+                    self.describe(),
+                    buffer_view.describe()
+                );
+                assert_eq!(
+                    self.storage,
+                    U::pixel_storage(),
+                    "storage mismatch copying between {} and {}",
+                    self.describe(),
+                    buffer_view.describe()
+                );
                 Command {
-                    inner: api::Command::TextureToBufferCopy(api::TextureToBufferCopyCommand {
-                        texture: self.handle(),
-                        storage: self.storage,
-                        texture_level: self.level,
-                        texture_size: self.size(),
-                        buffer: buffer_view.handle(),
-                        buffer_offset: buffer_view.offset,
-                    }),
+                    inner: Some(api::Command::TextureToBufferCopy(
+                        api::TextureToBufferCopyCommand {
+                            texture: self.handle(),
+                            storage: self.storage,
+                            texture_level: self.level,
+                            texture_size: self.size(),
+                            buffer: buffer_view.handle(),
+                            buffer_offset: buffer_view.offset,
+                        },
+                    )),
                     resource_tracker: rt,
                     marker: PhantomData,
                     callback: None,
+                    rearm: None,
                 }
             }
             pub fn copy_to_buffer<U: StorageTexel<T> + Value>(&self, buffer_view: &BufferView<U>) {
@@ -1289,20 +1931,35 @@ macro_rules! impl_tex_view {
                 let mut rt = ResourceTracker::new();
                 rt.add(self._handle());
                 rt.add(buffer_view._handle());
-                assert_eq!(buffer_view.len, self.texel_count() as usize);
-                assert_eq!(self.storage, U::pixel_storage());
+                assert_eq!(
+                    buffer_view.len,
+                    self.texel_count() as usize,
+                    "texel count mismatch copying between {} and {}",
+                    self.describe(),
+                    buffer_view.describe()
+                );
+                assert_eq!(
+                    self.storage,
+                    U::pixel_storage(),
+                    "storage mismatch copying between {} and {}",
+                    self.describe(),
+                    buffer_view.describe()
+                );
                 Command {
-                    inner: api::Command::BufferToTextureCopy(api::BufferToTextureCopyCommand {
-                        texture: self.handle(),
-                        storage: self.storage,
-                        texture_level: self.level,
-                        texture_size: self.size(),
-                        buffer: buffer_view.handle(),
-                        buffer_offset: buffer_view.offset,
-                    }),
+                    inner: Some(api::Command::BufferToTextureCopy(
+                        api::BufferToTextureCopyCommand {
+                            texture: self.handle(),
+                            storage: self.storage,
+                            texture_level: self.level,
+                            texture_size: self.size(),
+                            buffer: buffer_view.handle(),
+                            buffer_offset: buffer_view.offset,
+                        },
+                    )),
                     resource_tracker: rt,
                     marker: PhantomData,
                     callback: None,
+                    rearm: None,
                 }
             }
             pub fn copy_from_buffer<U: StorageTexel<T> + Value>(
@@ -1315,21 +1972,30 @@ macro_rules! impl_tex_view {
                 let mut rt = ResourceTracker::new();
                 rt.add(self._handle());
                 rt.add(other._handle());
-                assert_eq!(self.size(), other.size());
-                assert_eq!(self.storage, other.storage);
-                assert_eq!(self.format, other.format);
+                let mismatch = |what: &str| {
+                    format!(
+                        "{} mismatch copying from {} to {}",
+                        what,
+                        self.describe(),
+                        other.describe()
+                    )
+                };
+                assert_eq!(self.size(), other.size(), "{}", mismatch("size"));
+                assert_eq!(self.storage, other.storage, "{}", mismatch("storage"));
+                assert_eq!(self.format, other.format, "{}", mismatch("format"));
                 Command {
-                    inner: api::Command::TextureCopy(api::TextureCopyCommand {
+                    inner: Some(api::Command::TextureCopy(api::TextureCopyCommand {
                         src: self.handle(),
                         storage: self.storage,
                         src_level: self.level,
                         size: self.size(),
                         dst: other.handle(),
                         dst_level: other.level,
-                    }),
+                    })),
                     resource_tracker: rt,
                     marker: PhantomData,
                     callback: None,
+                    rearm: None,
                 }
             }
             pub fn copy_to_texture(&self, other: &$name<T>) {
@@ -1381,6 +2047,7 @@ impl_tex_view!(Tex3dView);
 impl Drop for TextureHandle {
     fn drop(&mut self) {
         self.device.inner.destroy_texture(self.handle);
+        self.device.inner.memory.release(self.allocation);
     }
 }
 
@@ -1444,9 +2111,30 @@ pub struct BufferVar<T: Value> {
     pub(crate) marker: PhantomData<T>,
     #[allow(dead_code)]
     pub(crate) handle: Option<Arc<BufferHandle>>,
+    /// index of the argument for buffers passed to the kernel or callable
+    pub(crate) param: Option<usize>,
+    /// byte offset of captured views, unknown for arguments
+    pub(crate) offset_bytes: Option<u64>,
     pub(crate) node: SafeNodeRef,
 }
 impl<T: Value> BufferVar<T> {
+    /// The buffer in messages of failed checks. These are built with the
+    /// kernel, so they keep the name the buffer had at that time.
+    pub(crate) fn describe(&self) -> String {
+        match (&self.handle, self.param) {
+            (Some(handle), _) => handle.describe(),
+            (None, Some(param)) => format!("buffer argument {}", param),
+            (None, None) => "buffer argument".to_string(),
+        }
+    }
+    fn check_index(&self, i: Expr<u64>) {
+        if need_runtime_check() {
+            lc_assert!(
+                i.lt(self.len_expr()),
+                &format!("index out of bounds of {}", self.describe())
+            );
+        }
+    }
     pub fn device_address(&self) -> Expr<u64> {
         let buf = self.node().get();
         Expr::from_node(
@@ -1933,8 +2621,10 @@ impl BindlessArrayVar {
             let binding = Binding::BindlessArray(BindlessArrayBinding { handle });
             if let Some((a, b)) = r.check_on_same_device(&array.device) {
                 panic!(
-                    "BindlessArray created for a device: `{:?}` but used in `{:?}`",
-                    b, a
+                    "{} created for a device: `{:?}` but used in `{:?}`",
+                    array.handle.describe(),
+                    b,
+                    a
                 );
             }
             r.capture_or_get(binding, &Arc::downgrade(&array.handle), || {
@@ -1986,9 +2676,7 @@ impl<T: Value> IndexRead for BufferVar<T> {
     type Element = T;
     fn read<I: IntoIndex>(&self, i: I) -> Expr<T> {
         let i = i.to_u64();
-        if need_runtime_check() {
-            lc_assert!(i.lt(self.len_expr()));
-        }
+        self.check_index(i);
         let self_node = self.node.get();
         let i = i.node.get();
         Expr::<T>::from_node(
@@ -2000,9 +2688,7 @@ impl<T: Value> IndexWrite for BufferVar<T> {
     fn write<I: IntoIndex, V: AsExpr<Value = T>>(&self, i: I, v: V) {
         let i = i.to_u64();
         let v = v.as_expr().node().get();
-        if need_runtime_check() {
-            lc_assert!(i.lt(self.len_expr()));
-        }
+        self.check_index(i);
         let i = i.node().get();
         let self_node = self.node.get();
         __current_scope(|b| b.call(Func::BufferWrite, &[self_node, i, v], Type::void()));
@@ -2018,8 +2704,10 @@ impl<T: Value> BufferVar<T> {
             });
             if let Some((a, b)) = r.check_on_same_device(&buffer.device) {
                 panic!(
-                    "Buffer created for a device: `{:?}` but used in `{:?}`",
-                    b, a
+                    "{} created for a device: `{:?}` but used in `{:?}`",
+                    buffer.describe(),
+                    b,
+                    a
                 );
             }
             r.capture_or_get(binding, &buffer.handle, || {
@@ -2031,13 +2719,13 @@ impl<T: Value> BufferVar<T> {
             node,
             marker: PhantomData,
             handle: Some(buffer._handle()),
+            param: None,
+            offset_bytes: Some((buffer.offset * std::mem::size_of::<T>()) as u64),
         }
     }
     pub fn atomic_ref(&self, i: impl IntoIndex) -> AtomicRef<T> {
         let i = i.to_u64();
-        if need_runtime_check() {
-            lc_assert!(i.lt(self.len_expr()));
-        }
+        self.check_index(i);
         let i = i.node().get();
         let self_node = self.node.get();
         AtomicRef::<T>::from_node(
@@ -2069,9 +2757,7 @@ macro_rules! impl_atomic {
             ) -> Expr<$t> {
                 let i = i.to_u64();
                 let v = v.as_expr();
-                if need_runtime_check() {
-                    lc_assert!(i.lt(self.len_expr()));
-                }
+                self.check_index(i);
                 let self_node = self.node.get();
                 let i = i.node().get();
                 let v = v.node().get();
@@ -2095,9 +2781,7 @@ macro_rules! impl_atomic {
                 let i = i.to_u64();
                 let expected = expected.as_expr();
                 let desired = desired.as_expr();
-                if need_runtime_check() {
-                    lc_assert!(i.lt(self.len_expr()));
-                }
+                self.check_index(i);
                 let self_node = self.node.get();
                 let i = i.node().get();
                 let expected = expected.node().get();
@@ -2120,9 +2804,7 @@ macro_rules! impl_atomic {
             ) -> Expr<$t> {
                 let i = i.to_u64();
                 let v = v.as_expr();
-                if need_runtime_check() {
-                    lc_assert!(i.lt(self.len_expr()));
-                }
+                self.check_index(i);
                 let self_node = self.node.get();
                 let i = i.node().get();
                 let v = v.node().get();
@@ -2140,9 +2822,7 @@ macro_rules! impl_atomic {
             ) -> Expr<$t> {
                 let i = i.to_u64();
                 let v = v.as_expr();
-                if need_runtime_check() {
-                    lc_assert!(i.lt(self.len_expr()));
-                }
+                self.check_index(i);
                 let self_node = self.node.get();
                 let i = i.node().get();
                 let v = v.node().get();
@@ -2160,9 +2840,7 @@ macro_rules! impl_atomic {
             ) -> Expr<$t> {
                 let i = i.to_u64();
                 let v = v.as_expr();
-                if need_runtime_check() {
-                    lc_assert!(i.lt(self.len_expr()));
-                }
+                self.check_index(i);
                 let self_node = self.node.get();
                 let i = i.node().get();
                 let v = v.node().get();
@@ -2180,9 +2858,7 @@ macro_rules! impl_atomic {
             ) -> Expr<$t> {
                 let i = i.to_u64();
                 let v = v.as_expr();
-                if need_runtime_check() {
-                    lc_assert!(i.lt(self.len_expr()));
-                }
+                self.check_index(i);
                 let self_node = self.node.get();
                 let i = i.node().get();
                 let v = v.node().get();
@@ -2206,9 +2882,7 @@ macro_rules! impl_atomic_bit {
             ) -> Expr<$t> {
                 let i = i.to_u64();
                 let v = v.as_expr();
-                if need_runtime_check() {
-                    lc_assert!(i.lt(self.len_expr()));
-                }
+                self.check_index(i);
                 let self_node = self.node.get();
                 let i = i.node().get();
                 let v = v.node().get();
@@ -2226,9 +2900,7 @@ macro_rules! impl_atomic_bit {
             ) -> Expr<$t> {
                 let i = i.to_u64();
                 let v = v.as_expr();
-                if need_runtime_check() {
-                    lc_assert!(i.lt(self.len_expr()));
-                }
+                self.check_index(i);
                 let self_node = self.node.get();
                 let i = i.node().get();
                 let v = v.node().get();
@@ -2246,9 +2918,7 @@ macro_rules! impl_atomic_bit {
             ) -> Expr<$t> {
                 let i = i.to_u64();
                 let v = v.as_expr();
-                if need_runtime_check() {
-                    lc_assert!(i.lt(self.len_expr()));
-                }
+                self.check_index(i);
                 let self_node = self.node.get();
                 let i = i.node().get();
                 let v = v.node().get();
@@ -2291,8 +2961,10 @@ impl<T: IoTexel> Tex2dVar<T> {
             });
             if let Some((a, b)) = r.check_on_same_device(&view.device) {
                 panic!(
-                    "Tex2d created for a device: `{:?}` but used in `{:?}`",
-                    b, a
+                    "{} created for a device: `{:?}` but used in `{:?}`",
+                    view.describe(),
+                    b,
+                    a
                 );
             }
             r.capture_or_get(binding, &view.handle, || {
@@ -2342,8 +3014,10 @@ impl<T: IoTexel> Tex3dVar<T> {
             });
             if let Some((a, b)) = r.check_on_same_device(&view.device) {
                 panic!(
-                    "Tex3d created for a device: `{:?}` but used in `{:?}`",
-                    b, a
+                    "{} created for a device: `{:?}` but used in `{:?}`",
+                    view.describe(),
+                    b,
+                    a
                 );
             }
             r.capture_or_get(binding, &view.handle, || {
@@ -0,0 +1,300 @@
+//! Slot management for [`BindlessArray`].
+//!
+//! A [`BindlessAllocator`] emplaces resources in free slots of a bindless array
+//! and hands out typed slots which remove them again when dropped. A removed
+//! slot is only reused once the [`BindlessAllocator::update_async`] command
+//! removing it has completed, so kernels submitted before it never see another
+//! resource in the slot.
+//!
+//! When all slots are taken, the allocator moves its resources into a new array
+//! twice as large. Pass [`BindlessAllocator::array`] to each dispatch instead of
+//! capturing it in a kernel, which would keep using the old array.
+use parking_lot::Mutex;
+
+use super::*;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum SlotKind {
+    Buffer,
+    Tex2d,
+    Tex3d,
+}
+const SLOT_KINDS: [SlotKind; 3] = [SlotKind::Buffer, SlotKind::Tex2d, SlotKind::Tex3d];
+impl SlotKind {
+    fn remove(self, array: &BindlessArray, index: usize) {
+        match self {
+            SlotKind::Buffer => array.remove_buffer_async(index),
+            SlotKind::Tex2d => array.remove_tex2d_async(index),
+            SlotKind::Tex3d => array.remove_tex3d_async(index),
+        }
+    }
+}
+
+#[derive(Clone)]
+enum SlotContent {
+    Buffer {
+        handle: Arc<BufferHandle>,
+        offset: usize,
+    },
+    Tex2d {
+        handle: Arc<TextureHandle>,
+        sampler: Sampler,
+    },
+    Tex3d {
+        handle: Arc<TextureHandle>,
+        sampler: Sampler,
+    },
+}
+impl SlotContent {
+    fn kind(&self) -> SlotKind {
+        match self {
+            SlotContent::Buffer { .. } => SlotKind::Buffer,
+            SlotContent::Tex2d { .. } => SlotKind::Tex2d,
+            SlotContent::Tex3d { .. } => SlotKind::Tex3d,
+        }
+    }
+    fn emplace(&self, array: &BindlessArray, index: usize) {
+        match self {
+            SlotContent::Buffer { handle, offset } => {
+                array.emplace_buffer_handle_async(index, handle.clone(), *offset)
+            }
+            SlotContent::Tex2d { handle, sampler } => {
+                array.emplace_tex2d_handle_async(index, handle, *sampler)
+            }
+            SlotContent::Tex3d { handle, sampler } => {
+                array.emplace_tex3d_handle_async(index, handle, *sampler)
+            }
+        }
+    }
+}
+
+/// The slots of one kind, buffers and textures in the same slot are independent.
+#[derive(Default)]
+struct SlotPool {
+    /// contents of the slots handed out so far, `None` once released
+    contents: Vec<Option<SlotContent>>,
+    /// released slots whose removal has completed
+    free: Vec<usize>,
+    /// released slots to remove in the next update
+    released: Vec<usize>,
+}
+
+struct AllocatorState {
+    array: Arc<BindlessArray>,
+    capacity: usize,
+    pools: [SlotPool; 3],
+}
+impl AllocatorState {
+    fn grow(&mut self, device: &Device) {
+        let capacity = self.capacity * 2;
+        let array = device.create_bindless_array(capacity);
+        if let Some(name) = self.array.name() {
+            array.set_name(&name);
+        }
+        for pool in &self.pools {
+            for (index, content) in pool.contents.iter().enumerate() {
+                if let Some(content) = content {
+                    content.emplace(&array, index);
+                }
+            }
+        }
+        self.array = Arc::new(array);
+        self.capacity = capacity;
+    }
+}
+
+pub(crate) struct BindlessAllocatorInner {
+    device: Device,
+    state: Mutex<AllocatorState>,
+}
+unsafe impl Send for BindlessAllocatorInner {}
+unsafe impl Sync for BindlessAllocatorInner {}
+
+/// Hands out slots of a [`BindlessArray`], see the [module documentation](self).
+///
+/// Emplacing and removing are recorded like [`BindlessArray::emplace_buffer_async`]
+/// and friends, call [`BindlessAllocator::update`] before using the array.
+#[derive(Clone)]
+pub struct BindlessAllocator {
+    pub(crate) inner: Arc<BindlessAllocatorInner>,
+}
+impl BindlessAllocator {
+    pub(crate) fn new(device: &Device, array: BindlessArray, slots: usize) -> Self {
+        Self {
+            inner: Arc::new(BindlessAllocatorInner {
+                device: device.internal(),
+                state: Mutex::new(AllocatorState {
+                    array: Arc::new(array),
+                    capacity: slots,
+                    pools: Default::default(),
+                }),
+            }),
+        }
+    }
+    /// The current array, which is replaced when the allocator grows.
+    pub fn array(&self) -> Arc<BindlessArray> {
+        self.inner.state.lock().array.clone()
+    }
+    /// Number of slots of the current array.
+    pub fn capacity(&self) -> usize {
+        self.inner.state.lock().capacity
+    }
+    pub fn alloc_buffer<T: Value>(&self, buffer: &Buffer<T>) -> BindlessBufferSlot<T> {
+        self.alloc_buffer_view(&buffer.view(..))
+    }
+    pub fn alloc_buffer_view<T: Value>(&self, buffer: &BufferView<T>) -> BindlessBufferSlot<T> {
+        BindlessBufferSlot {
+            slot: self.alloc(SlotContent::Buffer {
+                handle: buffer._handle(),
+                offset: buffer.offset,
+            }),
+            _marker: PhantomData,
+        }
+    }
+    pub fn alloc_tex2d<T: IoTexel>(
+        &self,
+        texture: &Tex2d<T>,
+        sampler: Sampler,
+    ) -> BindlessTex2dSlot {
+        BindlessTex2dSlot {
+            slot: self.alloc(SlotContent::Tex2d {
+                handle: texture.handle.clone(),
+                sampler,
+            }),
+        }
+    }
+    pub fn alloc_tex3d<T: IoTexel>(
+        &self,
+        texture: &Tex3d<T>,
+        sampler: Sampler,
+    ) -> BindlessTex3dSlot {
+        BindlessTex3dSlot {
+            slot: self.alloc(SlotContent::Tex3d {
+                handle: texture.handle.clone(),
+                sampler,
+            }),
+        }
+    }
+    fn alloc(&self, content: SlotContent) -> BindlessSlot {
+        let kind = content.kind();
+        let mut state = self.inner.state.lock();
+        let index = match state.pools[kind as usize].free.pop() {
+            Some(index) => index,
+            None => {
+                let index = state.pools[kind as usize].contents.len();
+                if index == state.capacity {
+                    state.grow(&self.inner.device);
+                }
+                state.pools[kind as usize].contents.push(None);
+                index
+            }
+        };
+        content.emplace(&state.array, index);
+        state.pools[kind as usize].contents[index] = Some(content);
+        BindlessSlot {
+            allocator: self.inner.clone(),
+            kind,
+            index,
+        }
+    }
+    /// Updates the current array with the slots allocated and dropped since the
+    /// last update. The dropped slots are reused once the command has completed.
+    pub fn update_async(&self) -> Command<'static, 'static> {
+        let (array, released) = {
+            let mut state = self.inner.state.lock();
+            let state = &mut *state;
+            let released = state
+                .pools
+                .iter_mut()
+                .map(|pool| std::mem::take(&mut pool.released))
+                .collect::<Vec<_>>();
+            for (kind, slots) in SLOT_KINDS.iter().zip(&released) {
+                for &index in slots {
+                    kind.remove(&state.array, index);
+                }
+            }
+            (state.array.clone(), released)
+        };
+        // the array is kept alive by the command
+        let mut command = unsafe { array.update_async().lift() };
+        command.resource_tracker.add(array);
+        let unlock = command.callback.take();
+        // replaying the update would remove the slots again after their reuse
+        command.rearm = None;
+        let inner = self.inner.clone();
+        command.callback = Some(Box::new(move || {
+            if let Some(unlock) = unlock {
+                unlock();
+            }
+            let mut state = inner.state.lock();
+            for (pool, slots) in state.pools.iter_mut().zip(released) {
+                pool.free.extend(slots);
+            }
+        }));
+        command
+    }
+    #[inline]
+    pub fn update(&self) {
+        submit_default_stream_and_sync(&self.inner.device, [self.update_async()]);
+    }
+}
+
+struct BindlessSlot {
+    allocator: Arc<BindlessAllocatorInner>,
+    kind: SlotKind,
+    index: usize,
+}
+impl Drop for BindlessSlot {
+    fn drop(&mut self) {
+        let mut state = self.allocator.state.lock();
+        let pool = &mut state.pools[self.kind as usize];
+        pool.contents[self.index] = None;
+        pool.released.push(self.index);
+    }
+}
+
+/// A buffer in a slot of a [`BindlessAllocator`], removed when dropped.
+pub struct BindlessBufferSlot<T: Value> {
+    slot: BindlessSlot,
+    _marker: PhantomData<fn() -> T>,
+}
+impl<T: Value> BindlessBufferSlot<T> {
+    #[inline]
+    pub fn index(&self) -> u32 {
+        self.slot.index as u32
+    }
+    /// The buffer in `array`, which should be [`BindlessAllocator::array`].
+    pub fn var(&self, array: &BindlessArrayVar) -> BindlessBufferVar<T> {
+        array.buffer::<T>(self.index())
+    }
+}
+
+/// A 2D texture in a slot of a [`BindlessAllocator`], removed when dropped.
+pub struct BindlessTex2dSlot {
+    slot: BindlessSlot,
+}
+impl BindlessTex2dSlot {
+    #[inline]
+    pub fn index(&self) -> u32 {
+        self.slot.index as u32
+    }
+    /// The texture in `array`, which should be [`BindlessAllocator::array`].
+    pub fn var(&self, array: &BindlessArrayVar) -> BindlessTex2dVar {
+        array.tex2d(self.index())
+    }
+}
+
+/// A 3D texture in a slot of a [`BindlessAllocator`], removed when dropped.
+pub struct BindlessTex3dSlot {
+    slot: BindlessSlot,
+}
+impl BindlessTex3dSlot {
+    #[inline]
+    pub fn index(&self) -> u32 {
+        self.slot.index as u32
+    }
+    /// The texture in `array`, which should be [`BindlessAllocator::array`].
+    pub fn var(&self, array: &BindlessArrayVar) -> BindlessTex3dVar {
+        array.tex3d(self.index())
+    }
+}
@@ -0,0 +1,144 @@
+//! Typed references to the slots of a [`BindlessArray`].
+//!
+//! [`BufferRef<T>`], [`Tex2dRef`] and [`Tex3dRef`] are [`Value`]s holding a slot
+//! index, so they can be fields of `#[derive(Value)]` structs and stored in
+//! buffers. In kernels they are dereferenced against a [`BindlessArrayVar`]:
+//! ```ignore
+//! #[derive(Clone, Copy, Value)]
+//! #[repr(C)]
+//! struct Material {
+//!     albedo: Tex2dRef,
+//!     weights: BufferRef<f32>,
+//! }
+//! let w = material.weights.get(&heap).read(i);
+//! ```
+//! When runtime checks are enabled, dereferencing a [`Tex2dRef`] or
+//! [`Tex3dRef`] checks that the slot holds a texture. The element type of a
+//! [`BufferRef<T>`] is checked by [`BindlessArrayVar::buffer`], on the backends
+//! which keep the types of bindless buffers.
+use super::*;
+
+fn check_tex2d(tex: &BindlessTex2dVar) {
+    lc_assert!(
+        track!(tex.size().x > 0),
+        "bindless slot referenced as a 2D texture holds none"
+    );
+}
+fn check_tex3d(tex: &BindlessTex3dVar) {
+    lc_assert!(
+        track!(tex.size().x > 0),
+        "bindless slot referenced as a 3D texture holds none"
+    );
+}
+
+macro_rules! impl_bindless_ref {
+    ($name:ident $([$T:ident])?, $expr:ident, $var:ident, $atomic:ident, $slot:ident, $target:ty, $get:ident, $what:literal $(, $check:ident)?) => {
+        #[doc = concat!("A typed reference to the ", $what, " in a slot of a [`BindlessArray`], see the [module documentation](self).")]
+        #[repr(transparent)]
+        pub struct $name$(<$T: Value>)? {
+            index: u32,
+            $(_marker: PhantomData<fn() -> $T>,)?
+        }
+        impl$(<$T: Value>)? $name$(<$T>)? {
+            #[inline]
+            pub fn new(index: u32) -> Self {
+                Self {
+                    index,
+                    $(_marker: PhantomData::<fn() -> $T>,)?
+                }
+            }
+            #[inline]
+            pub fn index(&self) -> u32 {
+                self.index
+            }
+        }
+        impl$(<$T: Value>)? Clone for $name$(<$T>)? {
+            fn clone(&self) -> Self {
+                *self
+            }
+        }
+        impl$(<$T: Value>)? Copy for $name$(<$T>)? {}
+        impl$(<$T: Value>)? PartialEq for $name$(<$T>)? {
+            fn eq(&self, other: &Self) -> bool {
+                self.index == other.index
+            }
+        }
+        impl$(<$T: Value>)? Eq for $name$(<$T>)? {}
+        impl$(<$T: Value>)? std::hash::Hash for $name$(<$T>)? {
+            fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+                self.index.hash(state)
+            }
+        }
+        impl$(<$T: Value>)? fmt::Debug for $name$(<$T>)? {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(f, "{}({})", stringify!($name), self.index)
+            }
+        }
+        // stored as the slot index
+        impl$(<$T: Value>)? TypeOf for $name$(<$T>)? {
+            fn type_() -> CArc<Type> {
+                <u32 as TypeOf>::type_()
+            }
+        }
+        impl$(<$T: Value>)? Value for $name$(<$T>)? {
+            type Expr = $expr$(<$T>)?;
+            type Var = $var$(<$T>)?;
+            type AtomicRef = $atomic$(<$T>)?;
+        }
+        crate::impl_simple_expr_proxy!($([$T: Value])? $expr $([$T])? for $name$(<$T>)?);
+        crate::impl_simple_var_proxy!($([$T: Value])? $var $([$T])? for $name$(<$T>)?);
+        crate::impl_simple_atomic_ref_proxy!($([$T: Value])? $atomic $([$T])? for $name$(<$T>)?);
+        impl$(<$T: Value>)? $expr$(<$T>)? {
+            pub fn index(&self) -> Expr<u32> {
+                self.0.bitcast::<u32>()
+            }
+            #[doc = concat!("The ", $what, " in `array`.")]
+            pub fn get(&self, array: &BindlessArrayVar) -> $target {
+                let target = array.$get(self.index());
+                $(if need_runtime_check() {
+                    $check(&target);
+                })?
+                target
+            }
+        }
+        impl$(<$T: Value>)? $slot$(<$T>)? {
+            /// A reference to this slot, valid as long as the slot is not dropped.
+            #[inline]
+            pub fn to_ref(&self) -> $name$(<$T>)? {
+                $name::new(self.index())
+            }
+        }
+    };
+}
+impl_bindless_ref!(
+    BufferRef[T],
+    BufferRefExpr,
+    BufferRefVar,
+    BufferRefAtomicRef,
+    BindlessBufferSlot,
+    BindlessBufferVar<T>,
+    buffer,
+    "buffer"
+);
+impl_bindless_ref!(
+    Tex2dRef,
+    Tex2dRefExpr,
+    Tex2dRefVar,
+    Tex2dRefAtomicRef,
+    BindlessTex2dSlot,
+    BindlessTex2dVar,
+    tex2d,
+    "2D texture",
+    check_tex2d
+);
+impl_bindless_ref!(
+    Tex3dRef,
+    Tex3dRefExpr,
+    Tex3dRefVar,
+    Tex3dRefAtomicRef,
+    BindlessTex3dSlot,
+    BindlessTex3dVar,
+    tex3d,
+    "3D texture",
+    check_tex3d
+);
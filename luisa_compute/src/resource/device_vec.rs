@@ -0,0 +1,162 @@
+//! Device vectors which kernels can push to.
+//!
+//! A [`DeviceVec<T>`] is a buffer with a counter of the values pushed by
+//! kernels with [`DeviceVecVar::push`]. Values pushed past the capacity are
+//! dropped but still counted, the host sees them with
+//! [`DeviceVec::overflowed`], and [`DeviceVec::clear`] grows the buffer to fit
+//! them for the next submission. Growing replaces the buffer, so pass the vector
+//! as a kernel argument instead of capturing it.
+use crate::error::{ensure_arg, Result};
+
+use super::*;
+
+/// A buffer with a counter of values pushed by kernels, see the
+/// [module documentation](self).
+pub struct DeviceVec<T: Value> {
+    pub(crate) data: Buffer<T>,
+    /// number of pushes since the last clear, may exceed the capacity
+    pub(crate) counter: Buffer<u32>,
+}
+crate::impl_resource_deref_to_var!(DeviceVec, DeviceVecVar [T: Value]);
+
+impl<T: Value> DeviceVec<T> {
+    pub(crate) fn new(device: &Device, capacity: usize) -> Result<Self> {
+        ensure_arg!(
+            capacity > 0 && capacity <= u32::MAX as usize,
+            "capacity must be in 1..=u32::MAX"
+        );
+        let counter = device.try_create_buffer::<u32>(1)?;
+        counter.copy_from(&[0]);
+        Ok(Self {
+            data: device.try_create_buffer(capacity)?,
+            counter,
+        })
+    }
+    #[inline]
+    pub fn device(&self) -> &Device {
+        &self.data.device
+    }
+    #[inline]
+    pub fn var(&self) -> DeviceVecVar<T> {
+        DeviceVecVar {
+            data: self.data.var(),
+            counter: self.counter.var(),
+        }
+    }
+    /// Number of values the buffer can hold.
+    #[inline]
+    pub fn capacity(&self) -> usize {
+        self.data.len()
+    }
+    /// The buffer, whose first [`DeviceVec::len`] values are pushed.
+    #[inline]
+    pub fn as_buffer(&self) -> &Buffer<T> {
+        &self.data
+    }
+    fn pushed(&self) -> usize {
+        self.counter.copy_to_vec()[0] as usize
+    }
+    /// Number of values pushed and kept.
+    pub fn len(&self) -> usize {
+        self.pushed().min(self.capacity())
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    /// Number of values pushed past the capacity and dropped since the last
+    /// [`DeviceVec::clear`].
+    pub fn overflowed(&self) -> usize {
+        self.pushed().saturating_sub(self.capacity())
+    }
+    /// Removes all values, growing the buffer first if pushes overflowed.
+    pub fn clear(&mut self) {
+        let pushed = self.pushed();
+        if pushed > self.capacity() {
+            self.grow(pushed, 0);
+        }
+        self.counter.copy_from(&[0]);
+    }
+    /// Grows the buffer to hold at least `additional` more values than
+    /// [`DeviceVec::len`], keeping the values.
+    pub fn reserve(&mut self, additional: usize) {
+        let len = self.len();
+        let required = len + additional;
+        if required > self.capacity() {
+            self.grow(required, len);
+        }
+        // dropped values are lost for good, don't count them anymore
+        self.counter.copy_from(&[len as u32]);
+    }
+    /// Replaces the buffer by one holding at least `required` values, with the
+    /// first `keep` values copied.
+    fn grow(&mut self, required: usize, keep: usize) {
+        assert!(
+            required <= u32::MAX as usize,
+            "device vector of {} values is too large",
+            required
+        );
+        let capacity = required.next_power_of_two().min(u32::MAX as usize);
+        let data = self.device().create_buffer::<T>(capacity);
+        if keep > 0 {
+            self.data.view(..keep).copy_to_buffer(&data.view(..keep));
+        }
+        if let Some(name) = self.data.name() {
+            data.set_name(&name);
+        }
+        self.data = data;
+    }
+    /// Copies the values pushed and kept.
+    pub fn copy_to_vec(&self) -> Vec<T> {
+        match self.len() {
+            0 => vec![],
+            len => self.data.view(..len).copy_to_vec(),
+        }
+    }
+}
+
+/// A [`DeviceVec`] in a kernel.
+pub struct DeviceVecVar<T: Value> {
+    pub(crate) data: BufferVar<T>,
+    pub(crate) counter: BufferVar<u32>,
+}
+impl<T: Value> Clone for DeviceVecVar<T> {
+    fn clone(&self) -> Self {
+        Self {
+            data: self.data.clone(),
+            counter: self.counter.clone(),
+        }
+    }
+}
+impl<T: Value> DeviceVecVar<T> {
+    #[inline]
+    pub fn capacity_expr(&self) -> Expr<u32> {
+        self.data.len_expr_u32()
+    }
+    /// Appends `value` and returns its index, the value is dropped if the index
+    /// is not less than [`DeviceVecVar::capacity_expr`].
+    #[tracked]
+    pub fn push(&self, value: impl AsExpr<Value = T>) -> Expr<u32> {
+        let value = value.as_expr();
+        let index = self.counter.atomic_ref(0).fetch_add(1);
+        if index < self.capacity_expr() {
+            self.data.write(index, value);
+        };
+        index
+    }
+    /// Number of values pushed and kept so far.
+    #[tracked]
+    pub fn len_expr(&self) -> Expr<u32> {
+        self.counter.read(0).min_(self.capacity_expr())
+    }
+}
+impl<T: Value> IndexRead for DeviceVecVar<T> {
+    type Element = T;
+    fn read<I: IntoIndex>(&self, i: I) -> Expr<T> {
+        self.data.read(i)
+    }
+}
+impl<T: Value> IndexWrite for DeviceVecVar<T> {
+    fn write<I: IntoIndex, V: AsExpr<Value = T>>(&self, i: I, value: V) {
+        self.data.write(i, value)
+    }
+}
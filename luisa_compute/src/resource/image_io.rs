@@ -0,0 +1,286 @@
+//! Loading and saving [`Tex2d`]s as image files, enabled by the `image` feature.
+//!
+//! PNG and JPEG files are stored in sRGB and converted to linear values on load
+//! and back on save, HDR and OpenEXR files are linear. Textures are loaded as
+//! `Tex2d<Float4>` with a storage chosen to hold the decoded values without loss:
+//! `Half4` for linearized 8-bit images, `Float4` for 16-bit and floating point
+//! images, and the raw `Byte4` or `Short4` encoding if linearization is disabled.
+use std::path::Path;
+
+use half::f16;
+use image::{DynamicImage, ImageFormat};
+
+use super::*;
+use crate::error::Result;
+use crate::Error;
+
+/// Options of [`Device::load_tex2d_with_options`].
+#[derive(Clone)]
+pub struct ImageLoadOptions {
+    /// Convert 8 and 16-bit images from sRGB to linear values, defaults to true.
+    pub linearize: bool,
+    /// Number of mip levels to allocate and generate, 0 for a full chain.
+    /// Defaults to 1.
+    pub mips: u32,
+    pub mip_filter: MipmapFilter,
+}
+impl Default for ImageLoadOptions {
+    fn default() -> Self {
+        Self {
+            linearize: true,
+            mips: 1,
+            mip_filter: MipmapFilter::Box,
+        }
+    }
+}
+
+/// Options of [`Tex2d::save_with_options`].
+#[derive(Clone)]
+pub struct ImageSaveOptions {
+    /// Encode texels as sRGB when writing PNG or JPEG files, defaults to true.
+    pub srgb: bool,
+    /// Mip level to save, defaults to 0.
+    pub level: u32,
+}
+impl Default for ImageSaveOptions {
+    fn default() -> Self {
+        Self {
+            srgb: true,
+            level: 0,
+        }
+    }
+}
+
+fn srgb_to_linear(x: f32) -> f32 {
+    if x <= 0.04045 {
+        x / 12.92
+    } else {
+        ((x + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(x: f32) -> f32 {
+    if x <= 0.0031308 {
+        x * 12.92
+    } else {
+        1.055 * x.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+fn image_error(path: &Path, e: impl fmt::Display) -> Error {
+    Error::InvalidArgument(format!("cannot access image {}: {}", path.display(), e))
+}
+
+impl Device {
+    /// Loads an image file as a texture, see [`ImageLoadOptions`] for the defaults.
+    pub fn load_tex2d(&self, path: impl AsRef<Path>) -> Result<Tex2d<Float4>> {
+        self.load_tex2d_with_options(path, &ImageLoadOptions::default())
+    }
+    pub fn load_tex2d_with_options(
+        &self,
+        path: impl AsRef<Path>,
+        options: &ImageLoadOptions,
+    ) -> Result<Tex2d<Float4>> {
+        let path = path.as_ref();
+        let image = image::open(path).map_err(|e| image_error(path, e))?;
+        let (width, height) = (image.width(), image.height());
+        let max_mips = 32 - width.max(height).leading_zeros();
+        let mips = if options.mips == 0 {
+            max_mips
+        } else {
+            options.mips
+        };
+        let linear = |x: f32| {
+            if options.linearize {
+                srgb_to_linear(x)
+            } else {
+                x
+            }
+        };
+        let tex = match image {
+            DynamicImage::ImageLuma8(_)
+            | DynamicImage::ImageLumaA8(_)
+            | DynamicImage::ImageRgb8(_)
+            | DynamicImage::ImageRgba8(_) => {
+                let image = image.into_rgba8();
+                if options.linearize {
+                    let texels = image
+                        .pixels()
+                        .map(|p| {
+                            let c = |i: usize| f16::from_f32(linear(p[i] as f32 / 255.0));
+                            Half4::new(c(0), c(1), c(2), f16::from_f32(p[3] as f32 / 255.0))
+                        })
+                        .collect::<Vec<_>>();
+                    let tex = self.try_create_tex2d(PixelStorage::Half4, width, height, mips)?;
+                    tex.view(0).copy_from(&texels);
+                    tex
+                } else {
+                    let texels = image.pixels().map(|p| p.0).collect::<Vec<_>>();
+                    let tex = self.try_create_tex2d(PixelStorage::Byte4, width, height, mips)?;
+                    tex.view(0).copy_from(&texels);
+                    tex
+                }
+            }
+            DynamicImage::ImageLuma16(_)
+            | DynamicImage::ImageLumaA16(_)
+            | DynamicImage::ImageRgb16(_)
+            | DynamicImage::ImageRgba16(_) => {
+                let image = image.into_rgba16();
+                if options.linearize {
+                    let texels = image
+                        .pixels()
+                        .map(|p| {
+                            let c = |i: usize| linear(p[i] as f32 / 65535.0);
+                            Float4::new(c(0), c(1), c(2), p[3] as f32 / 65535.0)
+                        })
+                        .collect::<Vec<_>>();
+                    let tex = self.try_create_tex2d(PixelStorage::Float4, width, height, mips)?;
+                    tex.view(0).copy_from(&texels);
+                    tex
+                } else {
+                    let texels = image
+                        .pixels()
+                        .map(|p| Ushort4::new(p[0], p[1], p[2], p[3]))
+                        .collect::<Vec<_>>();
+                    let tex = self.try_create_tex2d(PixelStorage::Short4, width, height, mips)?;
+                    tex.view(0).copy_from(&texels);
+                    tex
+                }
+            }
+            // floating point images are linear
+            image => {
+                let texels = image
+                    .into_rgba32f()
+                    .pixels()
+                    .map(|p| Float4::new(p[0], p[1], p[2], p[3]))
+                    .collect::<Vec<_>>();
+                let tex = self.try_create_tex2d(PixelStorage::Float4, width, height, mips)?;
+                tex.view(0).copy_from(&texels);
+                tex
+            }
+        };
+        tex.generate_mipmaps(&options.mip_filter);
+        Ok(tex.with_name(&path.display().to_string()))
+    }
+}
+
+impl<T: IoTexel> Tex2d<T> {
+    /// Saves level 0 to an image file, the format is deduced from the extension.
+    /// See [`ImageSaveOptions`] for the defaults.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        self.save_with_options(path, &ImageSaveOptions::default())
+    }
+    /// Only textures with normalized or floating point formats can be saved.
+    pub fn save_with_options(
+        &self,
+        path: impl AsRef<Path>,
+        options: &ImageSaveOptions,
+    ) -> Result<()> {
+        let path = path.as_ref();
+        let format = ImageFormat::from_path(path).map_err(|e| image_error(path, e))?;
+        if options.level >= self.handle.levels {
+            return Err(Error::InvalidArgument(format!(
+                "cannot save level {} of {} with {} levels",
+                options.level,
+                self.handle.describe(),
+                self.handle.levels
+            )));
+        }
+        let view = self.view(options.level);
+        let texels = self.download_rgba(&view)?;
+        let [width, height, _] = view.size();
+        let image = image::Rgba32FImage::from_raw(width, height, texels).unwrap();
+        let image = match format {
+            ImageFormat::OpenExr => DynamicImage::ImageRgba32F(image),
+            ImageFormat::Hdr => {
+                DynamicImage::ImageRgb32F(DynamicImage::ImageRgba32F(image).into_rgb32f())
+            }
+            ImageFormat::Png | ImageFormat::Jpeg => {
+                let unorm = |x: f32| (x.clamp(0.0, 1.0) * 255.0).round() as u8;
+                let encode = |x: f32| unorm(if options.srgb { linear_to_srgb(x) } else { x });
+                let image =
+                    DynamicImage::ImageRgba8(image::RgbaImage::from_fn(width, height, |x, y| {
+                        let p = image.get_pixel(x, y);
+                        image::Rgba([encode(p[0]), encode(p[1]), encode(p[2]), unorm(p[3])])
+                    }));
+                if format == ImageFormat::Jpeg {
+                    // JPEG has no alpha channel
+                    DynamicImage::ImageRgb8(image.into_rgb8())
+                } else {
+                    image
+                }
+            }
+            format => {
+                return Err(Error::Unsupported(format!(
+                    "cannot save {}: {:?} images are not supported",
+                    path.display(),
+                    format
+                )))
+            }
+        };
+        image
+            .save_with_format(path, format)
+            .map_err(|e| image_error(path, e))
+    }
+    /// Texels of `view` as RGBA floats, missing channels are 0 and alpha is 1.
+    fn download_rgba(&self, view: &Tex2dView<T>) -> Result<Vec<f32>> {
+        use PixelFormat::*;
+        let (channels, bytes): (usize, usize) = match self.format() {
+            R8Unorm => (1, 1),
+            Rg8Unorm => (2, 1),
+            Rgba8Unorm => (4, 1),
+            R16Unorm | R16f => (1, 2),
+            Rg16Unorm | Rg16f => (2, 2),
+            Rgba16Unorm | Rgba16f => (4, 2),
+            R32f => (1, 4),
+            Rg32f => (2, 4),
+            Rgba32f => (4, 4),
+            format => {
+                return Err(Error::Unsupported(format!(
+                    "cannot save {:?} texture as an image",
+                    format
+                )))
+            }
+        };
+        let mut data = vec![0u8; view.texel_count() as usize * channels * bytes];
+        let mut rt = ResourceTracker::new();
+        rt.add(view._handle());
+        let command = Command {
+            inner: Some(api::Command::TextureDownload(api::TextureDownloadCommand {
+                texture: view.handle(),
+                storage: view.storage,
+                level: view.level,
+                size: view.size(),
+                data: data.as_mut_ptr(),
+            })),
+            resource_tracker: rt,
+            marker: PhantomData,
+            callback: None,
+            rearm: None,
+        };
+        submit_default_stream_and_sync(&view.device, [command]);
+        let channel = |c: &[u8]| -> f32 {
+            match (self.format(), c.len()) {
+                (_, 1) => c[0] as f32 / 255.0,
+                (R16f | Rg16f | Rgba16f, 2) => f16::from_le_bytes([c[0], c[1]]).to_f32(),
+                (_, 2) => u16::from_le_bytes([c[0], c[1]]) as f32 / 65535.0,
+                _ => f32::from_le_bytes([c[0], c[1], c[2], c[3]]),
+            }
+        };
+        Ok(data
+            .chunks(channels * bytes)
+            .flat_map(|texel| {
+                let mut rgba = [0.0, 0.0, 0.0, 1.0];
+                for (i, c) in texel.chunks(bytes).enumerate() {
+                    rgba[i] = channel(c);
+                }
+                if channels == 1 {
+                    // grayscale
+                    rgba[1] = rgba[0];
+                    rgba[2] = rgba[0];
+                }
+                rgba
+            })
+            .collect())
+    }
+}
@@ -0,0 +1,164 @@
+//! Dispatch sizes written by kernels.
+//!
+//! Kernels write the size of a later dispatch into an
+//! [`IndirectDispatchBuffer`], and `Kernel::dispatch_indirect_async` dispatches
+//! with it, so one kernel can decide how much work the next one does:
+//! ```ignore
+//! let args = device.create_indirect_dispatch_buffer(1, [1024, 1, 1]);
+//! let count = device.create_kernel::<fn(IndirectDispatchBuffer)>(&track!(|args| {
+//!     args.set_dispatch_size(0, Uint3::expr(hits.len_expr(), 1, 1));
+//! }));
+//! let shade = device.create_kernel::<fn()>(&track!(|| {
+//!     set_indirect_dispatch();
+//!     let hit = hits.read(dispatch_id().x);
+//!     ...
+//! }));
+//! stream.with_scope(|s| {
+//!     s.submit([
+//!         count.dispatch_async([1, 1, 1], &args),
+//!         shade.dispatch_indirect_async(&args, 0),
+//!     ]);
+//! });
+//! ```
+//! A size with a zero component skips the dispatch.
+//!
+//! The backend interface has no indirect dispatches, so they are emulated on
+//! the device: the grid launched is the maximum size of the buffer, and the
+//! threads outside the size read from the buffer return right away. The size
+//! never goes through the host, so indirect dispatches are submitted, recorded
+//! in a [`crate::runtime::CommandGraph`] and replayed like any other, at the
+//! cost of launching the threads up to the maximum size.
+use crate::error::{ensure_arg, Result};
+
+use super::*;
+
+/// Dispatch sizes written by kernels, see the [module documentation](self).
+pub struct IndirectDispatchBuffer {
+    pub(crate) sizes: Buffer<Uint3>,
+    pub(crate) max_size: [u32; 3],
+}
+crate::impl_resource_deref_to_var!(IndirectDispatchBuffer, IndirectDispatchBufferVar);
+
+impl IndirectDispatchBuffer {
+    pub(crate) fn new(device: &Device, count: usize, max_size: [u32; 3]) -> Result<Self> {
+        ensure_arg!(
+            count > 0,
+            "indirect dispatch buffer must hold at least one size"
+        );
+        ensure_arg!(
+            max_size.iter().all(|&s| s > 0),
+            "maximum dispatch size {:?} has a zero component",
+            max_size
+        );
+        let sizes = device.try_create_buffer::<Uint3>(count)?;
+        sizes.fill(Uint3::new(0, 0, 0));
+        Ok(Self { sizes, max_size })
+    }
+    #[inline]
+    pub fn device(&self) -> &Device {
+        &self.sizes.device
+    }
+    #[inline]
+    pub fn var(&self) -> IndirectDispatchBufferVar {
+        IndirectDispatchBufferVar {
+            sizes: self.sizes.var(),
+        }
+    }
+    /// Number of dispatch sizes the buffer holds.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.sizes.len()
+    }
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    /// The sizes, e.g. to initialize them from the host.
+    #[inline]
+    pub fn as_buffer(&self) -> &Buffer<Uint3> {
+        &self.sizes
+    }
+    /// The grid launched by the dispatches using the buffer, which bounds
+    /// the sizes written to it.
+    #[inline]
+    pub fn max_size(&self) -> [u32; 3] {
+        self.max_size
+    }
+}
+
+/// An [`IndirectDispatchBuffer`] in a kernel.
+#[derive(Clone)]
+pub struct IndirectDispatchBufferVar {
+    pub(crate) sizes: BufferVar<Uint3>,
+}
+impl IndirectDispatchBufferVar {
+    #[inline]
+    pub fn len_expr(&self) -> Expr<u32> {
+        self.sizes.len_expr_u32()
+    }
+    /// Sets the size of the dispatches using the size at `index`.
+    pub fn set_dispatch_size(&self, index: impl IntoIndex, size: impl AsExpr<Value = Uint3>) {
+        self.sizes.write(index, size)
+    }
+    pub fn dispatch_size(&self, index: impl IntoIndex) -> Expr<Uint3> {
+        self.sizes.read(index)
+    }
+}
+
+/// Lets the kernel being recorded be dispatched with
+/// `Kernel::dispatch_indirect_async`, see the [module documentation](self).
+///
+/// Call it first in the kernel, like [`set_block_size`]. It adds the sizes and
+/// the offset of the dispatch as hidden arguments, so the kernel can then only
+/// be dispatched indirectly. Threads outside the size return before running
+/// the rest of the kernel, so the kernel must not synchronize its blocks, and
+/// [`dispatch_size`] returns the size read from the buffer.
+pub fn set_indirect_dispatch() {
+    let (sizes, offset) = with_recorder(|r| {
+        assert!(
+            r.building_kernel,
+            "set_indirect_dispatch cannot be called in callable!"
+        );
+        assert!(
+            r.indirect_dispatch.is_none(),
+            "Indirect dispatch already set"
+        );
+        assert_eq!(
+            r.scopes.len(),
+            1,
+            "set_indirect_dispatch must be called outside of control flow"
+        );
+        let sizes = new_node(
+            &r.pools,
+            Node::new(CArc::new(Instruction::Buffer), Uint3::type_()),
+        );
+        let offset = new_node(
+            &r.pools,
+            Node::new(CArc::new(Instruction::Uniform), u32::type_()),
+        );
+        r.defined.insert(sizes, true);
+        r.defined.insert(offset, true);
+        (sizes, offset)
+    });
+    let buffer = BufferVar::<Uint3> {
+        node: sizes.into(),
+        marker: PhantomData,
+        handle: None,
+        param: None,
+        offset_bytes: None,
+    };
+    let size = buffer.read(Expr::<u32>::from_node(offset.into()));
+    let grid = Expr::<Uint3>::from_node(
+        __current_scope(|b| b.call(Func::DispatchSize, &[], Uint3::type_())).into(),
+    );
+    if need_runtime_check() {
+        lc_assert!(
+            size.le(grid).all(),
+            "indirect dispatch size exceeds the maximum size of its buffer"
+        );
+    }
+    if_!(dispatch_id().ge(size).any(), {
+        return_();
+    });
+    with_recorder(|r| r.indirect_dispatch = Some((sizes, offset, size.node().get())));
+}
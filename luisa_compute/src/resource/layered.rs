@@ -0,0 +1,511 @@
+//! Texture arrays and cube maps.
+//!
+//! Backends have no layered textures, so both are emulated with a [`Tex3d`]
+//! whose depth is the number of layers: a [`Tex2dArray`] with `n` layers is a
+//! `width x height x n` volume and a [`TexCube`] is a `size x size x 6` volume
+//! holding the faces in the order +X, -X, +Y, -Y, +Z, -Z. Mipmaps of a [`Tex3d`]
+//! also halve the depth, so layered textures have a single level.
+//!
+//! Layers are copied to and from buffers by kernels, compiled once per device
+//! and texel type, as copy commands cannot address a part of a texture. Copies
+//! of a layer to and from the host therefore go through a temporary buffer.
+use super::sampling::{filter, read_float4};
+use super::*;
+use crate::lang::control_flow::select;
+
+/// An array of 2D textures of the same size and format, see the
+/// [module documentation](self).
+pub struct Tex2dArray<T: IoTexel> {
+    pub(crate) tex: Tex3d<T>,
+}
+
+/// A cube map, see the [module documentation](self).
+pub struct TexCube<T: IoTexel> {
+    pub(crate) faces: Tex2dArray<T>,
+}
+
+impl Device {
+    pub fn create_tex2d_array<T: IoTexel>(
+        &self,
+        storage: PixelStorage,
+        width: u32,
+        height: u32,
+        layers: u32,
+    ) -> Tex2dArray<T> {
+        self.try_create_tex2d_array(storage, width, height, layers)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+    /// Fallible version of [`Device::create_tex2d_array`].
+    pub fn try_create_tex2d_array<T: IoTexel>(
+        &self,
+        storage: PixelStorage,
+        width: u32,
+        height: u32,
+        layers: u32,
+    ) -> Result<Tex2dArray<T>> {
+        if layers == 0 {
+            return Err(Error::InvalidArgument(
+                "texture array must have at least one layer".to_string(),
+            ));
+        }
+        Ok(Tex2dArray {
+            tex: self.try_create_tex3d(storage, width, height, layers, 1)?,
+        })
+    }
+    pub fn create_tex_cube<T: IoTexel>(&self, storage: PixelStorage, size: u32) -> TexCube<T> {
+        self.try_create_tex_cube(storage, size)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+    /// Fallible version of [`Device::create_tex_cube`].
+    pub fn try_create_tex_cube<T: IoTexel>(
+        &self,
+        storage: PixelStorage,
+        size: u32,
+    ) -> Result<TexCube<T>> {
+        Ok(TexCube {
+            faces: self.try_create_tex2d_array(storage, size, size, 6)?,
+        })
+    }
+}
+
+#[tracked]
+fn layer_from_buffer_kernel<T: IoTexel>(device: &Device) -> Kernel<fn(Tex3d<T>, Buffer<T>, u32)> {
+    device.create_kernel::<fn(Tex3d<T>, Buffer<T>, u32)>(&|tex, buffer, layer| {
+        let p = dispatch_id().xy();
+        tex.write(
+            Uint3::expr(p.x, p.y, layer),
+            buffer.read(p.y * tex.size().x + p.x),
+        );
+    })
+}
+
+#[tracked]
+fn layer_to_buffer_kernel<T: IoTexel>(device: &Device) -> Kernel<fn(Tex3d<T>, Buffer<T>, u32)> {
+    device.create_kernel::<fn(Tex3d<T>, Buffer<T>, u32)>(&|tex, buffer, layer| {
+        let p = dispatch_id().xy();
+        buffer.write(
+            p.y * tex.size().x + p.x,
+            tex.read(Uint3::expr(p.x, p.y, layer)),
+        );
+    })
+}
+
+impl<T: IoTexel> Tex2dArray<T> {
+    pub fn width(&self) -> u32 {
+        self.tex.width()
+    }
+    pub fn height(&self) -> u32 {
+        self.tex.height()
+    }
+    pub fn layers(&self) -> u32 {
+        self.tex.depth()
+    }
+    pub fn format(&self) -> PixelFormat {
+        self.tex.format()
+    }
+    pub fn storage(&self) -> PixelStorage {
+        self.tex.storage()
+    }
+    pub fn native_handle(&self) -> *mut std::ffi::c_void {
+        self.tex.native_handle()
+    }
+    /// The volume holding the layers.
+    pub fn as_tex3d(&self) -> &Tex3d<T> {
+        &self.tex
+    }
+    /// See [`Buffer::set_name`].
+    pub fn set_name(&self, name: &str) {
+        self.tex.set_name(name);
+    }
+    pub fn with_name(self, name: &str) -> Self {
+        self.set_name(name);
+        self
+    }
+    pub fn name(&self) -> Option<String> {
+        self.tex.name()
+    }
+    pub fn var(&self) -> Tex2dArrayVar<T> {
+        Tex2dArrayVar {
+            tex: self.tex.var(),
+        }
+    }
+    fn check_layer_copy(&self, layer: u32, len: usize) {
+        assert!(
+            layer < self.layers(),
+            "layer {} out of bounds of {} with {} layers",
+            layer,
+            self.tex.handle.describe(),
+            self.layers()
+        );
+        assert_eq!(
+            len,
+            (self.width() * self.height()) as usize,
+            "texel count mismatch copying layer {} of {}",
+            layer,
+            self.tex.handle.describe()
+        );
+    }
+    /// Copies `buffer`, in row-major order, into layer `layer`.
+    pub fn copy_layer_from_buffer_async(
+        &self,
+        layer: u32,
+        buffer: &BufferView<T>,
+    ) -> Command<'static, 'static> {
+        self.check_layer_copy(layer, buffer.len());
+        let device = &self.tex.handle.device;
+        let kernel = device.cached_kernel("layer_from_buffer", layer_from_buffer_kernel::<T>);
+        kernel.dispatch_async([self.width(), self.height(), 1], &self.tex, buffer, &layer)
+    }
+    pub fn copy_layer_from_buffer(&self, layer: u32, buffer: &BufferView<T>) {
+        submit_default_stream_and_sync(
+            &self.tex.handle.device,
+            [self.copy_layer_from_buffer_async(layer, buffer)],
+        );
+    }
+    /// Copies layer `layer`, in row-major order, into `buffer`.
+    pub fn copy_layer_to_buffer_async(
+        &self,
+        layer: u32,
+        buffer: &BufferView<T>,
+    ) -> Command<'static, 'static> {
+        self.check_layer_copy(layer, buffer.len());
+        let device = &self.tex.handle.device;
+        let kernel = device.cached_kernel("layer_to_buffer", layer_to_buffer_kernel::<T>);
+        kernel.dispatch_async([self.width(), self.height(), 1], &self.tex, buffer, &layer)
+    }
+    pub fn copy_layer_to_buffer(&self, layer: u32, buffer: &BufferView<T>) {
+        submit_default_stream_and_sync(
+            &self.tex.handle.device,
+            [self.copy_layer_to_buffer_async(layer, buffer)],
+        );
+    }
+    pub fn copy_layer_from(&self, layer: u32, data: &[T]) {
+        self.check_layer_copy(layer, data.len());
+        let buffer = self.tex.handle.device.create_buffer_from_slice(data);
+        self.copy_layer_from_buffer(layer, &buffer.view(..));
+    }
+    pub fn copy_layer_to(&self, layer: u32, data: &mut [T]) {
+        self.check_layer_copy(layer, data.len());
+        let buffer = self.tex.handle.device.create_buffer::<T>(data.len());
+        self.copy_layer_to_buffer(layer, &buffer.view(..));
+        buffer.copy_to(data);
+    }
+    pub fn copy_layer_to_vec(&self, layer: u32) -> Vec<T> {
+        let buffer = self
+            .tex
+            .handle
+            .device
+            .create_buffer::<T>((self.width() * self.height()) as usize);
+        self.copy_layer_to_buffer(layer, &buffer.view(..));
+        buffer.copy_to_vec()
+    }
+}
+
+impl<T: IoTexel> TexCube<T> {
+    pub fn size(&self) -> u32 {
+        self.faces.width()
+    }
+    pub fn format(&self) -> PixelFormat {
+        self.faces.format()
+    }
+    pub fn storage(&self) -> PixelStorage {
+        self.faces.storage()
+    }
+    pub fn native_handle(&self) -> *mut std::ffi::c_void {
+        self.faces.native_handle()
+    }
+    /// The faces as an array of 6 layers, e.g. to copy a single face.
+    pub fn faces(&self) -> &Tex2dArray<T> {
+        &self.faces
+    }
+    /// See [`Buffer::set_name`].
+    pub fn set_name(&self, name: &str) {
+        self.faces.set_name(name);
+    }
+    pub fn with_name(self, name: &str) -> Self {
+        self.set_name(name);
+        self
+    }
+    pub fn name(&self) -> Option<String> {
+        self.faces.name()
+    }
+    pub fn var(&self) -> TexCubeVar<T> {
+        TexCubeVar {
+            tex: self.faces.tex.var(),
+        }
+    }
+}
+
+/// Face of a cube map hit by direction `dir` and the normalized coordinates of
+/// the hit point on that face.
+#[tracked]
+fn cube_face(dir: Expr<Float3>) -> (Expr<u32>, Expr<Float2>) {
+    let (x, y, z) = (dir.x, dir.y, dir.z);
+    let (ax, ay, az) = (x.abs(), y.abs(), z.abs());
+    let on_x = ax >= ay && ax >= az;
+    let on_y = !on_x && ay >= az;
+    let face = select(
+        on_x,
+        select(x > 0.0, 0u32.expr(), 1u32.expr()),
+        select(
+            on_y,
+            select(y > 0.0, 2u32.expr(), 3u32.expr()),
+            select(z > 0.0, 4u32.expr(), 5u32.expr()),
+        ),
+    );
+    let major = select(on_x, ax, select(on_y, ay, az));
+    let s = select(
+        on_x,
+        select(x > 0.0, -z, z),
+        select(on_y, x, select(z > 0.0, x, -x)),
+    );
+    let t = select(on_y, select(y > 0.0, z, -z), -y);
+    let uv = (Float2::expr(s, t) / major + 1.0) * 0.5;
+    (face, uv)
+}
+
+/// A [`Tex2dArray`] in a kernel.
+#[derive(Clone)]
+pub struct Tex2dArrayVar<T: IoTexel> {
+    pub(crate) tex: Tex3dVar<T>,
+}
+
+impl<T: IoTexel> Tex2dArrayVar<T> {
+    pub fn new(array: &Tex2dArray<T>) -> Self {
+        array.var()
+    }
+    pub fn read(
+        &self,
+        coord: impl AsExpr<Value = Uint2>,
+        layer: impl AsExpr<Value = u32>,
+    ) -> Expr<T> {
+        let coord = coord.as_expr();
+        self.tex
+            .read(Uint3::expr(coord.x, coord.y, layer.as_expr()))
+    }
+    pub fn write(
+        &self,
+        coord: impl AsExpr<Value = Uint2>,
+        layer: impl AsExpr<Value = u32>,
+        v: impl AsExpr<Value = T>,
+    ) {
+        let coord = coord.as_expr();
+        self.tex
+            .write(Uint3::expr(coord.x, coord.y, layer.as_expr()), v)
+    }
+    pub fn size(&self) -> Expr<Uint2> {
+        self.tex.size().xy()
+    }
+    pub fn layers(&self) -> Expr<u32> {
+        self.tex.size().z
+    }
+}
+
+impl<T: IoTexel> Tex2dArrayVar<T>
+where
+    T::RwType: MipmapTexel,
+{
+    /// Samples layer `layer` at normalized coordinates `uv`, see
+    /// [`Tex2dVar::sample`].
+    pub fn sample(
+        &self,
+        sampler: impl AsSampler,
+        uv: impl AsExpr<Value = Float2>,
+        layer: impl AsExpr<Value = u32>,
+    ) -> Expr<Float4> {
+        let uv = uv.as_expr();
+        let layer = layer.as_expr();
+        let size = self.size().cast_i32();
+        let node = self.tex.node.get();
+        filter(
+            sampler.sampler_code(),
+            [uv.x, uv.y],
+            [size.x, size.y],
+            |p| {
+                let p = Uint3::expr(p[0].cast_u32(), p[1].cast_u32(), layer);
+                read_float4::<T::RwType>(Func::Texture3dRead, node, p.node().get())
+            },
+        )
+    }
+}
+
+/// A [`TexCube`] in a kernel.
+#[derive(Clone)]
+pub struct TexCubeVar<T: IoTexel> {
+    pub(crate) tex: Tex3dVar<T>,
+}
+
+impl<T: IoTexel> TexCubeVar<T> {
+    pub fn new(cube: &TexCube<T>) -> Self {
+        cube.var()
+    }
+    pub fn read(
+        &self,
+        coord: impl AsExpr<Value = Uint2>,
+        face: impl AsExpr<Value = u32>,
+    ) -> Expr<T> {
+        let coord = coord.as_expr();
+        self.tex.read(Uint3::expr(coord.x, coord.y, face.as_expr()))
+    }
+    pub fn write(
+        &self,
+        coord: impl AsExpr<Value = Uint2>,
+        face: impl AsExpr<Value = u32>,
+        v: impl AsExpr<Value = T>,
+    ) {
+        let coord = coord.as_expr();
+        self.tex
+            .write(Uint3::expr(coord.x, coord.y, face.as_expr()), v)
+    }
+    pub fn size(&self) -> Expr<u32> {
+        self.tex.size().x
+    }
+}
+
+impl<T: IoTexel> TexCubeVar<T>
+where
+    T::RwType: MipmapTexel,
+{
+    /// Samples the cube map in direction `dir`, which need not be normalized.
+    /// The filter of `sampler` is used, texels are clamped to the edge of the
+    /// face hit by `dir`, so like [`BindlessTexCubeVar::sample`] this is not
+    /// seamless across faces.
+    pub fn sample(
+        &self,
+        sampler: impl AsSampler,
+        dir: impl AsExpr<Value = Float3>,
+    ) -> Expr<Float4> {
+        let (face, uv) = cube_face(dir.as_expr());
+        let size = self.size().cast_i32();
+        let node = self.tex.node.get();
+        // keep the filter, clamp to edge
+        let sampler = sampler.sampler_code();
+        let sampler = track!(sampler & 3);
+        filter(sampler, [uv.x, uv.y], [size, size], |p| {
+            let p = Uint3::expr(p[0].cast_u32(), p[1].cast_u32(), face);
+            read_float4::<T::RwType>(Func::Texture3dRead, node, p.node().get())
+        })
+    }
+}
+
+impl BindlessArray {
+    /// Places the volume holding the layers of `array` in the 3D texture slot
+    /// `index`, see [`BindlessArrayVar::tex2d_array`].
+    pub fn emplace_tex2d_array_async<T: IoTexel>(
+        &self,
+        index: usize,
+        array: &Tex2dArray<T>,
+        sampler: Sampler,
+    ) {
+        self.emplace_tex3d_async(index, &array.tex, sampler);
+    }
+    pub fn set_tex2d_array<T: IoTexel>(
+        &self,
+        index: usize,
+        array: &Tex2dArray<T>,
+        sampler: Sampler,
+    ) {
+        self.emplace_tex2d_array_async(index, array, sampler);
+        self.update();
+    }
+    /// Places the faces of `cube` in the 3D texture slot `index`, see
+    /// [`BindlessArrayVar::tex_cube`].
+    pub fn emplace_tex_cube_async<T: IoTexel>(
+        &self,
+        index: usize,
+        cube: &TexCube<T>,
+        sampler: Sampler,
+    ) {
+        self.emplace_tex3d_async(index, &cube.faces.tex, sampler);
+    }
+    pub fn set_tex_cube<T: IoTexel>(&self, index: usize, cube: &TexCube<T>, sampler: Sampler) {
+        self.emplace_tex_cube_async(index, cube, sampler);
+        self.update();
+    }
+    pub fn tex2d_array(&self, index: impl AsExpr<Value = u32>) -> BindlessTex2dArrayVar {
+        self.var().tex2d_array(index)
+    }
+    pub fn tex_cube(&self, index: impl AsExpr<Value = u32>) -> BindlessTexCubeVar {
+        self.var().tex_cube(index)
+    }
+}
+
+impl BindlessArrayVar {
+    /// The texture array in 3D texture slot `index`.
+    pub fn tex2d_array(&self, index: impl AsExpr<Value = u32>) -> BindlessTex2dArrayVar {
+        BindlessTex2dArrayVar {
+            tex: self.tex3d(index),
+        }
+    }
+    /// The cube map in 3D texture slot `index`.
+    pub fn tex_cube(&self, index: impl AsExpr<Value = u32>) -> BindlessTexCubeVar {
+        BindlessTexCubeVar {
+            tex: self.tex3d(index),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct BindlessTex2dArrayVar {
+    tex: BindlessTex3dVar,
+}
+
+impl BindlessTex2dArrayVar {
+    /// Samples layer `layer` with the sampler of the slot. Layers are not
+    /// blended, the sample is taken at the center of the layer.
+    pub fn sample(
+        &self,
+        uv: impl AsExpr<Value = Float2>,
+        layer: impl AsExpr<Value = u32>,
+    ) -> Expr<Float4> {
+        let uv = uv.as_expr();
+        let (layer, layers) = (layer.as_expr(), self.layers());
+        let w = track!((layer.cast_f32() + 0.5) / layers.cast_f32());
+        self.tex.sample(Float3::expr(uv.x, uv.y, w))
+    }
+    pub fn read(
+        &self,
+        coord: impl AsExpr<Value = Uint2>,
+        layer: impl AsExpr<Value = u32>,
+    ) -> Expr<Float4> {
+        let coord = coord.as_expr();
+        self.tex
+            .read(Uint3::expr(coord.x, coord.y, layer.as_expr()))
+    }
+    pub fn size(&self) -> Expr<Uint2> {
+        self.tex.size().xy()
+    }
+    pub fn layers(&self) -> Expr<u32> {
+        self.tex.size().z
+    }
+}
+
+#[derive(Clone)]
+pub struct BindlessTexCubeVar {
+    tex: BindlessTex3dVar,
+}
+
+impl BindlessTexCubeVar {
+    /// Samples the cube map in direction `dir` with the sampler of the slot.
+    /// Only the address mode `Edge` keeps samples inside the face hit by `dir`.
+    ///
+    /// Sampling is not seamless: near an edge, a filtered sample blends the
+    /// texels at the border of the face hit by `dir` rather than those of the
+    /// adjacent face, so seams may show with bilinear filtering.
+    pub fn sample(&self, dir: impl AsExpr<Value = Float3>) -> Expr<Float4> {
+        let (face, uv) = cube_face(dir.as_expr());
+        let w = track!((face.cast_f32() + 0.5) / 6.0);
+        self.tex.sample(Float3::expr(uv.x, uv.y, w))
+    }
+    pub fn read(
+        &self,
+        coord: impl AsExpr<Value = Uint2>,
+        face: impl AsExpr<Value = u32>,
+    ) -> Expr<Float4> {
+        let coord = coord.as_expr();
+        self.tex.read(Uint3::expr(coord.x, coord.y, face.as_expr()))
+    }
+    pub fn size(&self) -> Expr<u32> {
+        self.tex.size().x
+    }
+}
@@ -0,0 +1,372 @@
+//! Mipmap generation for [`Tex2d`] and [`Tex3d`].
+//!
+//! Every level is computed from the previous one with a separable filter. The
+//! filter weights are evaluated on the host for each axis and uploaded as a
+//! table, so odd (non-power-of-two) sizes, where a texel of the next level
+//! covers a fractional number of texels, need no special casing in the kernel.
+//! The texture keeps the tables until it is filtered with another filter, and
+//! the kernels are compiled once per device and read/write type.
+//! Texels are filtered as floats in the read/write type of the texture
+//! ([`IoTexel::RwType`]) and rounded back for integer formats.
+use std::sync::Arc;
+
+use super::*;
+
+/// Read/write type of a texture, i.e. one of `Float4`, `Int4` and `Uint4`.
+pub trait MipmapTexel: IoTexel {
+    fn to_float4(texel: Expr<Self>) -> Expr<Float4>;
+    fn from_float4(value: Expr<Float4>) -> Expr<Self>;
+}
+impl MipmapTexel for Float4 {
+    fn to_float4(texel: Expr<Self>) -> Expr<Float4> {
+        texel
+    }
+    fn from_float4(value: Expr<Float4>) -> Expr<Self> {
+        value
+    }
+}
+impl MipmapTexel for Int4 {
+    fn to_float4(texel: Expr<Self>) -> Expr<Float4> {
+        texel.cast_f32()
+    }
+    fn from_float4(value: Expr<Float4>) -> Expr<Self> {
+        value.round().cast_i32()
+    }
+}
+impl MipmapTexel for Uint4 {
+    fn to_float4(texel: Expr<Self>) -> Expr<Float4> {
+        texel.cast_f32()
+    }
+    fn from_float4(value: Expr<Float4>) -> Expr<Self> {
+        // filters with negative lobes can undershoot
+        value.max_(Float4::splat_expr(0.0f32)).round().cast_u32()
+    }
+}
+
+/// Filter used by [`Tex2d::generate_mipmaps`] and [`Tex3d::generate_mipmaps`].
+#[derive(Clone, Default)]
+pub enum MipmapFilter {
+    /// Averages the texels covered by each texel of the next level, weighted by
+    /// the covered area.
+    #[default]
+    Box,
+    /// A Kaiser windowed sinc, sharper than [`MipmapFilter::Box`] but may ring.
+    /// `radius` is measured in texels of the next level.
+    Kaiser { radius: f32, alpha: f32 },
+    /// `weight(x)` is the weight of a texel at distance `x` from the center of a
+    /// texel of the next level, measured in texels of the next level. Texels
+    /// further away than `radius` are ignored.
+    Custom {
+        radius: f32,
+        weight: Arc<dyn Fn(f32) -> f32 + Send + Sync>,
+    },
+}
+
+impl MipmapFilter {
+    /// A Kaiser filter with radius 3 and alpha 4.
+    pub fn kaiser() -> Self {
+        Self::Kaiser {
+            radius: 3.0,
+            alpha: 4.0,
+        }
+    }
+    pub fn custom(radius: f32, weight: impl Fn(f32) -> f32 + Send + Sync + 'static) -> Self {
+        Self::Custom {
+            radius,
+            weight: Arc::new(weight),
+        }
+    }
+
+    /// Whether both filters have the same weights.
+    fn same_as(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Box, Self::Box) => true,
+            (
+                Self::Kaiser { radius, alpha },
+                Self::Kaiser {
+                    radius: r,
+                    alpha: a,
+                },
+            ) => radius == r && alpha == a,
+            (
+                Self::Custom { radius, weight },
+                Self::Custom {
+                    radius: r,
+                    weight: w,
+                },
+            ) => radius == r && Arc::ptr_eq(weight, w),
+            _ => false,
+        }
+    }
+    /// Source texels and normalized weights for every texel of an axis shrinking
+    /// from `src` to `dst` texels. Texels outside the axis are clamped to the edge.
+    fn weights(&self, src: u32, dst: u32) -> Vec<Vec<(u32, f32)>> {
+        let ratio = src as f64 / dst as f64;
+        (0..dst)
+            .map(|i| {
+                let center = (i as f64 + 0.5) * ratio;
+                let mut taps: Vec<(u32, f32)> = vec![];
+                let mut add = |j: i64, w: f64| {
+                    if w == 0.0 {
+                        return;
+                    }
+                    let j = j.clamp(0, src as i64 - 1) as u32;
+                    match taps.iter_mut().find(|t| t.0 == j) {
+                        Some(t) => t.1 += w as f32,
+                        None => taps.push((j, w as f32)),
+                    }
+                };
+                match self {
+                    Self::Box => {
+                        let (lo, hi) = (center - ratio / 2.0, center + ratio / 2.0);
+                        for j in lo.floor() as i64..hi.ceil() as i64 {
+                            let w = (hi.min(j as f64 + 1.0) - lo.max(j as f64)).max(0.0);
+                            add(j, w);
+                        }
+                    }
+                    Self::Kaiser { radius, .. } | Self::Custom { radius, .. } => {
+                        let radius = *radius as f64;
+                        let r = radius * ratio;
+                        for j in (center - r - 0.5).floor() as i64..=(center + r).ceil() as i64 {
+                            let x = (j as f64 + 0.5 - center) / ratio;
+                            if x.abs() < radius {
+                                add(j, self.eval(x));
+                            }
+                        }
+                    }
+                }
+                let sum: f32 = taps.iter().map(|t| t.1).sum();
+                if sum.abs() < 1e-6 {
+                    // the filter vanishes on all texels, fall back to the nearest one
+                    return vec![((center as u32).min(src - 1), 1.0)];
+                }
+                taps.iter().map(|&(j, w)| (j, w / sum)).collect()
+            })
+            .collect()
+    }
+    fn eval(&self, x: f64) -> f64 {
+        match self {
+            Self::Box => unreachable!(),
+            Self::Kaiser { radius, alpha } => {
+                let t = x / *radius as f64;
+                let alpha = *alpha as f64;
+                sinc(x) * bessel_i0(alpha * (1.0 - t * t).max(0.0).sqrt()) / bessel_i0(alpha)
+            }
+            Self::Custom { weight, .. } => weight(x as f32) as f64,
+        }
+    }
+}
+
+fn sinc(x: f64) -> f64 {
+    if x.abs() < 1e-6 {
+        1.0
+    } else {
+        let x = x * std::f64::consts::PI;
+        x.sin() / x
+    }
+}
+
+// modified Bessel function of the first kind of order zero
+fn bessel_i0(x: f64) -> f64 {
+    let mut sum = 1.0;
+    let mut term = 1.0;
+    let mut k = 1.0;
+    while term > sum * 1e-12 {
+        term *= (x / (2.0 * k)) * (x / (2.0 * k));
+        sum += term;
+        k += 1.0;
+    }
+    sum
+}
+
+/// Weight tables of the levels of a texture for the filter it was last
+/// filtered with, reused while the filter stays the same.
+#[derive(Default)]
+pub(crate) struct MipmapTables {
+    filter: Option<MipmapFilter>,
+    levels: Vec<Arc<LevelTables>>,
+}
+
+/// Weight tables of all axes of a level, see [`MipmapFilter::weights`].
+struct LevelTables {
+    taps: Uint3,
+    offsets: Uint3,
+    index: Arc<Buffer<u32>>,
+    weight: Arc<Buffer<f32>>,
+}
+
+impl LevelTables {
+    fn new(device: &Device, filter: &MipmapFilter, src: [u32; 3], dst: [u32; 3]) -> Self {
+        let mut index = vec![];
+        let mut weight = vec![];
+        let mut taps = [0u32; 3];
+        let mut offsets = [0u32; 3];
+        for axis in 0..3 {
+            let table = filter.weights(src[axis], dst[axis]);
+            let n = table.iter().map(|t| t.len()).max().unwrap();
+            taps[axis] = n as u32;
+            offsets[axis] = index.len() as u32;
+            for texel in table {
+                for k in 0..n {
+                    let (j, w) = texel.get(k).copied().unwrap_or((0, 0.0));
+                    index.push(j);
+                    weight.push(w);
+                }
+            }
+        }
+        Self {
+            taps: Uint3::new(taps[0], taps[1], taps[2]),
+            offsets: Uint3::new(offsets[0], offsets[1], offsets[2]),
+            index: Arc::new(device.create_buffer_from_slice(&index)),
+            weight: Arc::new(device.create_buffer_from_slice(&weight)),
+        }
+    }
+}
+
+type Downsample2dKernel<R> = Kernel<fn(Tex2d<R>, Tex2d<R>, Buffer<u32>, Buffer<f32>, Uint3, Uint3)>;
+type Downsample3dKernel<R> = Kernel<fn(Tex3d<R>, Tex3d<R>, Buffer<u32>, Buffer<f32>, Uint3, Uint3)>;
+
+#[tracked]
+fn downsample_2d_kernel<R: MipmapTexel>(device: &Device) -> Downsample2dKernel<R> {
+    device.create_kernel::<fn(Tex2d<R>, Tex2d<R>, Buffer<u32>, Buffer<f32>, Uint3, Uint3)>(
+        &|src, dst, index, weight, taps, offsets| {
+            let p = dispatch_id().xy();
+            let acc = Float4::splat_expr(0.0f32).var();
+            for ky in 0u32.expr()..taps.y {
+                let ey = offsets.y + p.y * taps.y + ky;
+                let (sy, wy) = (index.read(ey), weight.read(ey));
+                for kx in 0u32.expr()..taps.x {
+                    let ex = offsets.x + p.x * taps.x + kx;
+                    let (sx, wx) = (index.read(ex), weight.read(ex));
+                    let texel = R::to_float4(src.read(Uint2::expr(sx, sy)));
+                    *acc += texel * (wx * wy);
+                }
+            }
+            dst.write(p, R::from_float4(**acc));
+        },
+    )
+}
+
+#[tracked]
+fn downsample_3d_kernel<R: MipmapTexel>(device: &Device) -> Downsample3dKernel<R> {
+    device.create_kernel::<fn(Tex3d<R>, Tex3d<R>, Buffer<u32>, Buffer<f32>, Uint3, Uint3)>(
+        &|src, dst, index, weight, taps, offsets| {
+            let p = dispatch_id();
+            let acc = Float4::splat_expr(0.0f32).var();
+            for kz in 0u32.expr()..taps.z {
+                let ez = offsets.z + p.z * taps.z + kz;
+                let (sz, wz) = (index.read(ez), weight.read(ez));
+                for ky in 0u32.expr()..taps.y {
+                    let ey = offsets.y + p.y * taps.y + ky;
+                    let (sy, wy) = (index.read(ey), weight.read(ey));
+                    for kx in 0u32.expr()..taps.x {
+                        let ex = offsets.x + p.x * taps.x + kx;
+                        let (sx, wx) = (index.read(ex), weight.read(ex));
+                        let texel = R::to_float4(src.read(Uint3::expr(sx, sy, sz)));
+                        *acc += texel * (wx * wy * wz);
+                    }
+                }
+            }
+            dst.write(p, R::from_float4(**acc));
+        },
+    )
+}
+
+impl<T: IoTexel> Tex2dView<T> {
+    /// The same level accessed through the read/write type of the texture.
+    fn raw(&self) -> Tex2dView<T::RwType>
+    where
+        T::RwType: IoTexel,
+    {
+        Tex2dView {
+            device: self.device.internal(),
+            width: self.width,
+            height: self.height,
+            storage: self.storage,
+            format: self.format,
+            handle: self.handle.clone(),
+            level: self.level,
+            marker: PhantomData,
+        }
+    }
+}
+impl<T: IoTexel> Tex3dView<T> {
+    /// The same level accessed through the read/write type of the texture.
+    fn raw(&self) -> Tex3dView<T::RwType>
+    where
+        T::RwType: IoTexel,
+    {
+        Tex3dView {
+            device: self.device.internal(),
+            width: self.width,
+            height: self.height,
+            depth: self.depth,
+            storage: self.storage,
+            format: self.format,
+            handle: self.handle.clone(),
+            level: self.level,
+            marker: PhantomData,
+        }
+    }
+}
+
+macro_rules! impl_generate_mipmaps {
+    ($tex:ident, $kernel:ident) => {
+        impl<T: IoTexel> $tex<T>
+        where
+            T::RwType: MipmapTexel,
+        {
+            /// Fills levels `1..` of the texture by filtering the previous level
+            /// with `filter`, see [`MipmapFilter`].
+            pub fn generate_mipmaps_async(
+                &self,
+                filter: &MipmapFilter,
+            ) -> Vec<Command<'static, 'static>> {
+                let levels = self.handle.levels;
+                let mut commands = vec![];
+                if levels <= 1 {
+                    return commands;
+                }
+                let device = &self.handle.device;
+                let kernel = device.cached_kernel(stringify!($kernel), $kernel::<T::RwType>);
+                let mut cached = self.handle.mipmap_tables.lock();
+                if !cached.filter.as_ref().is_some_and(|f| f.same_as(filter)) {
+                    let levels = (1..levels)
+                        .map(|level| {
+                            let (src, dst) = (self.view(level - 1), self.view(level));
+                            Arc::new(LevelTables::new(device, filter, src.size(), dst.size()))
+                        })
+                        .collect();
+                    *cached = MipmapTables {
+                        filter: Some(filter.clone()),
+                        levels,
+                    };
+                }
+                let mut rt = ResourceTracker::new();
+                for (tables, level) in cached.levels.iter().zip(1..levels) {
+                    let src = self.view(level - 1).raw();
+                    let dst = self.view(level).raw();
+                    commands.push(kernel.dispatch_async(
+                        dst.size(),
+                        &src,
+                        &dst,
+                        &*tables.index,
+                        &*tables.weight,
+                        &tables.taps,
+                        &tables.offsets,
+                    ));
+                    rt.add(tables.clone());
+                }
+                // the weight tables live until the last level is done, even if
+                // the texture is filtered with another filter in the meantime
+                commands.last_mut().unwrap().resource_tracker.merge(rt);
+                commands
+            }
+            pub fn generate_mipmaps(&self, filter: &MipmapFilter) {
+                submit_default_stream(&self.handle.device, self.generate_mipmaps_async(filter));
+            }
+        }
+    };
+}
+impl_generate_mipmaps!(Tex2d, downsample_2d_kernel);
+impl_generate_mipmaps!(Tex3d, downsample_3d_kernel);
@@ -0,0 +1,179 @@
+//! Filtered sampling of textures bound directly to a kernel.
+//!
+//! Backends only sample textures through a [`BindlessArray`], so sampling a
+//! [`Tex2dVar`] or [`Tex3dVar`] is done in the kernel: the texels around the
+//! sample position are read from the bound level, wrapped according to the
+//! [`SamplerAddress`] and interpolated according to the [`SamplerFilter`].
+//! A bound view has a single level, so `LinearLinear` and `Anisotropic` filter
+//! like `LinearPoint`.
+//!
+//! For the same reason there is no `sample_level` or `sample_grad`: the kernel
+//! can only read the bound level, so there is no level to select. Sample
+//! through a [`BindlessArray`] to filter across mipmaps, see
+//! [`BindlessTex2dVar::sample_level`] and [`BindlessTex2dVar::sample_grad`].
+//!
+//! The sampler is either fixed when the kernel is recorded, by passing a
+//! [`Sampler`], or chosen at dispatch, by passing an `Expr<u32>` holding
+//! [`encode_sampler`] of it, e.g. a `u32` kernel argument.
+use super::*;
+use crate::lang::control_flow::{if_then_else, select};
+
+/// Packs `sampler` into a `u32` that can be passed to a kernel, see [`AsSampler`].
+pub fn encode_sampler(sampler: &Sampler) -> u32 {
+    let filter = match sampler.filter {
+        SamplerFilter::Point => 0,
+        SamplerFilter::LinearPoint => 1,
+        SamplerFilter::LinearLinear => 2,
+        SamplerFilter::Anisotropic => 3,
+    };
+    let address = match sampler.address {
+        SamplerAddress::Edge => 0,
+        SamplerAddress::Repeat => 1,
+        SamplerAddress::Mirror => 2,
+        SamplerAddress::Zero => 3,
+    };
+    filter | address << 2
+}
+
+/// A sampler used by [`Tex2dVar::sample`] and [`Tex3dVar::sample`].
+pub trait AsSampler {
+    /// The sampler as packed by [`encode_sampler`].
+    fn sampler_code(&self) -> Expr<u32>;
+}
+impl AsSampler for Sampler {
+    fn sampler_code(&self) -> Expr<u32> {
+        encode_sampler(self).expr()
+    }
+}
+impl AsSampler for Expr<u32> {
+    fn sampler_code(&self) -> Expr<u32> {
+        *self
+    }
+}
+
+/// Reads a texel of a directly bound texture as floats.
+pub(super) fn read_float4<R: MipmapTexel>(
+    func: Func,
+    tex: NodeRef,
+    coord: NodeRef,
+) -> Expr<Float4> {
+    R::to_float4(Expr::<R>::from_node(
+        __current_scope(|b| b.call(func, &[tex, coord], R::type_())).into(),
+    ))
+}
+
+/// Wraps texel index `i` of an axis with `n` texels, returns the index to read
+/// and whether the texel is inside the texture.
+#[tracked]
+fn wrap(i: Expr<i32>, n: Expr<i32>, address: Expr<u32>) -> (Expr<i32>, Expr<bool>) {
+    let clamped = i.clamp(0, n - 1);
+    let repeated = i.rem_euclid(n);
+    let m = i.rem_euclid(n * 2);
+    let mirrored = select(m < n, m, n * 2 - 1 - m);
+    let index = select(
+        address == 1,
+        repeated,
+        select(address == 2, mirrored, clamped),
+    );
+    let inside = address != 3 || (i >= 0 && i < n);
+    (index, inside)
+}
+
+pub(super) fn filter<const N: usize>(
+    sampler: Expr<u32>,
+    uv: [Expr<f32>; N],
+    size: [Expr<i32>; N],
+    read: impl Fn([Expr<i32>; N]) -> Expr<Float4>,
+) -> Expr<Float4> {
+    let address = track!(sampler >> 2);
+    let fetch = |p: [Expr<i32>; N]| {
+        let mut inside = true.expr();
+        let q = std::array::from_fn(|a| {
+            let (i, ok) = wrap(p[a], size[a], address);
+            inside = track!(inside && ok);
+            i
+        });
+        select(inside, read(q), Float4::splat_expr(0.0f32))
+    };
+    if_then_else(
+        track!((sampler & 3) == 0),
+        || {
+            fetch(std::array::from_fn(|a| {
+                track!((uv[a] * size[a].cast_f32()).floor().cast_i32())
+            }))
+        },
+        || {
+            // texel centers are at half-integer coordinates
+            let x: [Expr<f32>; N] =
+                std::array::from_fn(|a| track!(uv[a] * size[a].cast_f32() - 0.5));
+            let base: [Expr<f32>; N] = std::array::from_fn(|a| x[a].floor());
+            let mut texel = Float4::splat_expr(0.0f32);
+            for corner in 0..1usize << N {
+                let mut weight = 1.0f32.expr();
+                let p = std::array::from_fn(|a| {
+                    let t = track!(x[a] - base[a]);
+                    let i = base[a].cast_i32();
+                    if corner >> a & 1 == 1 {
+                        weight = track!(weight * t);
+                        track!(i + 1)
+                    } else {
+                        weight = track!(weight * (1.0 - t));
+                        i
+                    }
+                });
+                texel = track!(texel + fetch(p) * weight);
+            }
+            texel
+        },
+    )
+}
+
+impl<T: IoTexel> Tex2dVar<T>
+where
+    T::RwType: MipmapTexel,
+{
+    /// Samples the bound level at normalized coordinates `uv` with `sampler`.
+    /// Only the bound level is filtered, mipmaps are ignored, use
+    /// [`BindlessTex2dVar::sample_level`] to select a level.
+    pub fn sample(&self, sampler: impl AsSampler, uv: impl AsExpr<Value = Float2>) -> Expr<Float4> {
+        let uv = uv.as_expr();
+        let size = self.size().cast_i32();
+        let self_node = self.node.get();
+        filter(
+            sampler.sampler_code(),
+            [uv.x, uv.y],
+            [size.x, size.y],
+            |p| {
+                let p = Uint2::expr(p[0].cast_u32(), p[1].cast_u32());
+                read_float4::<T::RwType>(Func::Texture2dRead, self_node, p.node().get())
+            },
+        )
+    }
+}
+
+impl<T: IoTexel> Tex3dVar<T>
+where
+    T::RwType: MipmapTexel,
+{
+    /// Samples the bound level at normalized coordinates `uvw` with `sampler`.
+    /// Only the bound level is filtered, mipmaps are ignored, use
+    /// [`BindlessTex3dVar::sample_level`] to select a level.
+    pub fn sample(
+        &self,
+        sampler: impl AsSampler,
+        uvw: impl AsExpr<Value = Float3>,
+    ) -> Expr<Float4> {
+        let uvw = uvw.as_expr();
+        let size = self.size().cast_i32();
+        let self_node = self.node.get();
+        filter(
+            sampler.sampler_code(),
+            [uvw.x, uvw.y, uvw.z],
+            [size.x, size.y, size.z],
+            |p| {
+                let p = Uint3::expr(p[0].cast_u32(), p[1].cast_u32(), p[2].cast_u32());
+                read_float4::<T::RwType>(Func::Texture3dRead, self_node, p.node().get())
+            },
+        )
+    }
+}
@@ -0,0 +1,231 @@
+//! Strided views of buffers.
+//!
+//! A [`StridedBufferView`] sees `len` values spaced by a fixed number of bytes,
+//! e.g. every other element of a buffer with [`BufferView::stride`] or one field
+//! of every element with [`BufferView::field`]. It holds the bytes from its first
+//! to its last value, and is accessed as a byte buffer in kernels, so the values
+//! and the stride have to be aligned as by [`byte_record_alignment`].
+use super::*;
+
+/// A view of values spaced by a fixed number of bytes, see the
+/// [module documentation](self).
+pub struct StridedBufferView<T: Value> {
+    pub(crate) bytes: BufferView<u8>,
+    /// distance between values in bytes
+    pub(crate) stride: usize,
+    pub(crate) len: usize,
+    pub(crate) _marker: PhantomData<fn() -> T>,
+}
+impl<T: Value> Clone for StridedBufferView<T> {
+    fn clone(&self) -> Self {
+        Self {
+            bytes: self.bytes.clone(),
+            stride: self.stride,
+            len: self.len,
+            _marker: PhantomData,
+        }
+    }
+}
+crate::impl_resource_deref_to_var!(StridedBufferView, StridedBufferVar [T: Value]);
+
+impl<T: Value> BufferView<T> {
+    /// Every `k`-th element of the view, starting from the first one.
+    pub fn stride(&self, k: usize) -> StridedBufferView<T> {
+        self.strided_bytes().stride(k)
+    }
+    /// The `F` at byte offset `offset` of every element, e.g.
+    /// `vertices.field::<Float3>(std::mem::offset_of!(Vertex, normal))`.
+    pub fn field<F: Value>(&self, offset: usize) -> StridedBufferView<F> {
+        self.strided_bytes().field(offset)
+    }
+    fn strided_bytes(&self) -> StridedBufferView<T> {
+        let size = std::mem::size_of::<T>();
+        StridedBufferView::new(
+            BufferView {
+                device: self.device.internal(),
+                handle: self.handle.clone(),
+                offset: self.offset * size,
+                len: self.len * size,
+                total_size_bytes: self.total_size_bytes,
+                _marker: PhantomData,
+            },
+            size,
+            self.len,
+        )
+    }
+}
+
+impl<T: Value> StridedBufferView<T> {
+    fn new(bytes: BufferView<u8>, stride: usize, len: usize) -> Self {
+        let ty = std::any::type_name::<T>();
+        let align = byte_record_alignment::<T>();
+        assert!(
+            bytes.offset % align == 0 && (len <= 1 || stride % align == 0),
+            "strided `{}` view of {} at byte offset {} with stride {} is not {}-byte aligned",
+            ty,
+            bytes.describe(),
+            bytes.offset,
+            stride,
+            align
+        );
+        // only keep the bytes up to the end of the last value
+        let end = if len == 0 {
+            0
+        } else {
+            (len - 1) * stride + std::mem::size_of::<T>()
+        };
+        assert!(
+            end <= bytes.len,
+            "strided `{}` view out of bounds of {}",
+            ty,
+            bytes.describe()
+        );
+        Self {
+            bytes: bytes.view(..end),
+            stride,
+            len,
+            _marker: PhantomData,
+        }
+    }
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+    /// Distance between consecutive values in bytes.
+    #[inline]
+    pub fn stride_bytes(&self) -> usize {
+        self.stride
+    }
+    /// The bytes from the first to the last value.
+    pub fn as_bytes(&self) -> &BufferView<u8> {
+        &self.bytes
+    }
+    /// Every `k`-th value of the view, starting from the first one.
+    pub fn stride(&self, k: usize) -> StridedBufferView<T> {
+        assert!(
+            k > 0,
+            "stride of {} must be positive",
+            self.bytes.describe()
+        );
+        Self::new(self.bytes.clone(), self.stride * k, (self.len + k - 1) / k)
+    }
+    /// The `F` at byte offset `offset` of every value.
+    pub fn field<F: Value>(&self, offset: usize) -> StridedBufferView<F> {
+        assert!(
+            offset + std::mem::size_of::<F>() <= std::mem::size_of::<T>(),
+            "field `{}` at byte offset {} out of bounds of `{}`",
+            std::any::type_name::<F>(),
+            offset,
+            std::any::type_name::<T>()
+        );
+        let bytes = self.bytes.view(offset.min(self.bytes.len)..);
+        StridedBufferView::new(bytes, self.stride, self.len)
+    }
+    #[inline]
+    pub fn var(&self) -> StridedBufferVar<T> {
+        StridedBufferVar {
+            bytes: self.bytes.var(),
+            stride: (self.stride as u64).expr(),
+            len: (self.len as u64).expr(),
+            _marker: PhantomData,
+        }
+    }
+    /// Copies the values to `data`.
+    pub fn copy_to(&self, data: &mut [T]) {
+        assert_eq!(
+            data.len(),
+            self.len,
+            "length mismatch copying from strided view of {} to host",
+            self.bytes.describe()
+        );
+        let bytes = self.bytes.copy_to_vec();
+        for (i, value) in data.iter_mut().enumerate() {
+            *value =
+                unsafe { std::ptr::read_unaligned(bytes[i * self.stride..].as_ptr() as *const T) };
+        }
+    }
+    pub fn copy_to_vec(&self) -> Vec<T> {
+        let mut data = Vec::with_capacity(self.len);
+        unsafe {
+            let slice = std::slice::from_raw_parts_mut(data.as_mut_ptr(), self.len);
+            self.copy_to(slice);
+            data.set_len(self.len);
+        }
+        data
+    }
+    /// Copies `data` to the values.
+    ///
+    /// The bytes between the values are read back and written unchanged, so
+    /// they must not be written by other streams during the copy.
+    pub fn copy_from(&self, data: &[T]) {
+        assert_eq!(
+            data.len(),
+            self.len,
+            "length mismatch copying from host to strided view of {}",
+            self.bytes.describe()
+        );
+        let size = std::mem::size_of::<T>();
+        let mut bytes = if self.stride == size {
+            vec![0u8; self.bytes.len]
+        } else {
+            self.bytes.copy_to_vec()
+        };
+        for (i, value) in data.iter().enumerate() {
+            bytes[i * self.stride..i * self.stride + size].copy_from_slice(unsafe {
+                std::slice::from_raw_parts(value as *const T as *const u8, size)
+            });
+        }
+        self.bytes.copy_from(&bytes);
+    }
+}
+
+/// A [`StridedBufferView`] in a kernel.
+pub struct StridedBufferVar<T: Value> {
+    pub(crate) bytes: ByteBufferVar,
+    pub(crate) stride: Expr<u64>,
+    pub(crate) len: Expr<u64>,
+    pub(crate) _marker: PhantomData<fn() -> T>,
+}
+impl<T: Value> Clone for StridedBufferVar<T> {
+    fn clone(&self) -> Self {
+        Self {
+            bytes: self.bytes.clone(),
+            stride: self.stride,
+            len: self.len,
+            _marker: PhantomData,
+        }
+    }
+}
+impl<T: Value> StridedBufferVar<T> {
+    /// Number of values.
+    #[inline]
+    pub fn len_expr(&self) -> Expr<u64> {
+        self.len
+    }
+    #[tracked]
+    fn offset(&self, i: Expr<u64>) -> Expr<u64> {
+        if need_runtime_check() {
+            let buffer = self.bytes.describe();
+            lc_assert!(
+                i < self.len,
+                &format!("index out of bounds of strided view of {}", buffer)
+            );
+        }
+        i * self.stride
+    }
+}
+impl<T: Value> IndexRead for StridedBufferVar<T> {
+    type Element = T;
+    fn read<I: IntoIndex>(&self, i: I) -> Expr<T> {
+        self.bytes.read_as::<T>(self.offset(i.to_u64()))
+    }
+}
+impl<T: Value> IndexWrite for StridedBufferVar<T> {
+    fn write<I: IntoIndex, V: AsExpr<Value = T>>(&self, i: I, value: V) {
+        self.bytes.write_as::<T>(self.offset(i.to_u64()), value)
+    }
+}
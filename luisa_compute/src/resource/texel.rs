@@ -0,0 +1,210 @@
+//! Texel types for formats without a native [`PixelFormat`] and host types for
+//! packed storages.
+//!
+//! Signed normalized textures are stored as signed integers and converted in
+//! [`IoTexel::convert_from_read`] and [`IoTexel::convert_to_write`]: [`Snorm8`]
+//! and friends map `[-1, 1]` to `[-127, 127]` in `Byte*` storages, [`Snorm16`]
+//! and friends to `[-32767, 32767]` in `Short*` storages.
+//!
+//! [`PackedRgb10A2`] and [`PackedRg11B10f`] are the host types of the
+//! `R10G10B10A2` and `R11G11B10` storages.
+use half::f16;
+
+use super::*;
+
+/// The read/write types of textures, lets `#[derive(IoTexel)]` pack the channels
+/// of several fields into one texel.
+pub trait TexelChannels: Value {
+    /// Moves channel `offset` of `texel` to `x`, and the following ones after it.
+    fn extract(texel: Expr<Self>, offset: usize) -> Expr<Self>;
+    /// Replaces `count` channels of `texel` starting from `offset` with the
+    /// first `count` channels of `value`.
+    fn insert(texel: Expr<Self>, value: Expr<Self>, offset: usize, count: usize) -> Expr<Self>;
+}
+macro_rules! impl_texel_channels {
+    ($t:ty) => {
+        impl TexelChannels for $t {
+            fn extract(texel: Expr<Self>, offset: usize) -> Expr<Self> {
+                let c = [texel.x, texel.y, texel.z, texel.w];
+                let c = |i: usize| c[(offset + i).min(3)];
+                <$t>::expr(c(0), c(1), c(2), c(3))
+            }
+            fn insert(
+                texel: Expr<Self>,
+                value: Expr<Self>,
+                offset: usize,
+                count: usize,
+            ) -> Expr<Self> {
+                assert!(offset + count <= 4, "a texel has at most 4 channels");
+                let mut c = [texel.x, texel.y, texel.z, texel.w];
+                let v = [value.x, value.y, value.z, value.w];
+                c[offset..offset + count].copy_from_slice(&v[..count]);
+                <$t>::expr(c[0], c[1], c[2], c[3])
+            }
+        }
+    };
+}
+impl_texel_channels!(Float4);
+impl_texel_channels!(Int4);
+impl_texel_channels!(Uint4);
+
+#[tracked]
+fn snorm_to_float(texel: Expr<Int4>, max: f32) -> Expr<Float4> {
+    // both -max and -max - 1 map to -1
+    (texel.cast_f32() / max).max_(-1.0f32)
+}
+#[tracked]
+fn float_to_snorm(value: Expr<Float4>, max: f32) -> Expr<Int4> {
+    (value.clamp(-1.0f32, 1.0f32) * max).round().cast_i32()
+}
+
+macro_rules! impl_snorm {
+    ($name:ident, $comps:ident, $t:ty, $channels:literal, $max:literal, [$($st:ident),+], $cvt_from:expr, $cvt_to:expr) => {
+        #[doc = concat!(
+            "A signed normalized texel with ", stringify!($channels),
+            " channels, stored in `", stringify!($($st)|+), "`."
+        )]
+        #[derive(Clone, Copy, Debug, PartialEq, Value)]
+        #[repr(C)]
+        pub struct $name {
+            pub value: $t,
+        }
+        impl IoTexel for $name {
+            type RwType = Int4;
+            const CHANNELS: usize = $channels;
+            fn pixel_format(storage: PixelStorage) -> PixelFormat {
+                match storage {
+                    $(PixelStorage::$st)|+ => <i32 as GetPixelFormat>::pixel_format(storage),
+                    _ => panic!("Invalid pixel storage for {}", stringify!($name)),
+                }
+            }
+            fn convert_from_read(texel: Expr<Int4>) -> Expr<Self> {
+                let value = ($cvt_from)(snorm_to_float(texel, $max));
+                Self::from_comps_expr($comps { value })
+            }
+            fn convert_to_write(value: Expr<Self>) -> Expr<Int4> {
+                float_to_snorm(($cvt_to)(value.value), $max)
+            }
+        }
+    };
+}
+impl_snorm!(
+    Snorm8,
+    Snorm8Comps,
+    f32,
+    1,
+    127.0,
+    [Byte1, Byte2, Byte4],
+    |x: Expr<Float4>| x.x,
+    |x: Expr<f32>| Float4::splat_expr(x)
+);
+impl_snorm!(
+    Snorm8x2,
+    Snorm8x2Comps,
+    Float2,
+    2,
+    127.0,
+    [Byte2, Byte4],
+    |x: Expr<Float4>| x.xy(),
+    |x: Expr<Float2>| Float4::expr(x.x, x.y, 0.0, 0.0)
+);
+impl_snorm!(
+    Snorm8x4,
+    Snorm8x4Comps,
+    Float4,
+    4,
+    127.0,
+    [Byte4],
+    |x: Expr<Float4>| x,
+    |x: Expr<Float4>| x
+);
+impl_snorm!(
+    Snorm16,
+    Snorm16Comps,
+    f32,
+    1,
+    32767.0,
+    [Short1, Short2, Short4],
+    |x: Expr<Float4>| x.x,
+    |x: Expr<f32>| Float4::splat_expr(x)
+);
+impl_snorm!(
+    Snorm16x2,
+    Snorm16x2Comps,
+    Float2,
+    2,
+    32767.0,
+    [Short2, Short4],
+    |x: Expr<Float4>| x.xy(),
+    |x: Expr<Float2>| Float4::expr(x.x, x.y, 0.0, 0.0)
+);
+impl_snorm!(
+    Snorm16x4,
+    Snorm16x4Comps,
+    Float4,
+    4,
+    32767.0,
+    [Short4],
+    |x: Expr<Float4>| x,
+    |x: Expr<Float4>| x
+);
+
+/// A texel of the `R10G10B10A2` storage: 10 bits for each of `r`, `g` and `b`
+/// and 2 bits for `a`, starting from the least significant bits.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[repr(transparent)]
+pub struct PackedRgb10A2(pub u32);
+impl PackedRgb10A2 {
+    pub fn from_uint(v: Uint4) -> Self {
+        let [r, g, b, a] = v.elements;
+        Self((r & 0x3ff) | ((g & 0x3ff) << 10) | ((b & 0x3ff) << 20) | ((a & 0x3) << 30))
+    }
+    pub fn to_uint(self) -> Uint4 {
+        let x = self.0;
+        Uint4::new(x & 0x3ff, (x >> 10) & 0x3ff, (x >> 20) & 0x3ff, x >> 30)
+    }
+    /// Packs normalized values, which are clamped to `[0, 1]`.
+    pub fn from_unorm(v: Float4) -> Self {
+        let [r, g, b, a] = v.elements;
+        let unorm = |x: f32, max: f32| (x.clamp(0.0, 1.0) * max).round() as u32;
+        Self::from_uint(Uint4::new(
+            unorm(r, 1023.0),
+            unorm(g, 1023.0),
+            unorm(b, 1023.0),
+            unorm(a, 3.0),
+        ))
+    }
+    pub fn to_unorm(self) -> Float4 {
+        let [r, g, b, a] = self.to_uint().elements;
+        Float4::new(
+            r as f32 / 1023.0,
+            g as f32 / 1023.0,
+            b as f32 / 1023.0,
+            a as f32 / 3.0,
+        )
+    }
+}
+
+/// A texel of the `R11G11B10` storage: unsigned floats with 5 exponent bits and
+/// 6, 6 and 5 mantissa bits for `r`, `g` and `b`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[repr(transparent)]
+pub struct PackedRg11B10f(pub u32);
+impl PackedRg11B10f {
+    /// Packs `v`, negative values are clamped to 0 and the mantissas truncated.
+    pub fn from_float(v: Float3) -> Self {
+        // the formats share the exponent of f16, drop its sign and low mantissa bits
+        let bits = |x: f32, drop: u32| (f16::from_f32(x.max(0.0)).to_bits() as u32) >> drop;
+        let [r, g, b] = v.elements;
+        Self(bits(r, 4) | (bits(g, 4) << 11) | (bits(b, 5) << 22))
+    }
+    pub fn to_float(self) -> Float3 {
+        let x = self.0;
+        let float = |bits: u32, drop: u32| f16::from_bits((bits << drop) as u16).to_f32();
+        Float3::new(
+            float(x & 0x7ff, 4),
+            float((x >> 11) & 0x7ff, 4),
+            float(x >> 22, 5),
+        )
+    }
+}
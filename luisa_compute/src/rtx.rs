@@ -22,10 +22,13 @@ pub(crate) struct AccelHandle {
     pub(crate) device: Device,
     pub(crate) handle: api::Accel,
     pub(crate) native_handle: *mut std::ffi::c_void,
+    pub(crate) allocation: AllocationId,
+    pub(crate) name: ResourceName,
 }
 impl Drop for AccelHandle {
     fn drop(&mut self) {
         self.device.inner.destroy_accel(self.handle);
+        self.device.inner.memory.release(self.allocation);
     }
 }
 unsafe impl Send for AccelHandle {}
@@ -54,10 +57,12 @@ pub(crate) struct ProceduralPrimitiveHandle {
     pub(crate) native_handle: *mut std::ffi::c_void,
     #[allow(dead_code)]
     pub(crate) aabb_buffer: Arc<BufferHandle>,
+    pub(crate) allocation: AllocationId,
 }
 impl Drop for ProceduralPrimitiveHandle {
     fn drop(&mut self) {
         self.device.inner.destroy_procedural_primitive(self.handle);
+        self.device.inner.memory.release(self.allocation);
     }
 }
 unsafe impl Send for ProceduralPrimitiveHandle {}
@@ -77,16 +82,19 @@ impl ProceduralPrimitive {
         let mut rt = ResourceTracker::new();
         rt.add(self.handle.clone());
         Command {
-            inner: api::Command::ProceduralPrimitiveBuild(api::ProceduralPrimitiveBuildCommand {
-                handle: self.handle.handle,
-                request,
-                aabb_buffer: self.aabb_buffer,
-                aabb_buffer_offset: self.aabb_buffer_offset,
-                aabb_count: self.aabb_buffer_count,
-            }),
+            inner: Some(api::Command::ProceduralPrimitiveBuild(
+                api::ProceduralPrimitiveBuildCommand {
+                    handle: self.handle.handle,
+                    request,
+                    aabb_buffer: self.aabb_buffer,
+                    aabb_buffer_offset: self.aabb_buffer_offset,
+                    aabb_count: self.aabb_buffer_count,
+                },
+            )),
             marker: PhantomData,
             resource_tracker: rt,
             callback: None,
+            rearm: None,
         }
     }
     pub fn build(&self, request: AccelBuildRequest) {
@@ -101,10 +109,13 @@ pub(crate) struct MeshHandle {
     pub(crate) vbuffer: Arc<BufferHandle>,
     #[allow(dead_code)]
     pub(crate) ibuffer: Arc<BufferHandle>,
+    pub(crate) allocation: AllocationId,
+    pub(crate) name: ResourceName,
 }
 impl Drop for MeshHandle {
     fn drop(&mut self) {
         self.device.inner.destroy_mesh(self.handle);
+        self.device.inner.memory.release(self.allocation);
     }
 }
 unsafe impl Send for MeshHandle {}
@@ -124,11 +135,27 @@ impl Mesh {
     pub fn native_handle(&self) -> *mut std::ffi::c_void {
         self.handle.native_handle
     }
+    /// See [`Buffer::set_name`].
+    pub fn set_name(&self, name: &str) {
+        self.handle.name.set(name);
+        self.handle
+            .device
+            .inner
+            .memory
+            .set_name(self.handle.allocation, name);
+    }
+    pub fn with_name(self, name: &str) -> Self {
+        self.set_name(name);
+        self
+    }
+    pub fn name(&self) -> Option<String> {
+        self.handle.name.get()
+    }
     pub fn build_async(&self, request: AccelBuildRequest) -> Command<'static, 'static> {
         let mut rt = ResourceTracker::new();
         rt.add(self.handle.clone());
         Command {
-            inner: api::Command::MeshBuild(api::MeshBuildCommand {
+            inner: Some(api::Command::MeshBuild(api::MeshBuildCommand {
                 mesh: self.handle.handle,
                 request,
                 vertex_buffer: self.vertex_buffer,
@@ -139,10 +166,11 @@ impl Mesh {
                 index_buffer_offset: self.index_buffer_offset,
                 index_buffer_size: self.index_buffer_size,
                 index_stride: self.index_stride,
-            }),
+            })),
             marker: PhantomData,
             resource_tracker: rt,
             callback: None,
+            rearm: None,
         }
     }
     pub fn build(&self, request: AccelBuildRequest) {
@@ -297,16 +325,17 @@ impl Accel {
         rt.add(m.clone());
         Command {
             marker: PhantomData,
-            inner: api::Command::AccelBuild(api::AccelBuildCommand {
+            inner: Some(api::Command::AccelBuild(api::AccelBuildCommand {
                 accel: self.handle.handle,
                 request,
                 instance_count: instance_handles.len() as u32,
                 modifications: m.as_ptr(),
                 modifications_count: m.len(),
                 update_instance_buffer_only: false,
-            }),
+            })),
             resource_tracker: rt,
             callback: None,
+            rearm: None,
         }
     }
     pub fn var(&self) -> AccelVar {
@@ -318,6 +347,22 @@ impl Accel {
     pub fn native_handle(&self) -> *mut std::ffi::c_void {
         self.handle.native_handle
     }
+    /// See [`Buffer::set_name`].
+    pub fn set_name(&self, name: &str) {
+        self.handle.name.set(name);
+        self.handle
+            .device
+            .inner
+            .memory
+            .set_name(self.handle.allocation, name);
+    }
+    pub fn with_name(self, name: &str) -> Self {
+        self.set_name(name);
+        self
+    }
+    pub fn name(&self) -> Option<String> {
+        self.handle.name.get()
+    }
 }
 #[derive(Clone)]
 pub struct AccelVar {
@@ -1029,8 +1074,10 @@ impl AccelVar {
             let binding = Binding::Accel(AccelBinding { handle });
             if let Some((a, b)) = r.check_on_same_device(&accel.handle.device) {
                 panic!(
-                    "Accel created for a device: `{:?}` but used in `{:?}`",
-                    b, a
+                    "{} created for a device: `{:?}` but used in `{:?}`",
+                    accel.handle.name.describe("accel", accel.handle().0),
+                    b,
+                    a
                 );
             }
             r.capture_or_get(binding, &Arc::downgrade(&accel.handle), || {
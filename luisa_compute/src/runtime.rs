@@ -7,6 +7,7 @@ use std::hash::Hash;
 use std::ops::Deref;
 use std::path::PathBuf;
 use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, Weak};
 
 use parking_lot::lock_api::RawMutex as RawMutexTrait;
@@ -24,20 +25,55 @@ use ir::{
 };
 
 use crate::backend::Backend;
+use crate::error::{ensure_arg, is_valid_handle, is_valid_memory, Result};
+use crate::Error;
 use crate::rtx;
 use crate::rtx::{Accel, Mesh, MeshHandle, ProceduralPrimitiveHandle};
 
 use api::AccelOption;
 pub use luisa_compute_api_types as api;
-use luisa_compute_backend::proxy::ProxyBackend;
 
+mod capture;
+mod future;
+mod graph;
 mod kernel;
-
+mod memory;
+mod profile;
+mod scheduler;
+mod serialize;
+
+pub use capture::*;
+pub use future::*;
+pub use graph::*;
 pub use kernel::*;
+pub use memory::*;
+pub use profile::*;
+pub use scheduler::*;
+pub use serialize::KERNEL_FORMAT_VERSION;
 
-#[derive(Clone)]
 pub struct Device {
     pub(crate) inner: Arc<DeviceHandle>,
+    // counted in `DeviceHandle::held`, false for the handles kept by resources
+    pub(crate) held: bool,
+}
+
+impl Clone for Device {
+    fn clone(&self) -> Self {
+        self.inner.held.fetch_add(1, Ordering::Relaxed);
+        Self {
+            inner: self.inner.clone(),
+            held: true,
+        }
+    }
+}
+
+impl Drop for Device {
+    fn drop(&mut self) {
+        if self.held && self.inner.held.fetch_sub(1, Ordering::AcqRel) == 1 {
+            // the application no longer holds the device, see `Device::leak_report`
+            self.inner.memory.report_leaks();
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -52,7 +88,12 @@ impl WeakDevice {
         }
     }
     pub fn upgrade(&self) -> Option<Device> {
-        self.inner.upgrade().map(|inner| Device { inner })
+        self.upgrade_internal().map(|device| device.clone())
+    }
+    pub(crate) fn upgrade_internal(&self) -> Option<Device> {
+        self.inner
+            .upgrade()
+            .map(|inner| Device { inner, held: false })
     }
 }
 
@@ -72,20 +113,32 @@ impl PartialEq for Device {
 impl Eq for Device {}
 
 pub(crate) struct DeviceHandle {
-    pub(crate) backend: ProxyBackend,
+    pub(crate) backend: Box<dyn Backend>,
     pub(crate) default_stream: Option<Arc<StreamHandle>>,
+    pub(crate) memory: MemoryTracker,
+    /// [`Device`] handles held by the application
+    pub(crate) held: AtomicUsize,
+    pub(crate) kernels: KernelCache,
     #[allow(dead_code)]
     pub(crate) ctx: Arc<crate::backend::Context>,
 }
 
+/// Kernels compiled by the library itself, e.g. to generate mipmaps, by name
+/// and signature, see [`Device::cached_kernel`]. They refer to the device
+/// weakly, so their shaders are destroyed with the device.
+#[derive(Default)]
+pub(crate) struct KernelCache {
+    kernels: Mutex<HashMap<(&'static str, TypeId), Arc<RawKernel>>>,
+}
+
 unsafe impl Send for DeviceHandle {}
 
 unsafe impl Sync for DeviceHandle {}
 
 impl Deref for DeviceHandle {
-    type Target = ProxyBackend;
+    type Target = dyn Backend;
     fn deref(&self) -> &Self::Target {
-        &self.backend
+        &*self.backend
     }
 }
 
@@ -95,6 +148,10 @@ unsafe impl Sync for Device {}
 
 impl Drop for DeviceHandle {
     fn drop(&mut self) {
+        // the cached kernels can no longer upgrade their device to do it
+        for kernel in self.kernels.kernels.get_mut().values() {
+            self.backend.destroy_shader(kernel.unwrap());
+        }
         if let Some(s) = &self.default_stream {
             let handle = s.handle();
             self.backend.destroy_stream(handle);
@@ -240,7 +297,7 @@ pub mod extension {
                     api: self.inner,
                     inner: (self.inner.create)(&self.inner, stream.handle().0),
                     rt: None,
-                    device: self.device.clone(),
+                    device: self.device.internal(),
                     stream: stream.handle.clone(),
                 }
             }
@@ -271,7 +328,7 @@ pub mod extension {
             unsafe { (self.api.execute)(&self.api, self.inner, async_) }
             if !async_ {
                 let stream = Stream {
-                    device: self.device.clone(),
+                    device: self.device.internal(),
                     handle: self.stream.clone(),
                 };
                 let scope = stream.scope();
@@ -298,11 +355,14 @@ pub trait DeviceExtensions {
 
 impl DeviceExtensions for Device {
     fn denoiser_ext(&self) -> Option<DenoiserExt> {
+        if self.query("device_name").as_deref() == Some("interpreter") {
+            return None;
+        }
         let ext = self.inner.denoiser_ext();
         if ext.valid() {
             Some(DenoiserExt {
                 inner: ext,
-                device: self.clone(),
+                device: self.internal(),
             })
         } else {
             None
@@ -323,6 +383,41 @@ impl Device {
     pub fn native_handle(&self) -> *mut std::ffi::c_void {
         self.inner.native_handle()
     }
+    /// Live resources created on this device, see [`MemoryStats`].
+    pub fn memory_stats(&self) -> MemoryStats {
+        self.inner.memory.stats()
+    }
+    /// Resources created on this device by the current thread while the
+    /// returned guard is alive are grouped under `label` in
+    /// [`Device::memory_stats`]. Labels nest.
+    pub fn memory_label(&self, label: impl Into<String>) -> MemoryLabelGuard {
+        MemoryLabelGuard::push(self, label.into())
+    }
+    /// Describes every live resource, or returns `None` if there is none.
+    ///
+    /// When the `LUISA_LEAK_CHECK` environment variable is set to `1` or `ON`,
+    /// the report includes the backtrace of each creation, and is logged as a
+    /// warning when the last [`Device`] held by the application is dropped
+    /// while resources are still alive. Resources keep the device alive on
+    /// their own, so those the application frees after dropping the device
+    /// are reported too.
+    pub fn leak_report(&self) -> Option<String> {
+        self.inner.memory.leak_report()
+    }
+    /// Calls `handler` with the [`Device::leak_report`] instead of logging it
+    /// when the last [`Device`] held by the application is dropped, whether
+    /// `LUISA_LEAK_CHECK` is set or not.
+    pub fn set_leak_handler(&self, handler: impl Fn(&str) + Send + Sync + 'static) {
+        self.inner.memory.set_leak_handler(Box::new(handler));
+    }
+    /// A handle kept by the library, which is not counted as held by the
+    /// application, see [`Device::leak_report`].
+    pub(crate) fn internal(&self) -> Device {
+        Device {
+            inner: self.inner.clone(),
+            held: false,
+        }
+    }
     pub fn create_swapchain(
         &self,
         window: impl HasWindowHandle + HasDisplayHandle,
@@ -397,7 +492,7 @@ impl Device {
             stream.handle(),
         );
         let swapchain = Swapchain {
-            device: self.clone(),
+            device: self.internal(),
             handle: Arc::new(SwapchainHandle {
                 device: self.inner.clone(),
                 handle: api::Swapchain(swapchain.resource.handle),
@@ -436,30 +531,39 @@ impl Device {
             metadata,
             _marker: PhantomData,
             copy_kernel: Mutex::new(None),
-            device: self.clone(),
+            field_copy_kernels: Mutex::new(HashMap::new()),
+            device: self.internal(),
         };
         buffer
     }
 
     /// Creates an **unintialized** buffer of `count` elements of type `T`.
     pub fn create_buffer<T: Value>(&self, count: usize) -> Buffer<T> {
+        self.try_create_buffer(count)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+    /// Fallible version of [`Device::create_buffer`].
+    pub fn try_create_buffer<T: Value>(&self, count: usize) -> Result<Buffer<T>> {
         self._create_buffer(std::ptr::null_mut(), count)
     }
-    fn _create_buffer<T: Value>(&self, ext_mem: *mut c_void, count: usize) -> Buffer<T> {
+    fn _create_buffer<T: Value>(&self, ext_mem: *mut c_void, count: usize) -> Result<Buffer<T>> {
         let name = self.name();
-        assert!(
+        ensure_arg!(
             std::mem::size_of::<T>() > 0,
             "size of T must be greater than 0"
         );
+        let size_bytes = count.checked_mul(std::mem::size_of::<T>()).ok_or_else(|| {
+            Error::InvalidArgument(format!("buffer of {} elements is too large", count))
+        })?;
         let ty = if TypeId::of::<T>() == TypeId::of::<u8>() {
             Type::void()
         } else {
             if name == "dx" {
-                assert!(
+                ensure_arg!(
                     std::mem::align_of::<T>() >= 4,
                     "T must be aligned to 4 bytes on dx"
                 );
-                assert!(
+                ensure_arg!(
                     count < u32::MAX as usize,
                     "count must be less than u32::MAX on dx"
                 );
@@ -467,15 +571,26 @@ impl Device {
             <T as TypeOf>::type_()
         };
         let buffer = self.inner.create_buffer(&ty, count, ext_mem);
+        if !is_valid_memory(&buffer.resource) {
+            return Err(Error::OutOfMemory {
+                resource: "buffer",
+                requested_bytes: Some(size_bytes),
+            });
+        }
         let handle = Arc::new(BufferHandle {
-            device: self.clone(),
+            device: self.internal(),
             handle: api::Buffer(buffer.resource.handle),
             native_handle: buffer.resource.native_handle,
+            allocation: self
+                .inner
+                .memory
+                .register(ResourceKind::Buffer, buffer.total_size_bytes),
+            name: ResourceName::default(),
         });
         let buffer = Buffer {
             handle: handle.clone(),
             full_view: BufferView {
-                device: self.clone(),
+                device: self.internal(),
                 handle: Arc::downgrade(&handle),
                 offset: 0,
                 len: count,
@@ -483,12 +598,13 @@ impl Device {
                 _marker: PhantomData,
             },
         };
-        buffer
+        Ok(buffer)
     }
 
     /// Imports an external buffer of `count` elements of type `T`.
     pub unsafe fn import_external_buffer<T: Value>(&self, data: *mut T, count: usize) -> Buffer<T> {
         self._create_buffer(data as *mut c_void, count)
+            .unwrap_or_else(|e| panic!("{}", e))
     }
     pub fn create_buffer_from_slice<T: Value>(&self, data: &[T]) -> Buffer<T> {
         let buffer = self.create_buffer(data.len());
@@ -505,14 +621,27 @@ impl Device {
         buffer
     }
     pub fn create_bindless_array(&self, slots: usize) -> BindlessArray {
-        assert!(slots > 0, "slots must be greater than 0");
+        self.try_create_bindless_array(slots)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+    /// Fallible version of [`Device::create_bindless_array`].
+    pub fn try_create_bindless_array(&self, slots: usize) -> Result<BindlessArray> {
+        ensure_arg!(slots > 0, "slots must be greater than 0");
         let array = self.inner.create_bindless_array(slots);
-        BindlessArray {
-            device: self.clone(),
+        if !is_valid_handle(array.handle) {
+            return Err(Error::OutOfMemory {
+                resource: "bindless array",
+                requested_bytes: None,
+            });
+        }
+        Ok(BindlessArray {
+            device: self.internal(),
             handle: Arc::new(BindlessArrayHandle {
-                device: self.clone(),
+                device: self.internal(),
                 handle: api::BindlessArray(array.handle),
                 native_handle: array.native_handle,
+                allocation: self.inner.memory.register(ResourceKind::BindlessArray, 0),
+                name: ResourceName::default(),
             }),
             modifications: RefCell::new(HashMap::new()),
             slots: RefCell::new(vec![
@@ -524,7 +653,45 @@ impl Device {
                 slots
             ]),
             lock: Arc::new(RawMutex::INIT),
-        }
+        })
+    }
+    /// Creates a [`BindlessAllocator`] managing the slots of a bindless array
+    /// with `slots` slots initially.
+    pub fn create_bindless_allocator(&self, slots: usize) -> BindlessAllocator {
+        self.try_create_bindless_allocator(slots)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+    /// Fallible version of [`Device::create_bindless_allocator`].
+    pub fn try_create_bindless_allocator(&self, slots: usize) -> Result<BindlessAllocator> {
+        let array = self.try_create_bindless_array(slots)?;
+        Ok(BindlessAllocator::new(self, array, slots))
+    }
+    /// Creates a [`DeviceVec`] holding up to `capacity` values pushed by kernels.
+    pub fn create_device_vec<T: Value>(&self, capacity: usize) -> DeviceVec<T> {
+        self.try_create_device_vec(capacity)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+    /// Fallible version of [`Device::create_device_vec`].
+    pub fn try_create_device_vec<T: Value>(&self, capacity: usize) -> Result<DeviceVec<T>> {
+        DeviceVec::new(self, capacity)
+    }
+    /// Creates an [`IndirectDispatchBuffer`] holding `count` dispatch sizes, all
+    /// zero, each at most `max_size`.
+    pub fn create_indirect_dispatch_buffer(
+        &self,
+        count: usize,
+        max_size: [u32; 3],
+    ) -> IndirectDispatchBuffer {
+        self.try_create_indirect_dispatch_buffer(count, max_size)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+    /// Fallible version of [`Device::create_indirect_dispatch_buffer`].
+    pub fn try_create_indirect_dispatch_buffer(
+        &self,
+        count: usize,
+        max_size: [u32; 3],
+    ) -> Result<IndirectDispatchBuffer> {
+        IndirectDispatchBuffer::new(self, count, max_size)
     }
     pub fn create_tex2d<T: IoTexel>(
         &self,
@@ -533,12 +700,42 @@ impl Device {
         height: u32,
         mips: u32,
     ) -> Tex2d<T> {
+        self.try_create_tex2d(storage, width, height, mips)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+    /// Fallible version of [`Device::create_tex2d`].
+    pub fn try_create_tex2d<T: IoTexel>(
+        &self,
+        storage: PixelStorage,
+        width: u32,
+        height: u32,
+        mips: u32,
+    ) -> Result<Tex2d<T>> {
+        ensure_arg!(
+            width > 0 && height > 0,
+            "texture size must be greater than 0, got {}x{}",
+            width,
+            height
+        );
+        let max_mips = 32 - width.max(height).leading_zeros();
+        ensure_arg!(
+            mips > 0 && mips <= max_mips,
+            "mips must be in 1..={}, got {}",
+            max_mips,
+            mips
+        );
         let format = T::pixel_format(storage);
         let texture = self
             .inner
             .create_texture(format, 2, width, height, 1, mips, true, false);
+        if !is_valid_memory(&texture) {
+            return Err(Error::OutOfMemory {
+                resource: "texture",
+                requested_bytes: None,
+            });
+        }
         let handle = Arc::new(TextureHandle {
-            device: self.clone(),
+            device: self.internal(),
             handle: api::Texture(texture.handle),
             native_handle: texture.native_handle,
             format,
@@ -547,6 +744,12 @@ impl Device {
             height,
             depth: 1,
             storage: format.storage(),
+            allocation: self.inner.memory.register(
+                ResourceKind::Texture,
+                texture_size_bytes(format.storage(), width, height, 1, mips),
+            ),
+            name: ResourceName::default(),
+            mipmap_tables: Default::default(),
         });
         let weak = Arc::downgrade(&handle);
         let tex = Tex2d {
@@ -555,7 +758,7 @@ impl Device {
             handle,
             views: (0..mips)
                 .map(|level| Tex2dView {
-                    device: self.clone(),
+                    device: self.internal(),
                     width,
                     height,
                     storage,
@@ -566,7 +769,7 @@ impl Device {
                 })
                 .collect(),
         };
-        tex
+        Ok(tex)
     }
     pub fn create_tex3d<T: IoTexel>(
         &self,
@@ -576,12 +779,44 @@ impl Device {
         depth: u32,
         mips: u32,
     ) -> Tex3d<T> {
+        self.try_create_tex3d(storage, width, height, depth, mips)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+    /// Fallible version of [`Device::create_tex3d`].
+    pub fn try_create_tex3d<T: IoTexel>(
+        &self,
+        storage: PixelStorage,
+        width: u32,
+        height: u32,
+        depth: u32,
+        mips: u32,
+    ) -> Result<Tex3d<T>> {
+        ensure_arg!(
+            width > 0 && height > 0 && depth > 0,
+            "texture size must be greater than 0, got {}x{}x{}",
+            width,
+            height,
+            depth
+        );
+        let max_mips = 32 - width.max(height).max(depth).leading_zeros();
+        ensure_arg!(
+            mips > 0 && mips <= max_mips,
+            "mips must be in 1..={}, got {}",
+            max_mips,
+            mips
+        );
         let format = T::pixel_format(storage);
         let texture = self
             .inner
             .create_texture(format, 3, width, height, depth, mips, true, false);
+        if !is_valid_memory(&texture) {
+            return Err(Error::OutOfMemory {
+                resource: "texture",
+                requested_bytes: None,
+            });
+        }
         let handle = Arc::new(TextureHandle {
-            device: self.clone(),
+            device: self.internal(),
             handle: api::Texture(texture.handle),
             native_handle: texture.native_handle,
             format,
@@ -590,6 +825,12 @@ impl Device {
             height,
             depth,
             storage: format.storage(),
+            allocation: self.inner.memory.register(
+                ResourceKind::Texture,
+                texture_size_bytes(format.storage(), width, height, depth, mips),
+            ),
+            name: ResourceName::default(),
+            mipmap_tables: Default::default(),
         });
         let weak = Arc::downgrade(&handle);
         let tex = Tex3d {
@@ -599,7 +840,7 @@ impl Device {
             handle,
             views: (0..mips)
                 .map(|level| Tex3dView {
-                    device: self.clone(),
+                    device: self.internal(),
                     width,
                     height,
                     depth,
@@ -611,33 +852,44 @@ impl Device {
                 })
                 .collect(),
         };
-        tex
+        Ok(tex)
     }
 
     pub fn default_stream(&self) -> Stream {
         Stream {
-            device: self.clone(),
+            device: self.internal(),
             handle: self.inner.default_stream.clone().unwrap(),
         }
     }
     pub fn create_stream(&self, tag: api::StreamTag) -> Stream {
         let stream = self.inner.create_stream(tag);
         Stream {
-            device: self.clone(),
+            device: self.internal(),
             handle: Arc::new(StreamHandle::NonDefault {
                 device: self.inner.clone(),
                 handle: api::Stream(stream.handle),
                 native_handle: stream.native_handle,
+                name: ResourceName::default(),
             }),
         }
     }
+    /// Creates a [`Scheduler`] that inserts event waits between conflicting commands on different streams.
+    pub fn create_scheduler(&self) -> Scheduler {
+        self.create_scheduler_with_mode(SchedulerMode::Synchronize)
+    }
+    /// Use [`SchedulerMode::Report`] to detect missing synchronization instead of fixing it.
+    pub fn create_scheduler_with_mode(&self, mode: SchedulerMode) -> Scheduler {
+        Scheduler::new(self, mode)
+    }
     pub fn create_event(&self) -> Event {
         let event = self.inner.create_event();
         Event {
             handle: Arc::new(EventHandle {
-                device: self.clone(),
+                device: self.internal(),
                 handle: api::Event(event.handle),
                 native_handle: event.native_handle,
+                name: ResourceName::default(),
+                waiters: Mutex::new(vec![]),
             }),
         }
     }
@@ -649,10 +901,14 @@ impl Device {
         let primitive = self.inner.create_procedural_primitive(option);
         rtx::ProceduralPrimitive {
             handle: Arc::new(ProceduralPrimitiveHandle {
-                device: self.clone(),
+                device: self.internal(),
                 handle: api::ProceduralPrimitive(primitive.handle),
                 native_handle: primitive.native_handle,
                 aabb_buffer: aabb_buffer._handle(),
+                allocation: self
+                    .inner
+                    .memory
+                    .register(ResourceKind::ProceduralPrimitive, 0),
             }),
             aabb_buffer: aabb_buffer.handle(),
             aabb_buffer_offset: aabb_buffer.offset * std::mem::size_of::<rtx::Aabb>() as usize,
@@ -670,11 +926,13 @@ impl Device {
         let native_handle = mesh.native_handle;
         let mesh = Mesh {
             handle: Arc::new(MeshHandle {
-                device: self.clone(),
+                device: self.internal(),
                 handle: api::Mesh(handle),
                 native_handle,
                 vbuffer: vbuffer._handle(),
                 ibuffer: tbuffer._handle(),
+                allocation: self.inner.memory.register(ResourceKind::Mesh, 0),
+                name: ResourceName::default(),
             }),
             vertex_buffer: vbuffer.handle(),
             vertex_buffer_offset: vbuffer.offset * std::mem::size_of::<V>() as usize,
@@ -688,16 +946,31 @@ impl Device {
         mesh
     }
     pub fn create_accel(&self, option: api::AccelOption) -> rtx::Accel {
+        self.try_create_accel(option)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+    /// Fallible version of [`Device::create_accel`].
+    ///
+    /// Returns [`Error::Unsupported`] if the device has no ray tracing support.
+    pub fn try_create_accel(&self, option: api::AccelOption) -> Result<rtx::Accel> {
         let accel = self.inner.create_accel(option);
-        rtx::Accel {
+        if !is_valid_handle(accel.handle) {
+            return Err(Error::Unsupported(format!(
+                "failed to create accel on `{}` device",
+                self.name()
+            )));
+        }
+        Ok(rtx::Accel {
             handle: Arc::new(rtx::AccelHandle {
-                device: self.clone(),
+                device: self.internal(),
                 handle: api::Accel(accel.handle),
                 native_handle: accel.native_handle,
+                allocation: self.inner.memory.register(ResourceKind::Accel, 0),
+                name: ResourceName::default(),
             }),
             instance_handles: RwLock::new(Vec::new()),
             modifications: RwLock::new(HashMap::new()),
-        }
+        })
     }
 
     // pub fn create_callable<S: CallableSignature>(
@@ -713,23 +986,56 @@ impl Device {
     // }
 
     pub fn create_kernel<'a, S: KernelSignature2<'a>>(&self, f: S::Fn) -> Kernel<S> {
-        let mut builder = KernelBuilder::new(Some(self.clone()), true);
+        let mut builder = KernelBuilder::new(Some(self.internal()), true);
         let k = KernelBuildFn::build_kernel(&f, &mut builder);
         self.compile_kernel_def(&k)
     }
 
     pub fn create_kernel_async<'a, S: KernelSignature2<'a>>(&self, f: S::Fn) -> Kernel<S> {
-        let mut builder = KernelBuilder::new(Some(self.clone()), true);
+        let mut builder = KernelBuilder::new(Some(self.internal()), true);
         let k = KernelBuildFn::build_kernel(&f, &mut builder);
         self.compile_kernel_def_async(&k)
     }
 
+    /// The kernel cached as `name` for signature `S`, created by `create` on
+    /// first use. `create` must not capture resources, which would keep the
+    /// device alive.
+    ///
+    /// The returned kernel only refers to the device weakly and must not be
+    /// dispatched after the device is dropped.
+    pub(crate) fn cached_kernel<S: KernelSignature + 'static>(
+        &self,
+        name: &'static str,
+        create: impl FnOnce(&Device) -> Kernel<S>,
+    ) -> Kernel<S> {
+        let key = (name, TypeId::of::<S>());
+        let cached = self.inner.kernels.kernels.lock().get(&key).cloned();
+        let inner = cached.unwrap_or_else(|| {
+            // compiled without the lock, other threads may use the cache meanwhile
+            let mut kernel = create(self).inner;
+            Arc::get_mut(&mut kernel)
+                .expect("cached kernels must not be shared")
+                .held_device = None;
+            self.inner
+                .kernels
+                .kernels
+                .lock()
+                .entry(key)
+                .or_insert(kernel)
+                .clone()
+        });
+        Kernel {
+            inner,
+            _marker: PhantomData,
+        }
+    }
+
     pub fn create_kernel_with_options<'a, S: KernelSignature2<'a>>(
         &self,
         options: KernelBuildOptions,
         f: S::Fn,
     ) -> Kernel<S> {
-        let mut builder = KernelBuilder::new(Some(self.clone()), true);
+        let mut builder = KernelBuilder::new(Some(self.internal()), true);
         let k = KernelBuildFn::build_kernel(&f, &mut builder);
         self.compile_kernel_def_with_options(&k, options)
     }
@@ -758,10 +1064,80 @@ impl Device {
         k: &KernelDef<S>,
         options: KernelBuildOptions,
     ) -> Kernel<S> {
+        if !options.async_compile {
+            return self
+                .try_compile_kernel_def_with_options(k, options)
+                .unwrap_or_else(|e| panic!("{}", e));
+        }
+        let (shader_options, name, native_include) =
+            Self::shader_option(options).unwrap_or_else(|e| panic!("{}", e));
+        let module = k.inner.module.clone();
+        let artifact = ShaderArtifact::Async(AsyncShaderArtifact::new(
+            self.internal(),
+            module.clone(),
+            shader_options,
+            name.clone(),
+            native_include,
+        ));
+        self.make_kernel(k, module, name, artifact)
+    }
+
+    /// Fallible version of [`Device::create_kernel`].
+    pub fn try_create_kernel<'a, S: KernelSignature2<'a>>(&self, f: S::Fn) -> Result<Kernel<S>> {
+        self.try_create_kernel_with_options(KernelBuildOptions::default(), f)
+    }
+
+    /// Fallible version of [`Device::create_kernel_with_options`].
+    pub fn try_create_kernel_with_options<'a, S: KernelSignature2<'a>>(
+        &self,
+        options: KernelBuildOptions,
+        f: S::Fn,
+    ) -> Result<Kernel<S>> {
+        let mut builder = KernelBuilder::new(Some(self.internal()), true);
+        let k = KernelBuildFn::build_kernel(&f, &mut builder);
+        self.try_compile_kernel_def_with_options(&k, options)
+    }
+
+    /// Fallible version of [`Device::compile_kernel_def`].
+    pub fn try_compile_kernel_def<S: KernelSignature>(
+        &self,
+        k: &KernelDef<S>,
+    ) -> Result<Kernel<S>> {
+        self.try_compile_kernel_def_with_options(k, KernelBuildOptions::default())
+    }
+
+    /// Fallible version of [`Device::compile_kernel_def_with_options`].
+    ///
+    /// Compilation errors of asynchronously compiled kernels can only be
+    /// detected once the compilation finishes, so `async_compile` is ignored
+    /// and the kernel is always compiled synchronously.
+    pub fn try_compile_kernel_def_with_options<S: KernelSignature>(
+        &self,
+        k: &KernelDef<S>,
+        options: KernelBuildOptions,
+    ) -> Result<Kernel<S>> {
+        let (shader_options, name, _native_include) = Self::shader_option(options)?;
+        let module = k.inner.module.clone();
+        let shader = self.inner.create_shader(&module, &shader_options);
+        if !is_valid_handle(shader.resource.handle) {
+            return Err(Error::CompileFailure {
+                name: name.to_string_lossy().into_owned(),
+            });
+        }
+        Ok(self.make_kernel(k, module, name, ShaderArtifact::Sync(shader)))
+    }
+
+    fn shader_option(
+        options: KernelBuildOptions,
+    ) -> Result<(api::ShaderOption, Arc<CString>, Arc<CString>)> {
         let name = options.name.unwrap_or("".to_string());
-        let name = Arc::new(CString::new(name).unwrap());
+        let name = Arc::new(CString::new(name).map_err(|_| {
+            Error::InvalidArgument("kernel name must not contain NUL bytes".to_string())
+        })?);
         let native_include = options.native_include.unwrap_or("".to_string());
-        let native_include = Arc::new(CString::new(native_include).unwrap());
+        let native_include = Arc::new(CString::new(native_include).map_err(|_| {
+            Error::InvalidArgument("native include must not contain NUL bytes".to_string())
+        })?);
         let shader_options = api::ShaderOption {
             enable_cache: options.enable_cache,
             enable_fast_math: options.enable_fast_math,
@@ -772,23 +1148,23 @@ impl Device {
             name: name.as_ptr(),
             native_include: native_include.as_ptr(),
         };
-        let module = k.inner.module.clone();
-        let artifact = if options.async_compile {
-            ShaderArtifact::Async(AsyncShaderArtifact::new(
-                self.clone(),
-                module.clone(),
-                shader_options,
-                name,
-                native_include,
-            ))
-        } else {
-            ShaderArtifact::Sync(self.inner.create_shader(&module, &shader_options))
-        };
+        Ok((shader_options, name, native_include))
+    }
+
+    fn make_kernel<S: KernelSignature>(
+        &self,
+        k: &KernelDef<S>,
+        module: CArc<KernelModule>,
+        name: Arc<CString>,
+        artifact: ShaderArtifact,
+    ) -> Kernel<S> {
         Kernel {
             inner: Arc::new(RawKernel {
-                device: self.clone(),
+                device: WeakDevice::new(self),
+                held_device: Some(self.internal()),
                 artifact,
                 module,
+                name,
                 resource_tracker: k.inner.resource_tracker.clone(),
             }),
             _marker: PhantomData {},
@@ -801,11 +1177,13 @@ pub(crate) enum StreamHandle {
         device: Weak<DeviceHandle>,
         handle: api::Stream,
         native_handle: *mut std::ffi::c_void,
+        name: ResourceName,
     },
     NonDefault {
         device: Arc<DeviceHandle>,
         handle: api::Stream,
         native_handle: *mut std::ffi::c_void,
+        name: ResourceName,
     },
 }
 
@@ -869,6 +1247,17 @@ impl Event {
     pub fn native_handle(&self) -> *mut std::ffi::c_void {
         self.handle.native_handle
     }
+    /// See [`Buffer::set_name`].
+    pub fn set_name(&self, name: &str) {
+        self.handle.name.set(name);
+    }
+    pub fn with_name(self, name: &str) -> Self {
+        self.set_name(name);
+        self
+    }
+    pub fn name(&self) -> Option<String> {
+        self.handle.name.get()
+    }
     #[inline]
     pub fn synchronize(&self, ticket: u64) {
         self.handle
@@ -889,6 +1278,9 @@ pub(crate) struct EventHandle {
     pub(crate) device: Device,
     handle: api::Event,
     native_handle: *mut std::ffi::c_void,
+    name: ResourceName,
+    /// futures of [`Event::wait_async`] with their tickets
+    pub(crate) waiters: Mutex<Vec<(u64, GpuFutureCompleter<()>)>>,
 }
 
 unsafe impl Send for EventHandle {}
@@ -942,6 +1334,16 @@ impl StreamHandle {
             StreamHandle::NonDefault { native_handle, .. } => *native_handle,
         }
     }
+    #[inline]
+    pub(crate) fn name(&self) -> &ResourceName {
+        match self {
+            StreamHandle::Default { name, .. } => name,
+            StreamHandle::NonDefault { name, .. } => name,
+        }
+    }
+    pub(crate) fn describe(&self) -> String {
+        self.name().describe("stream", self.handle().0)
+    }
 }
 
 impl Drop for StreamHandle {
@@ -1009,7 +1411,7 @@ impl<'a> Scope<'a> {
         self.synchronized.set(false);
         let api_commands = commands
             .iter()
-            .map(|c| c.inner)
+            .filter_map(|c| c.inner)
             .filter(|c| match c {
                 // filters zero-size dispatches
                 api::Command::ShaderDispatch(c) => c.dispatch_size.iter().all(|&s| s > 0),
@@ -1037,23 +1439,21 @@ impl<'a> Scope<'a> {
         commands: impl IntoIterator<Item = Command<'cmd, 'a>>,
         callback: F,
     ) -> &Self {
-        let mut iter = commands.into_iter();
+        let mut iter = commands.into_iter().peekable();
         loop {
             let mut commands = vec![];
-            let mut end = false;
-            loop {
-                if let Some(cmd) = iter.next() {
-                    let should_break = cmd.callback.is_some();
-                    commands.push(cmd);
-                    if should_break {
-                        break;
-                    }
-                } else {
-                    end = true;
+            for cmd in iter.by_ref() {
+                let should_break = cmd.callback.is_some();
+                commands.push(cmd);
+                if should_break {
                     break;
                 }
             }
+            // the callback is chained after the callback of the last command
+            let end = iter.peek().is_none();
             if commands.is_empty() {
+                // still called once the work submitted before has finished
+                self.submit_impl(commands, callback);
                 return self;
             }
             // self.submit_impl(commands, callback)
@@ -1085,6 +1485,9 @@ impl<'a> Scope<'a> {
         self.handle
             .device()
             .signal_event(event.handle(), self.handle(), ticket);
+        // completes the futures of `Event::wait_async`
+        let event = event.handle.clone();
+        self.submit_impl(vec![], move || event.complete_waiters(ticket));
         self
     }
     #[inline]
@@ -1143,6 +1546,18 @@ impl Stream {
     pub fn native_handle(&self) -> *mut std::ffi::c_void {
         self.handle.native_handle()
     }
+    /// See [`Buffer::set_name`]. Streams returned by [`Device::default_stream`]
+    /// share the name of the default stream.
+    pub fn set_name(&self, name: &str) {
+        self.handle.name().set(name);
+    }
+    pub fn with_name(self, name: &str) -> Self {
+        self.set_name(name);
+        self
+    }
+    pub fn name(&self) -> Option<String> {
+        self.handle.name().get()
+    }
 }
 
 struct CommandCallbackCtx<'cmd, 'scope, F: FnOnce() + Send + 'static> {
@@ -1180,21 +1595,42 @@ pub fn submit_default_stream_and_sync<
 /// Commands are created by resources and submitted to a [`Scope<'a>`] via `scope.submit` and `scope.submit_with_callback`.
 #[must_use]
 pub struct Command<'from_data, 'to_data> {
-    #[allow(dead_code)]
-    pub(crate) inner: api::Command,
+    /// `None` for commands that only run their callback, see [`Command::callback_only`]
+    pub(crate) inner: Option<api::Command>,
     // is this really necessary?
     pub(crate) marker: PhantomData<(&'from_data (), &'to_data ())>,
     pub(crate) callback: Option<Box<dyn FnOnce() + Send + 'static>>,
+    /// Prepares the command to be submitted again by a [`CommandGraph`] and
+    /// returns the callback to run once that submission has completed, `None`
+    /// if the callback must not run more than once.
+    pub(crate) rearm: Option<Rearm>,
     #[allow(dead_code)]
     pub(crate) resource_tracker: ResourceTracker,
 }
 
+pub(crate) type Rearm = Arc<dyn Fn() -> Box<dyn FnOnce() + Send + 'static> + Send + Sync>;
+
+impl Command<'static, 'static> {
+    /// A command that runs `callback` once the commands submitted before it
+    /// have completed, without sending anything to the backend.
+    pub(crate) fn callback_only(callback: impl FnOnce() + Send + 'static) -> Self {
+        Command {
+            inner: None,
+            marker: PhantomData,
+            callback: Some(Box::new(callback)),
+            rearm: None,
+            resource_tracker: ResourceTracker::new(),
+        }
+    }
+}
+
 impl<'cmd, 'scope> Command<'cmd, 'scope> {
     pub unsafe fn lift(self) -> Command<'static, 'static> {
         Command {
             inner: self.inner,
             marker: PhantomData {},
             callback: self.callback,
+            rearm: self.rearm,
             resource_tracker: self.resource_tracker,
         }
     }
@@ -1246,17 +1682,25 @@ impl AsyncShaderArtifact {
 }
 
 pub struct RawKernel {
-    pub(crate) device: Device,
+    pub(crate) device: WeakDevice,
+    /// keeps the device alive, `None` for the kernels cached by the device,
+    /// see `KernelCache`
+    #[allow(dead_code)]
+    pub(crate) held_device: Option<Device>,
     pub(crate) artifact: ShaderArtifact,
     #[allow(dead_code)]
     pub(crate) resource_tracker: ResourceTracker,
     pub(crate) module: CArc<KernelModule>,
+    pub(crate) name: Arc<CString>,
 }
 
 impl Drop for RawKernel {
     fn drop(&mut self) {
-        let shader = self.unwrap();
-        self.device.inner.destroy_shader(shader);
+        // otherwise destroyed by the device, see `KernelCache`
+        if let Some(device) = self.device.upgrade_internal() {
+            let shader = self.unwrap();
+            device.inner.destroy_shader(shader);
+        }
     }
 }
 
@@ -1346,6 +1790,18 @@ impl KernelArgEncoder {
             size: buffer.len * std::mem::size_of::<T>(),
         }));
     }
+    pub fn strided_buffer_view<T: Value>(&mut self, view: &StridedBufferView<T>) {
+        self.buffer_view(&view.bytes);
+        self.uniform(view.stride as u64);
+        self.uniform(view.len as u64);
+    }
+    pub fn device_vec<T: Value>(&mut self, vec: &DeviceVec<T>) {
+        self.buffer(&vec.data);
+        self.buffer(&vec.counter);
+    }
+    pub fn indirect_dispatch_buffer(&mut self, sizes: &IndirectDispatchBuffer) {
+        self.buffer(&sizes.sizes);
+    }
     pub fn byte_buffer(&mut self, buffer: &ByteBuffer) {
         self.args.push(api::Argument::Buffer(api::BufferArgument {
             buffer: buffer.handle.handle,
@@ -1393,6 +1849,27 @@ impl<T: Value> KernelArg for Buffer<T> {
     }
 }
 
+impl<T: Value> KernelArg for StridedBufferView<T> {
+    type Parameter = StridedBufferVar<T>;
+    fn encode(&self, encoder: &mut KernelArgEncoder) {
+        encoder.strided_buffer_view(self);
+    }
+}
+
+impl<T: Value> KernelArg for DeviceVec<T> {
+    type Parameter = DeviceVecVar<T>;
+    fn encode(&self, encoder: &mut KernelArgEncoder) {
+        encoder.device_vec(self);
+    }
+}
+
+impl KernelArg for IndirectDispatchBuffer {
+    type Parameter = IndirectDispatchBufferVar;
+    fn encode(&self, encoder: &mut KernelArgEncoder) {
+        encoder.indirect_dispatch_buffer(self);
+    }
+}
+
 impl<T: SoaValue> KernelArg for SoaBuffer<T> {
     type Parameter = SoaBufferVar<T>;
     fn encode(&self, encoder: &mut KernelArgEncoder) {
@@ -1461,6 +1938,20 @@ impl<T: IoTexel> KernelArg for Tex3dView<T> {
     }
 }
 
+impl<T: IoTexel> KernelArg for Tex2dArray<T> {
+    type Parameter = Tex2dArrayVar<T>;
+    fn encode(&self, encoder: &mut KernelArgEncoder) {
+        encoder.tex3d(&self.tex.view(0));
+    }
+}
+
+impl<T: IoTexel> KernelArg for TexCube<T> {
+    type Parameter = TexCubeVar<T>;
+    fn encode(&self, encoder: &mut KernelArgEncoder) {
+        encoder.tex3d(&self.faces.tex.view(0));
+    }
+}
+
 impl KernelArg for BindlessArray {
     type Parameter = BindlessArrayVar;
     fn encode(&self, encoder: &mut KernelArgEncoder) {
@@ -1498,6 +1989,14 @@ macro_rules! impl_kernel_arg_for_tuple {
 impl_kernel_arg_for_tuple!(T0 T1 T2 T3 T4 T5 T6 T7 T8 T9 T10 T11 T12 T13 T14 T15);
 
 impl RawKernel {
+    pub fn name(&self) -> &str {
+        self.name.to_str().unwrap_or("")
+    }
+    fn device(&self) -> Device {
+        self.device
+            .upgrade_internal()
+            .unwrap_or_else(|| panic!("kernel `{}` used after its device was dropped", self.name()))
+    }
     fn unwrap(&self) -> api::Shader {
         match &self.artifact {
             ShaderArtifact::Sync(shader) => api::Shader(shader.resource.handle),
@@ -1523,28 +2022,70 @@ impl RawKernel {
         rt.add(self.clone());
         let args = args.args;
         let args = Arc::new(args);
-        assert_eq!(args.len(), self.module.args.len());
+        assert_eq!(
+            args.len(),
+            self.module.args.len(),
+            "wrong number of arguments for kernel `{}`, kernels calling \
+             `set_indirect_dispatch` can only be dispatched indirectly",
+            self.name()
+        );
         rt.add(args.clone());
         let captures = self.resource_tracker.upgrade();
         rt.merge(captures);
         Command {
-            inner: api::Command::ShaderDispatch(api::ShaderDispatchCommand {
+            inner: Some(api::Command::ShaderDispatch(api::ShaderDispatchCommand {
                 shader: self.unwrap(),
                 args: args.as_ptr(),
                 args_count: args.len(),
                 dispatch_size,
-            }),
+            })),
             marker: PhantomData,
             resource_tracker: rt,
             callback: None,
+            rearm: None,
         }
     }
+    pub fn dispatch_indirect_async(
+        self: &Arc<Self>,
+        mut args: KernelArgEncoder,
+        sizes: &IndirectDispatchBuffer,
+        offset: usize,
+    ) -> Command<'static, 'static> {
+        assert!(
+            offset < sizes.len(),
+            "dispatch size {} out of bounds of {} with {} sizes",
+            offset,
+            sizes.sizes.describe(),
+            sizes.len()
+        );
+        assert_eq!(
+            args.args.len() + 2,
+            self.module.args.len(),
+            "kernel `{}` must call `set_indirect_dispatch` to be dispatched indirectly",
+            self.name()
+        );
+        // the hidden arguments added by `set_indirect_dispatch`
+        args.indirect_dispatch_buffer(sizes);
+        args.uniform(offset as u32);
+        self.dispatch_async(args, sizes.max_size)
+    }
+    pub fn dispatch_indirect(
+        self: &Arc<Self>,
+        args: KernelArgEncoder,
+        sizes: &IndirectDispatchBuffer,
+        offset: usize,
+    ) {
+        submit_default_stream(
+            &self.device(),
+            [self.dispatch_indirect_async(args, sizes, offset)],
+        )
+    }
     pub fn dispatch(self: &Arc<Self>, args: KernelArgEncoder, dispatch_size: [u32; 3]) {
-        submit_default_stream(&self.device, [self.dispatch_async(args, dispatch_size)])
+        submit_default_stream(&self.device(), [self.dispatch_async(args, dispatch_size)])
     }
 
     pub fn dispatch_blocking(self: &Arc<Self>, args: KernelArgEncoder, dispatch_size: [u32; 3]) {
-        submit_default_stream_and_sync(&self.device, [self.dispatch_async(args, dispatch_size)])
+        submit_default_stream_and_sync(&self.device(), [self.dispatch_async(args, dispatch_size)])
     }
 }
 
@@ -1634,7 +2175,7 @@ impl<S: CallableSignature> DynCallable<S> {
                 kernel_id,
                 r_backup.clone(),
             ))));
-            (r_backup, device.upgrade().unwrap())
+            (r_backup, device.upgrade_internal().unwrap())
         });
         let mut builder = KernelBuilder::new(Some(device), false);
         let new_callable = (inner.builder)(args, &mut builder);
@@ -1748,12 +2289,16 @@ unsafe impl<T: KernelSignature> Sync for Kernel<T> {}
 impl<T: KernelSignature> Kernel<T> {
     pub fn cache_dir(&self) -> Option<PathBuf> {
         let handle = self.inner.unwrap();
-        let device = &self.inner.device;
+        let device = self.inner.device();
         device.inner.shader_cache_dir(handle)
     }
     pub fn dump(&self) -> String {
         ir::debug::dump_ir_human_readable(&self.inner.module.module)
     }
+    /// The name given by [`KernelBuildOptions::name`], empty if none was set.
+    pub fn name(&self) -> &str {
+        self.inner.name()
+    }
     pub fn num_arguments(&self) -> usize {
         self.inner.module.args.len()
     }
@@ -1790,6 +2335,18 @@ impl<'a, T: Value> AsKernelArg for BufferView<T> {
     type Output = Buffer<T>;
 }
 
+impl<T: Value> AsKernelArg for StridedBufferView<T> {
+    type Output = StridedBufferView<T>;
+}
+
+impl<T: Value> AsKernelArg for DeviceVec<T> {
+    type Output = DeviceVec<T>;
+}
+
+impl AsKernelArg for IndirectDispatchBuffer {
+    type Output = IndirectDispatchBuffer;
+}
+
 impl<T: SoaValue> AsKernelArg for SoaBuffer<T> {
     type Output = SoaBuffer<T>;
 }
@@ -1814,6 +2371,14 @@ impl<T: IoTexel> AsKernelArg for Tex3d<T> {
     type Output = Tex3d<T>;
 }
 
+impl<T: IoTexel> AsKernelArg for Tex2dArray<T> {
+    type Output = Tex2dArray<T>;
+}
+
+impl<T: IoTexel> AsKernelArg for TexCube<T> {
+    type Output = TexCube<T>;
+}
+
 impl AsKernelArg for BindlessArray {
     type Output = BindlessArray;
 }
@@ -1926,6 +2491,29 @@ macro_rules! impl_dispatch_for_kernel {
                 $($Ts.encode(&mut encoder);)*
                 self.inner.dispatch_async(encoder, dispatch_size)
             }
+            /// Dispatches with the size at `offset` of `sizes`, written by
+            /// earlier commands. The kernel must call [`set_indirect_dispatch`],
+            /// see [`IndirectDispatchBuffer`].
+            #[allow(non_snake_case)]
+            #[allow(unused_mut)]
+            pub fn dispatch_indirect(
+                &self,
+                sizes: &IndirectDispatchBuffer, offset: usize, $($Ts:&impl AsKernelArg<Output = $Ts>),*
+            ) {
+                let mut encoder = KernelArgEncoder::new();
+                $($Ts.encode(&mut encoder);)*
+                self.inner.dispatch_indirect(encoder, sizes, offset)
+            }
+            #[allow(non_snake_case)]
+            #[allow(unused_mut)]
+            pub fn dispatch_indirect_async(
+                &self,
+                sizes: &IndirectDispatchBuffer, offset: usize, $($Ts:&impl AsKernelArg<Output = $Ts>),*
+            ) -> Command<'static, 'static> {
+                let mut encoder = KernelArgEncoder::new();
+                $($Ts.encode(&mut encoder);)*
+                self.inner.dispatch_indirect_async(encoder, sizes, offset)
+            }
             /// Blocks until the kernel is compiled
             pub fn ensure_ready(&self) {
                 self.inner.unwrap();
@@ -0,0 +1,1429 @@
+//! Recording of backend API calls for bug reports.
+//!
+//! A device created with [`Context::create_device_with_capture`](crate::Context::create_device_with_capture)
+//! (or with the `LUISA_CAPTURE` environment variable set to a file path) writes
+//! every resource creation, command and kernel it receives to a capture file,
+//! including the contents of host to device uploads. [`CaptureFile`] loads such a
+//! file and [`Replayer`] executes it again on any device, one command at a time,
+//! so that buffer contents can be inspected after every step.
+//!
+//! Replays are serialized: all commands run on a single stream in the order the
+//! application submitted them, waiting for each command to complete before the
+//! next one starts. Cross-stream synchronization is therefore not replayed, and
+//! a replay on the cpu backend is deterministic up to the order of atomics.
+//!
+//! The file starts with a JSON header line, followed by entries made of a
+//! little-endian `u64` length, a JSON record, a second length and the raw payload
+//! of the record. Kernels are stored in the format of [`KernelDef::serialize`],
+//! with the handles of captured resources remapped on replay. Kernels calling
+//! [`CpuFn`]s, ray tracing and swapchains cannot be captured, the corresponding
+//! steps fail on replay with [`Error::Unsupported`].
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use super::serialize::{KernelRecord, TypeRecord};
+use super::*;
+use crate::error::Result;
+use crate::resource::{PixelFormat, PixelStorage, Sampler, SamplerAddress, SamplerFilter};
+use crate::Error;
+
+const MAGIC: &str = "luisa-compute-capture";
+/// Bumped whenever the layout of capture files changes.
+pub const CAPTURE_FORMAT_VERSION: u32 = 1;
+
+const PIXEL_FORMATS: [PixelFormat; 33] = [
+    PixelFormat::R8Sint,
+    PixelFormat::R8Uint,
+    PixelFormat::R8Unorm,
+    PixelFormat::Rg8Sint,
+    PixelFormat::Rg8Uint,
+    PixelFormat::Rg8Unorm,
+    PixelFormat::Rgba8Sint,
+    PixelFormat::Rgba8Uint,
+    PixelFormat::Rgba8Unorm,
+    PixelFormat::R16Sint,
+    PixelFormat::R16Uint,
+    PixelFormat::R16Unorm,
+    PixelFormat::Rg16Sint,
+    PixelFormat::Rg16Uint,
+    PixelFormat::Rg16Unorm,
+    PixelFormat::Rgba16Sint,
+    PixelFormat::Rgba16Uint,
+    PixelFormat::Rgba16Unorm,
+    PixelFormat::R32Sint,
+    PixelFormat::R32Uint,
+    PixelFormat::Rg32Sint,
+    PixelFormat::Rg32Uint,
+    PixelFormat::Rgba32Sint,
+    PixelFormat::Rgba32Uint,
+    PixelFormat::R16f,
+    PixelFormat::Rg16f,
+    PixelFormat::Rgba16f,
+    PixelFormat::R32f,
+    PixelFormat::Rg32f,
+    PixelFormat::Rgba32f,
+    PixelFormat::R10G10B10A2UNorm,
+    PixelFormat::R10G10B10A2UInt,
+    PixelFormat::R11G11B10F,
+];
+
+// storages and their pixel sizes in bytes
+const PIXEL_STORAGES: [(PixelStorage, usize); 17] = [
+    (PixelStorage::Byte1, 1),
+    (PixelStorage::Byte2, 2),
+    (PixelStorage::Byte4, 4),
+    (PixelStorage::Short1, 2),
+    (PixelStorage::Short2, 4),
+    (PixelStorage::Short4, 8),
+    (PixelStorage::Int1, 4),
+    (PixelStorage::Int2, 8),
+    (PixelStorage::Int4, 16),
+    (PixelStorage::Half1, 2),
+    (PixelStorage::Half2, 4),
+    (PixelStorage::Half4, 8),
+    (PixelStorage::Float1, 4),
+    (PixelStorage::Float2, 8),
+    (PixelStorage::Float4, 16),
+    (PixelStorage::R10G10B10A2, 4),
+    (PixelStorage::R11G11B10, 4),
+];
+
+const SAMPLER_FILTERS: [SamplerFilter; 4] = [
+    SamplerFilter::Point,
+    SamplerFilter::LinearPoint,
+    SamplerFilter::LinearLinear,
+    SamplerFilter::Anisotropic,
+];
+
+const SAMPLER_ADDRESSES: [SamplerAddress; 4] = [
+    SamplerAddress::Edge,
+    SamplerAddress::Repeat,
+    SamplerAddress::Mirror,
+    SamplerAddress::Zero,
+];
+
+const STREAM_TAGS: [api::StreamTag; 3] = [
+    api::StreamTag::Graphics,
+    api::StreamTag::Compute,
+    api::StreamTag::Copy,
+];
+
+fn index_of<T: PartialEq + std::fmt::Debug>(table: &[T], value: &T) -> Result<u32> {
+    table
+        .iter()
+        .position(|x| x == value)
+        .map(|i| i as u32)
+        .ok_or_else(|| Error::Unsupported(format!("{:?} cannot be captured", value)))
+}
+
+fn lookup<T: Copy>(table: &[T], index: u32, what: &str) -> Result<T> {
+    table
+        .get(index as usize)
+        .copied()
+        .ok_or_else(|| malformed(format!("{} #{}", what, index)))
+}
+
+fn malformed(what: impl std::fmt::Display) -> Error {
+    Error::InvalidArgument(format!("malformed capture: {}", what))
+}
+
+fn pixel_size(storage: u32) -> Result<usize> {
+    Ok(lookup(&PIXEL_STORAGES, storage, "pixel storage")?.1)
+}
+
+fn texel_bytes(storage: u32, size: [u32; 3]) -> Result<usize> {
+    Ok(size.iter().map(|x| *x as usize).product::<usize>() * pixel_size(storage)?)
+}
+
+// serde writes enums as `"Variant"` or `{"Variant":...}`
+fn variant_name<T: Serialize>(value: &T) -> String {
+    let json = serde_json::to_string(value).unwrap();
+    let name = json.trim_start_matches(['{', '"']);
+    name[..name.find('"').unwrap_or(name.len())].to_string()
+}
+
+#[derive(Serialize, Deserialize)]
+struct Header {
+    magic: String,
+    version: u32,
+    device: String,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct SamplerRecord {
+    filter: u32,
+    address: u32,
+}
+
+impl SamplerRecord {
+    fn new(sampler: &Sampler) -> Result<Self> {
+        Ok(Self {
+            filter: index_of(&SAMPLER_FILTERS, &sampler.filter)?,
+            address: index_of(&SAMPLER_ADDRESSES, &sampler.address)?,
+        })
+    }
+    fn load(&self) -> Result<Sampler> {
+        Ok(Sampler {
+            filter: lookup(&SAMPLER_FILTERS, self.filter, "sampler filter")?,
+            address: lookup(&SAMPLER_ADDRESSES, self.address, "sampler address")?,
+        })
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+enum SlotUpdate {
+    Keep,
+    Emplace {
+        handle: u64,
+        offset: usize,
+        sampler: Option<SamplerRecord>,
+    },
+    Remove,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct BindlessModification {
+    slot: usize,
+    buffer: SlotUpdate,
+    tex2d: SlotUpdate,
+    tex3d: SlotUpdate,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+enum ArgumentRecord {
+    Buffer {
+        buffer: u64,
+        offset: usize,
+        size: usize,
+    },
+    Texture {
+        texture: u64,
+        level: u32,
+    },
+    Uniform(Vec<u8>),
+    BindlessArray(u64),
+}
+
+/// A command of a dispatch, uploads carry their data in the payload of the record.
+#[derive(Clone, Serialize, Deserialize)]
+enum CommandRecord {
+    BufferUpload {
+        buffer: u64,
+        offset: usize,
+        size: usize,
+    },
+    BufferDownload {
+        buffer: u64,
+        offset: usize,
+        size: usize,
+    },
+    BufferCopy {
+        src: u64,
+        src_offset: usize,
+        dst: u64,
+        dst_offset: usize,
+        size: usize,
+    },
+    BufferToTextureCopy {
+        buffer: u64,
+        buffer_offset: usize,
+        texture: u64,
+        storage: u32,
+        level: u32,
+        size: [u32; 3],
+    },
+    TextureToBufferCopy {
+        texture: u64,
+        storage: u32,
+        level: u32,
+        size: [u32; 3],
+        buffer: u64,
+        buffer_offset: usize,
+    },
+    TextureUpload {
+        texture: u64,
+        storage: u32,
+        level: u32,
+        size: [u32; 3],
+    },
+    TextureDownload {
+        texture: u64,
+        storage: u32,
+        level: u32,
+        size: [u32; 3],
+    },
+    TextureCopy {
+        src: u64,
+        storage: u32,
+        src_level: u32,
+        dst: u64,
+        dst_level: u32,
+        size: [u32; 3],
+    },
+    BindlessArrayUpdate {
+        handle: u64,
+        modifications: Vec<BindlessModification>,
+    },
+    ShaderDispatch {
+        shader: u64,
+        args: Vec<ArgumentRecord>,
+        dispatch_size: [u32; 3],
+    },
+}
+
+impl CommandRecord {
+    /// Size of the payload the command carries.
+    fn payload_size(&self) -> Result<usize> {
+        Ok(match self {
+            Self::BufferUpload { size, .. } => *size,
+            Self::TextureUpload { storage, size, .. } => texel_bytes(*storage, *size)?,
+            _ => 0,
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct ShaderOptionRecord {
+    name: String,
+    enable_fast_math: bool,
+    enable_debug_info: bool,
+    max_registers: u32,
+}
+
+#[derive(Serialize, Deserialize)]
+enum Record {
+    CreateBuffer {
+        handle: u64,
+        ty: TypeRecord,
+        count: usize,
+        external: bool,
+    },
+    DestroyBuffer(u64),
+    CreateTexture {
+        handle: u64,
+        format: u32,
+        dimension: u32,
+        size: [u32; 3],
+        mipmap_levels: u32,
+        allow_simultaneous_access: bool,
+        allow_raster_target: bool,
+    },
+    DestroyTexture(u64),
+    CreateBindlessArray {
+        handle: u64,
+        size: usize,
+    },
+    DestroyBindlessArray(u64),
+    CreateStream {
+        handle: u64,
+        tag: u32,
+    },
+    DestroyStream(u64),
+    SynchronizeStream(u64),
+    Dispatch {
+        stream: u64,
+        commands: Vec<CommandRecord>,
+    },
+    CreateShader {
+        handle: u64,
+        // the reason the kernel could not be recorded
+        kernel: std::result::Result<KernelRecord, String>,
+        option: ShaderOptionRecord,
+    },
+    DestroyShader(u64),
+    CreateEvent(u64),
+    DestroyEvent(u64),
+    SignalEvent {
+        event: u64,
+        stream: u64,
+        value: u64,
+    },
+    WaitEvent {
+        event: u64,
+        stream: u64,
+        value: u64,
+    },
+    SynchronizeEvent {
+        event: u64,
+        value: u64,
+    },
+    /// A call that cannot be captured, the capture is incomplete from here on.
+    Unsupported(String),
+}
+
+struct CaptureWriter {
+    file: BufWriter<File>,
+    failed: bool,
+}
+
+impl CaptureWriter {
+    fn write(&mut self, record: &Record, payload: &[u8]) {
+        if self.failed {
+            return;
+        }
+        let json = serde_json::to_vec(record).unwrap();
+        let result = (|| {
+            self.file.write_all(&(json.len() as u64).to_le_bytes())?;
+            self.file.write_all(&json)?;
+            self.file.write_all(&(payload.len() as u64).to_le_bytes())?;
+            self.file.write_all(payload)?;
+            // the application may be about to crash, which is why it is captured
+            self.file.flush()
+        })();
+        if let Err(e) = result {
+            log::error!("failed to write API capture, capturing stopped: {}", e);
+            self.failed = true;
+        }
+    }
+}
+
+/// A [`Backend`] that forwards every call to another backend and records it.
+pub(crate) struct CaptureBackend {
+    inner: Box<dyn Backend>,
+    writer: Mutex<CaptureWriter>,
+}
+
+impl CaptureBackend {
+    pub(crate) fn new(inner: Box<dyn Backend>, device: &str, path: &Path) -> Result<Self> {
+        let file = File::create(path).map_err(|e| {
+            Error::InvalidArgument(format!("cannot create capture {}: {}", path.display(), e))
+        })?;
+        let mut file = BufWriter::new(file);
+        let header = Header {
+            magic: MAGIC.to_string(),
+            version: CAPTURE_FORMAT_VERSION,
+            device: device.to_string(),
+        };
+        serde_json::to_writer(&mut file, &header)
+            .map_err(|e| e.to_string())
+            .and_then(|_| file.write_all(b"\n").map_err(|e| e.to_string()))
+            .map_err(|e| {
+                Error::InvalidArgument(format!("cannot write capture {}: {}", path.display(), e))
+            })?;
+        Ok(Self {
+            inner,
+            writer: Mutex::new(CaptureWriter {
+                file,
+                failed: false,
+            }),
+        })
+    }
+    fn record(&self, record: Record) {
+        self.writer.lock().write(&record, &[]);
+    }
+    fn unsupported(&self, what: &str) {
+        self.record(Record::Unsupported(what.to_string()));
+    }
+
+    fn encode(command: &api::Command, payload: &mut Vec<u8>) -> Result<CommandRecord> {
+        let storage = |s: &PixelStorage| index_of(&PIXEL_STORAGES.map(|x| x.0), s);
+        Ok(match command {
+            api::Command::BufferUpload(c) => {
+                payload.extend_from_slice(unsafe { std::slice::from_raw_parts(c.data, c.size) });
+                CommandRecord::BufferUpload {
+                    buffer: c.buffer.0,
+                    offset: c.offset,
+                    size: c.size,
+                }
+            }
+            api::Command::BufferDownload(c) => CommandRecord::BufferDownload {
+                buffer: c.buffer.0,
+                offset: c.offset,
+                size: c.size,
+            },
+            api::Command::BufferCopy(c) => CommandRecord::BufferCopy {
+                src: c.src.0,
+                src_offset: c.src_offset,
+                dst: c.dst.0,
+                dst_offset: c.dst_offset,
+                size: c.size,
+            },
+            api::Command::BufferToTextureCopy(c) => CommandRecord::BufferToTextureCopy {
+                buffer: c.buffer.0,
+                buffer_offset: c.buffer_offset,
+                texture: c.texture.0,
+                storage: storage(&c.storage)?,
+                level: c.texture_level,
+                size: c.texture_size,
+            },
+            api::Command::TextureToBufferCopy(c) => CommandRecord::TextureToBufferCopy {
+                texture: c.texture.0,
+                storage: storage(&c.storage)?,
+                level: c.texture_level,
+                size: c.texture_size,
+                buffer: c.buffer.0,
+                buffer_offset: c.buffer_offset,
+            },
+            api::Command::TextureUpload(c) => {
+                let storage = storage(&c.storage)?;
+                let bytes = texel_bytes(storage, c.size)?;
+                payload.extend_from_slice(unsafe { std::slice::from_raw_parts(c.data, bytes) });
+                CommandRecord::TextureUpload {
+                    texture: c.texture.0,
+                    storage,
+                    level: c.level,
+                    size: c.size,
+                }
+            }
+            api::Command::TextureDownload(c) => CommandRecord::TextureDownload {
+                texture: c.texture.0,
+                storage: storage(&c.storage)?,
+                level: c.level,
+                size: c.size,
+            },
+            api::Command::TextureCopy(c) => CommandRecord::TextureCopy {
+                src: c.src.0,
+                storage: storage(&c.storage)?,
+                src_level: c.src_level,
+                dst: c.dst.0,
+                dst_level: c.dst_level,
+                size: c.size,
+            },
+            api::Command::BindlessArrayUpdate(c) => {
+                let modifications =
+                    unsafe { std::slice::from_raw_parts(c.modifications, c.modifications_count) };
+                let texture = |t: &api::BindlessArrayUpdateTexture| -> Result<SlotUpdate> {
+                    Ok(match t.op {
+                        api::BindlessArrayUpdateOperation::Emplace => SlotUpdate::Emplace {
+                            handle: t.handle.0,
+                            offset: 0,
+                            sampler: Some(SamplerRecord::new(&t.sampler)?),
+                        },
+                        api::BindlessArrayUpdateOperation::Remove => SlotUpdate::Remove,
+                        _ => SlotUpdate::Keep,
+                    })
+                };
+                CommandRecord::BindlessArrayUpdate {
+                    handle: c.handle.0,
+                    modifications: modifications
+                        .iter()
+                        .map(|m| {
+                            Ok(BindlessModification {
+                                slot: m.slot,
+                                buffer: match m.buffer.op {
+                                    api::BindlessArrayUpdateOperation::Emplace => {
+                                        SlotUpdate::Emplace {
+                                            handle: m.buffer.handle.0,
+                                            offset: m.buffer.offset,
+                                            sampler: None,
+                                        }
+                                    }
+                                    api::BindlessArrayUpdateOperation::Remove => SlotUpdate::Remove,
+                                    _ => SlotUpdate::Keep,
+                                },
+                                tex2d: texture(&m.tex2d)?,
+                                tex3d: texture(&m.tex3d)?,
+                            })
+                        })
+                        .collect::<Result<_>>()?,
+                }
+            }
+            api::Command::ShaderDispatch(c) => {
+                let args = unsafe { std::slice::from_raw_parts(c.args, c.args_count) };
+                CommandRecord::ShaderDispatch {
+                    shader: c.shader.0,
+                    args: args
+                        .iter()
+                        .map(|arg| {
+                            Ok(match arg {
+                                api::Argument::Buffer(b) => ArgumentRecord::Buffer {
+                                    buffer: b.buffer.0,
+                                    offset: b.offset,
+                                    size: b.size,
+                                },
+                                api::Argument::Texture(t) => ArgumentRecord::Texture {
+                                    texture: t.texture.0,
+                                    level: t.level,
+                                },
+                                api::Argument::Uniform(u) => ArgumentRecord::Uniform(
+                                    unsafe { std::slice::from_raw_parts(u.data, u.size) }.to_vec(),
+                                ),
+                                api::Argument::BindlessArray(a) => {
+                                    ArgumentRecord::BindlessArray(a.0)
+                                }
+                                api::Argument::Accel(_) => {
+                                    return Err(Error::Unsupported(
+                                        "accel arguments cannot be captured".to_string(),
+                                    ))
+                                }
+                            })
+                        })
+                        .collect::<Result<_>>()?,
+                    dispatch_size: c.dispatch_size,
+                }
+            }
+            api::Command::MeshBuild(_) => {
+                return Err(Error::Unsupported(
+                    "mesh builds cannot be captured".to_string(),
+                ))
+            }
+            api::Command::ProceduralPrimitiveBuild(_) => {
+                return Err(Error::Unsupported(
+                    "procedural primitive builds cannot be captured".to_string(),
+                ))
+            }
+            api::Command::AccelBuild(_) => {
+                return Err(Error::Unsupported(
+                    "accel builds cannot be captured".to_string(),
+                ))
+            }
+            #[allow(unreachable_patterns)]
+            _ => {
+                return Err(Error::Unsupported(
+                    "command is not known to the capture format".to_string(),
+                ))
+            }
+        })
+    }
+}
+
+impl Backend for CaptureBackend {
+    fn native_handle(&self) -> *mut c_void {
+        self.inner.native_handle()
+    }
+    fn compute_warp_size(&self) -> u32 {
+        self.inner.compute_warp_size()
+    }
+    fn create_buffer(
+        &self,
+        ty: &CArc<Type>,
+        count: usize,
+        ext_mem: *mut c_void,
+    ) -> api::CreatedBufferInfo {
+        let info = self.inner.create_buffer(ty, count, ext_mem);
+        if !is_valid_memory(&info.resource) {
+            return info;
+        }
+        match TypeRecord::new(ty) {
+            Ok(ty) => {
+                let record = Record::CreateBuffer {
+                    handle: info.resource.handle,
+                    ty,
+                    count,
+                    external: !ext_mem.is_null(),
+                };
+                // imported memory is initialized by the host
+                let contents = if ext_mem.is_null() {
+                    &[][..]
+                } else {
+                    unsafe {
+                        std::slice::from_raw_parts(ext_mem as *const u8, info.total_size_bytes)
+                    }
+                };
+                self.writer.lock().write(&record, contents);
+            }
+            Err(e) => self.unsupported(&format!("buffer: {}", e)),
+        }
+        info
+    }
+    fn destroy_buffer(&self, buffer: api::Buffer) {
+        self.record(Record::DestroyBuffer(buffer.0));
+        self.inner.destroy_buffer(buffer)
+    }
+    fn create_texture(
+        &self,
+        format: api::PixelFormat,
+        dimension: u32,
+        width: u32,
+        height: u32,
+        depth: u32,
+        mipmap_levels: u32,
+        allow_simultaneous_access: bool,
+        allow_raster_target: bool,
+    ) -> api::CreatedResourceInfo {
+        let info = self.inner.create_texture(
+            format,
+            dimension,
+            width,
+            height,
+            depth,
+            mipmap_levels,
+            allow_simultaneous_access,
+            allow_raster_target,
+        );
+        if !is_valid_memory(&info) {
+            return info;
+        }
+        match index_of(&PIXEL_FORMATS, &format) {
+            Ok(format) => self.record(Record::CreateTexture {
+                handle: info.handle,
+                format,
+                dimension,
+                size: [width, height, depth],
+                mipmap_levels,
+                allow_simultaneous_access,
+                allow_raster_target,
+            }),
+            Err(e) => self.unsupported(&format!("texture: {}", e)),
+        }
+        info
+    }
+    fn destroy_texture(&self, texture: api::Texture) {
+        self.record(Record::DestroyTexture(texture.0));
+        self.inner.destroy_texture(texture)
+    }
+    fn create_bindless_array(&self, size: usize) -> api::CreatedResourceInfo {
+        let info = self.inner.create_bindless_array(size);
+        if is_valid_handle(info.handle) {
+            self.record(Record::CreateBindlessArray {
+                handle: info.handle,
+                size,
+            });
+        }
+        info
+    }
+    fn destroy_bindless_array(&self, array: api::BindlessArray) {
+        self.record(Record::DestroyBindlessArray(array.0));
+        self.inner.destroy_bindless_array(array)
+    }
+    fn create_stream(&self, tag: api::StreamTag) -> api::CreatedResourceInfo {
+        let info = self.inner.create_stream(tag);
+        self.record(Record::CreateStream {
+            handle: info.handle,
+            tag: index_of(&STREAM_TAGS, &tag).unwrap_or(0),
+        });
+        info
+    }
+    fn destroy_stream(&self, stream: api::Stream) {
+        self.record(Record::DestroyStream(stream.0));
+        self.inner.destroy_stream(stream)
+    }
+    fn synchronize_stream(&self, stream: api::Stream) {
+        self.record(Record::SynchronizeStream(stream.0));
+        self.inner.synchronize_stream(stream)
+    }
+    fn dispatch(
+        &self,
+        stream: api::Stream,
+        command_list: &[api::Command],
+        callback: (extern "C" fn(*mut u8), *mut u8),
+    ) {
+        // recorded before the commands run, uploads may be freed once they complete
+        let mut payload = vec![];
+        let commands = command_list
+            .iter()
+            .map(|c| Self::encode(c, &mut payload))
+            .collect::<Result<Vec<_>>>();
+        match commands {
+            Ok(commands) => self.writer.lock().write(
+                &Record::Dispatch {
+                    stream: stream.0,
+                    commands,
+                },
+                &payload,
+            ),
+            Err(e) => self.unsupported(&e.to_string()),
+        }
+        self.inner.dispatch(stream, command_list, callback)
+    }
+    fn create_swapchain(
+        &self,
+        option: &api::SwapchainOption,
+        stream: api::Stream,
+    ) -> api::CreatedSwapchainInfo {
+        self.unsupported("swapchain");
+        self.inner.create_swapchain(option, stream)
+    }
+    fn destroy_swapchain(&self, swap_chain: api::Swapchain) {
+        self.inner.destroy_swapchain(swap_chain)
+    }
+    fn present_display_in_stream(
+        &self,
+        stream: api::Stream,
+        swapchain: api::Swapchain,
+        image: api::Texture,
+    ) {
+        self.inner
+            .present_display_in_stream(stream, swapchain, image)
+    }
+    fn create_shader(
+        &self,
+        kernel: &CArc<KernelModule>,
+        option: &api::ShaderOption,
+    ) -> api::CreatedShaderInfo {
+        let info = self.inner.create_shader(kernel, option);
+        if is_valid_handle(info.resource.handle) {
+            let name = if option.name.is_null() {
+                String::new()
+            } else {
+                unsafe { std::ffi::CStr::from_ptr(option.name) }
+                    .to_string_lossy()
+                    .into_owned()
+            };
+            self.record(Record::CreateShader {
+                handle: info.resource.handle,
+                kernel: KernelRecord::new(kernel).map_err(|e| e.to_string()),
+                option: ShaderOptionRecord {
+                    name,
+                    enable_fast_math: option.enable_fast_math,
+                    enable_debug_info: option.enable_debug_info,
+                    max_registers: option.max_registers,
+                },
+            });
+        }
+        info
+    }
+    fn shader_cache_dir(&self, shader: api::Shader) -> Option<PathBuf> {
+        self.inner.shader_cache_dir(shader)
+    }
+    fn destroy_shader(&self, shader: api::Shader) {
+        self.record(Record::DestroyShader(shader.0));
+        self.inner.destroy_shader(shader)
+    }
+    fn create_event(&self) -> api::CreatedResourceInfo {
+        let info = self.inner.create_event();
+        self.record(Record::CreateEvent(info.handle));
+        info
+    }
+    fn destroy_event(&self, event: api::Event) {
+        self.record(Record::DestroyEvent(event.0));
+        self.inner.destroy_event(event)
+    }
+    fn signal_event(&self, event: api::Event, stream: api::Stream, value: u64) {
+        self.record(Record::SignalEvent {
+            event: event.0,
+            stream: stream.0,
+            value,
+        });
+        self.inner.signal_event(event, stream, value)
+    }
+    fn wait_event(&self, event: api::Event, stream: api::Stream, value: u64) {
+        self.record(Record::WaitEvent {
+            event: event.0,
+            stream: stream.0,
+            value,
+        });
+        self.inner.wait_event(event, stream, value)
+    }
+    fn synchronize_event(&self, event: api::Event, value: u64) {
+        self.record(Record::SynchronizeEvent {
+            event: event.0,
+            value,
+        });
+        self.inner.synchronize_event(event, value)
+    }
+    fn is_event_completed(&self, event: api::Event, value: u64) -> bool {
+        self.inner.is_event_completed(event, value)
+    }
+    fn create_mesh(&self, option: api::AccelOption) -> api::CreatedResourceInfo {
+        self.unsupported("mesh");
+        self.inner.create_mesh(option)
+    }
+    fn create_procedural_primitive(&self, option: api::AccelOption) -> api::CreatedResourceInfo {
+        self.unsupported("procedural primitive");
+        self.inner.create_procedural_primitive(option)
+    }
+    fn destroy_mesh(&self, mesh: api::Mesh) {
+        self.inner.destroy_mesh(mesh)
+    }
+    fn destroy_procedural_primitive(&self, primitive: api::ProceduralPrimitive) {
+        self.inner.destroy_procedural_primitive(primitive)
+    }
+    fn create_accel(&self, option: api::AccelOption) -> api::CreatedResourceInfo {
+        self.unsupported("accel");
+        self.inner.create_accel(option)
+    }
+    fn destroy_accel(&self, accel: api::Accel) {
+        self.inner.destroy_accel(accel)
+    }
+    fn query(&self, property: &str) -> Option<String> {
+        self.inner.query(property)
+    }
+    fn denoiser_ext(&self) -> api::DenoiserExt {
+        self.inner.denoiser_ext()
+    }
+}
+
+/// A step of a capture, dispatches are split into their commands.
+enum Step {
+    Record(Record),
+    Command(CommandRecord, Vec<u8>),
+}
+
+/// A capture file written by a capturing device, see the [module docs](self).
+pub struct CaptureFile {
+    device: String,
+    steps: Vec<Step>,
+}
+
+impl CaptureFile {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let data = std::fs::read(path).map_err(|e| {
+            Error::InvalidArgument(format!("cannot read capture {}: {}", path.display(), e))
+        })?;
+        Self::from_bytes(&data)
+    }
+    /// Parses a capture, a file that was cut off by a crash is read up to its last
+    /// complete entry.
+    pub fn from_bytes(data: &[u8]) -> Result<Self> {
+        let header_end = data
+            .iter()
+            .position(|b| *b == b'\n')
+            .ok_or_else(|| malformed("missing header"))?;
+        let header: Header = serde_json::from_slice(&data[..header_end]).map_err(malformed)?;
+        if header.magic != MAGIC {
+            return Err(malformed("not a capture file"));
+        }
+        if header.version != CAPTURE_FORMAT_VERSION {
+            return Err(Error::InvalidArgument(format!(
+                "unsupported capture format version {}, expected {}",
+                header.version, CAPTURE_FORMAT_VERSION
+            )));
+        }
+        let mut rest = &data[header_end + 1..];
+        let mut chunk = || -> Option<&[u8]> {
+            let len = u64::from_le_bytes(rest.get(..8)?.try_into().unwrap()) as usize;
+            let chunk = rest.get(8..8usize.checked_add(len)?)?;
+            rest = &rest[8 + len..];
+            Some(chunk)
+        };
+        let mut steps = vec![];
+        while let (Some(json), Some(payload)) = (chunk(), chunk()) {
+            let record: Record = serde_json::from_slice(json).map_err(malformed)?;
+            match record {
+                Record::Dispatch { commands, .. } => {
+                    let mut payload = payload;
+                    for command in commands {
+                        let size = command.payload_size()?;
+                        if size > payload.len() {
+                            return Err(malformed("upload payload"));
+                        }
+                        let (data, remaining) = payload.split_at(size);
+                        payload = remaining;
+                        steps.push(Step::Command(command, data.to_vec()));
+                    }
+                }
+                Record::CreateBuffer {
+                    handle,
+                    external: true,
+                    ..
+                } => {
+                    // imported contents are replayed as an upload
+                    steps.push(Step::Record(record));
+                    steps.push(Step::Command(
+                        CommandRecord::BufferUpload {
+                            buffer: handle,
+                            offset: 0,
+                            size: payload.len(),
+                        },
+                        payload.to_vec(),
+                    ));
+                }
+                record => steps.push(Step::Record(record)),
+            }
+        }
+        Ok(Self {
+            device: header.device,
+            steps,
+        })
+    }
+    /// Name of the device the capture was taken on.
+    #[inline]
+    pub fn device_name(&self) -> &str {
+        &self.device
+    }
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.steps.len()
+    }
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+    /// A one-line description of the `index`-th step.
+    pub fn describe(&self, index: usize) -> String {
+        match &self.steps[index] {
+            Step::Record(Record::CreateBuffer { handle, count, .. }) => {
+                format!("create buffer #{} ({} elements)", handle, count)
+            }
+            Step::Record(Record::CreateTexture { handle, size, .. }) => {
+                format!("create texture #{} ({:?})", handle, size)
+            }
+            Step::Record(Record::CreateShader { handle, option, .. }) => {
+                format!("create shader #{} `{}`", handle, option.name)
+            }
+            Step::Record(Record::Unsupported(what)) => format!("unsupported: {}", what),
+            Step::Record(record) => variant_name(record),
+            Step::Command(
+                CommandRecord::ShaderDispatch {
+                    shader,
+                    dispatch_size,
+                    ..
+                },
+                _,
+            ) => format!("dispatch shader #{} {:?}", shader, dispatch_size),
+            Step::Command(command, _) => variant_name(command),
+        }
+    }
+    /// Starts replaying the capture on `device`.
+    pub fn replay<'a>(&'a self, device: &Device) -> Replayer<'a> {
+        let stream = device.inner.create_stream(api::StreamTag::Compute);
+        Replayer {
+            capture: self,
+            device: device.internal(),
+            stream: api::Stream(stream.handle),
+            handles: HashMap::new(),
+            buffers: BTreeMap::new(),
+            textures: HashSet::new(),
+            bindless_arrays: HashSet::new(),
+            shaders: HashSet::new(),
+            next: 0,
+        }
+    }
+}
+
+/// Executes a [`CaptureFile`] step by step, see [`CaptureFile::replay`].
+///
+/// Resources still alive at the end of the capture are destroyed when the
+/// replayer is dropped.
+pub struct Replayer<'a> {
+    capture: &'a CaptureFile,
+    device: Device,
+    stream: api::Stream,
+    // captured handle to replayed handle
+    handles: HashMap<u64, u64>,
+    // captured handle to size in bytes
+    buffers: BTreeMap<u64, usize>,
+    textures: HashSet<u64>,
+    bindless_arrays: HashSet<u64>,
+    shaders: HashSet<u64>,
+    next: usize,
+}
+
+extern "C" fn replay_callback(_: *mut u8) {}
+
+impl<'a> Replayer<'a> {
+    /// Index of the next step.
+    #[inline]
+    pub fn position(&self) -> usize {
+        self.next
+    }
+    #[inline]
+    pub fn is_finished(&self) -> bool {
+        self.next == self.capture.len()
+    }
+    /// Runs all remaining steps.
+    pub fn run(&mut self) -> Result<()> {
+        while !self.is_finished() {
+            self.step()?;
+        }
+        Ok(())
+    }
+    /// Captured handles of the buffers alive after the last step.
+    pub fn buffers(&self) -> Vec<u64> {
+        self.buffers.keys().copied().collect()
+    }
+    /// Downloads the contents of a buffer, `handle` is the handle in the capture.
+    pub fn read_buffer(&self, handle: u64) -> Result<Vec<u8>> {
+        let size = *self.buffers.get(&handle).ok_or_else(|| {
+            Error::InvalidArgument(format!("buffer #{} is not alive in the replay", handle))
+        })?;
+        let mut data = vec![0u8; size];
+        self.submit(&[api::Command::BufferDownload(api::BufferDownloadCommand {
+            buffer: api::Buffer(self.handles[&handle]),
+            offset: 0,
+            size,
+            data: data.as_mut_ptr(),
+        })]);
+        Ok(data)
+    }
+
+    fn submit(&self, commands: &[api::Command]) {
+        let backend = &self.device.inner;
+        backend.dispatch(
+            self.stream,
+            commands,
+            (replay_callback, std::ptr::null_mut()),
+        );
+        backend.synchronize_stream(self.stream);
+    }
+
+    fn handle(&self, captured: u64) -> Result<u64> {
+        self.handles
+            .get(&captured)
+            .copied()
+            .ok_or_else(|| malformed(format!("use of unknown resource #{}", captured)))
+    }
+
+    fn created(
+        &mut self,
+        captured: u64,
+        info: api::CreatedResourceInfo,
+        what: &'static str,
+    ) -> Result<()> {
+        if !is_valid_handle(info.handle) {
+            return Err(Error::OutOfMemory {
+                resource: what,
+                requested_bytes: None,
+            });
+        }
+        self.handles.insert(captured, info.handle);
+        Ok(())
+    }
+
+    /// Executes the next step, commands are waited for before returning.
+    pub fn step(&mut self) -> Result<()> {
+        let index = self.next;
+        let capture = self.capture;
+        let step = capture
+            .steps
+            .get(index)
+            .ok_or_else(|| Error::InvalidArgument("replay already finished".to_string()))?;
+        let backend = self.device.inner.clone();
+        match step {
+            Step::Record(record) => match record {
+                Record::CreateBuffer {
+                    handle, ty, count, ..
+                } => {
+                    let ty = ty.load()?;
+                    let info = backend.create_buffer(&ty, *count, std::ptr::null_mut());
+                    self.created(*handle, info.resource, "buffer")?;
+                    self.buffers.insert(*handle, info.total_size_bytes);
+                }
+                Record::DestroyBuffer(handle) => {
+                    backend.destroy_buffer(api::Buffer(self.handle(*handle)?));
+                    self.handles.remove(handle);
+                    self.buffers.remove(handle);
+                }
+                Record::CreateTexture {
+                    handle,
+                    format,
+                    dimension,
+                    size,
+                    mipmap_levels,
+                    allow_simultaneous_access,
+                    allow_raster_target,
+                } => {
+                    let info = backend.create_texture(
+                        lookup(&PIXEL_FORMATS, *format, "pixel format")?,
+                        *dimension,
+                        size[0],
+                        size[1],
+                        size[2],
+                        *mipmap_levels,
+                        *allow_simultaneous_access,
+                        *allow_raster_target,
+                    );
+                    self.created(*handle, info, "texture")?;
+                    self.textures.insert(*handle);
+                }
+                Record::DestroyTexture(handle) => {
+                    backend.destroy_texture(api::Texture(self.handle(*handle)?));
+                    self.handles.remove(handle);
+                    self.textures.remove(handle);
+                }
+                Record::CreateBindlessArray { handle, size } => {
+                    let info = backend.create_bindless_array(*size);
+                    self.created(*handle, info, "bindless array")?;
+                    self.bindless_arrays.insert(*handle);
+                }
+                Record::DestroyBindlessArray(handle) => {
+                    backend.destroy_bindless_array(api::BindlessArray(self.handle(*handle)?));
+                    self.handles.remove(handle);
+                    self.bindless_arrays.remove(handle);
+                }
+                Record::CreateShader {
+                    handle,
+                    kernel,
+                    option,
+                } => {
+                    let kernel = kernel.as_ref().map_err(|e| {
+                        Error::Unsupported(format!(
+                            "shader `{}` was not captured: {}",
+                            option.name, e
+                        ))
+                    })?;
+                    let kernel = kernel.load(&|h| self.handle(h))?;
+                    let name = CString::new(option.name.clone()).unwrap_or_default();
+                    let native_include = CString::default();
+                    let shader_option = api::ShaderOption {
+                        enable_cache: false,
+                        enable_fast_math: option.enable_fast_math,
+                        enable_debug_info: option.enable_debug_info,
+                        time_trace: false,
+                        max_registers: option.max_registers,
+                        compile_only: false,
+                        name: name.as_ptr(),
+                        native_include: native_include.as_ptr(),
+                    };
+                    let info = backend.create_shader(&CArc::new(kernel), &shader_option);
+                    if !is_valid_handle(info.resource.handle) {
+                        return Err(Error::CompileFailure {
+                            name: option.name.clone(),
+                        });
+                    }
+                    self.handles.insert(*handle, info.resource.handle);
+                    self.shaders.insert(*handle);
+                }
+                Record::DestroyShader(handle) => {
+                    backend.destroy_shader(api::Shader(self.handle(*handle)?));
+                    self.handles.remove(handle);
+                    self.shaders.remove(handle);
+                }
+                Record::Unsupported(what) => {
+                    return Err(Error::Unsupported(format!(
+                        "{} cannot be replayed, the capture is incomplete",
+                        what
+                    )))
+                }
+                // replays run on a single stream and wait for every command
+                Record::CreateStream { tag, .. } => {
+                    lookup(&STREAM_TAGS, *tag, "stream tag")?;
+                }
+                Record::Dispatch { .. }
+                | Record::DestroyStream(_)
+                | Record::SynchronizeStream(_)
+                | Record::CreateEvent(_)
+                | Record::DestroyEvent(_)
+                | Record::SignalEvent { .. }
+                | Record::WaitEvent { .. }
+                | Record::SynchronizeEvent { .. } => {}
+            },
+            Step::Command(command, payload) => self.execute(command, payload)?,
+        }
+        self.next += 1;
+        Ok(())
+    }
+
+    fn execute(&self, command: &CommandRecord, payload: &[u8]) -> Result<()> {
+        let buffer = |h: u64| self.handle(h).map(api::Buffer);
+        let texture = |h: u64| self.handle(h).map(api::Texture);
+        let storage = |s: u32| lookup(&PIXEL_STORAGES, s, "pixel storage").map(|x| x.0);
+        // downloads are replayed into scratch memory
+        let mut scratch = vec![];
+        let mut args = vec![];
+        let mut modifications = vec![];
+        let command = match command {
+            CommandRecord::BufferUpload {
+                buffer: b,
+                offset,
+                size,
+            } => api::Command::BufferUpload(api::BufferUploadCommand {
+                buffer: buffer(*b)?,
+                offset: *offset,
+                size: *size,
+                data: payload.as_ptr(),
+            }),
+            CommandRecord::BufferDownload {
+                buffer: b,
+                offset,
+                size,
+            } => {
+                scratch.resize(*size, 0u8);
+                api::Command::BufferDownload(api::BufferDownloadCommand {
+                    buffer: buffer(*b)?,
+                    offset: *offset,
+                    size: *size,
+                    data: scratch.as_mut_ptr(),
+                })
+            }
+            CommandRecord::BufferCopy {
+                src,
+                src_offset,
+                dst,
+                dst_offset,
+                size,
+            } => api::Command::BufferCopy(api::BufferCopyCommand {
+                src: buffer(*src)?,
+                src_offset: *src_offset,
+                dst: buffer(*dst)?,
+                dst_offset: *dst_offset,
+                size: *size,
+            }),
+            CommandRecord::BufferToTextureCopy {
+                buffer: b,
+                buffer_offset,
+                texture: t,
+                storage: s,
+                level,
+                size,
+            } => api::Command::BufferToTextureCopy(api::BufferToTextureCopyCommand {
+                buffer: buffer(*b)?,
+                buffer_offset: *buffer_offset,
+                texture: texture(*t)?,
+                storage: storage(*s)?,
+                texture_level: *level,
+                texture_size: *size,
+            }),
+            CommandRecord::TextureToBufferCopy {
+                texture: t,
+                storage: s,
+                level,
+                size,
+                buffer: b,
+                buffer_offset,
+            } => api::Command::TextureToBufferCopy(api::TextureToBufferCopyCommand {
+                texture: texture(*t)?,
+                storage: storage(*s)?,
+                texture_level: *level,
+                texture_size: *size,
+                buffer: buffer(*b)?,
+                buffer_offset: *buffer_offset,
+            }),
+            CommandRecord::TextureUpload {
+                texture: t,
+                storage: s,
+                level,
+                size,
+            } => api::Command::TextureUpload(api::TextureUploadCommand {
+                texture: texture(*t)?,
+                storage: storage(*s)?,
+                level: *level,
+                size: *size,
+                data: payload.as_ptr(),
+            }),
+            CommandRecord::TextureDownload {
+                texture: t,
+                storage: s,
+                level,
+                size,
+            } => {
+                scratch.resize(texel_bytes(*s, *size)?, 0u8);
+                api::Command::TextureDownload(api::TextureDownloadCommand {
+                    texture: texture(*t)?,
+                    storage: storage(*s)?,
+                    level: *level,
+                    size: *size,
+                    data: scratch.as_mut_ptr(),
+                })
+            }
+            CommandRecord::TextureCopy {
+                src,
+                storage: s,
+                src_level,
+                dst,
+                dst_level,
+                size,
+            } => api::Command::TextureCopy(api::TextureCopyCommand {
+                src: texture(*src)?,
+                storage: storage(*s)?,
+                src_level: *src_level,
+                size: *size,
+                dst: texture(*dst)?,
+                dst_level: *dst_level,
+            }),
+            CommandRecord::BindlessArrayUpdate {
+                handle,
+                modifications: m,
+            } => {
+                let op = |u: &SlotUpdate| match u {
+                    SlotUpdate::Keep => api::BindlessArrayUpdateOperation::None,
+                    SlotUpdate::Emplace { .. } => api::BindlessArrayUpdateOperation::Emplace,
+                    SlotUpdate::Remove => api::BindlessArrayUpdateOperation::Remove,
+                };
+                let target = |u: &SlotUpdate| -> Result<(u64, usize, Sampler)> {
+                    match u {
+                        SlotUpdate::Emplace {
+                            handle,
+                            offset,
+                            sampler,
+                        } => Ok((
+                            self.handle(*handle)?,
+                            *offset,
+                            match sampler {
+                                Some(s) => s.load()?,
+                                None => Sampler::default(),
+                            },
+                        )),
+                        _ => Ok((0, 0, Sampler::default())),
+                    }
+                };
+                for m in m {
+                    let (buffer_handle, offset, _) = target(&m.buffer)?;
+                    let (tex2d, _, tex2d_sampler) = target(&m.tex2d)?;
+                    let (tex3d, _, tex3d_sampler) = target(&m.tex3d)?;
+                    modifications.push(api::BindlessArrayUpdateModification {
+                        slot: m.slot,
+                        buffer: api::BindlessArrayUpdateBuffer {
+                            op: op(&m.buffer),
+                            handle: api::Buffer(buffer_handle),
+                            offset,
+                        },
+                        tex2d: api::BindlessArrayUpdateTexture {
+                            op: op(&m.tex2d),
+                            handle: api::Texture(tex2d),
+                            sampler: tex2d_sampler,
+                        },
+                        tex3d: api::BindlessArrayUpdateTexture {
+                            op: op(&m.tex3d),
+                            handle: api::Texture(tex3d),
+                            sampler: tex3d_sampler,
+                        },
+                    });
+                }
+                api::Command::BindlessArrayUpdate(api::BindlessArrayUpdateCommand {
+                    handle: api::BindlessArray(self.handle(*handle)?),
+                    modifications: modifications.as_ptr(),
+                    modifications_count: modifications.len(),
+                })
+            }
+            CommandRecord::ShaderDispatch {
+                shader,
+                args: a,
+                dispatch_size,
+            } => {
+                for arg in a {
+                    args.push(match arg {
+                        ArgumentRecord::Buffer {
+                            buffer: b,
+                            offset,
+                            size,
+                        } => api::Argument::Buffer(api::BufferArgument {
+                            buffer: buffer(*b)?,
+                            offset: *offset,
+                            size: *size,
+                        }),
+                        ArgumentRecord::Texture { texture: t, level } => {
+                            api::Argument::Texture(api::TextureArgument {
+                                texture: texture(*t)?,
+                                level: *level,
+                            })
+                        }
+                        ArgumentRecord::Uniform(data) => {
+                            api::Argument::Uniform(api::UniformArgument {
+                                data: data.as_ptr(),
+                                size: data.len(),
+                            })
+                        }
+                        ArgumentRecord::BindlessArray(a) => {
+                            api::Argument::BindlessArray(api::BindlessArray(self.handle(*a)?))
+                        }
+                    });
+                }
+                api::Command::ShaderDispatch(api::ShaderDispatchCommand {
+                    shader: api::Shader(self.handle(*shader)?),
+                    args: args.as_ptr(),
+                    args_count: args.len(),
+                    dispatch_size: *dispatch_size,
+                })
+            }
+        };
+        self.submit(&[command]);
+        Ok(())
+    }
+}
+
+impl<'a> Drop for Replayer<'a> {
+    fn drop(&mut self) {
+        let backend = &self.device.inner;
+        backend.synchronize_stream(self.stream);
+        for handle in &self.shaders {
+            backend.destroy_shader(api::Shader(self.handles[handle]));
+        }
+        for handle in &self.bindless_arrays {
+            backend.destroy_bindless_array(api::BindlessArray(self.handles[handle]));
+        }
+        for handle in &self.textures {
+            backend.destroy_texture(api::Texture(self.handles[handle]));
+        }
+        for handle in self.buffers.keys() {
+            backend.destroy_buffer(api::Buffer(self.handles[handle]));
+        }
+        backend.destroy_stream(self.stream);
+    }
+}
@@ -0,0 +1,150 @@
+//! Futures completing when the device has finished some work.
+//!
+//! [`Stream::submit_async`], [`Event::wait_async`] and
+//! [`BufferView::copy_to_vec_future`] return a [`GpuFuture`] which is completed
+//! by the backend callback of the submitted commands, so awaiting it does not
+//! block a thread. The futures work with any executor.
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context as TaskContext, Poll, Waker};
+
+use super::*;
+
+struct Completion<T> {
+    value: Option<T>,
+    waker: Option<Waker>,
+}
+
+/// A future completed by the device, see the [module documentation](self).
+#[must_use = "futures do nothing unless awaited"]
+pub struct GpuFuture<T> {
+    completion: Arc<Mutex<Completion<T>>>,
+}
+
+pub(crate) struct GpuFutureCompleter<T> {
+    completion: Arc<Mutex<Completion<T>>>,
+}
+
+pub(crate) fn gpu_future<T>() -> (GpuFuture<T>, GpuFutureCompleter<T>) {
+    let completion = Arc::new(Mutex::new(Completion {
+        value: None,
+        waker: None,
+    }));
+    (
+        GpuFuture {
+            completion: completion.clone(),
+        },
+        GpuFutureCompleter { completion },
+    )
+}
+
+impl<T> GpuFutureCompleter<T> {
+    pub(crate) fn complete(self, value: T) {
+        let waker = {
+            let mut completion = self.completion.lock();
+            completion.value = Some(value);
+            completion.waker.take()
+        };
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+impl<T> GpuFuture<T> {
+    pub(crate) fn ready(value: T) -> Self {
+        let (future, completer) = gpu_future();
+        completer.complete(value);
+        future
+    }
+    /// Whether the work has finished, i.e. polling would return [`Poll::Ready`].
+    pub fn is_ready(&self) -> bool {
+        self.completion.lock().value.is_some()
+    }
+}
+
+impl<T> Future for GpuFuture<T> {
+    type Output = T;
+    fn poll(self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<T> {
+        let mut completion = self.completion.lock();
+        match completion.value.take() {
+            Some(value) => Poll::Ready(value),
+            None => {
+                completion.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl Stream {
+    /// Submits `commands` without waiting for them, the returned future
+    /// completes once they and the work submitted before have finished.
+    pub fn submit_async(
+        &self,
+        commands: impl IntoIterator<Item = Command<'static, 'static>>,
+    ) -> GpuFuture<()> {
+        let (future, completer) = gpu_future();
+        let scope = self.scope();
+        scope.submit_with_callback(commands, move || completer.complete(()));
+        scope.detach();
+        future
+    }
+}
+
+impl Event {
+    /// Returns a future completing once the event is signaled with `ticket`
+    /// by [`Scope::signal`]. The future never completes if the event is
+    /// dropped before that.
+    pub fn wait_async(&self, ticket: u64) -> GpuFuture<()> {
+        // checked under the lock, the signal completes the waiters under it
+        let mut waiters = self.handle.waiters.lock();
+        if self.is_completed(ticket) {
+            return GpuFuture::ready(());
+        }
+        let (future, completer) = gpu_future();
+        waiters.push((ticket, completer));
+        future
+    }
+}
+
+impl EventHandle {
+    pub(crate) fn complete_waiters(&self, ticket: u64) {
+        let completed = {
+            let mut waiters = self.waiters.lock();
+            let (completed, pending) = std::mem::take(&mut *waiters)
+                .into_iter()
+                .partition::<Vec<_>, _>(|(t, _)| *t <= ticket);
+            *waiters = pending;
+            completed
+        };
+        for (_, completer) in completed {
+            completer.complete(());
+        }
+    }
+}
+
+impl<T: Value + Send> BufferView<T> {
+    /// Like [`BufferView::copy_to_vec`], but returns a future instead of
+    /// waiting for the copy on the default stream.
+    pub fn copy_to_vec_future(&self) -> GpuFuture<Vec<T>> {
+        let len = self.len;
+        if len == 0 {
+            return GpuFuture::ready(vec![]);
+        }
+        let mut data = Vec::<T>::with_capacity(len);
+        // the allocation stays in place when `data` is moved into the callback
+        let command = unsafe {
+            let slice = std::slice::from_raw_parts_mut(data.as_mut_ptr(), len);
+            self.copy_to_async(slice).lift()
+        };
+        let (future, completer) = gpu_future();
+        let scope = self.device.default_stream().scope();
+        scope.submit_with_callback([command], move || {
+            unsafe { data.set_len(len) };
+            completer.complete(data);
+        });
+        scope.detach();
+        future
+    }
+}
@@ -0,0 +1,270 @@
+//! Capture a sequence of [`Command`]s once and submit it repeatedly.
+//!
+//! A [`CommandGraph`] owns copies of the encoded commands together with their
+//! kernel arguments, so replaying it does not go through argument encoding again.
+//! Uniform arguments and dispatch sizes of captured dispatches can be changed
+//! between replays.
+use super::*;
+use crate::error::Result;
+use crate::Error;
+
+/// A command captured in a [`CommandGraph`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct GraphNode(usize);
+
+impl GraphNode {
+    #[inline]
+    pub fn index(&self) -> usize {
+        self.0
+    }
+}
+
+/// A uniform argument of a captured dispatch, see [`CommandGraph::set_uniform`].
+pub struct GraphUniform<T: Value> {
+    node: usize,
+    arg: usize,
+    _marker: PhantomData<T>,
+}
+
+impl<T: Value> Clone for GraphUniform<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+impl<T: Value> Copy for GraphUniform<T> {}
+
+struct GraphDispatch {
+    /// the dispatched kernel, to check the types of uniform arguments
+    module: Option<CArc<KernelModule>>,
+    args: Vec<api::Argument>,
+    // 16-byte aligned storage for uniform arguments, indexed like `args`
+    uniforms: Vec<Option<Box<[u128]>>>,
+}
+
+struct GraphCommand {
+    inner: api::Command,
+    dispatch: Option<GraphDispatch>,
+    rearm: Option<Rearm>,
+}
+
+/// A recorded list of commands that can be replayed on any [`Stream`] of the
+/// device it was created on.
+///
+/// All resources referenced by the captured commands are kept alive by the graph.
+/// A captured [`BindlessArray::update_async`] applies the same modifications on
+/// each replay and keeps the array locked until that replay has completed.
+/// Other commands with a completion callback, e.g.
+/// [`BindlessAllocator::update_async`], cannot be captured since the callback
+/// must not run more than once.
+///
+/// ```no_run
+/// # use luisa_compute::prelude::*;
+/// # fn f(device: &Device, kernel: &Kernel<fn(Buffer<f32>, f32)>, buffer: &Buffer<f32>) {
+/// let mut graph = CommandGraph::new(device);
+/// let node = graph.push(kernel.dispatch_async([1024, 1, 1], buffer, &1.0f32));
+/// let scale = graph.uniform::<f32>(node, 1);
+/// for frame in 0..10 {
+///     graph.set_uniform(scale, frame as f32);
+///     graph.replay(&device.default_stream());
+/// }
+/// # }
+/// ```
+pub struct CommandGraph<'a> {
+    device: Device,
+    commands: Vec<GraphCommand>,
+    resource_tracker: ResourceTracker,
+    marker: PhantomData<&'a ()>,
+}
+
+// not `Sync`: replays on several threads would write downloads concurrently
+unsafe impl<'a> Send for CommandGraph<'a> {}
+
+impl<'a> CommandGraph<'a> {
+    pub fn new(device: &Device) -> Self {
+        Self {
+            device: device.internal(),
+            commands: vec![],
+            resource_tracker: ResourceTracker::new(),
+            marker: PhantomData,
+        }
+    }
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.commands.len()
+    }
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.commands.is_empty()
+    }
+    /// Captures a command, panics if any resource it references has been dropped.
+    pub fn push(&mut self, command: Command<'a, 'a>) -> GraphNode {
+        self.try_push(command).unwrap_or_else(|e| panic!("{}", e))
+    }
+    /// Fallible version of [`CommandGraph::push`].
+    pub fn try_push(&mut self, command: Command<'a, 'a>) -> Result<GraphNode> {
+        let Command {
+            inner,
+            callback,
+            rearm,
+            resource_tracker,
+            ..
+        } = command;
+        if let Some(callback) = callback {
+            // releases what the command holds until it is replayed, e.g. the
+            // lock of a bindless array
+            callback();
+            if rearm.is_none() {
+                return Err(Error::InvalidArgument(
+                    "commands whose completion callback cannot run for each replay \
+                     cannot be recorded, e.g. bindless allocator updates must be \
+                     submitted before"
+                        .to_string(),
+                ));
+            }
+        }
+        // only callback-only commands have no backend command
+        let inner = inner.unwrap();
+        let resource_tracker = resource_tracker.try_upgrade().ok_or_else(|| {
+            Error::InvalidArgument(
+                "command references a resource that has already been dropped".to_string(),
+            )
+        })?;
+        let module = resource_tracker
+            .find::<RawKernel>()
+            .map(|kernel| kernel.module.clone());
+        self.resource_tracker.merge(resource_tracker);
+        let mut command = GraphCommand {
+            inner,
+            dispatch: None,
+            rearm,
+        };
+        if let api::Command::ShaderDispatch(dispatch) = &mut command.inner {
+            let args = unsafe { std::slice::from_raw_parts(dispatch.args, dispatch.args_count) };
+            let mut args = args.to_vec();
+            let uniforms = args
+                .iter_mut()
+                .map(|arg| match arg {
+                    api::Argument::Uniform(uniform) => {
+                        let mut data = vec![0u128; (uniform.size + 15) / 16].into_boxed_slice();
+                        unsafe {
+                            std::ptr::copy_nonoverlapping(
+                                uniform.data,
+                                data.as_mut_ptr() as *mut u8,
+                                uniform.size,
+                            );
+                        }
+                        uniform.data = data.as_ptr() as *const u8;
+                        Some(data)
+                    }
+                    _ => None,
+                })
+                .collect::<Vec<_>>();
+            // moving the Vec does not move its heap allocation
+            dispatch.args = args.as_ptr();
+            command.dispatch = Some(GraphDispatch {
+                module,
+                args,
+                uniforms,
+            });
+        }
+        self.commands.push(command);
+        Ok(GraphNode(self.commands.len() - 1))
+    }
+    pub fn extend(&mut self, commands: impl IntoIterator<Item = Command<'a, 'a>>) {
+        for command in commands {
+            self.push(command);
+        }
+    }
+    fn dispatch(&self, node: GraphNode) -> (&api::ShaderDispatchCommand, &GraphDispatch) {
+        let command = &self.commands[node.0];
+        match (&command.inner, &command.dispatch) {
+            (api::Command::ShaderDispatch(c), Some(d)) => (c, d),
+            _ => panic!("graph node {} is not a kernel dispatch", node.0),
+        }
+    }
+    /// Returns a handle to the `arg`-th argument of a captured dispatch,
+    /// which must be a uniform of type `T`.
+    pub fn uniform<T: Value>(&self, node: GraphNode, arg: usize) -> GraphUniform<T> {
+        let (_, dispatch) = self.dispatch(node);
+        assert!(
+            arg < dispatch.args.len(),
+            "argument index {} out of range, dispatch has {} arguments",
+            arg,
+            dispatch.args.len()
+        );
+        match &dispatch.args[arg] {
+            api::Argument::Uniform(uniform) => assert_eq!(
+                uniform.size,
+                std::mem::size_of::<T>(),
+                "uniform argument {} has a different size than {}",
+                arg,
+                std::any::type_name::<T>()
+            ),
+            _ => panic!("argument {} is not a uniform", arg),
+        }
+        if let Some(module) = &dispatch.module {
+            assert!(
+                ir::context::is_type_equal(module.args[arg].type_(), &T::type_()),
+                "uniform argument {} is not of type {}",
+                arg,
+                std::any::type_name::<T>()
+            );
+        }
+        GraphUniform {
+            node: node.0,
+            arg,
+            _marker: PhantomData,
+        }
+    }
+    pub fn set_uniform<T: Value>(&mut self, uniform: GraphUniform<T>, value: T) {
+        let data = self.commands[uniform.node]
+            .dispatch
+            .as_mut()
+            .unwrap()
+            .uniforms[uniform.arg]
+            .as_mut()
+            .unwrap();
+        unsafe {
+            std::ptr::copy_nonoverlapping(
+                &value as *const T as *const u8,
+                data.as_mut_ptr() as *mut u8,
+                std::mem::size_of::<T>(),
+            );
+        }
+    }
+    pub fn dispatch_size(&self, node: GraphNode) -> [u32; 3] {
+        self.dispatch(node).0.dispatch_size
+    }
+    pub fn set_dispatch_size(&mut self, node: GraphNode, dispatch_size: [u32; 3]) {
+        self.dispatch(node);
+        match &mut self.commands[node.0].inner {
+            api::Command::ShaderDispatch(c) => c.dispatch_size = dispatch_size,
+            _ => unreachable!(),
+        }
+    }
+    /// Submits all captured commands to `scope`.
+    ///
+    /// The graph stays borrowed until the scope is synchronized, so parameters
+    /// cannot be changed while a replay is in flight. Captured bindless array
+    /// updates lock their array again until the replay has completed.
+    pub fn replay_async<'s>(&'s self, scope: &Scope<'s>) {
+        assert!(
+            Arc::ptr_eq(&scope.handle.device(), &self.device.inner),
+            "command graph replayed on {} of a different device",
+            scope.handle.describe()
+        );
+        scope.submit(self.commands.iter().map(|c| Command {
+            inner: Some(c.inner),
+            marker: PhantomData,
+            callback: c.rearm.as_ref().map(|rearm| rearm()),
+            rearm: None,
+            resource_tracker: ResourceTracker::new(),
+        }));
+    }
+    /// Submits all captured commands to `stream` and waits for them to complete.
+    pub fn replay(&self, stream: &Stream) {
+        stream.with_scope(|s| {
+            self.replay_async(s);
+        });
+    }
+}
@@ -80,6 +80,38 @@ impl<T: Value + 'static> CallableParameter for BufferVar<T> {
 //         encoder.byte_buffer(self)
 //     }
 // }
+impl<T: Value + 'static> CallableParameter for StridedBufferVar<T> {
+    fn def_param(_: Option<Rc<dyn Any>>, builder: &mut KernelBuilder) -> Self {
+        StridedBufferVar {
+            bytes: builder.buffer(),
+            stride: builder.value(),
+            len: builder.value(),
+            _marker: PhantomData,
+        }
+    }
+    fn encode(&self, encoder: &mut CallableArgEncoder) {
+        encoder.buffer(&self.bytes);
+        encoder.var(self.stride);
+        encoder.var(self.len);
+    }
+}
+impl<T: Value + 'static> CallableParameter for DeviceVecVar<T> {
+    fn def_param(_: Option<Rc<dyn Any>>, builder: &mut KernelBuilder) -> Self {
+        builder.device_vec()
+    }
+    fn encode(&self, encoder: &mut CallableArgEncoder) {
+        encoder.buffer(&self.data);
+        encoder.buffer(&self.counter);
+    }
+}
+impl CallableParameter for IndirectDispatchBufferVar {
+    fn def_param(_: Option<Rc<dyn Any>>, builder: &mut KernelBuilder) -> Self {
+        builder.indirect_dispatch_buffer()
+    }
+    fn encode(&self, encoder: &mut CallableArgEncoder) {
+        encoder.buffer(&self.sizes);
+    }
+}
 impl<T: IoTexel + 'static> CallableParameter for Tex2dVar<T> {
     fn def_param(_: Option<Rc<dyn Any>>, builder: &mut KernelBuilder) -> Self {
         builder.tex2d()
@@ -98,6 +130,24 @@ impl<T: IoTexel + 'static> CallableParameter for Tex3dVar<T> {
     }
 }
 
+impl<T: IoTexel + 'static> CallableParameter for Tex2dArrayVar<T> {
+    fn def_param(_: Option<Rc<dyn Any>>, builder: &mut KernelBuilder) -> Self {
+        builder.tex2d_array()
+    }
+    fn encode(&self, encoder: &mut CallableArgEncoder) {
+        encoder.tex3d(&self.tex)
+    }
+}
+
+impl<T: IoTexel + 'static> CallableParameter for TexCubeVar<T> {
+    fn def_param(_: Option<Rc<dyn Any>>, builder: &mut KernelBuilder) -> Self {
+        builder.tex_cube()
+    }
+    fn encode(&self, encoder: &mut CallableArgEncoder) {
+        encoder.tex3d(&self.tex)
+    }
+}
+
 impl CallableParameter for BindlessArrayVar {
     fn def_param(_: Option<Rc<dyn Any>>, builder: &mut KernelBuilder) -> Self {
         builder.bindless_array()
@@ -131,6 +181,24 @@ impl<T: Value> KernelParameter for BufferVar<T> {
         builder.buffer()
     }
 }
+impl<T: Value> KernelParameter for StridedBufferVar<T> {
+    type Arg = StridedBufferView<T>;
+    fn def_param(builder: &mut KernelBuilder) -> Self {
+        builder.strided_buffer()
+    }
+}
+impl<T: Value> KernelParameter for DeviceVecVar<T> {
+    type Arg = DeviceVec<T>;
+    fn def_param(builder: &mut KernelBuilder) -> Self {
+        builder.device_vec()
+    }
+}
+impl KernelParameter for IndirectDispatchBufferVar {
+    type Arg = IndirectDispatchBuffer;
+    fn def_param(builder: &mut KernelBuilder) -> Self {
+        builder.indirect_dispatch_buffer()
+    }
+}
 impl<T: SoaValue> KernelParameter for SoaBufferVar<T> {
     type Arg = SoaBuffer<T>;
     fn def_param(builder: &mut KernelBuilder) -> Self {
@@ -158,6 +226,20 @@ impl<T: IoTexel> KernelParameter for Tex3dVar<T> {
     }
 }
 
+impl<T: IoTexel> KernelParameter for Tex2dArrayVar<T> {
+    type Arg = Tex2dArray<T>;
+    fn def_param(builder: &mut KernelBuilder) -> Self {
+        builder.tex2d_array()
+    }
+}
+
+impl<T: IoTexel> KernelParameter for TexCubeVar<T> {
+    type Arg = TexCube<T>;
+    fn def_param(builder: &mut KernelBuilder) -> Self {
+        builder.tex_cube()
+    }
+}
+
 impl KernelParameter for BindlessArrayVar {
     type Arg = BindlessArray;
     fn def_param(builder: &mut KernelBuilder) -> Self {
@@ -271,6 +353,7 @@ impl KernelBuilder {
             __module_pools(),
             Node::new(CArc::new(Instruction::Buffer), T::type_()),
         );
+        let param = self.args.len();
         self.args.push(node);
         with_recorder(|r| {
             r.defined.insert(node, true);
@@ -279,6 +362,8 @@ impl KernelBuilder {
             node: node.into(),
             marker: PhantomData,
             handle: None,
+            param: Some(param),
+            offset_bytes: None,
         }
     }
     pub fn soa_buffer<T: SoaValue>(&mut self) -> SoaBufferVar<T> {
@@ -288,6 +373,28 @@ impl KernelBuilder {
             proxy: T::SoaBuffer::from_soa_storage(storage, metadata.read(0), 0),
         }
     }
+    /// A strided view, see [`StridedBufferView`].
+    pub fn strided_buffer<T: Value>(&mut self) -> StridedBufferVar<T> {
+        StridedBufferVar {
+            bytes: self.buffer(),
+            stride: self.uniform(),
+            len: self.uniform(),
+            _marker: PhantomData,
+        }
+    }
+    /// A device vector, see [`DeviceVec`].
+    pub fn device_vec<T: Value>(&mut self) -> DeviceVecVar<T> {
+        DeviceVecVar {
+            data: self.buffer(),
+            counter: self.buffer(),
+        }
+    }
+    /// Dispatch sizes, see [`IndirectDispatchBuffer`].
+    pub fn indirect_dispatch_buffer(&mut self) -> IndirectDispatchBufferVar {
+        IndirectDispatchBufferVar {
+            sizes: self.buffer(),
+        }
+    }
     pub fn tex2d<T: IoTexel>(&mut self) -> Tex2dVar<T> {
         let node = new_node(
             __module_pools(),
@@ -320,6 +427,14 @@ impl KernelBuilder {
             level: None,
         }
     }
+    /// A texture array, see [`Tex2dArray`].
+    pub fn tex2d_array<T: IoTexel>(&mut self) -> Tex2dArrayVar<T> {
+        Tex2dArrayVar { tex: self.tex3d() }
+    }
+    /// A cube map, see [`TexCube`].
+    pub fn tex_cube<T: IoTexel>(&mut self) -> TexCubeVar<T> {
+        TexCubeVar { tex: self.tex3d() }
+    }
     pub fn bindless_array(&mut self) -> BindlessArrayVar {
         let node = new_node(
             __module_pools(),
@@ -457,7 +572,7 @@ impl KernelBuilder {
             let captured = r.captured_vars.values().map(|x| x.0).collect::<Vec<_>>();
             check_arg_alias(&captured);
             RawCallable {
-                device: self.device.clone(),
+                device: self.device.internal(),
                 module,
                 resource_tracker: rt,
                 captured_args: captured,
@@ -467,6 +582,16 @@ impl KernelBuilder {
         ret
     }
 
+    /// Traces the parameters of `S` without building a kernel and passes
+    /// their nodes to `f`.
+    pub(crate) fn with_params<S: KernelSignature, R>(f: impl FnOnce(&[NodeRef]) -> R) -> R {
+        let mut builder = Self::new(None, true);
+        S::def_params(&mut builder);
+        let ret = f(&builder.args);
+        pop_recorder();
+        ret
+    }
+
     /// Don't use this directly
     /// See [`Kernel`] for how to create a kernel
     #[doc(hidden)]
@@ -488,6 +613,10 @@ impl KernelBuilder {
             const_block.merge(entry);
             let entry = const_block;
             assert!(r.captured_vars.is_empty());
+            if let Some((sizes, offset, _)) = r.indirect_dispatch {
+                // bound after the parameters, see `RawKernel::dispatch_indirect_async`
+                self.args.extend([sizes, offset]);
+            }
             let ir_module = Module {
                 curve_basis_set: r.curve_bases,
                 entry,
@@ -509,7 +638,7 @@ impl KernelBuilder {
 
             KernelDef {
                 inner: RawKernelDef {
-                    device: self.device.clone(),
+                    device: self.device.internal(),
                     resource_tracker: rt,
                     module: CArc::new(module),
                 },
@@ -620,7 +749,7 @@ macro_rules! impl_callable {
          }
         impl<R:CallableRet +'static, $($Ts: CallableParameter +'static),*> Callable<fn($($Ts,)*)->R> {
             pub fn new<F:Fn($($Ts,)*)->R>(device: &Device, f:F)->Self where F:CallableBuildFn<fn($($Ts,)*)->R> {
-                Self::new_maybe_device(Some(device.clone()), f)
+                Self::new_maybe_device(Some(device.internal()), f)
             }
             pub fn new_maybe_device<F:Fn($($Ts,)*)->R>(device: Option<Device>, f:F)->Self where F:CallableBuildFn<fn($($Ts,)*)->R> {
                 let mut builder = KernelBuilder::new(device, false);
@@ -651,7 +780,7 @@ macro_rules! impl_callable {
         }
         impl<R:CallableRet +'static, $($Ts: CallableParameter +'static),*> DynCallable<fn($($Ts,)*)->R> {
             pub fn new(device: &Device, f:Box<dyn Fn($($Ts,)*)->R>)->Self where Box<dyn Fn($($Ts,)*)->R> : CallableBuildFn<fn($($Ts,)*)->R> {
-                DynCallable::_new(device.clone(), false, Box::new(move |arg, builder| {
+                DynCallable::_new(device.internal(), false, Box::new(move |arg, builder| {
                     let raw_callable = CallableBuildFn::build_callable(&f, Some(arg), builder);
                     Callable {
                         inner: raw_callable,
@@ -681,7 +810,10 @@ impl_callable!(T0 T1 T2 T3 T4 T5 T6 T7 T8 T9 T10 T11 T12 T13 );
 impl_callable!(T0 T1 T2 T3 T4 T5 T6 T7 T8 T9 T10 T11 T12 T13 T14 );
 impl_callable!(T0 T1 T2 T3 T4 T5 T6 T7 T8 T9 T10 T11 T12 T13 T14 T15);
 
-pub trait KernelSignature: Sized {}
+pub trait KernelSignature: Sized {
+    #[doc(hidden)]
+    fn def_params(builder: &mut KernelBuilder);
+}
 pub trait KernelSignature2<'a>: KernelSignature {
     type Fn: KernelBuildFn<'a, Self>;
 }
@@ -691,7 +823,12 @@ pub trait KernelBuildFn<'a, S: KernelSignature2<'a>> {
 
 macro_rules! impl_kernel {
     ($($Ts:ident)*) => {
-        impl<$($Ts: KernelArg +'static),*> KernelSignature for fn($($Ts,)*) {}
+        impl<$($Ts: KernelArg +'static),*> KernelSignature for fn($($Ts,)*) {
+            #[allow(unused_variables)]
+            fn def_params(builder: &mut KernelBuilder) {
+                $(<$Ts::Parameter as KernelParameter>::def_param(builder);)*
+            }
+        }
         impl<'a, $($Ts: KernelArg +'static),*> KernelSignature2<'a> for fn($($Ts,)*) {
             type Fn = &'a dyn Fn($($Ts::Parameter,)*);
         }
@@ -709,7 +846,7 @@ macro_rules! impl_kernel {
             #[allow(non_snake_case)]
             #[allow(unused_variables)]
             pub fn new_maybe_device(device: Option<&Device>, f:&dyn Fn($($Ts::Parameter,)*))->Self {
-                KernelBuildFn::build_kernel(&f, &mut KernelBuilder::new(device.cloned(), true))
+                KernelBuildFn::build_kernel(&f, &mut KernelBuilder::new(device.map(Device::internal), true))
             }
             pub fn new(device: &Device, f:&dyn Fn($($Ts::Parameter,)*))->Self {
                 Self::new_maybe_device(Some(device), f)
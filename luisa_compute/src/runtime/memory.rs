@@ -0,0 +1,300 @@
+//! Accounting of the device resources created through a [`Device`].
+//!
+//! Sizes are computed on the host: buffers report the size returned by the
+//! backend, textures the size of their mip chain in the chosen [`PixelStorage`].
+//! Meshes, procedural primitives, acceleration structures and bindless arrays
+//! have backend defined sizes and are only counted.
+use std::backtrace::Backtrace;
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::thread::ThreadId;
+
+use indexmap::IndexMap;
+
+use super::*;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum ResourceKind {
+    Buffer,
+    Texture,
+    BindlessArray,
+    Mesh,
+    ProceduralPrimitive,
+    Accel,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MemoryUsage {
+    pub count: usize,
+    pub bytes: usize,
+}
+
+impl std::ops::AddAssign for MemoryUsage {
+    fn add_assign(&mut self, rhs: Self) {
+        self.count += rhs.count;
+        self.bytes += rhs.bytes;
+    }
+}
+
+/// Snapshot returned by [`Device::memory_stats`].
+#[derive(Clone, Debug, Default)]
+pub struct MemoryStats {
+    /// Live resources grouped by kind and by the label that was active when
+    /// they were created, see [`Device::memory_label`].
+    pub groups: BTreeMap<(ResourceKind, Option<String>), MemoryUsage>,
+    pub total: MemoryUsage,
+    /// Highest `total.bytes` since the device was created.
+    pub peak_bytes: usize,
+}
+
+impl MemoryStats {
+    pub fn kind(&self, kind: ResourceKind) -> MemoryUsage {
+        let mut usage = MemoryUsage::default();
+        for (_, u) in self.groups.iter().filter(|((k, _), _)| *k == kind) {
+            usage += *u;
+        }
+        usage
+    }
+    pub fn label(&self, label: &str) -> MemoryUsage {
+        let mut usage = MemoryUsage::default();
+        for (_, u) in self
+            .groups
+            .iter()
+            .filter(|((_, l), _)| l.as_deref() == Some(label))
+        {
+            usage += *u;
+        }
+        usage
+    }
+}
+
+impl Display for MemoryStats {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "{} live resources, {} bytes (peak {} bytes)",
+            self.total.count, self.total.bytes, self.peak_bytes
+        )?;
+        for ((kind, label), usage) in &self.groups {
+            writeln!(
+                f,
+                "  {:?}{}: {} x, {} bytes",
+                kind,
+                label.as_ref().map_or(String::new(), |l| format!(" [{}]", l)),
+                usage.count,
+                usage.bytes
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Returned by [`Device::memory_label`], pops the label when dropped.
+pub struct MemoryLabelGuard {
+    device: Arc<DeviceHandle>,
+    // labels are per thread
+    _marker: PhantomData<*const ()>,
+}
+
+impl MemoryLabelGuard {
+    pub(crate) fn push(device: &Device, label: String) -> Self {
+        let thread = std::thread::current().id();
+        let memory = &device.inner.memory;
+        memory.labels.lock().entry(thread).or_default().push(label);
+        Self {
+            device: device.inner.clone(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl Drop for MemoryLabelGuard {
+    fn drop(&mut self) {
+        let thread = std::thread::current().id();
+        let mut labels = self.device.memory.labels.lock();
+        if let Some(stack) = labels.get_mut(&thread) {
+            stack.pop();
+            if stack.is_empty() {
+                labels.remove(&thread);
+            }
+        }
+    }
+}
+
+pub(crate) type AllocationId = u64;
+
+struct Allocation {
+    kind: ResourceKind,
+    bytes: usize,
+    label: Option<String>,
+    name: Option<String>,
+    backtrace: Option<Backtrace>,
+}
+
+#[derive(Default)]
+struct MemoryTrackerState {
+    next_id: AllocationId,
+    allocations: IndexMap<AllocationId, Allocation>,
+    bytes: usize,
+    peak_bytes: usize,
+}
+
+pub(crate) struct MemoryTracker {
+    state: Mutex<MemoryTrackerState>,
+    /// label stacks of [`Device::memory_label`] by thread
+    labels: Mutex<HashMap<ThreadId, Vec<String>>>,
+    leak_handler: Mutex<Option<Box<dyn Fn(&str) + Send + Sync>>>,
+    pub(crate) leak_check: bool,
+}
+
+impl MemoryTracker {
+    pub(crate) fn new() -> Self {
+        let leak_check = match env::var("LUISA_LEAK_CHECK") {
+            Ok(s) => s == "1" || s == "ON",
+            Err(_) => false,
+        };
+        Self {
+            state: Mutex::new(MemoryTrackerState::default()),
+            labels: Mutex::new(HashMap::new()),
+            leak_handler: Mutex::new(None),
+            leak_check,
+        }
+    }
+    pub(crate) fn register(&self, kind: ResourceKind, bytes: usize) -> AllocationId {
+        let label = self
+            .labels
+            .lock()
+            .get(&std::thread::current().id())
+            .and_then(|stack| stack.last().cloned());
+        let backtrace = if self.leak_check {
+            Some(get_backtrace())
+        } else {
+            None
+        };
+        let mut state = self.state.lock();
+        let id = state.next_id;
+        state.next_id += 1;
+        state.allocations.insert(
+            id,
+            Allocation {
+                kind,
+                bytes,
+                label,
+                name: None,
+                backtrace,
+            },
+        );
+        state.bytes += bytes;
+        state.peak_bytes = state.peak_bytes.max(state.bytes);
+        id
+    }
+    pub(crate) fn release(&self, id: AllocationId) {
+        let mut state = self.state.lock();
+        if let Some(allocation) = state.allocations.shift_remove(&id) {
+            state.bytes -= allocation.bytes;
+        }
+    }
+    pub(crate) fn set_name(&self, id: AllocationId, name: &str) {
+        if let Some(allocation) = self.state.lock().allocations.get_mut(&id) {
+            allocation.name = Some(name.to_string());
+        }
+    }
+    pub(crate) fn stats(&self) -> MemoryStats {
+        let state = self.state.lock();
+        let mut stats = MemoryStats {
+            peak_bytes: state.peak_bytes,
+            ..Default::default()
+        };
+        for a in state.allocations.values() {
+            let usage = MemoryUsage {
+                count: 1,
+                bytes: a.bytes,
+            };
+            *stats.groups.entry((a.kind, a.label.clone())).or_default() += usage;
+            stats.total += usage;
+        }
+        stats
+    }
+    pub(crate) fn set_leak_handler(&self, handler: Box<dyn Fn(&str) + Send + Sync>) {
+        *self.leak_handler.lock() = Some(handler);
+    }
+    /// Hands the leak report to the handler, or logs it under `LUISA_LEAK_CHECK`.
+    pub(crate) fn report_leaks(&self) {
+        let handler = self.leak_handler.lock();
+        if handler.is_none() && !self.leak_check {
+            return;
+        }
+        let Some(report) = self.leak_report() else {
+            return;
+        };
+        match &*handler {
+            Some(handler) => handler(&report),
+            None => log::warn!("device dropped with live resources, {}", report),
+        }
+    }
+    pub(crate) fn leak_report(&self) -> Option<String> {
+        let state = self.state.lock();
+        if state.allocations.is_empty() {
+            return None;
+        }
+        let mut report = format!(
+            "{} resource(s) still alive, {} bytes:",
+            state.allocations.len(),
+            state.bytes
+        );
+        for a in state.allocations.values() {
+            report.push_str(&format!("\n  {:?}", a.kind));
+            if let Some(name) = &a.name {
+                report.push_str(&format!(" `{}`", name));
+            }
+            if let Some(label) = &a.label {
+                report.push_str(&format!(" [{}]", label));
+            }
+            report.push_str(&format!(", {} bytes", a.bytes));
+            match &a.backtrace {
+                Some(backtrace) => report.push_str(&format!(", created at:\n{}", backtrace)),
+                None => report.push_str(" (set LUISA_LEAK_CHECK=1 to capture creation backtraces)"),
+            }
+        }
+        Some(report)
+    }
+}
+
+pub(crate) fn texture_size_bytes(
+    storage: PixelStorage,
+    width: u32,
+    height: u32,
+    depth: u32,
+    levels: u32,
+) -> usize {
+    // block width and bytes per block, block-compressed storages use 4x4 blocks
+    let (block, block_size) = match storage {
+        PixelStorage::Byte1 => (1, 1),
+        PixelStorage::Byte2 | PixelStorage::Short1 | PixelStorage::Half1 => (1, 2),
+        PixelStorage::Byte4
+        | PixelStorage::Short2
+        | PixelStorage::Half2
+        | PixelStorage::Int1
+        | PixelStorage::Float1
+        | PixelStorage::R10G10B10A2
+        | PixelStorage::R11G11B10 => (1, 4),
+        PixelStorage::Short4 | PixelStorage::Half4 | PixelStorage::Int2 | PixelStorage::Float2 => {
+            (1, 8)
+        }
+        PixelStorage::Int4 | PixelStorage::Float4 => (1, 16),
+        PixelStorage::Bc1 | PixelStorage::Bc4 => (4, 8),
+        PixelStorage::Bc2
+        | PixelStorage::Bc3
+        | PixelStorage::Bc5
+        | PixelStorage::Bc6
+        | PixelStorage::Bc7 => (4, 16),
+    };
+    (0..levels)
+        .map(|l| {
+            let w = ((width >> l).max(1) as usize + block - 1) / block;
+            let h = ((height >> l).max(1) as usize + block - 1) / block;
+            let d = (depth >> l).max(1) as usize;
+            w * h * d * block_size
+        })
+        .sum()
+}
@@ -0,0 +1,243 @@
+//! Timing of device work.
+//!
+//! The backend interface has no timestamp queries, so time is measured on the
+//! host: a [`Timestamp`] command ends a command batch, and its completion
+//! callback records the time at which everything submitted before it on the
+//! stream has finished. Measurements therefore include the latency of the
+//! completion callback and the cost of splitting the batch, which is negligible
+//! on the CPU backend but may be noticeable for short kernels on GPUs.
+use std::fmt::Write as _;
+use std::time::{Duration, Instant};
+
+use indexmap::IndexMap;
+
+use super::*;
+
+type TimeSlot = Arc<Mutex<Option<Instant>>>;
+
+/// Records the time at which the stream reaches it.
+#[derive(Clone, Default)]
+pub struct Timestamp {
+    time: TimeSlot,
+}
+
+impl Timestamp {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// The returned command can be submitted any number of times, the last completed one wins.
+    pub fn record_async(&self) -> Command<'static, 'static> {
+        let time = self.time.clone();
+        Command::callback_only(move || {
+            *time.lock() = Some(Instant::now());
+        })
+    }
+    /// `None` until a command from [`Timestamp::record_async`] has completed.
+    pub fn get(&self) -> Option<Instant> {
+        *self.time.lock()
+    }
+}
+
+/// Measures the time between two points of a command stream.
+///
+/// ```no_run
+/// # use luisa_compute::prelude::*;
+/// # use luisa_compute::runtime::Timer;
+/// # fn f(device: &Device, kernel: &Kernel<fn()>) {
+/// let timer = Timer::new();
+/// device.default_stream().with_scope(|s| {
+///     s.submit([timer.start_async(), kernel.dispatch_async([1024, 1, 1]), timer.stop_async()]);
+/// });
+/// println!("{:?}", timer.elapsed().unwrap());
+/// # }
+/// ```
+#[derive(Clone, Default)]
+pub struct Timer {
+    start: Timestamp,
+    stop: Timestamp,
+}
+
+impl Timer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn start_async(&self) -> Command<'static, 'static> {
+        self.start.record_async()
+    }
+    pub fn stop_async(&self) -> Command<'static, 'static> {
+        self.stop.record_async()
+    }
+    /// `None` until both the start and the stop command have completed.
+    pub fn elapsed(&self) -> Option<Duration> {
+        Some(self.stop.get()?.saturating_duration_since(self.start.get()?))
+    }
+}
+
+/// A single kernel dispatch timed by a [`Profiler`].
+#[derive(Clone, Debug)]
+pub struct DispatchRecord {
+    pub name: String,
+    /// Raw handle of the stream the dispatch was submitted to.
+    pub stream: u64,
+    /// Relative to the creation of the profiler.
+    pub start: Duration,
+    pub end: Duration,
+}
+
+impl DispatchRecord {
+    pub fn duration(&self) -> Duration {
+        self.end.saturating_sub(self.start)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct KernelStats {
+    pub name: String,
+    pub count: usize,
+    pub total: Duration,
+    pub mean: Duration,
+}
+
+/// Times every kernel dispatch submitted through it and aggregates the results
+/// by kernel name, see [`KernelBuildOptions::name`].
+///
+/// Timing splits the submitted commands into one batch per dispatch, so it is
+/// meant for profiling sessions rather than for always-on use.
+pub struct Profiler {
+    epoch: Instant,
+    records: Arc<Mutex<Vec<DispatchRecord>>>,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self {
+            epoch: Instant::now(),
+            records: Arc::new(Mutex::new(vec![])),
+        }
+    }
+    /// Submits `commands` to `scope`, timing each kernel dispatch.
+    pub fn submit<'cmd, 'a>(
+        &self,
+        scope: &Scope<'a>,
+        commands: impl IntoIterator<Item = Command<'cmd, 'a>>,
+    ) {
+        let stream = scope.handle().0;
+        let mut timed = vec![];
+        for command in commands {
+            let Some(api::Command::ShaderDispatch(dispatch)) = &command.inner else {
+                timed.push(command);
+                continue;
+            };
+            let name = match command.resource_tracker.find::<RawKernel>() {
+                Some(kernel) if !kernel.name().is_empty() => kernel.name().to_string(),
+                _ => format!("kernel_{}", dispatch.shader.0),
+            };
+            let start = TimeSlot::default();
+            let start_ = start.clone();
+            timed.push(Command::callback_only(move || {
+                *start_.lock() = Some(Instant::now());
+            }));
+            timed.push(command);
+            let epoch = self.epoch;
+            let records = self.records.clone();
+            timed.push(Command::callback_only(move || {
+                let end = Instant::now();
+                let start = start.lock().unwrap_or(end);
+                records.lock().push(DispatchRecord {
+                    name,
+                    stream,
+                    start: start.saturating_duration_since(epoch),
+                    end: end.saturating_duration_since(epoch),
+                });
+            }));
+        }
+        scope.submit(timed);
+    }
+    /// Completed dispatches in order of completion.
+    pub fn records(&self) -> Vec<DispatchRecord> {
+        self.records.lock().clone()
+    }
+    pub fn clear(&self) {
+        self.records.lock().clear();
+    }
+    /// Per kernel statistics, sorted by descending total time.
+    pub fn stats(&self) -> Vec<KernelStats> {
+        let mut stats = IndexMap::<String, (usize, Duration)>::new();
+        for record in self.records.lock().iter() {
+            let entry = stats.entry(record.name.clone()).or_default();
+            entry.0 += 1;
+            entry.1 += record.duration();
+        }
+        let mut stats = stats
+            .into_iter()
+            .map(|(name, (count, total))| KernelStats {
+                name,
+                count,
+                total,
+                mean: total / count as u32,
+            })
+            .collect::<Vec<_>>();
+        stats.sort_by(|a, b| b.total.cmp(&a.total));
+        stats
+    }
+    /// A plain text table of [`Profiler::stats`].
+    pub fn report(&self) -> String {
+        let stats = self.stats();
+        let width = stats.iter().map(|s| s.name.len()).max().unwrap_or(0).max(6);
+        let mut report = format!(
+            "{:<width$} {:>8} {:>12} {:>12}\n",
+            "kernel",
+            "count",
+            "total (ms)",
+            "mean (ms)",
+            width = width
+        );
+        for s in &stats {
+            writeln!(
+                report,
+                "{:<width$} {:>8} {:>12.3} {:>12.3}",
+                s.name,
+                s.count,
+                s.total.as_secs_f64() * 1e3,
+                s.mean.as_secs_f64() * 1e3,
+                width = width
+            )
+            .unwrap();
+        }
+        report
+    }
+    /// The recorded dispatches in the Chrome trace event format,
+    /// viewable in `chrome://tracing` or Perfetto.
+    pub fn chrome_trace(&self) -> String {
+        let events = self
+            .records
+            .lock()
+            .iter()
+            .map(|r| {
+                serde_json::json!({
+                    "name": r.name,
+                    "cat": "kernel",
+                    "ph": "X",
+                    "ts": r.start.as_secs_f64() * 1e6,
+                    "dur": r.duration().as_secs_f64() * 1e6,
+                    "pid": 0,
+                    "tid": r.stream,
+                })
+            })
+            .collect::<Vec<_>>();
+        serde_json::json!({
+            "traceEvents": events,
+            "displayTimeUnit": "ms",
+        })
+        .to_string()
+    }
+    pub fn write_chrome_trace(&self, path: impl AsRef<std::path::Path>) -> std::io::Result<()> {
+        std::fs::write(path, self.chrome_trace())
+    }
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
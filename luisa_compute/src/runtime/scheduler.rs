@@ -0,0 +1,656 @@
+//! Automatic synchronization of commands submitted to multiple streams.
+//!
+//! The [`Scheduler`] derives which resources every command reads and writes
+//! from the encoded [`api::Command`] and, for kernel dispatches, from the kernel
+//! IR. Every batch of commands submitted through the scheduler is followed by an
+//! event signal on its stream, and a later batch on another stream that touches
+//! the same resource either waits for that signal or, in
+//! [`SchedulerMode::Report`], records a [`Race`].
+//!
+//! Resources accessed through a [`BindlessArray`] are not tracked individually;
+//! a kernel reading from a bindless array only depends on the array itself.
+//!
+//! Accesses that are known to have completed are forgotten from time to time,
+//! so the state of the scheduler only grows with the work in flight.
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::ops::Range;
+
+use super::*;
+use ir::{Binding, Capture};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ResourceId {
+    Buffer(u64),
+    Texture(u64),
+    BindlessArray(u64),
+    Accel(u64),
+    /// A mesh or procedural primitive.
+    Primitive(u64),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum AccessKind {
+    Read,
+    Write,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SchedulerMode {
+    /// Inserts event waits between conflicting commands on different streams.
+    Synchronize,
+    /// Submits commands as they are and records conflicting commands as [`Race`]s.
+    Report,
+}
+
+/// Two unordered accesses to the same resource from different streams,
+/// at least one of which is a write.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Race {
+    pub resource: ResourceId,
+    /// Raw stream handles, i.e. `stream.handle().0`.
+    pub first_stream: u64,
+    pub first_access: AccessKind,
+    pub second_stream: u64,
+    pub second_access: AccessKind,
+}
+
+impl Display for Race {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:?} on stream {} races with {:?} on stream {} for {:?}",
+            self.second_access,
+            self.second_stream,
+            self.first_access,
+            self.first_stream,
+            self.resource
+        )
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Access {
+    resource: ResourceId,
+    // byte range, `None` for the whole resource
+    bytes: Option<Range<usize>>,
+    kind: AccessKind,
+}
+
+impl Access {
+    fn new(resource: ResourceId, kind: AccessKind) -> Self {
+        Self {
+            resource,
+            bytes: None,
+            kind,
+        }
+    }
+    fn buffer(buffer: api::Buffer, offset: usize, size: usize, kind: AccessKind) -> Self {
+        Self {
+            resource: ResourceId::Buffer(buffer.0),
+            bytes: Some(offset..offset + size),
+            kind,
+        }
+    }
+    fn overlaps(&self, bytes: &Option<Range<usize>>) -> bool {
+        match (&self.bytes, bytes) {
+            (Some(a), Some(b)) => a.start < b.end && b.start < a.end,
+            _ => true,
+        }
+    }
+    fn covers(&self, bytes: &Option<Range<usize>>) -> bool {
+        match (&self.bytes, bytes) {
+            (None, _) => true,
+            (Some(_), None) => false,
+            (Some(a), Some(b)) => a.start <= b.start && b.end <= a.end,
+        }
+    }
+}
+
+struct AccessRecord {
+    access: Access,
+    stream: u64,
+    ticket: u64,
+}
+
+struct StreamState {
+    event: Event,
+    ticket: u64,
+    // all tickets up to this one are known to have completed
+    completed: u64,
+    // highest ticket of other streams this stream has waited for, directly or not
+    clock: HashMap<u64, u64>,
+    // clocks in effect from a ticket on, for waits on earlier tickets of this stream
+    history: BTreeMap<u64, HashMap<u64, u64>>,
+}
+
+impl StreamState {
+    /// Advances `completed` to the last completed ticket.
+    fn poll(&mut self) -> u64 {
+        let (mut lo, mut hi) = (self.completed, self.ticket);
+        while lo < hi {
+            let mid = lo + (hi - lo + 1) / 2;
+            if self.event.is_completed(mid) {
+                lo = mid;
+            } else {
+                hi = mid - 1;
+            }
+        }
+        self.completed = lo;
+        lo
+    }
+    fn clock_at(&self, ticket: u64) -> Option<&HashMap<u64, u64>> {
+        self.history
+            .range(..=ticket)
+            .next_back()
+            .map(|(_, clock)| clock)
+    }
+}
+
+/// Which arguments and captures of a kernel may be written.
+struct KernelUsage {
+    args: Vec<AccessKind>,
+    captures: Vec<AccessKind>,
+}
+
+impl KernelUsage {
+    fn new(module: &KernelModule) -> Self {
+        let mut written = HashSet::new();
+        Self::visit_block(&module.module.entry, &mut written);
+        let kind = |node: &NodeRef| {
+            if written.contains(node) {
+                AccessKind::Write
+            } else {
+                AccessKind::Read
+            }
+        };
+        Self {
+            args: module.args.as_ref().iter().map(kind).collect(),
+            captures: module
+                .captures
+                .as_ref()
+                .iter()
+                .map(|c: &Capture| kind(&c.node))
+                .collect(),
+        }
+    }
+    fn visit_block(block: &Pooled<BasicBlock>, written: &mut HashSet<NodeRef>) {
+        for node in block.iter() {
+            match node.get().instruction.as_ref() {
+                Instruction::Call(func, args) => {
+                    let args = args.as_ref();
+                    match func {
+                        Func::BufferWrite
+                        | Func::ByteBufferWrite
+                        | Func::Texture2dWrite
+                        | Func::Texture3dWrite
+                        | Func::BindlessBufferWrite
+                        | Func::BufferAddress
+                        | Func::AtomicRef
+                        | Func::AtomicExchange
+                        | Func::AtomicCompareExchange
+                        | Func::AtomicFetchAdd
+                        | Func::AtomicFetchSub
+                        | Func::AtomicFetchAnd
+                        | Func::AtomicFetchOr
+                        | Func::AtomicFetchXor
+                        | Func::AtomicFetchMin
+                        | Func::AtomicFetchMax => {
+                            if let Some(resource) = args.first() {
+                                written.insert(*resource);
+                            }
+                        }
+                        // resources passed to callables are conservatively assumed to be written
+                        Func::Callable(callable) => {
+                            written.extend(args.iter().copied());
+                            // captures of the callable are captures of the kernel
+                            Self::visit_block(&callable.0.module.entry, written);
+                        }
+                        _ => {}
+                    }
+                }
+                Instruction::Loop { body, .. } | Instruction::AdDetach(body) => {
+                    Self::visit_block(body, written)
+                }
+                Instruction::AdScope { body, .. } => Self::visit_block(body, written),
+                Instruction::GenericLoop {
+                    prepare,
+                    body,
+                    update,
+                    ..
+                } => {
+                    Self::visit_block(prepare, written);
+                    Self::visit_block(body, written);
+                    Self::visit_block(update, written);
+                }
+                Instruction::If {
+                    true_branch,
+                    false_branch,
+                    ..
+                } => {
+                    Self::visit_block(true_branch, written);
+                    Self::visit_block(false_branch, written);
+                }
+                Instruction::Switch { default, cases, .. } => {
+                    Self::visit_block(default, written);
+                    for case in cases.as_ref() {
+                        Self::visit_block(&case.block, written);
+                    }
+                }
+                Instruction::RayQuery {
+                    on_triangle_hit,
+                    on_procedural_hit,
+                    ..
+                } => {
+                    Self::visit_block(on_triangle_hit, written);
+                    Self::visit_block(on_procedural_hit, written);
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+struct SchedulerState {
+    streams: HashMap<u64, StreamState>,
+    accesses: HashMap<ResourceId, Vec<AccessRecord>>,
+    // shader handles are reused, so the kernel is kept to tell them apart
+    kernels: HashMap<u64, (Weak<RawKernel>, Arc<KernelUsage>)>,
+    // (event, ticket) signaled through the scheduler -> (stream, stream ticket)
+    signals: HashMap<(u64, u64), (u64, u64)>,
+    races: Vec<Race>,
+    // access records added since the last pruning, or left by it
+    recorded: usize,
+    // `recorded` from which completed work is pruned next
+    prune_at: usize,
+}
+
+const MIN_PRUNE_AT: usize = 256;
+
+impl SchedulerState {
+    /// Forgets accesses, clocks and signals of work that has completed, which
+    /// are ordered before anything submitted later.
+    fn prune(&mut self) {
+        for s in self.streams.values_mut() {
+            let completed = s.poll();
+            // `clock_at` still needs the clock in effect at `completed`
+            if let Some(&first) = s.history.range(..=completed).next_back().map(|(t, _)| t) {
+                s.history = s.history.split_off(&first);
+            }
+        }
+        let streams = &self.streams;
+        let pending = |stream: u64, ticket: u64| ticket > streams[&stream].completed;
+        self.accesses.retain(|_, records| {
+            records.retain(|r| pending(r.stream, r.ticket));
+            !records.is_empty()
+        });
+        self.signals
+            .retain(|_, (stream, ticket)| pending(*stream, *ticket));
+        self.kernels
+            .retain(|_, (kernel, _)| kernel.strong_count() > 0);
+        self.recorded = self.accesses.values().map(Vec::len).sum();
+        self.prune_at = (self.recorded * 2).max(MIN_PRUNE_AT);
+    }
+}
+
+/// Tracks resource hazards between streams, see the [module documentation](self).
+///
+/// Created by [`Device::create_scheduler`]. Commands must be submitted through
+/// [`Scheduler::submit`] for their accesses to be tracked, and explicit event
+/// synchronization between streams should go through [`Scheduler::signal`] and
+/// [`Scheduler::wait`] so that the scheduler knows about it. Work that has
+/// completed on the host side, e.g. because its [`Scope`] was dropped,
+/// never conflicts with later commands.
+pub struct Scheduler {
+    device: Device,
+    mode: SchedulerMode,
+    state: Mutex<SchedulerState>,
+}
+
+impl Scheduler {
+    pub(crate) fn new(device: &Device, mode: SchedulerMode) -> Self {
+        Self {
+            device: device.internal(),
+            mode,
+            state: Mutex::new(SchedulerState {
+                streams: HashMap::new(),
+                accesses: HashMap::new(),
+                kernels: HashMap::new(),
+                signals: HashMap::new(),
+                races: vec![],
+                recorded: 0,
+                prune_at: MIN_PRUNE_AT,
+            }),
+        }
+    }
+    #[inline]
+    pub fn mode(&self) -> SchedulerMode {
+        self.mode
+    }
+    /// Races detected so far in [`SchedulerMode::Report`].
+    pub fn races(&self) -> Vec<Race> {
+        self.state.lock().races.clone()
+    }
+    /// Human readable summary of [`Scheduler::races`].
+    pub fn race_report(&self) -> String {
+        let state = self.state.lock();
+        if state.races.is_empty() {
+            return "no races detected".to_string();
+        }
+        let mut report = format!("{} race(s) detected:", state.races.len());
+        for race in &state.races {
+            report.push_str(&format!("\n  {}", race));
+        }
+        report
+    }
+
+    fn stream<'s>(
+        &self,
+        state: &'s mut SchedulerState,
+        stream: api::Stream,
+    ) -> &'s mut StreamState {
+        state.streams.entry(stream.0).or_insert_with(|| StreamState {
+            event: self.device.create_event(),
+            ticket: 0,
+            completed: 0,
+            clock: HashMap::new(),
+            history: BTreeMap::new(),
+        })
+    }
+
+    /// Records that `stream` waited for `ticket` of `other`, and so for
+    /// everything `other` had waited for before it.
+    fn merge_clock(
+        &self,
+        state: &mut SchedulerState,
+        stream: api::Stream,
+        other: u64,
+        ticket: u64,
+    ) {
+        let mut merged = state
+            .streams
+            .get(&other)
+            .and_then(|s| s.clock_at(ticket))
+            .cloned()
+            .unwrap_or_default();
+        merged.insert(other, ticket);
+        let s = self.stream(state, stream);
+        let mut changed = false;
+        for (other, ticket) in merged {
+            if other == stream.0 {
+                continue;
+            }
+            let t = s.clock.entry(other).or_insert(0);
+            if ticket > *t {
+                *t = ticket;
+                changed = true;
+            }
+        }
+        if changed {
+            s.history.insert(s.ticket + 1, s.clock.clone());
+        }
+    }
+
+    fn kernel_usage(
+        state: &mut SchedulerState,
+        command: &Command<'_, '_>,
+        shader: api::Shader,
+    ) -> Option<Arc<KernelUsage>> {
+        let kernel = command.resource_tracker.find_arc::<RawKernel>()?;
+        if let Some((cached, usage)) = state.kernels.get(&shader.0) {
+            if std::ptr::eq(cached.as_ptr(), Arc::as_ptr(&kernel)) {
+                return Some(usage.clone());
+            }
+        }
+        let usage = Arc::new(KernelUsage::new(&kernel.module));
+        state
+            .kernels
+            .insert(shader.0, (Arc::downgrade(&kernel), usage.clone()));
+        Some(usage)
+    }
+
+    fn accesses(state: &mut SchedulerState, command: &Command<'_, '_>) -> Vec<Access> {
+        use AccessKind::*;
+        let Some(inner) = &command.inner else {
+            return vec![];
+        };
+        match inner {
+            api::Command::BufferUpload(c) => {
+                vec![Access::buffer(c.buffer, c.offset, c.size, Write)]
+            }
+            api::Command::BufferDownload(c) => {
+                vec![Access::buffer(c.buffer, c.offset, c.size, Read)]
+            }
+            api::Command::BufferCopy(c) => vec![
+                Access::buffer(c.src, c.src_offset, c.size, Read),
+                Access::buffer(c.dst, c.dst_offset, c.size, Write),
+            ],
+            api::Command::BufferToTextureCopy(c) => vec![
+                Access::new(ResourceId::Buffer(c.buffer.0), Read),
+                Access::new(ResourceId::Texture(c.texture.0), Write),
+            ],
+            api::Command::TextureToBufferCopy(c) => vec![
+                Access::new(ResourceId::Texture(c.texture.0), Read),
+                Access::new(ResourceId::Buffer(c.buffer.0), Write),
+            ],
+            api::Command::TextureUpload(c) => {
+                vec![Access::new(ResourceId::Texture(c.texture.0), Write)]
+            }
+            api::Command::TextureDownload(c) => {
+                vec![Access::new(ResourceId::Texture(c.texture.0), Read)]
+            }
+            api::Command::TextureCopy(c) => vec![
+                Access::new(ResourceId::Texture(c.src.0), Read),
+                Access::new(ResourceId::Texture(c.dst.0), Write),
+            ],
+            api::Command::BindlessArrayUpdate(c) => {
+                vec![Access::new(ResourceId::BindlessArray(c.handle.0), Write)]
+            }
+            api::Command::MeshBuild(c) => vec![
+                Access::new(ResourceId::Primitive(c.mesh.0), Write),
+                Access::buffer(c.vertex_buffer, c.vertex_buffer_offset, c.vertex_buffer_size, Read),
+                Access::buffer(c.index_buffer, c.index_buffer_offset, c.index_buffer_size, Read),
+            ],
+            api::Command::ProceduralPrimitiveBuild(c) => vec![
+                Access::new(ResourceId::Primitive(c.handle.0), Write),
+                Access::new(ResourceId::Buffer(c.aabb_buffer.0), Read),
+            ],
+            api::Command::AccelBuild(c) => {
+                let modifications =
+                    unsafe { std::slice::from_raw_parts(c.modifications, c.modifications_count) };
+                let mut accesses = vec![Access::new(ResourceId::Accel(c.accel.0), Write)];
+                accesses.extend(
+                    modifications
+                        .iter()
+                        .filter(|m| m.mesh != 0)
+                        .map(|m| Access::new(ResourceId::Primitive(m.mesh), Read)),
+                );
+                accesses
+            }
+            api::Command::ShaderDispatch(c) => {
+                let usage = Self::kernel_usage(state, command, c.shader);
+                // without IR, every resource argument is assumed to be written
+                let arg_kind = |i: usize| usage.as_ref().map_or(Write, |u| u.args[i]);
+                let args = unsafe { std::slice::from_raw_parts(c.args, c.args_count) };
+                let mut accesses = args
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(i, arg)| match arg {
+                        api::Argument::Buffer(b) => {
+                            Some(Access::buffer(b.buffer, b.offset, b.size, arg_kind(i)))
+                        }
+                        api::Argument::Texture(t) => {
+                            Some(Access::new(ResourceId::Texture(t.texture.0), arg_kind(i)))
+                        }
+                        api::Argument::BindlessArray(a) => {
+                            Some(Access::new(ResourceId::BindlessArray(a.0), arg_kind(i)))
+                        }
+                        api::Argument::Accel(a) => {
+                            Some(Access::new(ResourceId::Accel(a.0), arg_kind(i)))
+                        }
+                        api::Argument::Uniform(_) => None,
+                    })
+                    .collect::<Vec<_>>();
+                if let (Some(usage), Some(kernel)) =
+                    (&usage, command.resource_tracker.find::<RawKernel>())
+                {
+                    let captures = kernel.module.captures.as_ref();
+                    for (capture, kind) in captures.iter().zip(&usage.captures) {
+                        accesses.push(match &capture.binding {
+                            Binding::Buffer(b) => Access {
+                                resource: ResourceId::Buffer(b.handle),
+                                bytes: Some(b.offset as usize..b.offset as usize + b.size),
+                                kind: *kind,
+                            },
+                            Binding::Texture(t) => {
+                                Access::new(ResourceId::Texture(t.handle), *kind)
+                            }
+                            Binding::BindlessArray(a) => {
+                                Access::new(ResourceId::BindlessArray(a.handle), *kind)
+                            }
+                            Binding::Accel(a) => Access::new(ResourceId::Accel(a.handle), *kind),
+                        });
+                    }
+                }
+                accesses
+            }
+            _ => vec![],
+        }
+    }
+
+    /// Whether the access recorded in `record` is known to happen before new work on `stream`.
+    fn is_ordered(state: &SchedulerState, record: &AccessRecord, stream: u64) -> bool {
+        if record.stream == stream {
+            return true;
+        }
+        let waited = state
+            .streams
+            .get(&stream)
+            .and_then(|s| s.clock.get(&record.stream))
+            .map_or(false, |t| *t >= record.ticket);
+        waited || state.streams[&record.stream].event.is_completed(record.ticket)
+    }
+
+    /// Submits `commands` to `scope`, waiting for conflicting work on other streams first.
+    pub fn submit<'cmd, 'a>(
+        &self,
+        scope: &Scope<'a>,
+        commands: impl IntoIterator<Item = Command<'cmd, 'a>>,
+    ) {
+        assert!(
+            Arc::ptr_eq(&scope.handle.device(), &self.device.inner),
+            "scheduler used with a stream of a different device"
+        );
+        let commands = commands.into_iter().collect::<Vec<_>>();
+        let stream = scope.handle();
+        let mut state = self.state.lock();
+        if state.recorded >= state.prune_at {
+            state.prune();
+        }
+        let accesses = commands
+            .iter()
+            .flat_map(|c| Self::accesses(&mut state, c))
+            .collect::<Vec<_>>();
+        self.stream(&mut state, stream);
+
+        // latest conflicting ticket per stream
+        let mut deps = HashMap::<u64, u64>::new();
+        let mut races = vec![];
+        for access in &accesses {
+            let Some(records) = state.accesses.get(&access.resource) else {
+                continue;
+            };
+            for record in records {
+                let conflict = (access.kind == AccessKind::Write
+                    || record.access.kind == AccessKind::Write)
+                    && access.overlaps(&record.access.bytes);
+                if !conflict || Self::is_ordered(&state, record, stream.0) {
+                    continue;
+                }
+                let ticket = deps.entry(record.stream).or_insert(0);
+                *ticket = (*ticket).max(record.ticket);
+                let race = Race {
+                    resource: access.resource,
+                    first_stream: record.stream,
+                    first_access: record.access.kind,
+                    second_stream: stream.0,
+                    second_access: access.kind,
+                };
+                if !races.contains(&race) {
+                    races.push(race);
+                }
+            }
+        }
+        match self.mode {
+            SchedulerMode::Synchronize => {
+                for (&other, &ticket) in &deps {
+                    scope.wait(&state.streams[&other].event, ticket);
+                }
+                for (other, ticket) in deps {
+                    self.merge_clock(&mut state, stream, other, ticket);
+                }
+            }
+            SchedulerMode::Report => {
+                for race in &races {
+                    log::warn!("{}", race);
+                }
+                state.races.extend(races);
+            }
+        }
+
+        scope.submit(commands);
+        let s = self.stream(&mut state, stream);
+        s.ticket += 1;
+        let ticket = s.ticket;
+        scope.signal(&s.event, ticket);
+        for access in accesses {
+            let resource = access.resource;
+            let mut records = state.accesses.remove(&resource).unwrap_or_default();
+            // drop records that are shadowed by the new access: anything conflicting
+            // with them conflicts with the new access, which is ordered after them
+            records.retain(|r| {
+                !(access.covers(&r.access.bytes)
+                    && (access.kind == AccessKind::Write || r.access.kind == AccessKind::Read)
+                    && Self::is_ordered(&state, r, stream.0))
+            });
+            records.push(AccessRecord {
+                access,
+                stream: stream.0,
+                ticket,
+            });
+            state.accesses.insert(resource, records);
+            state.recorded += 1;
+        }
+    }
+
+    /// Signals `event` on `scope`, recording that waiting for `ticket` orders
+    /// after all work submitted to the stream through this scheduler so far.
+    pub fn signal<'a>(&self, scope: &Scope<'a>, event: &Event, ticket: u64) {
+        let mut state = self.state.lock();
+        let stream = scope.handle();
+        let stream_ticket = self.stream(&mut state, stream).ticket;
+        state
+            .signals
+            .insert((event.handle().0, ticket), (stream.0, stream_ticket));
+        scope.signal(event, ticket);
+    }
+    /// Waits for `event` on `scope`, see [`Scheduler::signal`].
+    pub fn wait<'a>(&self, scope: &Scope<'a>, event: &Event, ticket: u64) {
+        let mut state = self.state.lock();
+        let stream = scope.handle();
+        // events are timelines, waiting for `ticket` also waits for all smaller ones
+        let known = state
+            .signals
+            .iter()
+            .filter(|((e, t), _)| *e == event.handle().0 && *t <= ticket)
+            .map(|(_, v)| *v)
+            .collect::<Vec<_>>();
+        for (other, other_ticket) in known {
+            self.merge_clock(&mut state, stream, other, other_ticket);
+        }
+        scope.wait(event, ticket);
+    }
+}
+
+unsafe impl Send for Scheduler {}
+unsafe impl Sync for Scheduler {}
@@ -0,0 +1,1001 @@
+//! Versioned on-disk format for [`KernelDef`].
+//!
+//! The IR graph is flattened into tables of types, nodes, blocks and callables
+//! that reference each other by index. Nodes are stored in post order, i.e.
+//! every node appears after its operands and after the nodes of its child blocks,
+//! so deserialization is a single forward pass.
+//!
+//! Only self-contained kernels can be serialized: resources captured from the
+//! host environment and cpu custom ops cannot outlive the process that traced them
+//! and have to be passed as kernel arguments instead. API captures (see
+//! [`CaptureFile`]) are the exception, they record the handles of
+//! captured resources and remap them when the capture is replayed.
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use super::*;
+use crate::error::Result;
+use crate::Error;
+use ir::context::type_hash;
+use ir::{
+    AccelBinding, ArrayType, BasicBlock, Binding, BindlessArrayBinding, BufferBinding,
+    CurveBasisSet, MatrixType, ModulePools, PhiIncoming, Primitive, StructType, SwitchCase,
+    TextureBinding, VectorElementType, VectorType,
+};
+
+const MAGIC: &str = "luisa-compute-kernel";
+/// Bumped whenever the layout of [`SerializedKernel`] changes.
+pub const KERNEL_FORMAT_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize)]
+struct SerializedKernel {
+    magic: String,
+    version: u32,
+    // empty in API captures
+    signature: Vec<SerializedParam>,
+    types: Vec<SerializedType>,
+    callables: Vec<SerializedCallable>,
+    module: SerializedModule,
+    args: Vec<u32>,
+    shared: Vec<u32>,
+    block_size: [u32; 3],
+    // only present in API captures
+    #[serde(default)]
+    captures: Vec<(u32, SerializedBinding)>,
+}
+
+/// Instruction of a kernel parameter and [`type_hash`] of its type.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+struct SerializedParam {
+    kind: SerializedParamKind,
+    type_hash: u64,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+enum SerializedParamKind {
+    Buffer,
+    Bindless,
+    Texture2D,
+    Texture3D,
+    Accel,
+    Uniform,
+}
+
+/// The parameters of the kernel signature `T`, which are stable across
+/// processes and builds unlike [`std::any::type_name`].
+fn signature<T: KernelSignature>() -> Vec<SerializedParam> {
+    KernelBuilder::with_params::<T, _>(|params| {
+        params
+            .iter()
+            .map(|p| {
+                let kind = match p.get().instruction.as_ref() {
+                    Instruction::Buffer => SerializedParamKind::Buffer,
+                    Instruction::Bindless => SerializedParamKind::Bindless,
+                    Instruction::Texture2D => SerializedParamKind::Texture2D,
+                    Instruction::Texture3D => SerializedParamKind::Texture3D,
+                    Instruction::Accel => SerializedParamKind::Accel,
+                    Instruction::Uniform => SerializedParamKind::Uniform,
+                    _ => unreachable!("kernel parameters are resources or uniforms"),
+                };
+                SerializedParam {
+                    kind,
+                    type_hash: type_hash(p.type_()),
+                }
+            })
+            .collect()
+    })
+}
+
+#[derive(Serialize, Deserialize)]
+struct SerializedModule {
+    flags: u32,
+    curve_basis_set: u32,
+    nodes: Vec<SerializedNode>,
+    blocks: Vec<Vec<u32>>,
+    entry: u32,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SerializedCallable {
+    module: SerializedModule,
+    ret_type: u32,
+    args: Vec<u32>,
+    #[serde(default)]
+    captures: Vec<(u32, SerializedBinding)>,
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
+enum SerializedBinding {
+    Buffer { handle: u64, offset: u64, size: usize },
+    Texture { handle: u64, level: u32 },
+    BindlessArray(u64),
+    Accel(u64),
+}
+
+impl SerializedBinding {
+    fn new(binding: &Binding) -> Self {
+        match binding {
+            Binding::Buffer(b) => Self::Buffer {
+                handle: b.handle,
+                offset: b.offset,
+                size: b.size,
+            },
+            Binding::Texture(t) => Self::Texture {
+                handle: t.handle,
+                level: t.level,
+            },
+            Binding::BindlessArray(a) => Self::BindlessArray(a.handle),
+            Binding::Accel(a) => Self::Accel(a.handle),
+        }
+    }
+    fn load(&self, remap: &dyn Fn(u64) -> Result<u64>) -> Result<Binding> {
+        Ok(match *self {
+            Self::Buffer {
+                handle,
+                offset,
+                size,
+            } => Binding::Buffer(BufferBinding {
+                handle: remap(handle)?,
+                offset,
+                size,
+            }),
+            Self::Texture { handle, level } => Binding::Texture(TextureBinding {
+                handle: remap(handle)?,
+                level,
+            }),
+            Self::BindlessArray(handle) => Binding::BindlessArray(BindlessArrayBinding {
+                handle: remap(handle)?,
+            }),
+            Self::Accel(handle) => Binding::Accel(AccelBinding {
+                handle: remap(handle)?,
+            }),
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+enum SerializedType {
+    Void,
+    Primitive(u8),
+    Vector { element: u32, length: u32 },
+    Matrix { element: u32, dimension: u32 },
+    Struct { fields: Vec<u32>, alignment: usize, size: usize },
+    Array { element: u32, length: usize },
+    Opaque(String),
+}
+
+#[derive(Serialize, Deserialize)]
+struct SerializedNode {
+    ty: u32,
+    instruction: SerializedInstruction,
+}
+
+#[derive(Serialize, Deserialize)]
+enum SerializedConst {
+    Zero(u32),
+    One(u32),
+    Bool(bool),
+    Int32(i32),
+    Uint32(u32),
+    Int64(i64),
+    Uint64(u64),
+    Float32(f32),
+    Float64(f64),
+    Generic(Vec<u8>, u32),
+}
+
+#[derive(Serialize, Deserialize)]
+enum SerializedFunc {
+    Callable(u32),
+    Builtin(serde_json::Value),
+}
+
+#[derive(Serialize, Deserialize)]
+enum SerializedInstruction {
+    Buffer,
+    Bindless,
+    Texture2D,
+    Texture3D,
+    Accel,
+    Shared,
+    Uniform,
+    Argument { by_value: bool },
+    Invalid,
+    Local { init: u32 },
+    Const(SerializedConst),
+    Update { var: u32, value: u32 },
+    Call(SerializedFunc, Vec<u32>),
+    Phi(Vec<(u32, u32)>),
+    Return(Option<u32>),
+    Loop { body: u32, cond: u32 },
+    GenericLoop { prepare: u32, cond: u32, body: u32, update: u32 },
+    Break,
+    Continue,
+    If { cond: u32, true_branch: u32, false_branch: u32 },
+    Switch { value: u32, default: u32, cases: Vec<(i32, u32)> },
+    AdScope { body: u32, forward: bool, n_forward_grads: usize },
+    RayQuery { ray_query: u32, on_triangle_hit: u32, on_procedural_hit: u32 },
+    Print { fmt: Vec<u8>, args: Vec<u32> },
+    AdDetach(u32),
+    Comment(Vec<u8>),
+}
+
+const PRIMITIVES: [Primitive; 12] = [
+    Primitive::Bool,
+    Primitive::Int8,
+    Primitive::Uint8,
+    Primitive::Int16,
+    Primitive::Uint16,
+    Primitive::Int32,
+    Primitive::Uint32,
+    Primitive::Int64,
+    Primitive::Uint64,
+    Primitive::Float16,
+    Primitive::Float32,
+    Primitive::Float64,
+];
+
+fn malformed(what: impl std::fmt::Display) -> Error {
+    Error::InvalidArgument(format!("malformed kernel data: {}", what))
+}
+
+#[derive(Default)]
+struct Serializer {
+    // set for API captures, see `KernelRecord`
+    allow_captures: bool,
+    types: Vec<SerializedType>,
+    type_ids: HashMap<*const Type, u32>,
+    callables: Vec<SerializedCallable>,
+    callable_ids: HashMap<usize, u32>,
+}
+
+/// Per-module state, node and block ids are local to a module.
+#[derive(Default)]
+struct ModuleSerializer {
+    nodes: Vec<SerializedNode>,
+    node_ids: HashMap<NodeRef, u32>,
+    blocks: Vec<Vec<u32>>,
+    block_ids: HashMap<*const BasicBlock, u32>,
+}
+
+impl Serializer {
+    fn type_(&mut self, ty: &CArc<Type>) -> Result<u32> {
+        // types are interned, so pointer identity is enough
+        let key = CArc::as_ptr(ty);
+        if let Some(id) = self.type_ids.get(&key) {
+            return Ok(*id);
+        }
+        let vector_element = |s: &mut Self, e: &VectorElementType| match e {
+            VectorElementType::Scalar(p) => s.type_(&register_type(Type::Primitive(*p))),
+            VectorElementType::Vector(v) => s.type_(&register_type(Type::Vector(v.as_ref().clone()))),
+        };
+        let serialized = match ty.as_ref() {
+            Type::Void => SerializedType::Void,
+            Type::Primitive(p) => {
+                SerializedType::Primitive(PRIMITIVES.iter().position(|x| x == p).unwrap() as u8)
+            }
+            Type::Vector(v) => SerializedType::Vector {
+                element: vector_element(self, &v.element)?,
+                length: v.length,
+            },
+            Type::Matrix(m) => SerializedType::Matrix {
+                element: vector_element(self, &m.element)?,
+                dimension: m.dimension,
+            },
+            Type::Struct(s) => SerializedType::Struct {
+                fields: s
+                    .fields
+                    .as_ref()
+                    .iter()
+                    .map(|f| self.type_(f))
+                    .collect::<Result<_>>()?,
+                alignment: s.alignment,
+                size: s.size,
+            },
+            Type::Array(a) => SerializedType::Array {
+                element: self.type_(&a.element)?,
+                length: a.length,
+            },
+            Type::Opaque(name) => {
+                SerializedType::Opaque(String::from_utf8_lossy(name.as_ref()).into_owned())
+            }
+            Type::UserData => {
+                return Err(Error::Unsupported(
+                    "kernels with user data cannot be serialized".to_string(),
+                ))
+            }
+        };
+        let id = self.types.len() as u32;
+        self.types.push(serialized);
+        self.type_ids.insert(key, id);
+        Ok(id)
+    }
+
+    fn callable(&mut self, callable: &CallableModuleRef) -> Result<u32> {
+        let key = CArc::as_ptr(&callable.0) as usize;
+        if let Some(id) = self.callable_ids.get(&key) {
+            return Ok(*id);
+        }
+        let callable = callable.0.as_ref();
+        self.check_captures(callable.captures.as_ref(), callable.cpu_custom_ops.as_ref())?;
+        let mut m = ModuleSerializer::default();
+        let args = callable
+            .args
+            .as_ref()
+            .iter()
+            .map(|a| m.node(self, *a))
+            .collect::<Result<_>>()?;
+        let captures = m.captures(self, callable.captures.as_ref())?;
+        let module = m.module(self, &callable.module)?;
+        let serialized = SerializedCallable {
+            module,
+            ret_type: self.type_(&callable.ret_type)?,
+            args,
+            captures,
+        };
+        let id = self.callables.len() as u32;
+        self.callables.push(serialized);
+        self.callable_ids.insert(key, id);
+        Ok(id)
+    }
+
+    fn check_captures(
+        &self,
+        captures: &[Capture],
+        cpu_custom_ops: &[CArc<CpuCustomOp>],
+    ) -> Result<()> {
+        if !cpu_custom_ops.is_empty() || (!self.allow_captures && !captures.is_empty()) {
+            return Err(unsupported_capture());
+        }
+        Ok(())
+    }
+
+    fn kernel(
+        mut self,
+        kernel: &KernelModule,
+        signature: Vec<SerializedParam>,
+    ) -> Result<SerializedKernel> {
+        self.check_captures(kernel.captures.as_ref(), kernel.cpu_custom_ops.as_ref())?;
+        let mut m = ModuleSerializer::default();
+        let args = m.nodes(&mut self, kernel.args.as_ref())?;
+        let shared = m.nodes(&mut self, kernel.shared.as_ref())?;
+        let captures = m.captures(&mut self, kernel.captures.as_ref())?;
+        let module = m.module(&mut self, &kernel.module)?;
+        Ok(SerializedKernel {
+            magic: MAGIC.to_string(),
+            version: KERNEL_FORMAT_VERSION,
+            signature,
+            types: self.types,
+            callables: self.callables,
+            module,
+            args,
+            shared,
+            block_size: kernel.block_size,
+            captures,
+        })
+    }
+}
+
+fn unsupported_capture() -> Error {
+    Error::Unsupported(
+        "kernels that capture resources or cpu functions cannot be serialized, \
+         pass them as kernel arguments instead"
+            .to_string(),
+    )
+}
+
+impl ModuleSerializer {
+    fn module(mut self, s: &mut Serializer, module: &Module) -> Result<SerializedModule> {
+        let entry = self.block(s, &module.entry)?;
+        Ok(SerializedModule {
+            flags: module.flags.bits(),
+            curve_basis_set: module.curve_basis_set.bits(),
+            nodes: self.nodes,
+            blocks: self.blocks,
+            entry,
+        })
+    }
+
+    fn block(&mut self, s: &mut Serializer, block: &Pooled<BasicBlock>) -> Result<u32> {
+        let key = &**block as *const BasicBlock;
+        if let Some(id) = self.block_ids.get(&key) {
+            return Ok(*id);
+        }
+        let nodes = block
+            .iter()
+            .map(|n| self.node(s, n))
+            .collect::<Result<Vec<_>>>()?;
+        let id = self.blocks.len() as u32;
+        self.blocks.push(nodes);
+        self.block_ids.insert(key, id);
+        Ok(id)
+    }
+
+    fn nodes(&mut self, s: &mut Serializer, nodes: &[NodeRef]) -> Result<Vec<u32>> {
+        nodes.iter().map(|n| self.node(s, *n)).collect()
+    }
+
+    fn captures(
+        &mut self,
+        s: &mut Serializer,
+        captures: &[Capture],
+    ) -> Result<Vec<(u32, SerializedBinding)>> {
+        captures
+            .iter()
+            .map(|c| Ok((self.node(s, c.node)?, SerializedBinding::new(&c.binding))))
+            .collect()
+    }
+
+    fn node(&mut self, s: &mut Serializer, node: NodeRef) -> Result<u32> {
+        if let Some(id) = self.node_ids.get(&node) {
+            return Ok(*id);
+        }
+        let ty = s.type_(node.type_())?;
+        let instruction = match node.get().instruction.as_ref() {
+            Instruction::Buffer => SerializedInstruction::Buffer,
+            Instruction::Bindless => SerializedInstruction::Bindless,
+            Instruction::Texture2D => SerializedInstruction::Texture2D,
+            Instruction::Texture3D => SerializedInstruction::Texture3D,
+            Instruction::Accel => SerializedInstruction::Accel,
+            Instruction::Shared => SerializedInstruction::Shared,
+            Instruction::Uniform => SerializedInstruction::Uniform,
+            Instruction::Argument { by_value } => SerializedInstruction::Argument {
+                by_value: *by_value,
+            },
+            Instruction::Invalid => SerializedInstruction::Invalid,
+            Instruction::Local { init } => SerializedInstruction::Local {
+                init: self.node(s, *init)?,
+            },
+            Instruction::Const(c) => SerializedInstruction::Const(match c {
+                Const::Zero(t) => SerializedConst::Zero(s.type_(t)?),
+                Const::One(t) => SerializedConst::One(s.type_(t)?),
+                Const::Bool(v) => SerializedConst::Bool(*v),
+                Const::Int32(v) => SerializedConst::Int32(*v),
+                Const::Uint32(v) => SerializedConst::Uint32(*v),
+                Const::Int64(v) => SerializedConst::Int64(*v),
+                Const::Uint64(v) => SerializedConst::Uint64(*v),
+                Const::Float32(v) => SerializedConst::Float32(*v),
+                Const::Float64(v) => SerializedConst::Float64(*v),
+                Const::Generic(data, t) => {
+                    SerializedConst::Generic(data.as_ref().to_vec(), s.type_(t)?)
+                }
+                c => {
+                    return Err(Error::Unsupported(format!(
+                        "constant {:?} cannot be serialized",
+                        c
+                    )))
+                }
+            }),
+            Instruction::Update { var, value } => SerializedInstruction::Update {
+                var: self.node(s, *var)?,
+                value: self.node(s, *value)?,
+            },
+            Instruction::Call(func, args) => {
+                let func = match func {
+                    Func::Callable(c) => SerializedFunc::Callable(s.callable(c)?),
+                    Func::CpuCustomOp(_) => return Err(unsupported_capture()),
+                    func => SerializedFunc::Builtin(
+                        serde_json::to_value(func).map_err(|e| Error::Unsupported(e.to_string()))?,
+                    ),
+                };
+                SerializedInstruction::Call(func, self.nodes(s, args.as_ref())?)
+            }
+            Instruction::Phi(incomings) => SerializedInstruction::Phi(
+                incomings
+                    .as_ref()
+                    .iter()
+                    .map(|i| Ok((self.node(s, i.value)?, self.block(s, &i.block)?)))
+                    .collect::<Result<_>>()?,
+            ),
+            Instruction::Return(v) => {
+                SerializedInstruction::Return(if v.valid() { Some(self.node(s, *v)?) } else { None })
+            }
+            Instruction::Loop { body, cond } => {
+                let body = self.block(s, body)?;
+                SerializedInstruction::Loop {
+                    body,
+                    cond: self.node(s, *cond)?,
+                }
+            }
+            Instruction::GenericLoop {
+                prepare,
+                cond,
+                body,
+                update,
+            } => {
+                let prepare = self.block(s, prepare)?;
+                let cond = self.node(s, *cond)?;
+                SerializedInstruction::GenericLoop {
+                    prepare,
+                    cond,
+                    body: self.block(s, body)?,
+                    update: self.block(s, update)?,
+                }
+            }
+            Instruction::Break => SerializedInstruction::Break,
+            Instruction::Continue => SerializedInstruction::Continue,
+            Instruction::If {
+                cond,
+                true_branch,
+                false_branch,
+            } => SerializedInstruction::If {
+                cond: self.node(s, *cond)?,
+                true_branch: self.block(s, true_branch)?,
+                false_branch: self.block(s, false_branch)?,
+            },
+            Instruction::Switch {
+                value,
+                default,
+                cases,
+            } => SerializedInstruction::Switch {
+                value: self.node(s, *value)?,
+                default: self.block(s, default)?,
+                cases: cases
+                    .as_ref()
+                    .iter()
+                    .map(|c| Ok((c.value, self.block(s, &c.block)?)))
+                    .collect::<Result<_>>()?,
+            },
+            Instruction::AdScope {
+                body,
+                forward,
+                n_forward_grads,
+            } => SerializedInstruction::AdScope {
+                body: self.block(s, body)?,
+                forward: *forward,
+                n_forward_grads: *n_forward_grads,
+            },
+            Instruction::RayQuery {
+                ray_query,
+                on_triangle_hit,
+                on_procedural_hit,
+            } => SerializedInstruction::RayQuery {
+                ray_query: self.node(s, *ray_query)?,
+                on_triangle_hit: self.block(s, on_triangle_hit)?,
+                on_procedural_hit: self.block(s, on_procedural_hit)?,
+            },
+            Instruction::Print { fmt, args } => SerializedInstruction::Print {
+                fmt: fmt.as_ref().to_vec(),
+                args: self.nodes(s, args.as_ref())?,
+            },
+            Instruction::AdDetach(body) => SerializedInstruction::AdDetach(self.block(s, body)?),
+            Instruction::Comment(msg) => SerializedInstruction::Comment(msg.as_ref().to_vec()),
+            inst => {
+                return Err(Error::Unsupported(format!(
+                    "instruction {:?} cannot be serialized",
+                    inst
+                )))
+            }
+        };
+        let id = self.nodes.len() as u32;
+        self.nodes.push(SerializedNode { ty, instruction });
+        self.node_ids.insert(node, id);
+        Ok(id)
+    }
+}
+
+struct Deserializer {
+    types: Vec<CArc<Type>>,
+    callables: Vec<CallableModuleRef>,
+}
+
+struct ModuleDeserializer<'a> {
+    d: &'a Deserializer,
+    pools: CArc<ModulePools>,
+    nodes: Vec<NodeRef>,
+    blocks: Vec<Pooled<BasicBlock>>,
+}
+
+impl Deserializer {
+    fn new(serialized: &SerializedKernel, remap: &dyn Fn(u64) -> Result<u64>) -> Result<Self> {
+        let mut d = Self::with_types(&serialized.types)?;
+        // callables only reference callables serialized before them
+        for callable in &serialized.callables {
+            let pools = CArc::new(ModulePools::new());
+            let mut m = ModuleDeserializer::new(&d, pools.clone(), &callable.module)?;
+            let args = m.node_refs(&callable.args)?;
+            let captures = m.captures(&callable.captures, remap)?;
+            let module = m.module(&callable.module, ModuleKind::Function)?;
+            let callable = CallableModule {
+                module,
+                ret_type: d.type_(callable.ret_type)?,
+                cpu_custom_ops: CBoxedSlice::new(vec![]),
+                captures: CBoxedSlice::new(captures),
+                args: CBoxedSlice::new(args),
+                pools,
+            };
+            d.callables.push(CallableModuleRef(CArc::new(callable)));
+        }
+        Ok(d)
+    }
+
+    fn with_types(types: &[SerializedType]) -> Result<Self> {
+        let mut d = Self {
+            types: Vec::with_capacity(types.len()),
+            callables: vec![],
+        };
+        for ty in types {
+            let ty = match ty {
+                SerializedType::Void => Type::void(),
+                SerializedType::Primitive(p) => register_type(Type::Primitive(
+                    *PRIMITIVES
+                        .get(*p as usize)
+                        .ok_or_else(|| malformed("primitive type"))?,
+                )),
+                SerializedType::Vector { element, length } => {
+                    register_type(Type::Vector(VectorType {
+                        element: d.vector_element(*element)?,
+                        length: *length,
+                    }))
+                }
+                SerializedType::Matrix { element, dimension } => {
+                    register_type(Type::Matrix(MatrixType {
+                        element: d.vector_element(*element)?,
+                        dimension: *dimension,
+                    }))
+                }
+                SerializedType::Struct {
+                    fields,
+                    alignment,
+                    size,
+                } => register_type(Type::Struct(StructType {
+                    fields: CBoxedSlice::new(
+                        fields
+                            .iter()
+                            .map(|f| d.type_(*f))
+                            .collect::<Result<Vec<_>>>()?,
+                    ),
+                    alignment: *alignment,
+                    size: *size,
+                })),
+                SerializedType::Array { element, length } => register_type(Type::Array(ArrayType {
+                    element: d.type_(*element)?,
+                    length: *length,
+                })),
+                SerializedType::Opaque(name) => {
+                    register_type(Type::Opaque(CBoxedSlice::new(name.as_bytes().to_vec())))
+                }
+            };
+            d.types.push(ty);
+        }
+        Ok(d)
+    }
+
+    fn type_(&self, id: u32) -> Result<CArc<Type>> {
+        self.types
+            .get(id as usize)
+            .cloned()
+            .ok_or_else(|| malformed(format!("type #{}", id)))
+    }
+
+    fn vector_element(&self, id: u32) -> Result<VectorElementType> {
+        match self.type_(id)?.as_ref() {
+            Type::Primitive(p) => Ok(VectorElementType::Scalar(*p)),
+            Type::Vector(v) => Ok(VectorElementType::Vector(CArc::new(v.clone()))),
+            _ => Err(malformed("vector element type")),
+        }
+    }
+}
+
+impl<'a> ModuleDeserializer<'a> {
+    fn new(
+        d: &'a Deserializer,
+        pools: CArc<ModulePools>,
+        module: &SerializedModule,
+    ) -> Result<Self> {
+        let blocks = (0..module.blocks.len())
+            .map(|_| BasicBlock::new(&pools))
+            .collect();
+        let mut m = Self {
+            d,
+            pools,
+            nodes: Vec::with_capacity(module.nodes.len()),
+            blocks,
+        };
+        for node in &module.nodes {
+            let ty = d.type_(node.ty)?;
+            let instruction = m.instruction(&node.instruction)?;
+            let node = new_node(&m.pools, Node::new(CArc::new(instruction), ty));
+            m.nodes.push(node);
+        }
+        for (block, nodes) in m.blocks.iter().zip(&module.blocks) {
+            for n in nodes {
+                let n = *m
+                    .nodes
+                    .get(*n as usize)
+                    .ok_or_else(|| malformed(format!("node #{}", n)))?;
+                block.push(n);
+            }
+        }
+        Ok(m)
+    }
+
+    fn module(&self, module: &SerializedModule, kind: ModuleKind) -> Result<Module> {
+        Ok(Module {
+            curve_basis_set: CurveBasisSet::from_bits_truncate(module.curve_basis_set),
+            entry: self.block(module.entry)?,
+            kind,
+            pools: self.pools.clone(),
+            flags: ModuleFlags::from_bits_truncate(module.flags),
+        })
+    }
+
+    fn node(&self, id: u32) -> Result<NodeRef> {
+        // nodes are stored in post order, so operands are always created first
+        self.nodes
+            .get(id as usize)
+            .copied()
+            .ok_or_else(|| malformed(format!("forward reference to node #{}", id)))
+    }
+
+    fn node_refs(&self, ids: &[u32]) -> Result<Vec<NodeRef>> {
+        ids.iter().map(|id| self.node(*id)).collect()
+    }
+
+    fn captures(
+        &self,
+        captures: &[(u32, SerializedBinding)],
+        remap: &dyn Fn(u64) -> Result<u64>,
+    ) -> Result<Vec<Capture>> {
+        captures
+            .iter()
+            .map(|(node, binding)| {
+                Ok(Capture {
+                    node: self.node(*node)?,
+                    binding: binding.load(remap)?,
+                })
+            })
+            .collect()
+    }
+
+    fn block(&self, id: u32) -> Result<Pooled<BasicBlock>> {
+        self.blocks
+            .get(id as usize)
+            .copied()
+            .ok_or_else(|| malformed(format!("block #{}", id)))
+    }
+
+    fn instruction(&self, inst: &SerializedInstruction) -> Result<Instruction> {
+        use SerializedInstruction as S;
+        Ok(match inst {
+            S::Buffer => Instruction::Buffer,
+            S::Bindless => Instruction::Bindless,
+            S::Texture2D => Instruction::Texture2D,
+            S::Texture3D => Instruction::Texture3D,
+            S::Accel => Instruction::Accel,
+            S::Shared => Instruction::Shared,
+            S::Uniform => Instruction::Uniform,
+            S::Argument { by_value } => Instruction::Argument {
+                by_value: *by_value,
+            },
+            S::Invalid => Instruction::Invalid,
+            S::Local { init } => Instruction::Local {
+                init: self.node(*init)?,
+            },
+            S::Const(c) => Instruction::Const(match c {
+                SerializedConst::Zero(t) => Const::Zero(self.d.type_(*t)?),
+                SerializedConst::One(t) => Const::One(self.d.type_(*t)?),
+                SerializedConst::Bool(v) => Const::Bool(*v),
+                SerializedConst::Int32(v) => Const::Int32(*v),
+                SerializedConst::Uint32(v) => Const::Uint32(*v),
+                SerializedConst::Int64(v) => Const::Int64(*v),
+                SerializedConst::Uint64(v) => Const::Uint64(*v),
+                SerializedConst::Float32(v) => Const::Float32(*v),
+                SerializedConst::Float64(v) => Const::Float64(*v),
+                SerializedConst::Generic(data, t) => {
+                    Const::Generic(CBoxedSlice::new(data.clone()), self.d.type_(*t)?)
+                }
+            }),
+            S::Update { var, value } => Instruction::Update {
+                var: self.node(*var)?,
+                value: self.node(*value)?,
+            },
+            S::Call(func, args) => {
+                let func = match func {
+                    SerializedFunc::Callable(id) => Func::Callable(
+                        self.d
+                            .callables
+                            .get(*id as usize)
+                            .cloned()
+                            .ok_or_else(|| malformed(format!("callable #{}", id)))?,
+                    ),
+                    SerializedFunc::Builtin(func) => {
+                        serde_json::from_value(func.clone()).map_err(malformed)?
+                    }
+                };
+                Instruction::Call(func, CBoxedSlice::new(self.node_refs(args)?))
+            }
+            S::Phi(incomings) => Instruction::Phi(CBoxedSlice::new(
+                incomings
+                    .iter()
+                    .map(|(value, block)| {
+                        Ok(PhiIncoming {
+                            value: self.node(*value)?,
+                            block: self.block(*block)?,
+                        })
+                    })
+                    .collect::<Result<Vec<_>>>()?,
+            )),
+            S::Return(v) => Instruction::Return(match v {
+                Some(v) => self.node(*v)?,
+                None => INVALID_REF,
+            }),
+            S::Loop { body, cond } => Instruction::Loop {
+                body: self.block(*body)?,
+                cond: self.node(*cond)?,
+            },
+            S::GenericLoop {
+                prepare,
+                cond,
+                body,
+                update,
+            } => Instruction::GenericLoop {
+                prepare: self.block(*prepare)?,
+                cond: self.node(*cond)?,
+                body: self.block(*body)?,
+                update: self.block(*update)?,
+            },
+            S::Break => Instruction::Break,
+            S::Continue => Instruction::Continue,
+            S::If {
+                cond,
+                true_branch,
+                false_branch,
+            } => Instruction::If {
+                cond: self.node(*cond)?,
+                true_branch: self.block(*true_branch)?,
+                false_branch: self.block(*false_branch)?,
+            },
+            S::Switch {
+                value,
+                default,
+                cases,
+            } => Instruction::Switch {
+                value: self.node(*value)?,
+                default: self.block(*default)?,
+                cases: CBoxedSlice::new(
+                    cases
+                        .iter()
+                        .map(|(value, block)| {
+                            Ok(SwitchCase {
+                                value: *value,
+                                block: self.block(*block)?,
+                            })
+                        })
+                        .collect::<Result<Vec<_>>>()?,
+                ),
+            },
+            S::AdScope {
+                body,
+                forward,
+                n_forward_grads,
+            } => Instruction::AdScope {
+                body: self.block(*body)?,
+                forward: *forward,
+                n_forward_grads: *n_forward_grads,
+            },
+            S::RayQuery {
+                ray_query,
+                on_triangle_hit,
+                on_procedural_hit,
+            } => Instruction::RayQuery {
+                ray_query: self.node(*ray_query)?,
+                on_triangle_hit: self.block(*on_triangle_hit)?,
+                on_procedural_hit: self.block(*on_procedural_hit)?,
+            },
+            S::Print { fmt, args } => Instruction::Print {
+                fmt: CBoxedSlice::new(fmt.clone()),
+                args: CBoxedSlice::new(self.node_refs(args)?),
+            },
+            S::AdDetach(body) => Instruction::AdDetach(self.block(*body)?),
+            S::Comment(msg) => Instruction::Comment(CBoxedSlice::new(msg.clone())),
+        })
+    }
+}
+
+impl<T: KernelSignature> KernelDef<T> {
+    /// Serializes the traced kernel so that it can be compiled later,
+    /// possibly in a different process, with [`Device::compile_kernel_def`].
+    ///
+    /// Fails with [`Error::Unsupported`] if the kernel captures resources or cpu functions.
+    pub fn serialize(&self) -> Result<Vec<u8>> {
+        let serialized =
+            Serializer::default().kernel(self.inner.module.as_ref(), signature::<T>())?;
+        Ok(serde_json::to_vec(&serialized).unwrap())
+    }
+
+    /// Loads a kernel previously saved with [`KernelDef::serialize`].
+    ///
+    /// Fails with [`Error::InvalidArgument`] if the data is malformed,
+    /// was produced by an incompatible version or for a different signature.
+    pub fn deserialize(data: &[u8]) -> Result<Self> {
+        let serialized: SerializedKernel = serde_json::from_slice(data).map_err(malformed)?;
+        if serialized.magic != MAGIC {
+            return Err(malformed("not a serialized kernel"));
+        }
+        if serialized.version != KERNEL_FORMAT_VERSION {
+            return Err(Error::InvalidArgument(format!(
+                "unsupported kernel format version {}, expected {}",
+                serialized.version, KERNEL_FORMAT_VERSION
+            )));
+        }
+        if serialized.signature != signature::<T>() {
+            return Err(Error::InvalidArgument(format!(
+                "kernel signature mismatch: the kernel does not take `{}`",
+                std::any::type_name::<T>()
+            )));
+        }
+        let no_captures =
+            |_| -> Result<u64> { Err(malformed("serialized kernel captures resources")) };
+        let module = load_kernel(&serialized, &no_captures)?;
+        Ok(KernelDef {
+            inner: RawKernelDef {
+                device: None,
+                module: CArc::new(module),
+                resource_tracker: ResourceTracker::new(),
+            },
+            _marker: PhantomData,
+        })
+    }
+}
+
+fn load_kernel(
+    serialized: &SerializedKernel,
+    remap: &dyn Fn(u64) -> Result<u64>,
+) -> Result<KernelModule> {
+    let d = Deserializer::new(serialized, remap)?;
+    let pools = CArc::new(ModulePools::new());
+    let m = ModuleDeserializer::new(&d, pools.clone(), &serialized.module)?;
+    Ok(KernelModule {
+        module: m.module(&serialized.module, ModuleKind::Kernel)?,
+        cpu_custom_ops: CBoxedSlice::new(vec![]),
+        captures: CBoxedSlice::new(m.captures(&serialized.captures, remap)?),
+        shared: CBoxedSlice::new(m.node_refs(&serialized.shared)?),
+        args: CBoxedSlice::new(m.node_refs(&serialized.args)?),
+        block_size: serialized.block_size,
+        pools,
+    })
+}
+
+/// A kernel as recorded by an API capture, captured resources are kept as the
+/// handles they had when the capture was taken.
+#[derive(Serialize, Deserialize)]
+#[serde(transparent)]
+pub(crate) struct KernelRecord(SerializedKernel);
+
+impl KernelRecord {
+    /// Fails with [`Error::Unsupported`] if the kernel calls cpu functions.
+    pub(crate) fn new(kernel: &KernelModule) -> Result<Self> {
+        let s = Serializer {
+            allow_captures: true,
+            ..Default::default()
+        };
+        Ok(Self(s.kernel(kernel, vec![])?))
+    }
+    /// Rebuilds the kernel, `remap` translates handles of captured resources.
+    pub(crate) fn load(&self, remap: &dyn Fn(u64) -> Result<u64>) -> Result<KernelModule> {
+        load_kernel(&self.0, remap)
+    }
+}
+
+/// A type and the types it is composed of.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct TypeRecord {
+    types: Vec<SerializedType>,
+    root: u32,
+}
+
+impl TypeRecord {
+    pub(crate) fn new(ty: &CArc<Type>) -> Result<Self> {
+        let mut s = Serializer::default();
+        let root = s.type_(ty)?;
+        Ok(Self {
+            types: s.types,
+            root,
+        })
+    }
+    pub(crate) fn load(&self) -> Result<CArc<Type>> {
+        Deserializer::with_types(&self.types)?.type_(self.root)
+    }
+}
@@ -0,0 +1,249 @@
+use luisa::algorithms::Algorithms;
+use luisa::prelude::*;
+use luisa_compute as luisa;
+use rand::prelude::*;
+#[path = "common.rs"]
+mod common;
+use common::*;
+
+#[tracked]
+fn add_i32(a: Expr<i32>, b: Expr<i32>) -> Expr<i32> {
+    a + b
+}
+#[tracked]
+fn max_i32(a: Expr<i32>, b: Expr<i32>) -> Expr<i32> {
+    a.max_(b)
+}
+#[tracked]
+fn is_even(x: Expr<i32>) -> Expr<bool> {
+    x % 2 == 0
+}
+
+fn random_i32(n: usize, seed: u64) -> Vec<i32> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    (0..n).map(|_| rng.gen_range(-1000..1000)).collect()
+}
+
+#[test]
+fn algorithms_inclusive_scan() {
+    let device = get_device();
+    let algo = Algorithms::new(&device);
+    for n in [1, 255, 256, 257, 100000] {
+        let data = random_i32(n, n as u64);
+        let input = device.create_buffer_from_slice(&data);
+        let output = device.create_buffer::<i32>(n);
+        device.default_stream().with_scope(|s| {
+            s.submit(algo.inclusive_scan(&input.view(..), &output.view(..), add_i32));
+        });
+        let mut expected = data.clone();
+        for i in 1..n {
+            expected[i] += expected[i - 1];
+        }
+        assert_eq!(output.copy_to_vec(), expected, "n = {}", n);
+    }
+}
+#[test]
+fn algorithms_exclusive_scan() {
+    let device = get_device();
+    let algo = Algorithms::new(&device);
+    let n = 70000;
+    let data = random_i32(n, 1);
+    let buffer = device.create_buffer_from_slice(&data);
+    // in place
+    device.default_stream().with_scope(|s| {
+        s.submit(algo.exclusive_scan(&buffer.view(..), &buffer.view(..), 10, add_i32));
+    });
+    let mut expected = vec![10; n];
+    for i in 1..n {
+        expected[i] = expected[i - 1] + data[i - 1];
+    }
+    assert_eq!(buffer.copy_to_vec(), expected);
+}
+#[test]
+fn algorithms_reduce() {
+    let device = get_device();
+    let algo = Algorithms::new(&device);
+    for n in [1, 256, 1000, 300000] {
+        let data = random_i32(n, n as u64);
+        let input = device.create_buffer_from_slice(&data);
+        let output = device.create_buffer::<i32>(1);
+        device.default_stream().with_scope(|s| {
+            s.submit(algo.reduce(&input.view(..), &output.view(..), max_i32));
+        });
+        assert_eq!(
+            output.copy_to_vec()[0],
+            *data.iter().max().unwrap(),
+            "n = {}",
+            n
+        );
+        device.default_stream().with_scope(|s| {
+            s.submit(algo.reduce(&input.view(..), &output.view(..), add_i32));
+        });
+        assert_eq!(output.copy_to_vec()[0], data.iter().sum::<i32>(), "n = {}", n);
+    }
+}
+#[test]
+fn algorithms_segmented_reduce() {
+    let device = get_device();
+    let algo = Algorithms::new(&device);
+    let data = random_i32(1000, 2);
+    let offsets = [0u32, 0, 10, 500, 501, 1000];
+    let input = device.create_buffer_from_slice(&data);
+    let offsets_buf = device.create_buffer_from_slice(&offsets);
+    let output = device.create_buffer::<i32>(offsets.len() - 1);
+    device.default_stream().with_scope(|s| {
+        s.submit(algo.segmented_reduce(
+            &input.view(..),
+            &offsets_buf.view(..),
+            &output.view(..),
+            0,
+            add_i32,
+        ));
+    });
+    let expected = offsets
+        .windows(2)
+        .map(|w| data[w[0] as usize..w[1] as usize].iter().sum::<i32>())
+        .collect::<Vec<_>>();
+    assert_eq!(output.copy_to_vec(), expected);
+}
+#[test]
+fn algorithms_compact() {
+    let device = get_device();
+    let algo = Algorithms::new(&device);
+    let n = 10000;
+    let data = random_i32(n, 3);
+    let input = device.create_buffer_from_slice(&data);
+    let output = device.create_buffer::<i32>(n);
+    let count = device.create_buffer::<u32>(1);
+    device.default_stream().with_scope(|s| {
+        s.submit(algo.compact(&input.view(..), &output.view(..), &count.view(..), is_even));
+    });
+    let expected = data.iter().copied().filter(|x| x % 2 == 0).collect::<Vec<_>>();
+    let count = count.copy_to_vec()[0] as usize;
+    assert_eq!(count, expected.len());
+    assert_eq!(output.view(..count).copy_to_vec(), expected);
+}
+#[test]
+fn algorithms_sort_keys() {
+    let device = get_device();
+    let algo = Algorithms::new(&device);
+    let mut rng = StdRng::seed_from_u64(4);
+    let n = 50000;
+    let keys = (0..n).map(|_| rng.gen::<u32>()).collect::<Vec<_>>();
+    let buffer = device.create_buffer_from_slice(&keys);
+    device.default_stream().with_scope(|s| {
+        s.submit(algo.sort_keys(&buffer.view(..)));
+    });
+    let mut expected = keys.clone();
+    expected.sort();
+    assert_eq!(buffer.copy_to_vec(), expected);
+}
+#[test]
+fn algorithms_sort_pairs() {
+    let device = get_device();
+    let algo = Algorithms::new(&device);
+    let mut rng = StdRng::seed_from_u64(5);
+    let n = 20000;
+    // few distinct keys to exercise stability
+    let keys = (0..n).map(|_| rng.gen_range(0..64u32)).collect::<Vec<_>>();
+    let values = (0..n as u32).collect::<Vec<_>>();
+    let key_buf = device.create_buffer_from_slice(&keys);
+    let value_buf = device.create_buffer_from_slice(&values);
+    device.default_stream().with_scope(|s| {
+        s.submit(algo.sort_pairs(&key_buf.view(..), &value_buf.view(..)));
+    });
+    let mut expected = keys.iter().copied().zip(values).collect::<Vec<_>>();
+    expected.sort_by_key(|(k, _)| *k);
+    let (expected_keys, expected_values): (Vec<_>, Vec<_>) = expected.into_iter().unzip();
+    assert_eq!(key_buf.copy_to_vec(), expected_keys);
+    assert_eq!(value_buf.copy_to_vec(), expected_values);
+}
+#[test]
+fn algorithms_closures() {
+    let device = get_device();
+    let algo = Algorithms::new(&device);
+    let data = random_i32(1000, 6);
+    let input = device.create_buffer_from_slice(&data);
+    let output = device.create_buffer::<i32>(1);
+    // every closure gets kernels of its own
+    let max = |a: Expr<i32>, b: Expr<i32>| a.max_(b);
+    let min = |a: Expr<i32>, b: Expr<i32>| a.min_(b);
+    device.default_stream().with_scope(|s| {
+        s.submit(algo.reduce(&input.view(..), &output.view(..), max));
+    });
+    assert_eq!(output.copy_to_vec()[0], *data.iter().max().unwrap());
+    device.default_stream().with_scope(|s| {
+        s.submit(algo.reduce(&input.view(..), &output.view(..), min));
+    });
+    assert_eq!(output.copy_to_vec()[0], *data.iter().min().unwrap());
+}
+#[test]
+fn algorithms_capturing_closure() {
+    let device = get_device();
+    let algo = Algorithms::new(&device);
+    let data = random_i32(1000, 7);
+    let input = device.create_buffer_from_slice(&data);
+    let output = device.create_buffer::<i32>(1);
+    // closures of the same type capturing different floors must not share kernels
+    let max_with_floor =
+        |floor: i32| move |a: Expr<i32>, b: Expr<i32>| a.max_(b).max_(floor.expr());
+    for floor in [-2000, 2000] {
+        device.default_stream().with_scope(|s| {
+            s.submit(algo.reduce(&input.view(..), &output.view(..), max_with_floor(floor)));
+        });
+        assert_eq!(
+            output.copy_to_vec()[0],
+            (*data.iter().max().unwrap()).max(floor)
+        );
+    }
+}
+#[test]
+fn algorithms_sort_signed_keys() {
+    let device = get_device();
+    let algo = Algorithms::new(&device);
+    let keys = random_i32(20000, 8);
+    let buffer = device.create_buffer_from_slice(&keys);
+    device.default_stream().with_scope(|s| {
+        s.submit(algo.sort_keys(&buffer.view(..)));
+    });
+    let mut expected = keys.clone();
+    expected.sort();
+    assert_eq!(buffer.copy_to_vec(), expected);
+}
+#[test]
+fn algorithms_sort_float_keys() {
+    let device = get_device();
+    let algo = Algorithms::new(&device);
+    let mut rng = StdRng::seed_from_u64(9);
+    let mut keys = (0..20000)
+        .map(|_| rng.gen_range(-1e6f32..1e6f32))
+        .collect::<Vec<_>>();
+    keys.extend([0.0, -0.0, f32::INFINITY, f32::NEG_INFINITY, f32::MIN_POSITIVE]);
+    let buffer = device.create_buffer_from_slice(&keys);
+    device.default_stream().with_scope(|s| {
+        s.submit(algo.sort_keys(&buffer.view(..)));
+    });
+    let mut expected = keys.clone();
+    expected.sort_by(f32::total_cmp);
+    let bits = |v: Vec<f32>| v.into_iter().map(f32::to_bits).collect::<Vec<_>>();
+    assert_eq!(bits(buffer.copy_to_vec()), bits(expected));
+}
+#[test]
+fn algorithms_sort_u64_pairs() {
+    let device = get_device();
+    let algo = Algorithms::new(&device);
+    let mut rng = StdRng::seed_from_u64(10);
+    let n = 20000;
+    let keys = (0..n).map(|_| rng.gen::<u64>()).collect::<Vec<_>>();
+    let values = (0..n as u32).collect::<Vec<_>>();
+    let key_buf = device.create_buffer_from_slice(&keys);
+    let value_buf = device.create_buffer_from_slice(&values);
+    device.default_stream().with_scope(|s| {
+        s.submit(algo.sort_pairs(&key_buf.view(..), &value_buf.view(..)));
+    });
+    let mut expected = keys.iter().copied().zip(values).collect::<Vec<_>>();
+    expected.sort_by_key(|(k, _)| *k);
+    let (expected_keys, expected_values): (Vec<_>, Vec<_>) = expected.into_iter().unzip();
+    assert_eq!(key_buf.copy_to_vec(), expected_keys);
+    assert_eq!(value_buf.copy_to_vec(), expected_values);
+}
@@ -1,3 +1,4 @@
+use std::io::Write;
 use std::ops::Range;
 
 use alias::*;
@@ -29,6 +30,22 @@ fn finite_difference(
     outputs
 }
 
+// the interpreter does not implement autodiff, so the tests report themselves
+// as skipped on it, on stderr directly since the harness captures `eprintln!`
+fn supports_autodiff(device: &Device) -> bool {
+    if device.name() != "interpreter" {
+        return true;
+    }
+    let thread = std::thread::current();
+    let test = thread.name().unwrap_or("autodiff test");
+    let _ = writeln!(
+        std::io::stderr(),
+        "{} skipped: the interpreter does not implement autodiff",
+        test
+    );
+    false
+}
+
 fn autodiff_helper<F: Fn(&[Expr<f32>]) -> Expr<f32>>(
     range: Range<f32>,
     repeats: usize,
@@ -36,6 +53,9 @@ fn autodiff_helper<F: Fn(&[Expr<f32>]) -> Expr<f32>>(
     f: F,
 ) {
     let device = get_device();
+    if !supports_autodiff(&device) {
+        return;
+    }
     if device.name() == "dx" {
         // DX has limit on writable buffers
         if n_inputs > 8 {
@@ -713,6 +733,9 @@ fn autodiff_mat_det() {
 #[test]
 fn autodiff_select() {
     let device = get_device();
+    if !supports_autodiff(&device) {
+        return;
+    }
     let x: Buffer<f32> = device.create_buffer(1024);
     let y: Buffer<f32> = device.create_buffer(1024);
     let dx: Buffer<f32> = device.create_buffer(1024);
@@ -760,6 +783,9 @@ fn autodiff_select() {
 #[test]
 fn autodiff_detach() {
     let device = get_device();
+    if !supports_autodiff(&device) {
+        return;
+    }
     let x: Buffer<f32> = device.create_buffer(1024);
     let y: Buffer<f32> = device.create_buffer(1024);
     let dx: Buffer<f32> = device.create_buffer(1024);
@@ -813,6 +839,9 @@ fn autodiff_detach() {
 #[test]
 fn autodiff_select_nan() {
     let device = get_device();
+    if !supports_autodiff(&device) {
+        return;
+    }
     let x: Buffer<f32> = device.create_buffer(1024);
     let y: Buffer<f32> = device.create_buffer(1024);
     let dx: Buffer<f32> = device.create_buffer(1024);
@@ -857,6 +886,9 @@ fn autodiff_select_nan() {
 #[test]
 fn autodiff_if_nan() {
     let device = get_device();
+    if !supports_autodiff(&device) {
+        return;
+    }
     let x: Buffer<f32> = device.create_buffer(1024);
     let y: Buffer<f32> = device.create_buffer(1024);
     let dx: Buffer<f32> = device.create_buffer(1024);
@@ -911,6 +943,9 @@ fn autodiff_if_nan() {
 #[test]
 fn autodiff_if_phi() {
     let device = get_device();
+    if !supports_autodiff(&device) {
+        return;
+    }
     let x: Buffer<f32> = device.create_buffer(1024);
     let y: Buffer<f32> = device.create_buffer(1024);
     let dx: Buffer<f32> = device.create_buffer(1024);
@@ -959,6 +994,9 @@ fn autodiff_if_phi() {
 #[test]
 fn autodiff_if_phi_outer_no_else() {
     let device = get_device();
+    if !supports_autodiff(&device) {
+        return;
+    }
     let x: Buffer<f32> = device.create_buffer(1024);
     let y: Buffer<f32> = device.create_buffer(1024);
     let dx: Buffer<f32> = device.create_buffer(1024);
@@ -1012,6 +1050,9 @@ fn autodiff_if_phi_outer_no_else() {
 #[test]
 fn autodiff_if_phi2() {
     let device = get_device();
+    if !supports_autodiff(&device) {
+        return;
+    }
     let x: Buffer<f32> = device.create_buffer(1024);
     let y: Buffer<f32> = device.create_buffer(1024);
     let dx: Buffer<f32> = device.create_buffer(1024);
@@ -1071,6 +1112,9 @@ fn autodiff_if_phi2() {
 #[test]
 fn autodiff_if_phi3() {
     let device = get_device();
+    if !supports_autodiff(&device) {
+        return;
+    }
     let x: Buffer<f32> = device.create_buffer(1024);
     let y: Buffer<f32> = device.create_buffer(1024);
     let dx: Buffer<f32> = device.create_buffer(1024);
@@ -1136,6 +1180,9 @@ fn autodiff_if_phi3() {
 #[test]
 fn autodiff_if_phi4() {
     let device = get_device();
+    if !supports_autodiff(&device) {
+        return;
+    }
     let x: Buffer<f32> = device.create_buffer(1024);
     let y: Buffer<f32> = device.create_buffer(1024);
     let dx: Buffer<f32> = device.create_buffer(1024);
@@ -1202,6 +1249,9 @@ fn autodiff_if_phi4() {
 #[test]
 fn autodiff_if_phi5() {
     let device = get_device();
+    if !supports_autodiff(&device) {
+        return;
+    }
     let x: Buffer<f32> = device.create_buffer(1024);
     let y: Buffer<f32> = device.create_buffer(1024);
     let dx: Buffer<f32> = device.create_buffer(1024);
@@ -1275,6 +1325,9 @@ fn autodiff_if_phi5() {
 #[test]
 fn autodiff_switch() {
     let device = get_device();
+    if !supports_autodiff(&device) {
+        return;
+    }
     let t: Buffer<i32> = device.create_buffer(1024);
     let x: Buffer<f32> = device.create_buffer(1024);
     let y: Buffer<f32> = device.create_buffer(1024);
@@ -1337,6 +1390,9 @@ fn autodiff_switch() {
 #[test]
 fn autodiff_callable() {
     let device = get_device();
+    if !supports_autodiff(&device) {
+        return;
+    }
     let t: Buffer<i32> = device.create_buffer(1024);
     let x: Buffer<f32> = device.create_buffer(1024);
     let y: Buffer<f32> = device.create_buffer(1024);
@@ -1411,6 +1467,9 @@ fn autodiff_callable() {
 #[test]
 fn autodiff_callable2() {
     let device = get_device();
+    if !supports_autodiff(&device) {
+        return;
+    }
     let t: Buffer<i32> = device.create_buffer(1024);
     let x: Buffer<f32> = device.create_buffer(1024);
     let y: Buffer<f32> = device.create_buffer(1024);
@@ -1481,3 +1540,262 @@ fn autodiff_callable2() {
         }
     }
 }
+
+fn loop_step_host(i: u32, x: f64, p: f64) -> f64 {
+    x * 0.9 + (x * p).sin() * 0.3 + i as f64 * 0.01
+}
+fn loop_loss_grad_host(n: u32, x: f64, p: f64) -> (f64, f64) {
+    let loss = |x0: f64, p: f64| {
+        let mut x = x0;
+        for i in 0..n {
+            x = loop_step_host(i, x, p);
+        }
+        x * x
+    };
+    let eps = 1e-5;
+    (
+        (loss(x + eps, p) - loss(x - eps, p)) / (2.0 * eps),
+        (loss(x, p + eps) - loss(x, p - eps)) / (2.0 * eps),
+    )
+}
+fn loop_vjp_helper(
+    max_iters: u32,
+    f: impl Fn(&Buffer<u32>, &Buffer<Float2>, &Buffer<Float2>) -> Kernel<fn()>,
+) {
+    let device = get_device();
+    if !supports_autodiff(&device) {
+        return;
+    }
+    let repeats = 1024;
+    let n = device.create_buffer::<u32>(repeats);
+    let init = device.create_buffer::<Float2>(repeats);
+    let grad = device.create_buffer::<Float2>(repeats);
+    let mut rng = StdRng::seed_from_u64(max_iters as u64);
+    n.view(..).fill_fn(|_| rng.gen_range(0..=max_iters));
+    init.view(..)
+        .fill_fn(|_| Float2::new(rng.gen_range(-1.0..1.0), rng.gen_range(0.5..1.5)));
+    let kernel = f(&n, &init, &grad);
+    kernel.dispatch([repeats as u32, 1, 1]);
+    let n = n.copy_to_vec();
+    let init = init.copy_to_vec();
+    let grad = grad.copy_to_vec();
+    for i in 0..repeats {
+        let (dx, dp) = loop_loss_grad_host(n[i], init[i].x as f64, init[i].y as f64);
+        for (ad, fd) in [(grad[i].x as f64, dx), (grad[i].y as f64, dp)] {
+            assert!(
+                (ad - fd).abs() <= 1e-3 + 1e-2 * fd.abs(),
+                "n = {}, init = {:?}, ad = {}, fd = {}, cache_dir: {:?}",
+                n[i],
+                init[i],
+                ad,
+                fd,
+                kernel.cache_dir()
+            );
+        }
+    }
+}
+#[tracked]
+fn loop_step(i: Expr<u32>, s: Expr<Float2>) -> Expr<Float2> {
+    let x = s.x * 0.9 + (s.x * s.y).sin() * 0.3 + i.as_f32() * 0.01;
+    Float2::expr(x, s.y)
+}
+#[tracked]
+fn loop_loss_grad(s: Expr<Float2>) -> Expr<Float2> {
+    Float2::expr(2.0 * s.x, 0.0)
+}
+#[test]
+fn autodiff_loop_local_checkpoint() {
+    loop_vjp_helper(16, |n, init, grad| {
+        Kernel::<fn()>::new(
+            &get_device(),
+            &track!(|| {
+                let tid = dispatch_id().x;
+                let (_, d_init) = loop_vjp(
+                    n.var().read(tid),
+                    init.var().read(tid),
+                    Checkpoint::local(16),
+                    loop_step,
+                    loop_loss_grad,
+                );
+                grad.var().write(tid, d_init);
+            }),
+        )
+    });
+}
+#[test]
+fn autodiff_loop_buffer_checkpoint() {
+    let max_iters = 40;
+    let interval = 4;
+    let tape = get_device().create_buffer::<Float2>(1024 * (max_iters / interval) as usize);
+    loop_vjp_helper(max_iters, |n, init, grad| {
+        Kernel::<fn()>::new(
+            &get_device(),
+            &track!(|| {
+                let tid = dispatch_id().x;
+                let offset = tid.as_u64() * (max_iters / interval) as u64;
+                let (_, d_init) = loop_vjp(
+                    n.var().read(tid),
+                    init.var().read(tid),
+                    Checkpoint::buffer(&tape.var(), offset).interval(interval),
+                    loop_step,
+                    loop_loss_grad,
+                );
+                grad.var().write(tid, d_init);
+            }),
+        )
+    });
+}
+#[test]
+fn autodiff_loop_callable() {
+    let device = get_device();
+    if !supports_autodiff(&device) {
+        return;
+    }
+    let step = Callable::<fn(Expr<u32>, Expr<Float2>) -> Expr<Float2>>::new(&device, loop_step);
+    loop_vjp_helper(24, |n, init, grad| {
+        Kernel::<fn()>::new(
+            &device,
+            &track!(|| {
+                let tid = dispatch_id().x;
+                let (_, d_init) = loop_vjp(
+                    n.var().read(tid),
+                    init.var().read(tid),
+                    Checkpoint::local(24).interval(5),
+                    |i, s| step.call(i, s),
+                    loop_loss_grad,
+                );
+                grad.var().write(tid, d_init);
+            }),
+        )
+    });
+}
+#[tracked]
+fn loop_prologue(a: Expr<Float2>) -> Expr<Float2> {
+    Float2::expr(a.x * a.x, a.y)
+}
+#[test]
+fn autodiff_loop_composed_sections() {
+    let device = get_device();
+    if !supports_autodiff(&device) {
+        return;
+    }
+    let repeats = 1024;
+    let max_iters = 16;
+    let n = device.create_buffer::<u32>(repeats);
+    let a = device.create_buffer::<Float2>(repeats);
+    let grad = device.create_buffer::<Float2>(repeats);
+    let mut rng = StdRng::seed_from_u64(0);
+    n.view(..).fill_fn(|_| rng.gen_range(0..=max_iters));
+    a.view(..)
+        .fill_fn(|_| Float2::new(rng.gen_range(-1.0..1.0), rng.gen_range(0.5..1.5)));
+    let kernel = Kernel::<fn()>::new(
+        &device,
+        &track!(|| {
+            let tid = dispatch_id().x;
+            let x = a.var().read(tid);
+            // the code before and after the loop is differentiated in sections of its own
+            let (_, d_init) = loop_vjp(
+                n.var().read(tid),
+                loop_prologue(x),
+                Checkpoint::local(max_iters),
+                loop_step,
+                |s| {
+                    let d_s = Float2::var_zeroed();
+                    autodiff(|| {
+                        requires_grad(s);
+                        backward(s.x * s.x);
+                        *d_s = gradient(s);
+                    });
+                    **d_s
+                },
+            );
+            let d_x = Float2::var_zeroed();
+            autodiff(|| {
+                requires_grad(x);
+                backward_with_grad(loop_prologue(x), d_init);
+                *d_x = gradient(x);
+            });
+            grad.var().write(tid, d_x);
+        }),
+    );
+    kernel.dispatch([repeats as u32, 1, 1]);
+    let n = n.copy_to_vec();
+    let a = a.copy_to_vec();
+    let grad = grad.copy_to_vec();
+    for i in 0..repeats {
+        let (x, p) = (a[i].x as f64, a[i].y as f64);
+        let (dx, dp) = loop_loss_grad_host(n[i], x * x, p);
+        for (ad, fd) in [(grad[i].x as f64, dx * 2.0 * x), (grad[i].y as f64, dp)] {
+            assert!(
+                (ad - fd).abs() <= 1e-3 + 1e-2 * fd.abs(),
+                "n = {}, a = {:?}, ad = {}, fd = {}",
+                n[i],
+                a[i],
+                ad,
+                fd
+            );
+        }
+    }
+}
+#[test]
+#[should_panic(expected = "cannot differentiate loops with a dynamic trip count")]
+fn autodiff_dynamic_loop_rejected() {
+    let device = get_device();
+    let x = device.create_buffer::<f32>(16);
+    Kernel::<fn()>::new(
+        &device,
+        &track!(|| {
+            let tid = dispatch_id().x;
+            autodiff(|| {
+                let v = x.var().read(tid);
+                requires_grad(v);
+                let y = v.var();
+                for _ in 0..tid {
+                    *y *= v;
+                }
+                backward(**y);
+                x.var().write(tid, gradient(v));
+            });
+        }),
+    );
+}
+#[test]
+fn autodiff_check_gradients() {
+    let device = get_device();
+    if !supports_autodiff(&device) {
+        return;
+    }
+    let mut rng = StdRng::seed_from_u64(0);
+    let mut gen = || Float3::new(rng.gen(), rng.gen(), rng.gen());
+    let samples = (0..256).map(|_| vec![gen(), gen()]).collect::<Vec<_>>();
+    let report = check_gradients(
+        &device,
+        &samples,
+        &GradientCheckOptions::default(),
+        track!(|x| (x[0].dot(x[1]) + x[0].length()).sin()),
+    );
+    assert_eq!(report.errors.len(), 256 * 2 * 3);
+    report.assert_ok();
+}
+#[test]
+fn autodiff_check_gradients_detects_wrong_gradient() {
+    let device = get_device();
+    if !supports_autodiff(&device) {
+        return;
+    }
+    let samples = (1..=16).map(|i| vec![i as f32 * 0.25]).collect::<Vec<_>>();
+    // d/dx x * detach(x) is x according to autodiff but 2x numerically
+    let report = check_gradients(
+        &device,
+        &samples,
+        &GradientCheckOptions::default(),
+        track!(|x| x[0] * detach(x[0])),
+    );
+    assert!(!report.is_ok());
+    assert_eq!(report.failures().count(), 16);
+    for e in report.failures() {
+        let x = samples[e.sample][0];
+        assert!((e.autodiff - x).abs() < 1e-4, "{:?}", e);
+        assert!((e.finite_difference - 2.0 * x).abs() < 1e-2, "{:?}", e);
+    }
+}
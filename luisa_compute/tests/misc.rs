@@ -1,9 +1,14 @@
 use std::cell::RefCell;
 
+use luisa::lang::external::CpuFn;
 use luisa::lang::types::array::VLArrayVar;
 use luisa::lang::types::dynamic::*;
 use luisa::lang::types::vector::{alias::*, Mat2};
 use luisa::prelude::*;
+use luisa::runtime::{
+    AccessKind, CaptureFile, Profiler, ResourceId, ResourceKind, SchedulerMode, Timer,
+};
+use luisa::DeviceType;
 use luisa_compute as luisa;
 use luisa_compute_api_types::StreamTag;
 use rand::prelude::*;
@@ -1370,6 +1375,92 @@ fn bindless_byte_buffer() {
     }
 }
 
+#[test]
+fn byte_buffer_layout() {
+    let device = get_device();
+    let values = [1.0f32, 2.0, 3.0, 4.0, 5.0];
+    let mut layout = ByteBufferLayout::new();
+    let count = layout.push(values.len() as u32);
+    let array = layout.push_slice(&values);
+    let flag = layout.push(7u8);
+    let tail = layout.reserve::<Float3>(1);
+    assert_eq!(array.offset(), 4);
+    assert_eq!(flag.offset(), 24);
+    assert_eq!(tail.offset(), 32);
+    assert_eq!(layout.len(), 48);
+    let buf = device.create_buffer_from_slice(layout.as_bytes());
+    assert_eq!(buf.read_as::<u32>(count.offset()), 5);
+    assert_eq!(buf.read_as::<f32>(array.at(3).offset()), 4.0);
+    assert_eq!(buf.read_as::<u8>(flag.offset()), 7);
+    Kernel::<fn()>::new(
+        &device,
+        &track!(|| {
+            let buf = buf.var();
+            let header = buf.record::<u32>(0u64);
+            let n = header.read();
+            let array = header.next::<f32>();
+            let sum = 0.0f32.var();
+            let i = 0u32.var();
+            while i < n {
+                *sum += array.at(**i).read();
+                *i += 1;
+            }
+            let flag = array.after::<u8>(n);
+            let tail = flag.next::<Float3>();
+            tail.write(Float3::expr(sum, flag.read().as_f32(), n.as_f32()));
+        }),
+    )
+    .dispatch([1, 1, 1]);
+    assert_eq!(buf.read_as::<Float3>(tail.offset()), Float3::new(15.0, 7.0, 5.0));
+}
+
+#[test]
+fn byte_buffer_view_read_write_as() {
+    let device = get_device();
+    let buf = device.create_byte_buffer(64);
+    buf.fill(0);
+    let view = buf.view(16..32);
+    view.write_as::<Float2>(8, Float2::new(1.0, 2.0));
+    assert_eq!(view.read_as::<Float2>(8), Float2::new(1.0, 2.0));
+    assert_eq!(buf.read_as::<f32>(28), 2.0);
+    view.write_as::<u32>(12, 3);
+    assert_eq!(buf.read_as::<u32>(28), 3);
+}
+
+#[test]
+#[should_panic(expected = "is not 4-byte aligned")]
+fn byte_buffer_read_as_unaligned() {
+    let device = get_device();
+    let buf = device.create_byte_buffer(64);
+    // aligned in the view but not in the buffer
+    buf.view(2..).read_as::<u32>(4);
+}
+
+#[test]
+#[cfg(debug_assertions)]
+#[should_panic(expected = "unaligned `")]
+fn byte_record_unaligned_in_view() {
+    let device = interpreter_device();
+    let buf = device.create_byte_buffer(64);
+    let view = buf.view(4..);
+    Kernel::<fn()>::new(
+        &device,
+        &track!(|| {
+            // aligned in the view but not in the buffer
+            view.var().record::<Float4>(0u64).read();
+        }),
+    )
+    .dispatch([1, 1, 1]);
+}
+
+#[test]
+#[should_panic(expected = "out of bounds")]
+fn byte_buffer_write_as_out_of_bounds() {
+    let device = get_device();
+    let buf = device.create_byte_buffer(64);
+    buf.view(..16).write_as::<Float4>(16, Float4::new(0.0, 0.0, 0.0, 0.0));
+}
+
 #[test]
 fn is_finite() {
     let device = get_device();
@@ -1472,6 +1563,12 @@ pub struct Bar {
     a: [i32; 4],
     f: Foo,
 }
+#[derive(Clone, Copy, Debug, Value, Soa, PartialEq)]
+#[repr(C)]
+pub struct Mixed {
+    a: f32,
+    b: u64,
+}
 #[derive(Clone, Copy, Debug, Value)]
 #[repr(C)]
 pub struct Foo2<T: Value> {
@@ -1820,3 +1917,1432 @@ fn test_tracked() {
         panic!();
     }
 }
+#[test]
+fn try_create_invalid_arguments() {
+    let device = get_device();
+    assert!(matches!(
+        device.try_create_bindless_array(0),
+        Err(luisa::Error::InvalidArgument(_))
+    ));
+    assert!(matches!(
+        device.try_create_buffer::<u64>(usize::MAX),
+        Err(luisa::Error::InvalidArgument(_))
+    ));
+    assert!(matches!(
+        device.try_create_tex2d::<Float4>(PixelStorage::Byte4, 0, 512, 1),
+        Err(luisa::Error::InvalidArgument(_))
+    ));
+    assert!(matches!(
+        device.try_create_tex3d::<Float4>(PixelStorage::Byte4, 4, 4, 4, 4),
+        Err(luisa::Error::InvalidArgument(_))
+    ));
+    let exe = std::env::current_exe().unwrap();
+    let ctx = Context::new(exe.parent().unwrap().parent().unwrap());
+    assert!(matches!(
+        ctx.try_create_device("not_a_backend"),
+        Err(luisa::Error::Unsupported(_))
+    ));
+}
+#[test]
+fn try_create_kernel() {
+    let device = get_device();
+    let x = device.try_create_buffer::<u32>(1024).unwrap();
+    let kernel = device
+        .try_create_kernel::<fn(Buffer<u32>)>(&track!(|x| {
+            let tid = dispatch_id().x;
+            x.write(tid, tid * 2);
+        }))
+        .unwrap();
+    kernel.dispatch([1024, 1, 1], &x);
+    let x = x.copy_to_vec();
+    for i in 0..1024 {
+        assert_eq!(x[i], i as u32 * 2);
+    }
+}
+#[test]
+fn kernel_def_serialize_roundtrip() {
+    let device = get_device();
+    type Signature = fn(Buffer<f32>, Buffer<f32>, u32);
+    let square = track!(Callable::<fn(Expr<f32>) -> Expr<f32>>::new(&device, |x| x * x));
+    let def = KernelDef::<Signature>::new(
+        &device,
+        &track!(|x, y, n| {
+            let tid = dispatch_id().x;
+            let acc = 0.0f32.var();
+            for i in 0..n {
+                *acc += square.call(x.read(tid)) + i.as_f32();
+            }
+            if tid % 2 == 0 {
+                y.write(tid, acc);
+            } else {
+                y.write(tid, -acc);
+            }
+        }),
+    );
+    let data = def.serialize().unwrap();
+    let loaded = KernelDef::<Signature>::deserialize(&data).unwrap();
+    assert!(KernelDef::<fn(Buffer<f32>)>::deserialize(&data).is_err());
+    assert!(KernelDef::<fn(Buffer<f32>, Buffer<f32>, f32)>::deserialize(&data).is_err());
+    assert!(KernelDef::<fn(Buffer<f32>, Buffer<u32>, u32)>::deserialize(&data).is_err());
+
+    let x = device.create_buffer_from_fn(1024, |i| i as f32 * 0.01);
+    let y0 = device.create_buffer::<f32>(1024);
+    let y1 = device.create_buffer::<f32>(1024);
+    device.compile_kernel_def(&def).dispatch([1024, 1, 1], &x, &y0, &4);
+    device
+        .compile_kernel_def(&loaded)
+        .dispatch([1024, 1, 1], &x, &y1, &4);
+    assert_eq!(y0.copy_to_vec(), y1.copy_to_vec());
+}
+#[test]
+fn kernel_def_serialize_rejects_captures() {
+    let device = get_device();
+    let x = device.create_buffer::<f32>(1024);
+    let def = KernelDef::<fn()>::new(
+        &device,
+        &track!(|| {
+            let tid = dispatch_id().x;
+            x.write(tid, tid.as_f32());
+        }),
+    );
+    assert!(matches!(
+        def.serialize(),
+        Err(luisa::Error::Unsupported(_))
+    ));
+}
+#[test]
+fn command_graph_replay_matches_direct_submission() {
+    let device = get_device();
+    let kernel = device.create_kernel::<fn(Buffer<f32>, Buffer<f32>, f32)>(&track!(|x, y, s| {
+        let tid = dispatch_id().x;
+        y.write(tid, y.read(tid) * 0.5 + x.read(tid) * s);
+    }));
+    let n = 1024;
+    let x = device.create_buffer_from_fn(n, |i| i as f32);
+    let run = |graph_mode: bool| {
+        let y = device.create_buffer_from_fn(n, |i| (i % 7) as f32);
+        let z = device.create_buffer::<f32>(n);
+        let sizes = [n as u32, 100, 0, 513];
+        if graph_mode {
+            let mut graph = CommandGraph::new(&device);
+            let node = graph.push(kernel.dispatch_async([1, 1, 1], &x, &y, &0.0f32));
+            graph.push(y.view(..).copy_to_buffer_async(&z.view(..)));
+            let scale = graph.uniform::<f32>(node, 2);
+            assert_eq!(graph.len(), 2);
+            for (frame, size) in sizes.iter().enumerate() {
+                graph.set_uniform(scale, frame as f32 + 1.0);
+                graph.set_dispatch_size(node, [*size, 1, 1]);
+                assert_eq!(graph.dispatch_size(node), [*size, 1, 1]);
+                graph.replay(&device.default_stream());
+            }
+        } else {
+            for (frame, size) in sizes.iter().enumerate() {
+                device.default_stream().with_scope(|s| {
+                    s.submit([
+                        kernel.dispatch_async([*size, 1, 1], &x, &y, &(frame as f32 + 1.0)),
+                        y.view(..).copy_to_buffer_async(&z.view(..)),
+                    ]);
+                });
+            }
+        }
+        (y.copy_to_vec(), z.copy_to_vec())
+    };
+    assert_eq!(run(true), run(false));
+}
+#[test]
+fn command_graph_bindless_update_and_stream() {
+    let device = get_device();
+    let stream = device.create_stream(StreamTag::Compute);
+    let a = device.create_buffer_from_fn(64, |i| i as u32);
+    let out = device.create_buffer::<u32>(64);
+    let heap = device.create_bindless_array(2);
+    let kernel = device.create_kernel::<fn(Buffer<u32>)>(&track!(|out| {
+        let tid = dispatch_id().x;
+        out.write(tid, heap.buffer::<u32>(0).read(tid) * 3);
+    }));
+    heap.emplace_buffer_async(0, &a);
+    let mut graph = CommandGraph::new(&device);
+    graph.extend([heap.update_async(), kernel.dispatch_async([64, 1, 1], &out)]);
+    for _ in 0..3 {
+        graph.replay(&stream);
+    }
+    let expected = (0..64).map(|i| i * 3).collect::<Vec<u32>>();
+    assert_eq!(out.copy_to_vec(), expected);
+    // each replay unlocks the array once it has completed
+    heap.emplace_buffer_async(1, &a);
+    heap.update();
+
+    // the slots released by an allocator update must only be freed once
+    let allocator = device.create_bindless_allocator(1);
+    let slot = allocator.alloc_buffer(&a);
+    assert!(graph.try_push(allocator.update_async()).is_err());
+    drop(slot);
+    allocator.update();
+}
+#[test]
+#[should_panic]
+fn command_graph_uniform_type_mismatch() {
+    let device = get_device();
+    let kernel = device.create_kernel::<fn(Buffer<f32>, f32)>(&track!(|x, s| {
+        x.write(dispatch_id().x, s);
+    }));
+    let x = device.create_buffer::<f32>(16);
+    let mut graph = CommandGraph::new(&device);
+    let node = graph.push(kernel.dispatch_async([16, 1, 1], &x, &1.0f32));
+    // same size, different type
+    graph.uniform::<u32>(node, 1);
+}
+#[test]
+fn scheduler_synchronizes_streams() {
+    let device = get_device();
+    let scheduler = device.create_scheduler();
+    let compute = device.create_stream(StreamTag::Compute);
+    let copy = device.create_stream(StreamTag::Copy);
+    let n = 1 << 16;
+    let x = device.create_buffer::<u32>(n);
+    let y = device.create_buffer::<u32>(n);
+    let fill = device.create_kernel::<fn(Buffer<u32>)>(&track!(|x| {
+        let tid = dispatch_id().x;
+        let v = tid.var();
+        for _ in 0..1000u32 {
+            *v = (v * 1103515245 + 12345) % 65536;
+        }
+        x.write(tid, v + 1);
+    }));
+    let expected = {
+        fill.dispatch([n as u32, 1, 1], &y);
+        y.copy_to_vec()
+    };
+    let zeros = vec![0u32; n];
+    for _ in 0..4 {
+        let c = compute.scope();
+        let d = copy.scope();
+        scheduler.submit(&d, [y.copy_from_async(&zeros)]);
+        scheduler.submit(&c, [fill.dispatch_async([n as u32, 1, 1], &x)]);
+        scheduler.submit(&d, [x.copy_to_buffer_async(&y)]);
+        drop(d);
+        drop(c);
+        assert_eq!(y.copy_to_vec(), expected);
+    }
+    assert!(scheduler.races().is_empty());
+}
+#[test]
+fn scheduler_reports_races() {
+    let device = get_device();
+    let scheduler = device.create_scheduler_with_mode(SchedulerMode::Report);
+    let a = device.create_stream(StreamTag::Compute);
+    let b = device.create_stream(StreamTag::Compute);
+    let gate_stream = device.create_stream(StreamTag::Compute);
+    let gate = device.create_event();
+    let x = device.create_buffer::<f32>(1024);
+    let y = device.create_buffer::<f32>(1024);
+    let z = device.create_buffer::<f32>(1024);
+    let read_x = device.create_kernel::<fn(Buffer<f32>, Buffer<f32>)>(&track!(|x, y| {
+        let tid = dispatch_id().x;
+        y.write(tid, x.read(tid) * 2.0);
+    }));
+    {
+        let sa = a.scope();
+        let sb = b.scope();
+        // keeps the work on `a` from completing until all of it has been submitted
+        sa.wait(&gate, 1);
+        scheduler.submit(&sa, [read_x.dispatch_async([1024, 1, 1], &x, &y)]);
+        // reading the same buffer on two streams is fine
+        scheduler.submit(&sb, [read_x.dispatch_async([1024, 1, 1], &x, &z)]);
+        assert!(scheduler.races().is_empty());
+        // writing `y` while `a` may still be writing it is not
+        scheduler.submit(&sb, [z.copy_to_buffer_async(&y)]);
+        // explicitly synchronized through the scheduler
+        scheduler.signal(&sa, &gate, 2);
+        scheduler.wait(&sb, &gate, 2);
+        scheduler.submit(&sb, [x.copy_to_buffer_async(&y)]);
+        gate_stream.with_scope(|s| {
+            s.signal(&gate, 1);
+        });
+    }
+    let races = scheduler.races();
+    assert_eq!(races.len(), 1, "{}", scheduler.race_report());
+    assert_eq!(races[0].resource, ResourceId::Buffer(y.handle().0));
+    assert_eq!(races[0].first_stream, a.handle().0);
+    assert_eq!(races[0].first_access, AccessKind::Write);
+    assert_eq!(races[0].second_stream, b.handle().0);
+    assert_eq!(races[0].second_access, AccessKind::Write);
+}
+#[test]
+fn scheduler_forgets_completed_work() {
+    let device = get_device();
+    let scheduler = device.create_scheduler_with_mode(SchedulerMode::Report);
+    let a = device.create_stream(StreamTag::Compute);
+    let b = device.create_stream(StreamTag::Compute);
+    let gate_stream = device.create_stream(StreamTag::Compute);
+    let gate = device.create_event();
+    let x = device.create_buffer::<f32>(1024);
+    let y = device.create_buffer::<f32>(1024);
+    let z = device.create_buffer::<f32>(1024);
+    let values = vec![1.0f32; 1024];
+    // disjoint writes do not shadow each other, and complete with their scope
+    for i in 0..1024 {
+        let sa = a.scope();
+        scheduler.submit(&sa, [x.view(i..i + 1).copy_from_async(&values[i..i + 1])]);
+    }
+    {
+        let sa = a.scope();
+        let sb = b.scope();
+        scheduler.submit(&sb, [x.copy_to_buffer_async(&z)]);
+        assert!(scheduler.races().is_empty(), "{}", scheduler.race_report());
+        // work still in flight is not forgotten
+        sa.wait(&gate, 1);
+        scheduler.submit(&sa, [y.copy_from_async(&values)]);
+        for i in 0..1024 {
+            scheduler.submit(&sb, [z.view(i..i + 1).copy_from_async(&values[i..i + 1])]);
+        }
+        scheduler.submit(&sb, [y.copy_to_buffer_async(&x)]);
+        gate_stream.with_scope(|s| {
+            s.signal(&gate, 1);
+        });
+    }
+    let races = scheduler.races();
+    assert_eq!(races.len(), 1, "{}", scheduler.race_report());
+    assert_eq!(races[0].resource, ResourceId::Buffer(y.handle().0));
+    assert_eq!(races[0].first_stream, a.handle().0);
+    assert_eq!(races[0].first_access, AccessKind::Write);
+}
+#[test]
+fn scheduler_orders_transitively() {
+    let device = get_device();
+    let scheduler = device.create_scheduler_with_mode(SchedulerMode::Report);
+    let a = device.create_stream(StreamTag::Compute);
+    let b = device.create_stream(StreamTag::Compute);
+    let c = device.create_stream(StreamTag::Compute);
+    let d = device.create_stream(StreamTag::Compute);
+    let gate_stream = device.create_stream(StreamTag::Compute);
+    let gate = device.create_event();
+    let ab = device.create_event();
+    let bc = device.create_event();
+    let x = device.create_buffer::<f32>(1024);
+    let w = device.create_buffer::<f32>(1024);
+    let y = device.create_buffer::<f32>(1024);
+    let z = device.create_buffer::<f32>(1024);
+    let write_w = track!(Callable::<fn(Expr<u32>)>::new(&device, |i| {
+        w.write(i, 1.0);
+    }));
+    let fill = device.create_kernel::<fn(Buffer<f32>)>(&track!(|x| {
+        let tid = dispatch_id().x;
+        x.write(tid, 2.0);
+        write_w.call(tid);
+    }));
+    {
+        let (sa, sb, sc, sd) = (a.scope(), b.scope(), c.scope(), d.scope());
+        // keeps the work on `a` from completing until all of it has been submitted
+        sa.wait(&gate, 1);
+        scheduler.submit(&sa, [fill.dispatch_async([1024, 1, 1], &x)]);
+        // `c` waits for `b`, which waited for `a`
+        scheduler.signal(&sa, &ab, 1);
+        scheduler.wait(&sb, &ab, 1);
+        scheduler.signal(&sb, &bc, 1);
+        scheduler.wait(&sc, &bc, 1);
+        scheduler.submit(&sc, [z.copy_to_buffer_async(&x)]);
+        assert!(scheduler.races().is_empty(), "{}", scheduler.race_report());
+        // `w` is written by the callable
+        scheduler.submit(&sd, [w.copy_to_buffer_async(&y)]);
+        gate_stream.with_scope(|s| {
+            s.signal(&gate, 1);
+        });
+    }
+    let races = scheduler.races();
+    assert_eq!(races.len(), 1, "{}", scheduler.race_report());
+    assert_eq!(races[0].resource, ResourceId::Buffer(w.handle().0));
+    assert_eq!(races[0].first_stream, a.handle().0);
+    assert_eq!(races[0].first_access, AccessKind::Write);
+    assert_eq!(races[0].second_stream, d.handle().0);
+}
+#[test]
+fn timer_and_profiler() {
+    let device = get_device();
+    let busy = device.create_kernel_with_options::<fn(Buffer<f32>, u32)>(
+        KernelBuildOptions {
+            name: Some("busy".to_string()),
+            ..Default::default()
+        },
+        &track!(|x, n| {
+            let tid = dispatch_id().x;
+            let acc = 0.0f32.var();
+            for i in 0..n {
+                *acc += (i.as_f32() * 0.001).sin();
+            }
+            x.write(tid, acc);
+        }),
+    );
+    let unnamed = device.create_kernel::<fn(Buffer<f32>)>(&track!(|x| {
+        x.write(dispatch_id().x, 1.0);
+    }));
+    let x = device.create_buffer::<f32>(1 << 14);
+    let y = device.create_buffer::<f32>(1 << 14);
+
+    let timer = Timer::new();
+    assert!(timer.elapsed().is_none());
+    device.default_stream().with_scope(|s| {
+        s.submit([
+            timer.start_async(),
+            busy.dispatch_async([1 << 14, 1, 1], &x, &1000),
+            timer.stop_async(),
+        ]);
+    });
+    assert!(timer.elapsed().unwrap() > std::time::Duration::ZERO);
+
+    let profiler = Profiler::new();
+    device.default_stream().with_scope(|s| {
+        for _ in 0..3 {
+            profiler.submit(
+                s,
+                [
+                    busy.dispatch_async([1 << 14, 1, 1], &x, &1000),
+                    x.copy_to_buffer_async(&y),
+                    unnamed.dispatch_async([1 << 14, 1, 1], &x),
+                ],
+            );
+        }
+    });
+    let stats = profiler.stats();
+    assert_eq!(stats.len(), 2, "{}", profiler.report());
+    let busy_stats = stats.iter().find(|s| s.name == "busy").unwrap();
+    assert_eq!(busy_stats.count, 3);
+    assert_eq!(busy_stats.mean, busy_stats.total / 3);
+    assert!(busy_stats.total > std::time::Duration::ZERO);
+    assert!(stats.iter().any(|s| s.name.starts_with("kernel_") && s.count == 3));
+    let records = profiler.records();
+    assert_eq!(records.len(), 6);
+    assert!(records.windows(2).all(|w| w[0].end <= w[1].start));
+
+    let trace: serde_json::Value = serde_json::from_str(&profiler.chrome_trace()).unwrap();
+    let events = trace["traceEvents"].as_array().unwrap();
+    assert_eq!(events.len(), 6);
+    assert!(events.iter().all(|e| e["ph"] == "X" && e["dur"].as_f64().unwrap() >= 0.0));
+    profiler.clear();
+    assert!(profiler.stats().is_empty());
+}
+
+#[test]
+fn memory_stats() {
+    let device = get_device();
+    assert_eq!(device.memory_stats().total.count, 0);
+    let a = device.create_buffer::<f32>(1024);
+    let (b, tex) = {
+        let _label = device.memory_label("gbuffer");
+        let b = device.create_buffer::<u32>(256);
+        let tex = device.create_tex2d::<Float4>(PixelStorage::Float4, 16, 16, 1);
+        (b, tex)
+    };
+    let c = device.create_buffer::<u32>(16);
+    let stats = device.memory_stats();
+    assert_eq!(stats.total.count, 4, "{}", stats);
+    assert_eq!(stats.kind(ResourceKind::Buffer).count, 3);
+    assert!(stats.kind(ResourceKind::Buffer).bytes >= (1024 + 256 + 16) * 4);
+    assert_eq!(stats.kind(ResourceKind::Texture).bytes, 16 * 16 * 16);
+    let gbuffer = stats.label("gbuffer");
+    assert_eq!(gbuffer.count, 2);
+    assert!(gbuffer.bytes >= 256 * 4 + 16 * 16 * 16);
+    assert_eq!(stats.groups[&(ResourceKind::Buffer, None)].count, 2);
+
+    let peak = stats.total.bytes;
+    drop(a);
+    drop(tex);
+    let stats = device.memory_stats();
+    assert_eq!(stats.total.count, 2);
+    assert!(stats.total.bytes < peak);
+    assert_eq!(stats.peak_bytes, peak);
+    drop((b, c));
+    assert_eq!(device.memory_stats().total, Default::default());
+    assert!(device.leak_report().is_none());
+
+    // labels only apply to the device they were pushed on
+    let other = get_device();
+    let _label = other.memory_label("other");
+    let _d = device.create_buffer::<u32>(16);
+    let _e = other.create_buffer::<u32>(16);
+    assert_eq!(device.memory_stats().label("other").count, 0);
+    assert_eq!(other.memory_stats().label("other").count, 1);
+}
+
+#[test]
+fn leak_report_on_device_drop() {
+    let device = get_device();
+    let report = std::sync::Arc::new(std::sync::Mutex::new(None::<String>));
+    {
+        let report = report.clone();
+        device.set_leak_handler(move |r| *report.lock().unwrap() = Some(r.to_string()));
+    }
+    let leaked = device.create_buffer::<f32>(64);
+    let kernel = Kernel::<fn(Buffer<f32>)>::new(
+        &device,
+        &track!(|buf| {
+            buf.write(dispatch_id().x, 1.0f32);
+        }),
+    );
+    kernel.dispatch([64, 1, 1], &leaked);
+    std::mem::forget(leaked);
+    drop(kernel);
+
+    // resources and kernels do not count as handles held by the application
+    let clone = device.clone();
+    drop(device);
+    assert!(report.lock().unwrap().is_none());
+    drop(clone);
+    let report = report.lock().unwrap().take().expect("leak not reported");
+    assert!(report.contains("Buffer"), "{}", report);
+}
+
+#[test]
+fn leak_report() {
+    let device = get_device();
+    let leaked = {
+        let _label = device.memory_label("scratch");
+        device.create_buffer::<f32>(64)
+    };
+    std::mem::forget(leaked);
+    let report = device.leak_report().unwrap();
+    assert!(report.contains("Buffer"), "{}", report);
+    assert!(report.contains("[scratch]"), "{}", report);
+}
+
+#[test]
+fn resource_names() {
+    let device = get_device();
+    let buffer = device.create_buffer::<f32>(16).with_name("positions");
+    assert_eq!(buffer.name().as_deref(), Some("positions"));
+    assert_eq!(buffer.view(4..8).name().as_deref(), Some("positions"));
+    let tex = device.create_tex2d::<Float4>(PixelStorage::Byte4, 4, 4, 1);
+    assert!(tex.name().is_none());
+    tex.set_name("albedo");
+    assert_eq!(tex.name().as_deref(), Some("albedo"));
+    let stream = device.create_stream(StreamTag::Compute).with_name("async compute");
+    assert_eq!(stream.name().as_deref(), Some("async compute"));
+    device.default_stream().set_name("main");
+    assert_eq!(device.default_stream().name().as_deref(), Some("main"));
+    let event = device.create_event().with_name("frame done");
+    assert_eq!(event.name().as_deref(), Some("frame done"));
+
+    let report = device.leak_report().unwrap();
+    assert!(report.contains("`positions`"), "{}", report);
+    assert!(report.contains("`albedo`"), "{}", report);
+}
+
+#[test]
+#[should_panic(expected = "buffer `positions`")]
+fn resource_name_in_panic() {
+    let device = get_device();
+    let buffer = device.create_buffer::<f32>(16).with_name("positions");
+    buffer.copy_from(&[0.0; 8]);
+}
+
+fn interpreter_device() -> Device {
+    let curr_exe = std::env::current_exe().unwrap();
+    let runtime_dir = curr_exe.parent().unwrap().parent().unwrap();
+    Context::new(runtime_dir).create_device(DeviceType::Interpreter)
+}
+
+fn collatz(device: &Device, n: u32) -> (Vec<u32>, Vec<f32>, u32) {
+    let steps = device.create_buffer::<u32>(n as usize);
+    let values = device.create_buffer::<f32>(n as usize);
+    let total = device.create_buffer_from_slice(&[0u32]);
+    let scale = Callable::<fn(Expr<f32>, Expr<f32>) -> Expr<f32>>::new(device, |x, s| {
+        track!(if x > 10.0 { x * s } else { x.sqrt() + s })
+    });
+    let kernel = Kernel::<fn(Buffer<u32>, Buffer<f32>, f32)>::new(
+        device,
+        &track!(|steps, values, s| {
+            let i = dispatch_id().x;
+            let x = (i + 1).var();
+            let count = 0u32.var();
+            while x != 1 {
+                if x % 2 == 0 {
+                    *x /= 2;
+                } else {
+                    *x = 3 * x + 1;
+                }
+                *count += 1;
+            }
+            steps.write(i, count);
+            values.write(i, scale.call(count.cast_f32(), s));
+            total.var().atomic_fetch_add(0, count);
+        }),
+    );
+    kernel.dispatch([n, 1, 1], &steps, &values, &0.5);
+    (
+        steps.copy_to_vec(),
+        values.copy_to_vec(),
+        total.copy_to_vec()[0],
+    )
+}
+
+#[test]
+fn interpreter_matches_device() {
+    let (steps, values, total) = collatz(&interpreter_device(), 100);
+    assert_eq!(steps[26], 111);
+    assert_eq!(total, steps.iter().sum::<u32>());
+    let (expected_steps, expected_values, expected_total) = collatz(&get_device(), 100);
+    assert_eq!(steps, expected_steps);
+    assert_eq!(total, expected_total);
+    for (a, b) in values.iter().zip(&expected_values) {
+        assert!((a - b).abs() < 1e-5, "{} != {}", a, b);
+    }
+}
+
+#[test]
+fn interpreter_cpu_fn() {
+    let device = interpreter_device();
+    let buffer = device.create_buffer::<u32>(64);
+    let double = CpuFn::new(|x: &mut u32| *x *= 2);
+    let kernel = Kernel::<fn()>::new(
+        &device,
+        &track!(|| {
+            let i = dispatch_id().x;
+            buffer.var().write(i, double.call(i));
+        }),
+    );
+    kernel.dispatch([64, 1, 1]);
+    let data = buffer.copy_to_vec();
+    assert!(data.iter().enumerate().all(|(i, x)| *x == 2 * i as u32));
+}
+
+// debug builds check indices in the kernel before the interpreter does
+#[test]
+#[cfg_attr(
+    debug_assertions,
+    should_panic(expected = "index out of bounds of buffer")
+)]
+#[cfg_attr(
+    not(debug_assertions),
+    should_panic(expected = "buffer index 16 out of bounds")
+)]
+fn interpreter_out_of_bounds() {
+    let device = interpreter_device();
+    let buffer = device.create_buffer::<f32>(16);
+    let kernel = Kernel::<fn()>::new(
+        &device,
+        &track!(|| {
+            buffer.var().write(dispatch_id().x + 1, 1.0f32);
+        }),
+    );
+    kernel.dispatch([16, 1, 1]);
+}
+
+#[test]
+#[cfg_attr(
+    debug_assertions,
+    should_panic(expected = "index out of bounds of buffer")
+)]
+#[cfg_attr(
+    not(debug_assertions),
+    should_panic(expected = "buffer index 16 out of bounds")
+)]
+fn interpreter_panic_in_synchronized_block() {
+    let device = interpreter_device();
+    let buffer = device.create_buffer::<f32>(16);
+    let kernel = Kernel::<fn()>::new(
+        &device,
+        &track!(|| {
+            set_block_size([4, 1, 1]);
+            // the other threads of the block wait at the barrier
+            if dispatch_id().x == 1 {
+                buffer.var().write(16, 1.0f32);
+            }
+            luisa::lang::functions::sync_block();
+            buffer.var().write(dispatch_id().x, 2.0f32);
+        }),
+    );
+    kernel.dispatch([4, 1, 1]);
+}
+
+#[test]
+fn interpreter_out_of_memory() {
+    let device = interpreter_device();
+    assert!(matches!(
+        device.try_create_buffer::<u64>(usize::MAX / 8),
+        Err(luisa::Error::OutOfMemory { .. })
+    ));
+    assert!(device.try_create_buffer::<u64>(16).is_ok());
+}
+
+#[test]
+fn capture_replay() {
+    let curr_exe = std::env::current_exe().unwrap();
+    let runtime_dir = curr_exe.parent().unwrap().parent().unwrap();
+    let path = std::env::temp_dir().join(format!("luisa-capture-{}.bin", std::process::id()));
+    let device =
+        Context::new(runtime_dir).create_device_with_capture(DeviceType::Interpreter, &path);
+    let x = device.create_buffer_from_fn(256, |i| i as f32);
+    let y = device.create_buffer::<f32>(256);
+    let kernel = Kernel::<fn(Buffer<f32>)>::new(
+        &device,
+        &track!(|y| {
+            let i = dispatch_id().x;
+            y.write(i, x.var().read(i) * 2.0 + 1.0);
+        }),
+    );
+    kernel.dispatch([256, 1, 1], &y);
+    let expected = y.copy_to_vec();
+
+    let capture = CaptureFile::load(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(capture.device_name(), "interpreter");
+    let mut replayer = capture.replay(&get_device());
+    replayer.run().unwrap();
+    let data = replayer.read_buffer(y.handle().0).unwrap();
+    let replayed = data
+        .chunks(4)
+        .map(|b| f32::from_le_bytes(b.try_into().unwrap()))
+        .collect::<Vec<_>>();
+    assert_eq!(replayed, expected);
+}
+
+#[test]
+fn tex2d_generate_mipmaps_npot() {
+    let device = get_device();
+    let tex = device.create_tex2d::<f32>(PixelStorage::Float1, 5, 3, 3);
+    let texels = (0..15).map(|i| (i % 5) as f32 + 10.0 * (i / 5) as f32);
+    tex.view(0).copy_from(&texels.collect::<Vec<_>>());
+    tex.generate_mipmaps(&MipmapFilter::Box);
+    // 5 -> 2 texels: the middle column is split between both texels of level 1
+    let level1 = tex.view(1).copy_to_vec::<f32>();
+    assert_eq!(tex.view(1).size(), [2, 1, 1]);
+    assert!((level1[0] - 10.8).abs() < 1e-4, "{:?}", level1);
+    assert!((level1[1] - 13.2).abs() < 1e-4, "{:?}", level1);
+    let level2 = tex.view(2).copy_to_vec::<f32>();
+    assert!((level2[0] - 12.0).abs() < 1e-4, "{:?}", level2);
+}
+
+#[test]
+fn tex2d_generate_mipmaps_integer() {
+    let device = get_device();
+    let tex = device.create_tex2d::<u32>(PixelStorage::Int1, 4, 4, 3);
+    let texels = (0..16u32).map(|i| i * i).collect::<Vec<_>>();
+    tex.view(0).copy_from(&texels);
+    device
+        .default_stream()
+        .with_scope(|s| s.submit(tex.generate_mipmaps_async(&MipmapFilter::Box)));
+    let level1 = tex.view(1).copy_to_vec::<u32>();
+    for (i, v) in level1.iter().enumerate() {
+        let (x, y) = (i as u32 % 2 * 2, i as u32 / 2 * 2);
+        let sum: u32 = [(0, 0), (1, 0), (0, 1), (1, 1)]
+            .iter()
+            .map(|(dx, dy)| texels[((y + dy) * 4 + x + dx) as usize])
+            .sum();
+        assert_eq!(*v, (sum as f32 / 4.0).round() as u32);
+    }
+}
+
+#[test]
+fn cached_kernels_release_device() {
+    let device = get_device();
+    let weak = luisa::runtime::WeakDevice::new(&device);
+    let tex = device.create_tex2d::<f32>(PixelStorage::Float1, 4, 4, 3);
+    tex.view(0).copy_from(&[1.0f32; 16]);
+    tex.generate_mipmaps(&MipmapFilter::Box);
+    drop(tex);
+    // kernels created by the application still keep the device alive
+    let buf = device.create_buffer::<u32>(4);
+    let kernel = Kernel::<fn(Buffer<u32>)>::new(
+        &device,
+        &track!(|buf| {
+            buf.write(dispatch_id().x, 7u32);
+        }),
+    );
+    drop(device);
+    kernel.dispatch([4, 1, 1], &buf);
+    assert_eq!(buf.copy_to_vec(), vec![7; 4]);
+    drop((kernel, buf));
+    assert!(weak.upgrade().is_none());
+}
+
+#[test]
+fn tex3d_generate_mipmaps_filters_preserve_constants() {
+    let device = get_device();
+    let filters = [
+        MipmapFilter::Box,
+        MipmapFilter::kaiser(),
+        MipmapFilter::custom(1.0, |x| 1.0 - x.abs()),
+    ];
+    for filter in &filters {
+        let tex = device.create_tex3d::<Float4>(PixelStorage::Float4, 7, 5, 3, 3);
+        tex.view(0).copy_from(&vec![Float4::new(0.25, 0.5, 1.0, 2.0); 7 * 5 * 3]);
+        tex.generate_mipmaps(filter);
+        for level in 1..3 {
+            for v in tex.view(level).copy_to_vec::<Float4>() {
+                let d = [v.x - 0.25, v.y - 0.5, v.z - 1.0, v.w - 2.0];
+                assert!(d.iter().all(|d| d.abs() < 1e-4), "level {}: {:?}", level, v);
+            }
+        }
+    }
+}
+#[cfg(feature = "image")]
+#[test]
+fn tex2d_image_roundtrip() {
+    let device = get_device();
+    let (w, h) = (13u32, 6u32);
+    let texels = (0..w * h)
+        .map(|i| Float4::new((i % w) as f32 / w as f32, (i / w) as f32 / h as f32, 0.5, 1.0))
+        .collect::<Vec<_>>();
+    let tex = device.create_tex2d::<Float4>(PixelStorage::Float4, w, h, 1);
+    tex.view(0).copy_from(&texels);
+    for (ext, tolerance) in [("png", 2e-2), ("exr", 1e-6)] {
+        let path = std::env::temp_dir().join(format!(
+            "luisa-image-{}.{}",
+            std::process::id(),
+            ext
+        ));
+        tex.save(&path).unwrap();
+        let loaded = device
+            .load_tex2d_with_options(
+                &path,
+                &ImageLoadOptions {
+                    mips: 0,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!((loaded.width(), loaded.height()), (w, h));
+        // linearized 8-bit images are stored as halves
+        let out = match loaded.storage() {
+            PixelStorage::Half4 => loaded
+                .view(0)
+                .copy_to_vec::<Half4>()
+                .iter()
+                .map(|v| Float4::new(v.x.to_f32(), v.y.to_f32(), v.z.to_f32(), v.w.to_f32()))
+                .collect(),
+            _ => loaded.view(0).copy_to_vec::<Float4>(),
+        };
+        for (a, b) in texels.iter().zip(out.iter()) {
+            let d = [a.x - b.x, a.y - b.y, a.z - b.z, a.w - b.w];
+            assert!(d.iter().all(|d| d.abs() < tolerance), "{}: {:?} {:?}", ext, a, b);
+        }
+        // a full mip chain down to 1x1
+        assert_eq!(loaded.view(3).size(), [1, 1, 1]);
+    }
+    let unsupported = device.create_tex2d::<Int4>(PixelStorage::Int4, w, h, 1);
+    assert!(unsupported.save(std::env::temp_dir().join("luisa-image.png")).is_err());
+}
+
+#[test]
+fn tex2d_sample_bound_texture() {
+    let device = get_device();
+    let tex = device.create_tex2d::<f32>(PixelStorage::Float1, 2, 1, 1);
+    tex.view(0).copy_from(&[0.2f32, 1.0]);
+    let uvs = device.create_buffer_from_slice(&[
+        Float2::new(0.0, 0.5),
+        Float2::new(0.25, 0.5),
+        Float2::new(0.5, 0.5),
+    ]);
+    let out = device.create_buffer::<Float4>(3);
+    // the sampler is a uniform chosen at dispatch
+    let kernel = device.create_kernel::<fn(Tex2d<f32>, Buffer<Float2>, Buffer<Float4>, u32)>(
+        &|tex, uvs, out, sampler| {
+            let i = dispatch_id().x;
+            out.write(i, tex.sample(sampler, uvs.read(i)));
+        },
+    );
+    let sample = |filter, address| {
+        let sampler = Sampler { filter, address };
+        kernel.dispatch([3, 1, 1], &tex, &uvs, &out, &encode_sampler(&sampler));
+        out.copy_to_vec().iter().map(|v| v.x).collect::<Vec<_>>()
+    };
+    let cases = [
+        (SamplerFilter::Point, SamplerAddress::Edge, [0.2, 0.2, 1.0]),
+        (SamplerFilter::LinearPoint, SamplerAddress::Edge, [0.2, 0.2, 0.6]),
+        (SamplerFilter::LinearPoint, SamplerAddress::Repeat, [0.6, 0.2, 0.6]),
+        (SamplerFilter::LinearPoint, SamplerAddress::Mirror, [0.2, 0.2, 0.6]),
+        (SamplerFilter::LinearPoint, SamplerAddress::Zero, [0.1, 0.2, 0.6]),
+    ];
+    for (filter, address, expected) in cases {
+        let texels = sample(filter, address);
+        for (a, b) in texels.iter().zip(expected) {
+            assert!((a - b).abs() < 1e-4, "{:?} {:?}: {:?}", filter, address, texels);
+        }
+    }
+    // a sampler fixed when recording
+    let kernel = device.create_kernel::<fn(Tex2d<f32>, Buffer<Float4>)>(&|tex, out| {
+        let sampler = Sampler {
+            filter: SamplerFilter::Point,
+            address: SamplerAddress::Edge,
+        };
+        out.write(0, tex.sample(sampler, Float2::expr(0.5, 0.5)));
+    });
+    kernel.dispatch([1, 1, 1], &tex, &out);
+    assert_eq!(out.view(0..1).copy_to_vec()[0].x, 1.0);
+}
+
+#[test]
+fn tex2d_array_layers() {
+    let device = get_device();
+    let array = device.create_tex2d_array::<f32>(PixelStorage::Float1, 4, 3, 5);
+    assert_eq!(array.layers(), 5);
+    for layer in 0..5 {
+        let texels = (0..12).map(|i| (layer * 100 + i) as f32).collect::<Vec<_>>();
+        array.copy_layer_from(layer, &texels);
+    }
+    // double layer 2 in a kernel taking the array as a parameter
+    let kernel = device.create_kernel::<fn(Tex2dArray<f32>, u32)>(&track!(|array, layer| {
+        let p = dispatch_id().xy();
+        array.write(p, layer, array.read(p, layer) * 2.0);
+    }));
+    kernel.dispatch([4, 3, 1], &array, &2);
+    for layer in 0..5 {
+        let scale = if layer == 2 { 2.0 } else { 1.0 };
+        let expected = (0..12)
+            .map(|i| (layer * 100 + i) as f32 * scale)
+            .collect::<Vec<_>>();
+        assert_eq!(array.copy_layer_to_vec(layer), expected);
+    }
+    let buffer = device.create_buffer::<f32>(12);
+    array.copy_layer_to_buffer(4, &buffer.view(..));
+    assert_eq!(buffer.copy_to_vec()[11], 411.0);
+}
+
+#[test]
+fn tex_cube_sample_faces() {
+    let device = get_device();
+    let cube = device.create_tex_cube::<f32>(PixelStorage::Float1, 4);
+    for face in 0..6 {
+        cube.faces().copy_layer_from(face, &[face as f32; 16]);
+    }
+    let dirs = device.create_buffer_from_slice(&[
+        Float3::new(1.0, 0.2, -0.3),
+        Float3::new(-2.0, 0.5, 0.5),
+        Float3::new(0.1, 3.0, 0.0),
+        Float3::new(0.1, -1.0, 0.2),
+        Float3::new(0.3, 0.2, 0.9),
+        Float3::new(0.0, 0.0, -1.0),
+    ]);
+    let out = device.create_buffer::<f32>(12);
+    let heap = device.create_bindless_array(1);
+    let sampler = Sampler {
+        filter: SamplerFilter::LinearPoint,
+        address: SamplerAddress::Edge,
+    };
+    heap.set_tex_cube(0, &cube, sampler);
+    let kernel = device.create_kernel::<fn(TexCube<f32>, Buffer<Float3>, Buffer<f32>)>(
+        &track!(|cube, dirs, out| {
+            let i = dispatch_id().x;
+            let dir = dirs.read(i);
+            out.write(i, cube.sample(sampler, dir).x);
+            out.write(i + 6, heap.tex_cube(0).sample(dir).x);
+        }),
+    );
+    kernel.dispatch([6, 1, 1], &cube, &dirs, &out);
+    let out = out.copy_to_vec();
+    for face in 0..6 {
+        assert_eq!(out[face], face as f32, "{:?}", out);
+        assert_eq!(out[face + 6], face as f32, "{:?}", out);
+    }
+}
+
+#[test]
+fn tex2d_narrow_integer_texels() {
+    let device = get_device();
+    let tex = device.create_tex2d::<u16>(PixelStorage::Short1, 4, 1, 1);
+    tex.view(0).copy_from(&[0u16, 1, 1000, 65534]);
+    let kernel = device.create_kernel::<fn(Tex2d<u16>)>(&track!(|tex| {
+        let p = dispatch_id().xy();
+        tex.write(p, tex.read(p) + 1);
+    }));
+    kernel.dispatch([4, 1, 1], &tex);
+    assert_eq!(tex.view(0).copy_to_vec::<u16>(), vec![1, 2, 1001, 65535]);
+}
+
+#[test]
+fn tex2d_snorm_texels() {
+    let device = get_device();
+    let tex = device.create_tex2d::<Snorm8x4>(PixelStorage::Byte4, 2, 1, 1);
+    tex.view(0).copy_from(&[Byte4::new(127, -127, -128, 0), Byte4::new(0, 0, 0, 0)]);
+    let out = device.create_buffer::<Float4>(1);
+    let kernel = device.create_kernel::<fn(Tex2d<Snorm8x4>, Buffer<Float4>)>(&|tex, out| {
+        out.write(0, tex.read(Uint2::expr(0, 0)).value);
+        let v = Snorm8x4::from_comps_expr(Snorm8x4Comps {
+            value: Float4::expr(0.25, -0.25, 2.0, -1.0),
+        });
+        tex.write(Uint2::expr(1, 0), v);
+    });
+    kernel.dispatch([1, 1, 1], &tex, &out);
+    assert_eq!(out.copy_to_vec()[0].elements, [1.0, -1.0, -1.0, 0.0]);
+    // out of range values are clamped
+    assert_eq!(tex.view(0).copy_to_vec::<Byte4>()[1].elements, [32, -32, 127, -127]);
+}
+
+#[test]
+fn tex2d_packed_storages() {
+    let device = get_device();
+    let rgb10a2 = PackedRgb10A2::from_unorm(Float4::new(0.0, 0.5, 1.0, 1.0 / 3.0));
+    assert_eq!(rgb10a2.to_uint().elements, [0, 512, 1023, 1]);
+    let rg11b10 = PackedRg11B10f::from_float(Float3::new(1.5, 0.25, 1024.0));
+    assert_eq!(rg11b10.to_float().elements, [1.5, 0.25, 1024.0]);
+
+    let tex = device.create_tex2d::<Float4>(PixelStorage::R10G10B10A2, 1, 1, 1);
+    assert_eq!(device.memory_stats().kind(ResourceKind::Texture).bytes, 4);
+    tex.view(0).copy_from(&[rgb10a2]);
+    let out = device.create_buffer::<Float4>(1);
+    let kernel = device.create_kernel::<fn(Tex2d<Float4>, Buffer<Float4>)>(&|tex, out| {
+        out.write(0, tex.read(Uint2::expr(0, 0)));
+    });
+    kernel.dispatch([1, 1, 1], &tex, &out);
+    let texel = out.copy_to_vec()[0];
+    for (a, b) in texel.elements.iter().zip(rgb10a2.to_unorm().elements) {
+        assert!((a - b).abs() < 1e-3, "{:?}", texel);
+    }
+
+    let tex = device.create_tex2d::<Float4>(PixelStorage::R11G11B10, 1, 1, 1);
+    let kernel = device.create_kernel::<fn(Tex2d<Float4>)>(&|tex| {
+        tex.write(Uint2::expr(0, 0), Float4::expr(1.5, 0.25, 1024.0, 0.0));
+    });
+    kernel.dispatch([1, 1, 1], &tex);
+    assert_eq!(tex.view(0).copy_to_vec::<PackedRg11B10f>()[0], rg11b10);
+}
+
+#[derive(Clone, Copy, Debug, Value, IoTexel)]
+#[repr(C)]
+struct SurfaceTexel {
+    normal: Float2,
+    roughness: f32,
+    metallic: f32,
+}
+impl StorageTexel<SurfaceTexel> for Half4 {
+    fn pixel_storage() -> PixelStorage {
+        PixelStorage::Half4
+    }
+}
+
+#[test]
+fn tex2d_derived_packed_texel() {
+    assert_eq!(<SurfaceTexel as IoTexel>::CHANNELS, 4);
+    let device = get_device();
+    let tex = device.create_tex2d::<SurfaceTexel>(PixelStorage::Half4, 2, 1, 1);
+    let out = device.create_buffer::<Float4>(2);
+    let write = device.create_kernel::<fn(Tex2d<SurfaceTexel>)>(&track!(|tex| {
+        let x = dispatch_id().x;
+        let v = SurfaceTexel::from_comps_expr(SurfaceTexelComps {
+            normal: Float2::expr(0.5, -0.5),
+            roughness: x.cast_f32() * 0.25,
+            metallic: 0.75f32.expr(),
+        });
+        tex.write(dispatch_id().xy(), v);
+    }));
+    let read = device.create_kernel::<fn(Tex2d<SurfaceTexel>, Buffer<Float4>)>(&|tex, out| {
+        let x = dispatch_id().x;
+        let v = tex.read(dispatch_id().xy());
+        out.write(x, Float4::expr(v.normal.x, v.normal.y, v.roughness, v.metallic));
+    });
+    write.dispatch([2, 1, 1], &tex);
+    read.dispatch([2, 1, 1], &tex, &out);
+    let out = out.copy_to_vec();
+    assert_eq!(out[0].elements, [0.5, -0.5, 0.0, 0.75]);
+    assert_eq!(out[1].elements, [0.5, -0.5, 0.25, 0.75]);
+    // the channels are in declaration order
+    assert_eq!(
+        tex.view(0).copy_to_vec::<Half4>()[1].elements.map(|x| x.to_f32()),
+        [0.5, -0.5, 0.25, 0.75]
+    );
+}
+
+#[test]
+fn buffer_view_reinterpret() {
+    let device = get_device();
+    let colors = device.create_buffer_from_fn(4, |i| Float4::splat(i as f32));
+    // the view starts at the 6th f32
+    let channels = colors.view(1..3).reinterpret::<f32>();
+    assert_eq!(channels.len(), 8);
+    let kernel = device.create_kernel::<fn(Buffer<f32>)>(&track!(|channels| {
+        let i = dispatch_id().x;
+        channels.write(i, channels.read(i) + i.cast_f32());
+    }));
+    kernel.dispatch([8, 1, 1], &channels);
+    let colors = colors.copy_to_vec();
+    assert_eq!(colors[0].elements, [0.0; 4]);
+    assert_eq!(colors[1].elements, [1.0, 2.0, 3.0, 4.0]);
+    assert_eq!(colors[2].elements, [6.0, 7.0, 8.0, 9.0]);
+    assert_eq!(colors[3].elements, [3.0; 4]);
+    let bytes = device.create_buffer_from_slice(&[0x04030201u32, 0x08070605]);
+    assert_eq!(
+        bytes.view(1..).reinterpret::<[u8; 4]>().copy_to_vec(),
+        vec![[5, 6, 7, 8]]
+    );
+}
+
+#[test]
+#[should_panic]
+fn buffer_view_reinterpret_misaligned() {
+    let device = get_device();
+    let buffer = device.create_buffer::<f32>(8);
+    let _ = buffer.view(1..5).reinterpret::<Float4>();
+}
+
+#[derive(Clone, Copy, Debug, Value, PartialEq)]
+#[repr(C)]
+struct Particle {
+    position: Float2,
+    mass: f32,
+    id: u32,
+}
+
+#[test]
+fn buffer_view_strided_fields() {
+    let device = get_device();
+    let particles = device.create_buffer_from_fn(6, |i| Particle {
+        position: Float2::new(i as f32, 0.0),
+        mass: 1.0,
+        id: 0,
+    });
+    let masses = particles.view(..).field::<f32>(std::mem::offset_of!(Particle, mass));
+    let ids = particles.view(..).field::<u32>(std::mem::offset_of!(Particle, id));
+    assert_eq!(masses.len(), 6);
+    ids.copy_from(&[10, 11, 12, 13, 14, 15]);
+    // a strided view as a kernel argument
+    let scale = device.create_kernel::<fn(StridedBufferView<f32>, f32)>(&track!(|masses, k| {
+        let i = dispatch_id().x;
+        masses.write(i, masses.read(i) * k);
+    }));
+    let every_other = masses.stride(2);
+    assert_eq!(every_other.len(), 3);
+    scale.dispatch([3, 1, 1], &every_other, &2.0f32);
+    // and captured
+    let kernel = device.create_kernel::<fn()>(&track!(|| {
+        let i = dispatch_id().x;
+        ids.write(i, ids.read(i) + 100);
+    }));
+    kernel.dispatch([6, 1, 1]);
+    assert_eq!(masses.copy_to_vec(), vec![2.0, 1.0, 2.0, 1.0, 2.0, 1.0]);
+    assert_eq!(ids.stride(3).copy_to_vec(), vec![110, 113]);
+    let particles = particles.copy_to_vec();
+    for (i, p) in particles.iter().enumerate() {
+        // the other fields are untouched
+        assert_eq!(p.position.elements, [i as f32, 0.0]);
+        assert_eq!(p.id, 110 + i as u32);
+    }
+}
+
+#[test]
+fn soa_fields() {
+    let device = get_device();
+    let mut rng = thread_rng();
+    let bars = (0..64)
+        .map(|_| Bar {
+            i: rng.gen(),
+            v: Float2::new(rng.gen(), rng.gen()),
+            a: [rng.gen(), rng.gen(), rng.gen(), rng.gen()],
+            f: Foo {
+                i: rng.gen(),
+                v: Float2::new(rng.gen(), rng.gen()),
+                a: [rng.gen(), rng.gen(), rng.gen(), rng.gen()],
+                m: Mat2::from_column_array(&[[rng.gen(), rng.gen()], [rng.gen(), rng.gen()]]),
+            },
+        })
+        .collect::<Vec<_>>();
+    let bars_soa = device.create_soa_buffer::<Bar>(64);
+    bars_soa.copy_from(&bars);
+    assert_eq!(bars_soa.copy_to_vec(), bars);
+    assert_eq!(bars_soa.view(16..32).copy_to_vec(), &bars[16..32]);
+
+    // primitives of at least 32bits are plain arrays
+    let ids = bars_soa.field(|x| x.i);
+    assert!(ids.is_dense());
+    assert_eq!(
+        ids.buffer_view().copy_to_vec(),
+        bars.iter().map(|b| b.i).collect::<Vec<_>>()
+    );
+    let nested = bars_soa.view(8..24).field(|x| x.f).field(|f| f.v);
+    assert!(!nested.is_dense());
+    assert_eq!(
+        nested.copy_to_vec(),
+        bars[8..24].iter().map(|b| b.f.v).collect::<Vec<_>>()
+    );
+    assert_eq!(
+        bars_soa.field(|x| x.a[2].clone()).copy_to_vec(),
+        bars.iter().map(|b| b.a[2]).collect::<Vec<_>>()
+    );
+    bars_soa
+        .field(|x| x.f.i)
+        .copy_from(&(0..64).collect::<Vec<u32>>());
+
+    // only the read fields are loaded
+    let out = device.create_buffer::<i32>(64);
+    let positions = device.create_buffer::<Float2>(64);
+    let v = bars_soa.field(|x| x.v);
+    let kernel = device.create_kernel::<fn()>(&track!(|| {
+        let i = dispatch_id().x;
+        let soa = bars_soa.var();
+        out.write(i, soa.f.a[1].read(i) + soa.f.i.read(i).as_i32());
+        positions.write(i, v.var().read(i));
+    }));
+    kernel.dispatch([64, 1, 1]);
+    let out = out.copy_to_vec();
+    let positions = positions.copy_to_vec();
+    let also_bars = bars_soa.copy_to_vec();
+    for i in 0..64 {
+        assert_eq!(out[i], bars[i].f.a[1].wrapping_add(i as i32));
+        assert_eq!(positions[i], bars[i].v);
+        assert_eq!(also_bars[i].f.i, i as u32);
+        assert_eq!(also_bars[i].f.v, bars[i].f.v);
+    }
+
+    // `b` starts 12 bytes into the storage, too far for a plain u64 view
+    let mixed = device.create_soa_buffer::<Mixed>(3);
+    mixed.copy_from(&[
+        Mixed { a: 1.0, b: 1 << 40 },
+        Mixed { a: 2.0, b: 2 },
+        Mixed { a: 3.0, b: u64::MAX },
+    ]);
+    let b = mixed.field(|x| x.b);
+    assert!(b.is_dense());
+    assert_eq!(b.copy_to_vec(), [1 << 40, 2, u64::MAX]);
+    b.copy_from(&[3, 4, 5]);
+    assert_eq!(b.copy_to_vec(), [3, 4, 5]);
+    assert_eq!(mixed.field(|x| x.a).copy_to_vec(), [1.0, 2.0, 3.0]);
+}
+
+#[test]
+fn bindless_allocator() {
+    let device = get_device();
+    let allocator = device.create_bindless_allocator(2);
+    let buffers = (0..3)
+        .map(|i| device.create_buffer_from_fn(16, move |j| (i * 100 + j) as u32))
+        .collect::<Vec<_>>();
+    let a = allocator.alloc_buffer(&buffers[0]);
+    let b = allocator.alloc_buffer(&buffers[1]);
+    assert_eq!(allocator.capacity(), 2);
+    // the third buffer moves everything into a larger array
+    let c = allocator.alloc_buffer(&buffers[2]);
+    assert_eq!(allocator.capacity(), 4);
+    // textures have their own slots
+    let tex = device.create_tex2d::<f32>(PixelStorage::Float1, 4, 4, 1);
+    let t = allocator.alloc_tex2d(&tex, Sampler::default());
+    assert_eq!(t.index(), 0);
+    allocator.update();
+
+    let out = device.create_buffer::<u32>(16);
+    let read = device.create_kernel::<fn(BindlessArray, u32)>(&track!(|heap, slot| {
+        let i = dispatch_id().x;
+        out.write(i, heap.buffer::<u32>(slot).read(i));
+    }));
+    for (slot, buffer) in [a.index(), b.index(), c.index()].iter().zip(&buffers) {
+        read.dispatch([16, 1, 1], &*allocator.array(), slot);
+        assert_eq!(out.copy_to_vec(), buffer.copy_to_vec());
+    }
+
+    // a dropped slot is only reused after the update removing it
+    let b_index = b.index();
+    drop(b);
+    let d = allocator.alloc_buffer(&buffers[0]);
+    assert_ne!(d.index(), b_index);
+    allocator.update();
+    let e = allocator.alloc_buffer(&buffers[2]);
+    assert_eq!(e.index(), b_index);
+    allocator.update();
+    let read_e = device.create_kernel::<fn(BindlessArray)>(&track!(|heap| {
+        let i = dispatch_id().x;
+        out.write(i, e.var(&heap).read(i));
+    }));
+    read_e.dispatch([16, 1, 1], &*allocator.array());
+    assert_eq!(out.copy_to_vec(), buffers[2].copy_to_vec());
+}
+
+#[derive(Clone, Copy, Debug, Value, PartialEq)]
+#[repr(C)]
+pub struct BindlessMaterial {
+    albedo: Tex2dRef,
+    weights: BufferRef<f32>,
+    scale: f32,
+}
+#[test]
+fn bindless_refs() {
+    let device = get_device();
+    let allocator = device.create_bindless_allocator(4);
+    let weights = device.create_buffer_from_fn(4, |i| i as f32);
+    let tex = device.create_tex2d::<Float4>(PixelStorage::Float4, 2, 2, 1);
+    tex.view(0).copy_from(&[Float4::new(1.0, 2.0, 3.0, 4.0); 4]);
+    let weights_slot = allocator.alloc_buffer(&weights);
+    let tex_slot = allocator.alloc_tex2d(&tex, Sampler::default());
+    allocator.update();
+    let materials = device.create_buffer_from_slice(&[BindlessMaterial {
+        albedo: tex_slot.to_ref(),
+        weights: weights_slot.to_ref(),
+        scale: 2.0,
+    }]);
+    assert_eq!(materials.copy_to_vec()[0].weights.index(), weights_slot.index());
+    let out = device.create_buffer::<f32>(4);
+    let kernel = device.create_kernel::<fn(BindlessArray)>(&track!(|heap| {
+        let i = dispatch_id().x;
+        let m = materials.read(0);
+        let albedo = m.albedo.get(&heap).read(Uint2::expr(0, 0));
+        out.write(i, m.weights.get(&heap).read(i) * m.scale + albedo.y);
+    }));
+    kernel.dispatch([4, 1, 1], &*allocator.array());
+    assert_eq!(out.copy_to_vec(), vec![2.0, 4.0, 6.0, 8.0]);
+}
+
+// only checked by the cpu backend, with runtime checks
+#[cfg(debug_assertions)]
+#[test]
+#[should_panic]
+fn bindless_ref_type_mismatch() {
+    let device = get_device();
+    let allocator = device.create_bindless_allocator(1);
+    let values = device.create_buffer_from_fn(4, |i| i as f32);
+    let slot = allocator.alloc_buffer(&values);
+    allocator.update();
+    // the slot holds `f32`s
+    let refs = device.create_buffer_from_slice(&[BufferRef::<u32>::new(slot.index())]);
+    let out = device.create_buffer::<u32>(4);
+    let kernel = device.create_kernel::<fn(BindlessArray)>(&track!(|heap| {
+        let i = dispatch_id().x;
+        out.write(i, refs.read(0).get(&heap).read(i));
+    }));
+    kernel.dispatch([4, 1, 1], &*allocator.array());
+}
+
+#[test]
+fn device_vec_push() {
+    let device = get_device();
+    let mut evens = device.create_device_vec::<u32>(16);
+    let compact = device.create_kernel::<fn(DeviceVec<u32>)>(&track!(|evens| {
+        let i = dispatch_id().x;
+        if i % 2 == 0 {
+            evens.push(i);
+        }
+    }));
+    compact.dispatch([100, 1, 1], &evens);
+    assert_eq!(evens.len(), 16);
+    assert_eq!(evens.overflowed(), 34);
+    // clearing grows the buffer to fit the dropped values
+    evens.clear();
+    assert!(evens.is_empty());
+    assert!(evens.capacity() >= 50);
+    compact.dispatch([100, 1, 1], &evens);
+    assert_eq!(evens.overflowed(), 0);
+    let mut values = evens.copy_to_vec();
+    values.sort();
+    assert_eq!(values, (0..100).step_by(2).collect::<Vec<u32>>());
+    // reserving keeps the values
+    evens.reserve(1000);
+    assert!(evens.capacity() >= 1050);
+    let mut also_values = evens.copy_to_vec();
+    also_values.sort();
+    assert_eq!(also_values, values);
+}
+
+fn block_on<F: std::future::Future>(future: F) -> F::Output {
+    struct ThreadWaker(std::thread::Thread);
+    impl std::task::Wake for ThreadWaker {
+        fn wake(self: std::sync::Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+    let waker = std::task::Waker::from(std::sync::Arc::new(ThreadWaker(std::thread::current())));
+    let mut cx = std::task::Context::from_waker(&waker);
+    let mut future = std::pin::pin!(future);
+    loop {
+        match future.as_mut().poll(&mut cx) {
+            std::task::Poll::Ready(value) => return value,
+            std::task::Poll::Pending => std::thread::park(),
+        }
+    }
+}
+
+#[test]
+fn futures() {
+    let device = get_device();
+    let stream = device.create_stream(StreamTag::Compute);
+    let x = device.create_buffer::<u32>(1024);
+    let fill = device.create_kernel::<fn(Buffer<u32>)>(&track!(|x| {
+        let i = dispatch_id().x;
+        x.write(i, i * 2);
+    }));
+    block_on(stream.submit_async([fill.dispatch_async([1024, 1, 1], &x)]));
+    let expected = (0..1024).map(|i| i * 2).collect::<Vec<u32>>();
+    assert_eq!(block_on(x.view(..).copy_to_vec_future()), expected);
+    assert_eq!(
+        block_on(x.view(10..20).copy_to_vec_future()),
+        expected[10..20]
+    );
+    // without commands the future completes after the work submitted before
+    block_on(stream.submit_async([]));
+
+    let event = device.create_event();
+    let signaled = event.wait_async(1);
+    assert!(!signaled.is_ready());
+    stream.with_scope(|s| {
+        s.submit([fill.dispatch_async([1024, 1, 1], &x)])
+            .signal(&event, 1);
+    });
+    block_on(signaled);
+    assert!(event.wait_async(1).is_ready());
+    // dropping an event with a pending wait does not block
+    let unsignaled = device.create_event();
+    let never = unsignaled.wait_async(1);
+    drop(unsignaled);
+    assert!(!never.is_ready());
+
+    // the last command carries a callback of its own
+    let allocator = device.create_bindless_allocator(4);
+    let _slot = allocator.alloc_buffer(&x);
+    block_on(stream.submit_async([allocator.update_async()]));
+    let array = device.create_bindless_array(4);
+    array.emplace_buffer_async(0, &x);
+    let (sender, receiver) = std::sync::mpsc::channel();
+    stream.with_scope(|s| {
+        s.submit_with_callback([array.update_async()], move || sender.send(()).unwrap());
+    });
+    assert!(receiver.try_recv().is_ok());
+}
+
+#[test]
+fn indirect_dispatch() {
+    let device = get_device();
+    let sizes = device.create_indirect_dispatch_buffer(2, [64, 1, 1]);
+    let hits = device.create_device_vec::<u32>(64);
+    let out = device.create_buffer::<u32>(64);
+    out.fill(0);
+    let launched = device.create_buffer::<u32>(1);
+    launched.fill(0);
+    let select = device.create_kernel::<fn(DeviceVec<u32>)>(&track!(|hits| {
+        let i = dispatch_id().x;
+        if i % 3 == 0 {
+            hits.push(i);
+        }
+    }));
+    let count = device.create_kernel::<fn(DeviceVec<u32>, IndirectDispatchBuffer)>(&track!(
+        |hits, sizes| {
+            sizes.set_dispatch_size(0, Uint3::expr(hits.len_expr(), 1, 1));
+        }
+    ));
+    let shade = device.create_kernel::<fn(DeviceVec<u32>)>(&track!(|hits| {
+        set_indirect_dispatch();
+        let hit = hits.read(dispatch_id().x);
+        out.write(hit, hit + 1);
+        launched.var().atomic_ref(0).fetch_max(dispatch_size().x);
+    }));
+    // the size is written and used within one submission
+    let stream = device.create_stream(StreamTag::Compute);
+    block_on(stream.submit_async([
+        select.dispatch_async([30, 1, 1], &hits),
+        count.dispatch_async([1, 1, 1], &hits, &sizes),
+        shade.dispatch_indirect_async(&sizes, 0, &hits),
+    ]));
+    assert_eq!(sizes.as_buffer().copy_to_vec()[0].elements, [10, 1, 1]);
+    assert_eq!(launched.copy_to_vec(), vec![10]);
+    let expected = (0..64)
+        .map(|i| if i < 30 && i % 3 == 0 { i + 1 } else { 0 })
+        .collect::<Vec<u32>>();
+    assert_eq!(out.copy_to_vec(), expected);
+    // sizes start out zero, which skips the dispatch
+    out.fill(0);
+    shade.dispatch_indirect(&sizes, 1, &hits);
+    assert_eq!(out.copy_to_vec(), vec![0; 64]);
+}
+
+#[test]
+#[should_panic(expected = "must call `set_indirect_dispatch`")]
+fn indirect_dispatch_requires_opt_in() {
+    let device = get_device();
+    let sizes = device.create_indirect_dispatch_buffer(1, [64, 1, 1]);
+    let kernel = device.create_kernel::<fn()>(&|| {});
+    kernel.dispatch_indirect(&sizes, 0);
+}
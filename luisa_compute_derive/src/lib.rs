@@ -3,7 +3,9 @@ use syn::__private::quote::quote;
 use syn::parse::{Parse, ParseStream};
 use syn::spanned::Spanned;
 
-/// Derives the `IoTexel` trait for a `#[repr(transparent)]` struct and a `Value` impl.
+/// Derives the `IoTexel` trait for a `Value` struct. A `#[repr(transparent)]` struct is
+/// read and written as its only field, otherwise the channels of the fields are packed
+/// into one texel in declaration order.
 #[proc_macro_derive(IoTexel, attributes(luisa))]
 pub fn derive_iotexel(item: TokenStream) -> TokenStream {
     let item: syn::Item = syn::parse(item).unwrap();
@@ -247,13 +247,42 @@ impl Compiler {
         let field_types: Vec<_> = fields.iter().map(|f| &f.ty).collect();
         let field_names: Vec<_> = fields.iter().map(|f| f.ident.as_ref().unwrap()).collect();
         let soa_proxy_name = syn::Ident::new(&format!("{}Soa", name), name.span());
+        let soa_fields_name = syn::Ident::new(&format!("{}SoaFields", name), name.span());
         quote_spanned!(span=>
             #[derive(Clone)]
             #vis struct #soa_proxy_name #generics #where_clause{
                 #(#field_vis #field_names: <#field_types as #lang_path::types::SoaValue>::SoaBuffer),*
             }
+            #[derive(Clone)]
+            #vis struct #soa_fields_name #generics #where_clause{
+                ___global_offset: usize,
+                #(#field_vis #field_names: <#field_types as #lang_path::types::SoaValue>::SoaFields),*
+            }
             impl #impl_generics #lang_path::types::SoaValue for #name #ty_generics #where_clause{
                 type SoaBuffer = #soa_proxy_name #ty_generics;
+                type SoaFields = #soa_fields_name #ty_generics;
+            }
+            impl #impl_generics #lang_path::types::SoaFieldsProxy for #soa_fields_name #ty_generics #where_clause{
+                type Value = #name #ty_generics;
+
+                #[allow(unused_assignments)]
+                fn from_global_offset(___global_offset: usize) -> Self {
+                    use #lang_path::types::{SoaBufferProxy, SoaFieldsProxy};
+                    let mut ___i = 0usize;
+                    #(
+                        let #field_names = <#field_types as #lang_path::types::SoaValue>::SoaFields::from_global_offset(
+                            ___global_offset + ___i,
+                        );
+                        ___i += <<#field_types as #lang_path::types::SoaValue>::SoaBuffer as SoaBufferProxy>::num_buffers();
+                    )*
+                    Self{
+                        ___global_offset,
+                        #(#field_names),*
+                    }
+                }
+                fn global_offset(&self) -> usize {
+                    self.___global_offset
+                }
             }
             impl #impl_generics #lang_path::types::SoaBufferProxy for #soa_proxy_name #ty_generics #where_clause{
                 type Value = #name #ty_generics;
@@ -316,7 +345,7 @@ impl Compiler {
         let span = struct_.span();
         let resource_path = self.resource_path();
         let lang_path = self.lang_path();
-        // Make sure that the struct has repr(transparent).
+        // A #[repr(transparent)] struct is the texel of its only field.
         let mut has_repr_transparent = false;
         for Attribute { meta, .. } in &struct_.attrs {
             if let syn::Meta::List(list) = meta {
@@ -332,33 +361,83 @@ impl Compiler {
                 }
             }
         }
-        if !has_repr_transparent {
-            panic!("Struct must have #[repr(transparent)] attribute");
-        }
         let generics = &struct_.generics;
         let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
         let syn::Fields::Named(syn::FieldsNamed { named, .. }) = &struct_.fields else {
             panic!("IoTexel derive currently only supports named fields.")
         };
-        assert_eq!(named.len(), 1);
-        let syn::Field { ident, ty, .. } = &named[0];
-        let ident = ident.as_ref().unwrap();
         let struct_name = &struct_.ident;
         let struct_comps_name =
             syn::Ident::new(&format!("{}Comps", struct_name), struct_name.span());
+        if has_repr_transparent {
+            assert_eq!(named.len(), 1);
+            let syn::Field { ident, ty, .. } = &named[0];
+            let ident = ident.as_ref().unwrap();
+            return quote_spanned! {span=>
+                impl #impl_generics #resource_path::IoTexel for #struct_name #ty_generics #where_clause {
+                    type RwType = <#ty as #resource_path::IoTexel>::RwType;
+                    const CHANNELS: usize = <#ty as #resource_path::IoTexel>::CHANNELS;
+                    fn pixel_format(storage: #resource_path::PixelStorage) -> #resource_path::PixelFormat {
+                        <#ty as #resource_path::IoTexel>::pixel_format(storage)
+                    }
+                    fn convert_from_read(texel: #lang_path::types::Expr<Self::RwType>) -> #lang_path::types::Expr<Self> {
+                        #struct_name::from_comps_expr(#struct_comps_name {
+                            #ident: <#ty as #resource_path::IoTexel>::convert_from_read(texel),
+                        })
+                    }
+                    fn convert_to_write(value: #lang_path::types::Expr<Self>) -> #lang_path::types::Expr<Self::RwType> {
+                        <#ty as #resource_path::IoTexel>::convert_to_write(value.#ident)
+                    }
+                }
+            };
+        }
+        // Otherwise the channels of the fields are packed into one texel in
+        // declaration order, all fields must have the same read/write type.
+        if named.is_empty() {
+            panic!("IoTexel derive requires at least one field.");
+        }
+        let idents: Vec<_> = named.iter().map(|f| f.ident.as_ref().unwrap()).collect();
+        let tys: Vec<_> = named.iter().map(|f| &f.ty).collect();
+        let (first_ident, first_ty) = (idents[0], tys[0]);
+        let (rest_idents, rest_tys) = (&idents[1..], &tys[1..]);
         quote_spanned! {span=>
-            impl #impl_generics #resource_path::IoTexel for #struct_name<#ty_generics> #where_clause {
-                type RwType = <#ty as #resource_path::IoTexel>::RwType;
+            impl #impl_generics #resource_path::IoTexel for #struct_name #ty_generics #where_clause {
+                type RwType = <#first_ty as #resource_path::IoTexel>::RwType;
+                const CHANNELS: usize = 0 #(+ <#tys as #resource_path::IoTexel>::CHANNELS)*;
                 fn pixel_format(storage: #resource_path::PixelStorage) -> #resource_path::PixelFormat {
-                    <#ty as #resource_path::IoTexel>::pixel_format(storage)
+                    assert!(
+                        Self::CHANNELS <= 4,
+                        "{} packs {} channels but a texel has at most 4",
+                        stringify!(#struct_name),
+                        Self::CHANNELS
+                    );
+                    <#first_ty as #resource_path::IoTexel>::pixel_format(storage)
                 }
                 fn convert_from_read(texel: #lang_path::types::Expr<Self::RwType>) -> #lang_path::types::Expr<Self> {
-                    #struct_name::from_comps_expr(#struct_comps_name {
-                        #ident: <#ty as #resource_path::IoTexel>::convert_from_read(texel),
-                    })
+                    let mut offset = 0usize;
+                    #(
+                        let #idents = <#tys as #resource_path::IoTexel>::convert_from_read(
+                            <Self::RwType as #resource_path::TexelChannels>::extract(texel, offset),
+                        );
+                        offset += <#tys as #resource_path::IoTexel>::CHANNELS;
+                    )*
+                    let _ = offset;
+                    #struct_name::from_comps_expr(#struct_comps_name { #(#idents),* })
                 }
                 fn convert_to_write(value: #lang_path::types::Expr<Self>) -> #lang_path::types::Expr<Self::RwType> {
-                    <#ty as #resource_path::IoTexel>::convert_to_write(value.#ident)
+                    let mut texel = <#first_ty as #resource_path::IoTexel>::convert_to_write(value.#first_ident);
+                    let mut offset = <#first_ty as #resource_path::IoTexel>::CHANNELS;
+                    #(
+                        texel = <Self::RwType as #resource_path::TexelChannels>::insert(
+                            texel,
+                            <#rest_tys as #resource_path::IoTexel>::convert_to_write(value.#rest_idents),
+                            offset,
+                            <#rest_tys as #resource_path::IoTexel>::CHANNELS,
+                        );
+                        offset += <#rest_tys as #resource_path::IoTexel>::CHANNELS;
+                    )*
+                    let _ = offset;
+                    texel
                 }
             }
         }